@@ -0,0 +1,52 @@
+//! Embed information about the build, shown by `/version`.
+
+use std::{
+    path::Path,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Get the commit being built, from `GIT_COMMIT` or the repository.
+fn git_commit() -> Option<String> {
+    if let Ok(commit) = std::env::var("GIT_COMMIT") {
+        return Some(commit);
+    }
+
+    let output = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+
+    String::from_utf8(output.stdout)
+        .ok()
+        .map(|commit| commit.trim().to_string())
+}
+
+fn main() {
+    let commit = git_commit().unwrap_or_else(|| "unknown".to_string());
+
+    // Reproducible builds set the time to use instead of the current time.
+    let built_at = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|timestamp| timestamp.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        });
+
+    println!("cargo:rustc-env=MCAPI_GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=MCAPI_BUILT_AT={}", built_at);
+
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    // Paths that do not exist would cause this to run on every build.
+    for path in [".git/HEAD", ".git/refs/heads"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+}
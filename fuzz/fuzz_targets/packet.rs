@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mcapi_rs::protocol::{fuzzing, Limits};
+
+fuzz_target!(|data: &[u8]| {
+    let limits = Limits::default();
+
+    if let Ok(status) = futures::executor::block_on(fuzzing::read_status(data, &limits)) {
+        assert!(status.len() <= limits.max_status_bytes);
+    }
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mcapi_rs::protocol::fuzzing;
+
+fuzz_target!(|data: &[u8]| {
+    let query = futures::executor::block_on(fuzzing::parse_query(data));
+    let _ = serde_json::to_string(&query);
+});
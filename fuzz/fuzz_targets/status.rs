@@ -0,0 +1,24 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mcapi_rs::{
+    motd,
+    protocol::{fuzzing, Limits},
+};
+
+fuzz_target!(|status: &str| {
+    let limits = Limits {
+        max_favicon_bytes: 1024,
+        max_player_sample: 4,
+        ..Default::default()
+    };
+
+    if let Ok(ping) = fuzzing::parse_status(status, &limits) {
+        assert!(ping.favicon.iter().all(|favicon| favicon.len() <= 1024));
+        assert!(ping.players.sample.iter().all(|sample| sample.len() <= 4));
+
+        let _ = ping.get_motd();
+        motd::lines(&motd::parse(&ping.description));
+        let _ = serde_json::to_string(&ping);
+    }
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mcapi_rs::protocol::fuzzing;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = futures::executor::block_on(fuzzing::read_varint(data));
+});
@@ -0,0 +1,9 @@
+CREATE TABLE server_history (
+    host TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    checked_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    online BOOLEAN NOT NULL,
+    players INTEGER NOT NULL
+);
+
+CREATE INDEX server_history_lookup_idx ON server_history (host, port, checked_at);
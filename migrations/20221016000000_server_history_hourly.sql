@@ -0,0 +1,9 @@
+CREATE TABLE server_history_hourly (
+    host TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    hour TIMESTAMPTZ NOT NULL,
+    checks INTEGER NOT NULL,
+    online_checks INTEGER NOT NULL,
+    players_sum BIGINT NOT NULL,
+    PRIMARY KEY (host, port, hour)
+);
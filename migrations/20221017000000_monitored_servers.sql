@@ -0,0 +1,7 @@
+CREATE TABLE monitored_servers (
+    host TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    interval_secs INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (host, port)
+);
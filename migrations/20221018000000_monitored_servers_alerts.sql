@@ -0,0 +1 @@
+ALTER TABLE monitored_servers ADD COLUMN alerts JSONB NOT NULL DEFAULT '[]';
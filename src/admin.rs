@@ -0,0 +1,501 @@
+use actix_web::{
+    dev::Payload, error::InternalError, http::header, web, FromRequest, HttpRequest, HttpResponse,
+    Resource, Responder,
+};
+use futures::future::{ready, Ready};
+use serde::Deserialize;
+
+use mcapi_rs::resolver;
+
+use crate::{
+    alerts::AlertRule,
+    ban::BanList,
+    cache::CacheStore,
+    client_ip,
+    config::Config,
+    monitor::{self, Monitor, Registration},
+    usage, ServerAddr, ServerRequest,
+};
+
+/// Keys listed from the cache when no limit is provided.
+const DEFAULT_KEY_LIMIT: usize = 100;
+
+/// Most keys that may be listed from the cache at once.
+const MAX_KEY_LIMIT: usize = 1000;
+
+/// Days of API key usage returned when no number of days is provided.
+const DEFAULT_USAGE_DAYS: u64 = 30;
+
+/// Proof that a request included a valid admin token.
+///
+/// Tokens are provided as `Authorization: Bearer <token>`, or as the password
+/// of basic authentication so browsers can open admin pages, and must match
+/// one of the configured admin tokens.
+pub struct Admin;
+
+impl FromRequest for Admin {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let token = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(authorization_token);
+
+        let authorized = match (req.app_data::<web::Data<Config>>(), token) {
+            (Some(config), Some(token)) => config
+                .admin_tokens
+                .iter()
+                .any(|admin_token| tokens_match(admin_token.as_bytes(), token.as_bytes())),
+            _ => false,
+        };
+
+        if authorized {
+            return ready(Ok(Admin));
+        }
+
+        ready(Err(InternalError::from_response(
+            "missing or invalid admin token",
+            HttpResponse::Unauthorized()
+                .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+                .json(serde_json::json!({
+                    "status": "error",
+                    "error": "missing or invalid admin token",
+                })),
+        )
+        .into()))
+    }
+}
+
+/// Get the token from an `Authorization` header, either a bearer token or the
+/// password of basic authentication.
+fn authorization_token(value: &str) -> Option<String> {
+    if let Some(token) = value.strip_prefix("Bearer ") {
+        return Some(token.to_string());
+    }
+
+    let credentials = base64::decode(value.strip_prefix("Basic ")?).ok()?;
+    let credentials = String::from_utf8(credentials).ok()?;
+    let (_username, password) = credentials.split_once(':')?;
+
+    Some(password.to_string())
+}
+
+/// Compare tokens without returning early, so the time taken does not reveal
+/// how much of a token was correct.
+pub fn tokens_match(expected: &[u8], provided: &[u8]) -> bool {
+    expected.len() == provided.len()
+        && expected
+            .iter()
+            .zip(provided)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MonitorRequest {
+    #[serde(rename = "ip")]
+    pub host: String,
+    pub port: Option<u16>,
+
+    /// Seconds between refreshes.
+    pub interval: u32,
+    /// Rules to notify about, replacing any existing rules.
+    #[serde(default)]
+    pub alerts: Vec<AlertRule>,
+}
+
+impl ServerAddr for MonitorRequest {
+    fn host(&self) -> &str {
+        &self.host
+    }
+
+    fn port(&self) -> Option<u16> {
+        self.port
+    }
+}
+
+/// Endpoints for managing servers registered for monitoring.
+pub fn monitors() -> Resource {
+    let json_cfg = web::JsonConfig::default().error_handler(|err, _req| {
+        let message = err.to_string();
+        InternalError::from_response(err, bad_request(&message)).into()
+    });
+
+    web::resource("/admin/monitors")
+        .app_data(json_cfg)
+        .route(web::get().to(list_monitors))
+        .route(web::post().to(register_monitor))
+        .route(web::delete().to(remove_monitor))
+}
+
+async fn list_monitors(_admin: Admin, monitor: web::Data<Monitor>) -> impl Responder {
+    HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "monitors": monitor.registrations(),
+    }))
+}
+
+async fn register_monitor(
+    _admin: Admin,
+    config: web::Data<Config>,
+    monitor: web::Data<Monitor>,
+    web::Json(req): web::Json<MonitorRequest>,
+) -> impl Responder {
+    let (host, port) = req.parse_host();
+
+    if host.is_empty() {
+        return bad_request("missing server address");
+    }
+
+    if let Err(err) = crate::validate_port(port) {
+        return bad_request(&err.to_string());
+    }
+
+    let host = match resolver::normalize_host(host) {
+        Ok(host) => host,
+        Err(err) => return bad_request(&err.to_string()),
+    };
+
+    if !(config.monitor_min_interval..=monitor::MAX_INTERVAL).contains(&req.interval) {
+        return bad_request(&format!(
+            "interval must be between {} and {} seconds",
+            config.monitor_min_interval,
+            monitor::MAX_INTERVAL
+        ));
+    }
+
+    if let Some(rule) = req
+        .alerts
+        .iter()
+        .find(|rule| reqwest::Url::parse(rule.notify.url()).is_err())
+    {
+        return bad_request(&format!("invalid alert url: {}", rule.notify.url()));
+    }
+
+    let registration = Registration {
+        host,
+        port,
+        interval: req.interval,
+        alerts: req.alerts.clone(),
+    };
+
+    if let Err(err) = monitor.register(registration.clone()).await {
+        tracing::error!(
+            "could not register {}:{}: {}",
+            registration.host,
+            registration.port,
+            err
+        );
+        return internal_error("could not save monitored server");
+    }
+
+    tracing::info!(
+        "monitoring {}:{} every {} seconds",
+        registration.host,
+        registration.port,
+        registration.interval
+    );
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "monitor": registration,
+    }))
+}
+
+async fn remove_monitor(
+    _admin: Admin,
+    monitor: web::Data<Monitor>,
+    web::Query(addr): web::Query<ServerRequest>,
+) -> impl Responder {
+    let (host, port) = addr.parse_host();
+
+    match monitor.unregister(host, port).await {
+        Ok(true) => {
+            tracing::info!("stopped monitoring {}:{}", host, port);
+
+            HttpResponse::Ok().json(serde_json::json!({
+                "status": "success",
+            }))
+        }
+        Ok(false) => HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "error": "server is not registered",
+        })),
+        Err(err) => {
+            tracing::error!("could not unregister {}:{}: {}", host, port, err);
+            internal_error("could not remove monitored server")
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CacheKeysRequest {
+    /// Glob pattern keys must match, defaulting to all keys.
+    pub pattern: Option<String>,
+    /// Most keys to return.
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CacheEntryRequest {
+    pub key: String,
+}
+
+/// Endpoint for listing keys in the cache.
+pub fn cache_keys() -> Resource {
+    web::resource("/admin/cache/keys").route(web::get().to(list_cache_keys))
+}
+
+/// Endpoint for reading a value from the cache.
+pub fn cache_entry() -> Resource {
+    web::resource("/admin/cache/entry").route(web::get().to(get_cache_entry))
+}
+
+async fn list_cache_keys(
+    _admin: Admin,
+    cache: web::Data<dyn CacheStore>,
+    web::Query(req): web::Query<CacheKeysRequest>,
+) -> impl Responder {
+    let pattern = req.pattern.as_deref().unwrap_or("*");
+    let limit = req.limit.unwrap_or(DEFAULT_KEY_LIMIT).min(MAX_KEY_LIMIT);
+
+    match cache.keys(pattern, limit).await {
+        Ok(keys) => HttpResponse::Ok().json(serde_json::json!({
+            "status": "success",
+            "keys": keys,
+        })),
+        Err(err) => {
+            tracing::error!("could not list cache keys matching {}: {}", pattern, err);
+            internal_error("could not list cache keys")
+        }
+    }
+}
+
+/// Respond with a cached value and the seconds until it expires.
+///
+/// Values are returned as JSON when they can be parsed, such as ping and query
+/// data, otherwise they are base64 encoded.
+async fn get_cache_entry(
+    _admin: Admin,
+    cache: web::Data<dyn CacheStore>,
+    web::Query(req): web::Query<CacheEntryRequest>,
+) -> impl Responder {
+    let value = match cache.get(&req.key).await {
+        Ok(Some(value)) => value,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(serde_json::json!({
+                "status": "error",
+                "error": "key is not cached",
+            }))
+        }
+        Err(err) => {
+            tracing::error!("could not get cache entry {}: {}", req.key, err);
+            return internal_error("could not get cache entry");
+        }
+    };
+
+    let ttl = match cache.ttl(&req.key).await {
+        Ok(ttl) => ttl,
+        Err(err) => {
+            tracing::warn!("could not get ttl of cache entry {}: {}", req.key, err);
+            None
+        }
+    };
+
+    let (encoding, value) = match serde_json::from_slice::<serde_json::Value>(&value) {
+        Ok(value) => ("json", value),
+        Err(_err) => ("base64", serde_json::Value::String(base64::encode(&value))),
+    };
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "key": req.key,
+        "ttl": ttl,
+        "encoding": encoding,
+        "value": value,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BanRequest {
+    /// Network or single address to ban.
+    pub network: String,
+    /// Explanation returned to banned clients.
+    pub reason: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnbanRequest {
+    pub network: String,
+}
+
+/// Endpoints for managing banned networks.
+pub fn bans() -> Resource {
+    let json_cfg = web::JsonConfig::default().error_handler(|err, _req| {
+        let message = err.to_string();
+        InternalError::from_response(err, bad_request(&message)).into()
+    });
+
+    web::resource("/admin/bans")
+        .app_data(json_cfg)
+        .route(web::get().to(list_bans))
+        .route(web::post().to(add_ban))
+        .route(web::delete().to(remove_ban))
+}
+
+async fn list_bans(_admin: Admin, bans: web::Data<BanList>) -> impl Responder {
+    HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "bans": bans.bans(),
+    }))
+}
+
+async fn add_ban(
+    _admin: Admin,
+    bans: web::Data<BanList>,
+    cache: web::Data<dyn CacheStore>,
+    web::Json(req): web::Json<BanRequest>,
+) -> impl Responder {
+    let network = match client_ip::parse_network(req.network.trim()) {
+        Ok(network) => network,
+        Err(_err) => return bad_request(&format!("invalid network: {}", req.network)),
+    };
+
+    match bans.ban(cache.get_ref(), network, &req.reason).await {
+        Ok(ban) => {
+            tracing::info!("banned {}: {}", ban.network, ban.reason);
+
+            HttpResponse::Ok().json(serde_json::json!({
+                "status": "success",
+                "ban": ban,
+            }))
+        }
+        Err(err) => {
+            tracing::error!("could not ban {}: {}", network, err);
+            internal_error("could not save ban")
+        }
+    }
+}
+
+async fn remove_ban(
+    _admin: Admin,
+    bans: web::Data<BanList>,
+    cache: web::Data<dyn CacheStore>,
+    web::Query(req): web::Query<UnbanRequest>,
+) -> impl Responder {
+    let network = match client_ip::parse_network(req.network.trim()) {
+        Ok(network) => network,
+        Err(_err) => return bad_request(&format!("invalid network: {}", req.network)),
+    };
+
+    match bans.unban(cache.get_ref(), network).await {
+        Ok(true) => {
+            tracing::info!("unbanned {}", network);
+
+            HttpResponse::Ok().json(serde_json::json!({
+                "status": "success",
+            }))
+        }
+        Ok(false) => HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "error": "network is not banned",
+        })),
+        Err(err) => {
+            tracing::error!("could not unban {}: {}", network, err);
+            internal_error("could not remove ban")
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KeyUsageRequest {
+    /// Days of usage to return, starting with today.
+    pub days: Option<u64>,
+}
+
+/// Endpoint for the daily usage of an API key.
+pub fn key_usage() -> Resource {
+    web::resource("/admin/keys/{id}/usage").route(web::get().to(get_key_usage))
+}
+
+async fn get_key_usage(
+    _admin: Admin,
+    config: web::Data<Config>,
+    cache: web::Data<dyn CacheStore>,
+    id: web::Path<String>,
+    web::Query(req): web::Query<KeyUsageRequest>,
+) -> impl Responder {
+    let id = id.into_inner();
+
+    if !config.api_keys.iter().any(|key| key.id == id) {
+        return HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "error": "unknown api key",
+        }));
+    }
+
+    let days = req
+        .days
+        .unwrap_or(DEFAULT_USAGE_DAYS)
+        .min(config.api_key_usage_retention);
+
+    match usage::daily_usage(cache.get_ref(), &id, days).await {
+        Ok(usage) => HttpResponse::Ok().json(serde_json::json!({
+            "status": "success",
+            "id": id,
+            "usage": usage,
+        })),
+        Err(err) => {
+            tracing::error!("could not get usage for api key {}: {}", id, err);
+            internal_error("could not get api key usage")
+        }
+    }
+}
+
+fn bad_request(message: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({
+        "status": "error",
+        "error": message,
+    }))
+}
+
+fn internal_error(message: &str) -> HttpResponse {
+    HttpResponse::InternalServerError().json(serde_json::json!({
+        "status": "error",
+        "error": message,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_match() {
+        assert!(tokens_match(b"secret", b"secret"));
+        assert!(!tokens_match(b"secret", b"secreT"));
+        assert!(!tokens_match(b"secret", b"secret2"));
+        assert!(!tokens_match(b"secret", b""));
+    }
+
+    #[test]
+    fn test_authorization_token() {
+        assert_eq!(
+            authorization_token("Bearer secret").as_deref(),
+            Some("secret")
+        );
+        assert_eq!(
+            authorization_token(&format!("Basic {}", base64::encode("admin:secret"))).as_deref(),
+            Some("secret")
+        );
+        assert_eq!(
+            authorization_token(&format!("Basic {}", base64::encode("secret"))),
+            None
+        );
+        assert_eq!(authorization_token("Basic !"), None);
+        assert_eq!(authorization_token("secret"), None);
+    }
+}
@@ -0,0 +1,341 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use mcapi_rs::types::{Metadata, ServerPing};
+
+use crate::cache::CacheStore;
+
+/// Seconds to remember the state of an alert, matching how long status
+/// changes are tracked.
+const STATE_TTL: u32 = 60 * 60 * 24 * 30;
+
+/// How long to wait for a notification to be delivered.
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("cache error: {0}")]
+    Cache(#[from] mcapi_rs::types::Error),
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// What must be true of a server for an alert to trigger.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "condition", rename_all = "snake_case")]
+pub enum Condition {
+    /// The server is offline.
+    Offline,
+    /// Fewer than this many players are online, including while the server is
+    /// offline.
+    PlayersBelow { players: i64 },
+}
+
+impl Condition {
+    fn matches(&self, data: &ServerPing) -> bool {
+        match *self {
+            Self::Offline => !data.is_online(),
+            Self::PlayersBelow { players } => data.players_online() < players,
+        }
+    }
+}
+
+/// Where notifications for an alert are sent.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Notifier {
+    /// A Discord channel webhook.
+    Discord { url: String },
+    /// A Slack incoming webhook.
+    Slack { url: String },
+    /// Any URL accepting a JSON [`Notification`].
+    Webhook { url: String },
+}
+
+impl Notifier {
+    pub fn url(&self) -> &str {
+        match self {
+            Self::Discord { url } | Self::Slack { url } | Self::Webhook { url } => url,
+        }
+    }
+}
+
+/// A condition to alert on for a registered server.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AlertRule {
+    #[serde(flatten)]
+    pub condition: Condition,
+    /// Minutes the condition must hold before notifying.
+    #[serde(default)]
+    pub minutes: u32,
+    pub notify: Notifier,
+}
+
+impl AlertRule {
+    /// Identify the rule for tracking its state.
+    ///
+    /// This is derived from the whole rule, so state follows a rule when
+    /// other rules are added or removed and starts over when it is changed.
+    fn id(&self) -> String {
+        let rule = serde_json::to_vec(self).expect("rules should always serialize");
+        let digest = Sha256::digest(&rule);
+
+        digest[..8].iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Describe the alert for a server.
+    fn message(&self, host: &str, port: u16, status: AlertStatus) -> String {
+        let duration = match self.minutes {
+            0 => String::new(),
+            1 => " for 1 minute".to_string(),
+            minutes => format!(" for {} minutes", minutes),
+        };
+
+        match (&self.condition, status) {
+            (Condition::Offline, AlertStatus::Triggered) => {
+                format!("{}:{} has been offline{}", host, port, duration)
+            }
+            (Condition::Offline, AlertStatus::Resolved) => {
+                format!("{}:{} is back online", host, port)
+            }
+            (Condition::PlayersBelow { players }, AlertStatus::Triggered) => format!(
+                "{}:{} has had fewer than {} players online{}",
+                host, port, players, duration
+            ),
+            (Condition::PlayersBelow { players }, AlertStatus::Resolved) => format!(
+                "{}:{} has at least {} players online again",
+                host, port, players
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertStatus {
+    Triggered,
+    Resolved,
+}
+
+/// Body sent to generic webhooks.
+#[derive(Debug, Serialize)]
+pub struct Notification<'a> {
+    pub host: &'a str,
+    pub port: u16,
+    pub status: AlertStatus,
+    #[serde(flatten)]
+    pub condition: &'a Condition,
+    pub minutes: u32,
+    pub message: String,
+    pub timestamp: u64,
+}
+
+/// Progress of an alert for a server.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct AlertState {
+    /// When the condition started holding, if it currently holds.
+    since: Option<u64>,
+    /// If a notification was sent that has not been resolved.
+    triggered: bool,
+}
+
+impl AlertState {
+    /// Update the state after checking if the condition holds, returning the
+    /// new state and a notification to send, if any.
+    fn next(self, rule: &AlertRule, matches: bool, now: u64) -> (Self, Option<AlertStatus>) {
+        if !matches {
+            let status = self.triggered.then_some(AlertStatus::Resolved);
+            return (Self::default(), status);
+        }
+
+        let since = self.since.unwrap_or(now);
+        let held = now.saturating_sub(since) >= u64::from(rule.minutes) * 60;
+
+        let state = Self {
+            since: Some(since),
+            triggered: self.triggered || held,
+        };
+        let status = (!self.triggered && held).then_some(AlertStatus::Triggered);
+
+        (state, status)
+    }
+}
+
+/// Checks alert rules when registered servers are refreshed, notifying when
+/// alerts trigger or resolve.
+pub struct Alerts {
+    client: reqwest::Client,
+}
+
+impl Default for Alerts {
+    fn default() -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(NOTIFY_TIMEOUT)
+                .user_agent(concat!("mcapi-rs/", env!("CARGO_PKG_VERSION")))
+                .build()
+                .expect("alert client should build"),
+        }
+    }
+}
+
+impl Alerts {
+    /// Check each rule against a refreshed server.
+    ///
+    /// Each alert's state is kept in the cache, so conditions are tracked
+    /// across instances sharing a cache.
+    pub async fn check(
+        &self,
+        cache: &dyn CacheStore,
+        host: &str,
+        port: u16,
+        rules: &[AlertRule],
+        data: &ServerPing,
+    ) -> Result<(), Error> {
+        let now = crate::unix_timestamp();
+
+        for rule in rules {
+            let key = format!("alert:{}:{}:{}", host, port, rule.id()).to_lowercase();
+
+            let previous: AlertState = match cache.get(&key).await? {
+                Some(value) => serde_json::from_slice(&value).unwrap_or_default(),
+                None => AlertState::default(),
+            };
+
+            let (state, status) = previous.next(rule, rule.condition.matches(data), now);
+
+            if state != previous {
+                cache
+                    .set(&key, serde_json::to_vec(&state)?, STATE_TTL)
+                    .await?;
+            }
+
+            let status = match status {
+                Some(status) => status,
+                None => continue,
+            };
+
+            let notification = Notification {
+                host,
+                port,
+                status,
+                condition: &rule.condition,
+                minutes: rule.minutes,
+                message: rule.message(host, port, status),
+                timestamp: now,
+            };
+
+            tracing::info!("{}", notification.message);
+
+            let request = self.request(&rule.notify, &notification);
+            let key = format!("{}:{}", host, port);
+
+            actix_web::rt::spawn(async move {
+                let result = match request.send().await {
+                    Ok(resp) => resp.error_for_status().map(|_resp| ()),
+                    Err(err) => Err(err),
+                };
+
+                if let Err(err) = result {
+                    tracing::error!("could not send alert for {}: {}", key, err);
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Build the request to deliver a notification in the notifier's format.
+    fn request(&self, notifier: &Notifier, notification: &Notification) -> reqwest::RequestBuilder {
+        let request = self.client.post(notifier.url());
+
+        match notifier {
+            Notifier::Discord { .. } => request.json(&serde_json::json!({
+                "content": notification.message,
+            })),
+            Notifier::Slack { .. } => request.json(&serde_json::json!({
+                "text": notification.message,
+            })),
+            Notifier::Webhook { .. } => request.json(notification),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rule_format() {
+        let rule: AlertRule = serde_json::from_str(
+            r#"{"condition": "players_below", "players": 2, "minutes": 5, "notify": {"type": "slack", "url": "https://hooks.slack.com/services/x"}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(rule.condition, Condition::PlayersBelow { players: 2 });
+        assert_eq!(rule.minutes, 5);
+        assert_eq!(rule.notify.url(), "https://hooks.slack.com/services/x");
+
+        let rule: AlertRule = serde_json::from_str(
+            r#"{"condition": "offline", "notify": {"type": "discord", "url": "https://discord.com/api/webhooks/x"}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(rule.condition, Condition::Offline);
+        assert_eq!(rule.minutes, 0);
+    }
+
+    #[test]
+    fn test_rule_id() {
+        let rule = AlertRule {
+            condition: Condition::Offline,
+            minutes: 5,
+            notify: Notifier::Webhook {
+                url: "http://localhost/".to_string(),
+            },
+        };
+
+        assert_eq!(rule.id(), rule.clone().id());
+        assert_eq!(rule.id().len(), 16);
+
+        let changed = AlertRule {
+            minutes: 10,
+            ..rule.clone()
+        };
+        assert_ne!(rule.id(), changed.id());
+    }
+
+    #[test]
+    fn test_state() {
+        let rule = AlertRule {
+            condition: Condition::Offline,
+            minutes: 5,
+            notify: Notifier::Webhook {
+                url: "http://localhost/".to_string(),
+            },
+        };
+
+        // Condition must hold for the full duration before triggering.
+        let (state, status) = AlertState::default().next(&rule, true, 1000);
+        assert_eq!(status, None);
+        let (state, status) = state.next(&rule, true, 1200);
+        assert_eq!(status, None);
+        let (state, status) = state.next(&rule, true, 1300);
+        assert_eq!(status, Some(AlertStatus::Triggered));
+
+        // Only triggers once while the condition holds.
+        let (state, status) = state.next(&rule, true, 1600);
+        assert_eq!(status, None);
+
+        let (state, status) = state.next(&rule, false, 1700);
+        assert_eq!(status, Some(AlertStatus::Resolved));
+        assert_eq!(state, AlertState::default());
+
+        // Recovering before the duration never notifies.
+        let (state, _status) = state.next(&rule, true, 1800);
+        let (_state, status) = state.next(&rule, false, 1900);
+        assert_eq!(status, None);
+    }
+}
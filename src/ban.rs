@@ -0,0 +1,222 @@
+use std::{future::Future, net::IpAddr, sync::RwLock, time::Duration};
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse},
+    web, HttpMessage, HttpResponse,
+};
+use ipnet::IpNet;
+use lazy_static::lazy_static;
+use prometheus::{register_counter, Counter};
+use serde::{Deserialize, Serialize};
+
+use mcapi_rs::types::Error;
+
+use crate::{
+    cache::CacheStore,
+    client_ip::{self, ClientIp},
+};
+
+/// Hash holding each ban, keyed by network.
+const BANS_KEY: &str = "bans";
+
+/// How often bans are reloaded from the cache, to pick up changes made through
+/// other instances.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    static ref REQUESTS_BANNED: Counter = register_counter!(
+        "mcapi_requests_banned_total",
+        "Number of requests rejected because the client was banned"
+    )
+    .unwrap();
+}
+
+/// A network that may not make requests.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ban {
+    /// Network or single address that was banned.
+    pub network: String,
+    /// Explanation returned to banned clients.
+    pub reason: String,
+    /// Unix timestamp of when the ban was created.
+    pub created_at: u64,
+}
+
+/// Bans saved in the cache, with a copy kept in memory so checking a request
+/// does not need to wait for the cache.
+#[derive(Debug, Default)]
+pub struct BanList {
+    bans: RwLock<Vec<(IpNet, Ban)>>,
+}
+
+impl BanList {
+    /// Find the ban covering an address, if it is banned.
+    pub fn find(&self, ip: IpAddr) -> Option<Ban> {
+        self.bans
+            .read()
+            .unwrap()
+            .iter()
+            .find(|(network, _ban)| network.contains(&ip))
+            .map(|(_network, ban)| ban.clone())
+    }
+
+    /// All bans, ordered by network.
+    pub fn bans(&self) -> Vec<Ban> {
+        self.bans
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(_network, ban)| ban.clone())
+            .collect()
+    }
+
+    /// Replace the bans in memory with those saved in the cache.
+    pub async fn reload(&self, cache: &dyn CacheStore) -> Result<(), Error> {
+        let mut bans: Vec<(IpNet, Ban)> = cache
+            .fields(BANS_KEY)
+            .await?
+            .into_iter()
+            .filter_map(|(network, value)| {
+                let network = client_ip::parse_network(&network).ok()?;
+                let ban = serde_json::from_slice(&value).ok()?;
+
+                Some((network, ban))
+            })
+            .collect();
+
+        bans.sort_by(|a, b| a.0.cmp(&b.0));
+        *self.bans.write().unwrap() = bans;
+
+        Ok(())
+    }
+
+    /// Ban a network, replacing the reason of an existing ban.
+    pub async fn ban(
+        &self,
+        cache: &dyn CacheStore,
+        network: IpNet,
+        reason: &str,
+    ) -> Result<Ban, Error> {
+        let network = network.trunc();
+
+        let ban = Ban {
+            network: network.to_string(),
+            reason: reason.to_string(),
+            created_at: crate::unix_timestamp(),
+        };
+
+        cache
+            .set_field(BANS_KEY, &ban.network, serde_json::to_vec(&ban)?)
+            .await?;
+        self.reload(cache).await?;
+
+        Ok(ban)
+    }
+
+    /// Remove the ban on a network, returning if it was banned.
+    pub async fn unban(&self, cache: &dyn CacheStore, network: IpNet) -> Result<bool, Error> {
+        let removed = cache
+            .remove_field(BANS_KEY, &network.trunc().to_string())
+            .await?;
+        self.reload(cache).await?;
+
+        Ok(removed)
+    }
+
+    /// Periodically reload bans from the cache.
+    pub async fn run_reload(&self, cache: web::Data<dyn CacheStore>) {
+        let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(err) = self.reload(cache.get_ref()).await {
+                tracing::error!("could not reload bans: {}", err);
+            }
+        }
+    }
+}
+
+/// Middleware to reject requests from banned clients with a 403 error
+/// explaining why they were banned.
+pub fn middleware<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<BoxBody>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody + 'static,
+{
+    let ip = req
+        .extensions()
+        .get::<ClientIp>()
+        .map(|ip| ip.0)
+        .or_else(|| req.peer_addr().map(|addr| addr.ip()));
+
+    let ban = match (req.app_data::<web::Data<BanList>>(), ip) {
+        (Some(bans), Some(ip)) => bans.find(ip),
+        _ => None,
+    };
+
+    let call = match ban {
+        Some(ban) => {
+            tracing::debug!("rejecting request from banned network {}", ban.network);
+            REQUESTS_BANNED.inc();
+
+            let res = HttpResponse::Forbidden().json(serde_json::json!({
+                "status": "error",
+                "error": "you have been banned",
+                "reason": ban.reason,
+            }));
+
+            Err(req.into_response(res))
+        }
+        None => Ok(srv.call(req)),
+    };
+
+    async move {
+        match call {
+            Ok(fut) => fut.await.map(ServiceResponse::map_into_boxed_body),
+            Err(res) => Ok(res),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::cache::MemoryStore;
+
+    #[tokio::test]
+    async fn test_ban_list() {
+        let cache = MemoryStore::new(10);
+        let bans = BanList::default();
+
+        let ban = bans
+            .ban(&cache, "192.0.2.7/24".parse().unwrap(), "scraping")
+            .await
+            .unwrap();
+        assert_eq!(ban.network, "192.0.2.0/24");
+
+        assert_eq!(
+            bans.find("192.0.2.200".parse().unwrap())
+                .map(|ban| ban.reason),
+            Some("scraping".to_string())
+        );
+        assert_eq!(bans.find("198.51.100.1".parse().unwrap()), None);
+
+        // Other instances see bans once they reload.
+        let other = BanList::default();
+        other.reload(&cache).await.unwrap();
+        assert_eq!(other.bans(), vec![ban]);
+
+        assert!(bans
+            .unban(&cache, "192.0.2.0/24".parse().unwrap())
+            .await
+            .unwrap());
+        assert_eq!(bans.find("192.0.2.200".parse().unwrap()), None);
+        assert!(bans.bans().is_empty());
+    }
+}
@@ -0,0 +1,468 @@
+//! Command line tool for checking servers without running the API.
+
+use std::{
+    path::PathBuf,
+    process::ExitCode,
+    time::{Duration, Instant, SystemTime},
+};
+
+use clap::{ArgEnum, Parser, Subcommand};
+use tokio::time::timeout;
+
+use mcapi_rs::{
+    image, locale,
+    motd::Color,
+    protocol::{self, Limits, Outbound, Timeouts},
+    resolver::{self, Resolver, Target},
+    types::{Error, Metadata, ResolvedAddress, ServerPing, ServerQuery},
+};
+
+#[derive(Parser)]
+#[clap(about, version)]
+struct Args {
+    /// Seconds each phase of checking a server may take.
+    #[clap(long, default_value = "5", global = true, parse(try_from_str = parse_timeout))]
+    timeout: Duration,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Ping a server and print the response as JSON.
+    Ping {
+        /// Server address, optionally including the port.
+        address: String,
+    },
+    /// Query a server and print the response as JSON.
+    Query {
+        /// Server address, optionally including the port.
+        address: String,
+    },
+    /// Ping a server and save its favicon as a PNG.
+    Icon {
+        /// Server address, optionally including the port.
+        address: String,
+        /// Path to write the PNG to.
+        #[clap(short, long)]
+        output: PathBuf,
+    },
+    /// Ping a server and save an image of its status, or an animated banner
+    /// as a GIF.
+    Image {
+        /// Server address, optionally including the port.
+        address: String,
+        /// Path to write the image to.
+        #[clap(short, long)]
+        output: PathBuf,
+        /// Title displayed on the image, defaults to the address.
+        #[clap(long)]
+        title: Option<String>,
+        #[clap(long, arg_enum, default_value = "light")]
+        theme: Theme,
+        /// Style of the background.
+        #[clap(long, arg_enum, default_value = "flat")]
+        style: Style,
+        /// Arrangement of the icon, text, and MOTD.
+        #[clap(long, arg_enum, default_value = "wide")]
+        layout: Layout,
+        /// Width of the image before scaling.
+        #[clap(long)]
+        width: Option<u32>,
+        /// Height of the image before scaling.
+        #[clap(long)]
+        height: Option<u32>,
+        /// Multiplier for the size of the image, for high density displays.
+        #[clap(long)]
+        scale: Option<u32>,
+        /// Background color as hex, replacing the theme's background.
+        #[clap(long)]
+        bg: Option<Color>,
+        /// Text color as hex, replacing the theme's text color.
+        #[clap(long)]
+        fg: Option<Color>,
+        /// Color of the status line as hex.
+        #[clap(long)]
+        accent: Option<Color>,
+        /// List names from the player sample under the status line.
+        #[clap(long)]
+        players: bool,
+        /// Show the server's latency in the top right corner.
+        #[clap(long)]
+        latency: bool,
+        /// Element to show that is hidden by default, may be repeated.
+        #[clap(long, arg_enum)]
+        show: Vec<Element>,
+        /// Element to hide, may be repeated.
+        #[clap(long, arg_enum)]
+        hide: Vec<Element>,
+        /// Path to a TrueType or OpenType font to render text with.
+        #[clap(long)]
+        font: Option<PathBuf>,
+        /// Path to a font to use for characters missing from the font, may be
+        /// repeated to try several fonts in order.
+        #[clap(long)]
+        fallback_font: Vec<PathBuf>,
+        /// Language of text on the image.
+        #[clap(long, default_value = "en")]
+        lang: String,
+        #[clap(long, arg_enum, default_value = "png")]
+        format: Format,
+        /// Quality of JPEG images, from 1 to 100.
+        #[clap(long, default_value_t = image::DEFAULT_QUALITY)]
+        quality: u8,
+    },
+}
+
+#[derive(Clone, Copy, ArgEnum)]
+enum Theme {
+    Light,
+    Dark,
+}
+
+impl From<Theme> for image::Theme {
+    fn from(theme: Theme) -> Self {
+        match theme {
+            Theme::Light => Self::Light,
+            Theme::Dark => Self::Dark,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ArgEnum)]
+enum Style {
+    Flat,
+    Cover,
+}
+
+impl From<Style> for image::ImageStyle {
+    fn from(style: Style) -> Self {
+        match style {
+            Style::Flat => Self::Flat,
+            Style::Cover => Self::Cover,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ArgEnum)]
+enum Layout {
+    Wide,
+    Compact,
+    Square,
+    Tall,
+}
+
+impl From<Layout> for image::LayoutPreset {
+    fn from(layout: Layout) -> Self {
+        match layout {
+            Layout::Wide => Self::Wide,
+            Layout::Compact => Self::Compact,
+            Layout::Square => Self::Square,
+            Layout::Tall => Self::Tall,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ArgEnum)]
+enum Element {
+    Icon,
+    Players,
+    Version,
+    Motd,
+}
+
+#[derive(Clone, Copy, ArgEnum)]
+enum Format {
+    Png,
+    Gif,
+    Svg,
+    Webp,
+    Jpeg,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let duration = args.timeout;
+    let checker = Checker {
+        resolver: Resolver::default(),
+        timeouts: Timeouts {
+            dns: duration,
+            connect: duration,
+            read: duration,
+            total: duration * 3,
+        },
+        outbound: Outbound::default(),
+    };
+
+    match run(&checker, args.command).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(checker: &Checker, command: Command) -> Result<(), Error> {
+    match command {
+        Command::Ping { address } => {
+            let ping = checker.ping(&address).await?;
+            println!("{}", serde_json::to_string_pretty(&ping)?);
+        }
+        Command::Query { address } => {
+            let query = checker.query(&address).await?;
+            println!("{}", serde_json::to_string_pretty(&query)?);
+        }
+        Command::Icon { address, output } => {
+            let ping = checker.ping(&address).await?;
+            let icon = image::encode_png(image::server_icon(&ping.favicon));
+            std::fs::write(output, icon)?;
+        }
+        Command::Image {
+            address,
+            output,
+            title,
+            theme,
+            style,
+            layout,
+            width,
+            height,
+            scale,
+            bg,
+            fg,
+            accent,
+            players,
+            latency,
+            show,
+            hide,
+            font,
+            fallback_font,
+            lang,
+            format,
+            quality,
+        } => {
+            if locale::Strings::get(&lang).is_none() {
+                return Err(Error::from(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("no translations for language {}", lang),
+                )));
+            }
+
+            let ping = checker.ping(&address).await?;
+            let options = image::ImageOptions {
+                title: title.unwrap_or(address),
+                theme: theme.into(),
+                style: style.into(),
+                layout: layout.into(),
+                width,
+                height,
+                scale,
+                background: bg,
+                foreground: fg,
+                accent,
+                players,
+                latency,
+                show: image::Elements {
+                    icon: !hide.contains(&Element::Icon),
+                    players: !hide.contains(&Element::Players),
+                    motd: !hide.contains(&Element::Motd),
+                    version: if hide.contains(&Element::Version) {
+                        Some(false)
+                    } else if show.contains(&Element::Version) {
+                        Some(true)
+                    } else {
+                        None
+                    },
+                },
+                font: font.map(load_font).transpose()?,
+                fallback_fonts: fallback_font
+                    .into_iter()
+                    .map(load_font)
+                    .collect::<Result<_, _>>()?,
+                language: Some(lang),
+                offline: Default::default(),
+            };
+            let image = match format {
+                Format::Png => image::server_image(&options, ping),
+                Format::Gif => image::server_banner(&options, ping),
+                Format::Svg => image::server_svg(&options, ping).into_bytes(),
+                Format::Webp => image::encode_webp(image::render_server_image(&options, ping)),
+                Format::Jpeg => {
+                    image::encode_jpeg(image::render_server_image(&options, ping), quality)
+                }
+            };
+            std::fs::write(output, image)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Load a font from a file.
+fn load_font(path: PathBuf) -> Result<rusttype::Font<'static>, Error> {
+    let data = std::fs::read(&path)?;
+
+    rusttype::Font::try_from_vec(data).ok_or_else(|| {
+        Error::from(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{} is not a valid font", path.display()),
+        ))
+    })
+}
+
+struct Checker {
+    resolver: Resolver,
+    timeouts: Timeouts,
+    outbound: Outbound,
+}
+
+impl Checker {
+    async fn ping(&self, address: &str) -> Result<ServerPing, Error> {
+        let (host, port) = parse_address(address);
+        let host = &resolver::normalize_host(host)?;
+        let start = Instant::now();
+
+        let (ping, srv_target) = timeout(self.timeouts.total, async {
+            let target = self.lookup(host, port).await?;
+            let ping = protocol::send_ping(
+                &target.addrs,
+                host,
+                port,
+                &self.timeouts,
+                &Limits::default(),
+                &self.outbound,
+            )
+            .await?;
+
+            Ok::<_, Error>((ping, target.srv_target))
+        })
+        .await??;
+
+        let mut ping = ServerPing::from(ping);
+        if let Some(resolved) = &mut ping.resolved {
+            resolved.srv_target = srv_target;
+        }
+
+        Ok(ping.set_times(unix_timestamp(), elapsed_nanos(start)))
+    }
+
+    async fn query(&self, address: &str) -> Result<ServerQuery, Error> {
+        let (host, port) = parse_address(address);
+        let host = &resolver::normalize_host(host)?;
+        let start = Instant::now();
+
+        let (query, resolved) = timeout(self.timeouts.total, async {
+            let target = self.lookup(host, port).await?;
+            let addr = target.addrs[0];
+            let query = protocol::send_query(addr, &self.timeouts, &self.outbound).await?;
+
+            Ok::<_, Error>((query, ResolvedAddress::new(addr, target.srv_target)))
+        })
+        .await??;
+
+        let query = ServerQuery {
+            resolved: Some(resolved),
+            ..ServerQuery::from(query)
+        };
+
+        Ok(query.set_times(unix_timestamp(), elapsed_nanos(start)))
+    }
+
+    async fn lookup(&self, host: &str, port: u16) -> Result<Target, Error> {
+        let target = timeout(
+            self.timeouts.dns,
+            self.resolver.lookup_target(host.to_owned(), port),
+        )
+        .await?;
+
+        if target.addrs.is_empty() {
+            return Err(Error::ResolveFailed);
+        }
+
+        Ok(target)
+    }
+}
+
+/// Parse a timeout in seconds, which must be positive.
+fn parse_timeout(seconds: &str) -> Result<Duration, String> {
+    let seconds: f64 = seconds
+        .parse()
+        .map_err(|err| format!("invalid timeout: {}", err))?;
+
+    // Anything longer than a day is a mistake, and much larger values could
+    // not be represented.
+    if !(seconds > 0.0 && seconds <= 86400.0) {
+        return Err("timeout must be between 0 and 86400 seconds".to_string());
+    }
+
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+/// Split an address into a host and port, using the default port if none was
+/// provided.
+///
+/// IPv6 addresses with a port must be in brackets, like `[::1]:25565`.
+fn parse_address(address: &str) -> (&str, u16) {
+    if let Some(rest) = address.strip_prefix('[') {
+        if let Some((host, port)) = rest.split_once(']') {
+            let port = match port.strip_prefix(':') {
+                Some(port) => port.parse().ok(),
+                None if port.is_empty() => Some(25565),
+                None => None,
+            };
+
+            if let Some(port) = port {
+                return (host, port);
+            }
+        }
+    }
+
+    // Addresses with more than one colon are IPv6 addresses without a port.
+    if let Some((host, port)) = address.split_once(':') {
+        if !port.contains(':') {
+            if let Ok(port) = port.parse::<u16>() {
+                return (host, port);
+            }
+        }
+    }
+
+    (address, 25565)
+}
+
+/// Get the current unix timestamp, as seconds.
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn elapsed_nanos(start: Instant) -> u64 {
+    start.elapsed().as_nanos() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(parse_address("example.com"), ("example.com", 25565));
+        assert_eq!(parse_address("example.com:25566"), ("example.com", 25566));
+        assert_eq!(parse_address("[::1]:25566"), ("::1", 25566));
+        assert_eq!(parse_address("[::1]"), ("::1", 25565));
+        assert_eq!(parse_address("2001:db8::1"), ("2001:db8::1", 25565));
+    }
+
+    #[test]
+    fn test_parse_timeout() {
+        assert_eq!(parse_timeout("1.5"), Ok(Duration::from_millis(1500)));
+        assert!(parse_timeout("-1").is_err());
+        assert!(parse_timeout("0").is_err());
+        assert!(parse_timeout("NaN").is_err());
+        assert!(parse_timeout("inf").is_err());
+    }
+}
@@ -0,0 +1,1738 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    convert::TryFrom,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use lru::LruCache;
+use prometheus::{register_histogram_vec, HistogramVec};
+use redis::{aio::MultiplexedConnection, AsyncCommands, Client as RedisClient};
+use redlock::RedLock;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::Mutex,
+};
+
+use mcapi_rs::types::Error;
+
+lazy_static! {
+    static ref REDIS_DURATION: HistogramVec = register_histogram_vec!(
+        "mcapi_redis_operation_duration_seconds",
+        "Duration of Redis operations",
+        &["operation"]
+    )
+    .unwrap();
+    /// Deletes a lock only if it still has the value it was created with.
+    static ref UNLOCK_SCRIPT: redis::Script = redis::Script::new(
+        r#"
+        if redis.call("GET", KEYS[1]) == ARGV[1] then
+            return redis.call("DEL", KEYS[1])
+        else
+            return 0
+        end
+        "#
+    );
+}
+
+/// How many times to attempt acquiring a lock before giving up.
+const LOCK_RETRY_COUNT: usize = 3;
+/// How long to wait between attempts to acquire a lock.
+const LOCK_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// A held lock on a cache key, returned from [CacheStore::lock].
+#[derive(Debug, Clone)]
+pub struct CacheLock {
+    pub resource: Vec<u8>,
+    pub value: Vec<u8>,
+}
+
+impl CacheLock {
+    /// Create a new lock for a resource with a random value.
+    fn new(resource: &[u8]) -> Self {
+        Self {
+            resource: resource.to_vec(),
+            value: rand::random::<[u8; 20]>().to_vec(),
+        }
+    }
+}
+
+/// An increment of counters or a unique count, for making several at once with
+/// [CacheStore::increment_many].
+#[derive(Debug, Clone, Copy)]
+pub enum Increment<'a> {
+    /// Add to counters in a hash, like [CacheStore::increment_counters].
+    Counters {
+        key: &'a str,
+        counters: &'a [(&'a str, u64)],
+        ttl: u32,
+    },
+    /// Add a member to a unique count, like [CacheStore::add_unique].
+    Unique {
+        key: &'a str,
+        member: &'a str,
+        ttl: u32,
+    },
+}
+
+impl<'a> Increment<'a> {
+    fn key(&self) -> &'a str {
+        match self {
+            Self::Counters { key, .. } | Self::Unique { key, .. } => *key,
+        }
+    }
+
+    /// The same increment applied to another key.
+    fn with_key<'b>(self, key: &'b str) -> Increment<'b>
+    where
+        'a: 'b,
+    {
+        match self {
+            Self::Counters { counters, ttl, .. } => Increment::Counters { key, counters, ttl },
+            Self::Unique { member, ttl, .. } => Increment::Unique { key, member, ttl },
+        }
+    }
+}
+
+/// A place to store cached data and coordinate who is allowed to update it.
+#[async_trait]
+pub trait CacheStore: Send + Sync {
+    /// Get the value for a key, if it exists.
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error>;
+
+    /// Set the value for a key, expiring after `ttl` seconds. A `ttl` of 0
+    /// expires the value immediately, removing any value already set.
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: u32) -> Result<(), Error>;
+
+    /// Get the seconds until a key expires, if it exists and has an
+    /// expiration.
+    async fn ttl(&self, key: &str) -> Result<Option<u64>, Error>;
+
+    /// Find up to `count` keys matching a glob pattern, where `*` matches any
+    /// characters, `?` matches a single character, and `\` escapes the next
+    /// character.
+    async fn keys(&self, pattern: &str, count: usize) -> Result<Vec<String>, Error>;
+
+    /// Attempt to obtain an exclusive lock on a resource for `ttl`.
+    ///
+    /// Returns none if the lock could not be obtained after a few attempts.
+    async fn lock(&self, resource: &[u8], ttl: Duration) -> Result<Option<CacheLock>, Error>;
+
+    /// Release a previously obtained lock.
+    async fn unlock(&self, lock: &CacheLock) -> Result<(), Error>;
+
+    /// Add to the score of a member of a sorted set, creating either if they
+    /// do not exist, then remove the members with the lowest scores while
+    /// there are more than `max`.
+    async fn increment_score(
+        &self,
+        key: &str,
+        member: &str,
+        amount: f64,
+        max: usize,
+    ) -> Result<(), Error>;
+
+    /// Get up to `count` members of a sorted set with the highest scores,
+    /// ordered from highest to lowest.
+    async fn top_scores(&self, key: &str, count: usize) -> Result<Vec<(String, f64)>, Error>;
+
+    /// Multiply every score in a sorted set by `factor`, then remove all but
+    /// the `keep` members with the highest scores.
+    async fn decay_scores(&self, key: &str, factor: f64, keep: usize) -> Result<(), Error>;
+
+    /// Add to counters in a hash, creating either if they do not exist, then
+    /// expire the hash after `ttl` seconds.
+    async fn increment_counters(
+        &self,
+        key: &str,
+        counters: &[(&str, u64)],
+        ttl: u32,
+    ) -> Result<(), Error>;
+
+    /// Get every counter in a hash.
+    async fn counters(&self, key: &str) -> Result<HashMap<String, u64>, Error>;
+
+    /// Add a member to a count of unique members, creating it if it does not
+    /// exist, then expire the count after `ttl` seconds.
+    async fn add_unique(&self, key: &str, member: &str, ttl: u32) -> Result<(), Error>;
+
+    /// Get the number of unique members added to a count, which may be
+    /// approximate.
+    async fn unique_count(&self, key: &str) -> Result<u64, Error>;
+
+    /// Make several increments at once, in a single round trip if the backend
+    /// supports it.
+    async fn increment_many(&self, increments: &[Increment<'_>]) -> Result<(), Error>;
+
+    /// Set a field in a hash that never expires, creating it if it does not
+    /// exist.
+    async fn set_field(&self, key: &str, field: &str, value: Vec<u8>) -> Result<(), Error>;
+
+    /// Remove a field from a hash, returning if it existed.
+    async fn remove_field(&self, key: &str, field: &str) -> Result<bool, Error>;
+
+    /// Get every field in a hash.
+    async fn fields(&self, key: &str) -> Result<HashMap<String, Vec<u8>>, Error>;
+
+    /// Check that the store is reachable.
+    async fn ping(&self) -> Result<(), Error>;
+}
+
+/// Which cache backend to use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheBackend {
+    Redis,
+    Memory,
+    Memcached,
+}
+
+impl std::str::FromStr for CacheBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "redis" => Ok(Self::Redis),
+            "memory" => Ok(Self::Memory),
+            "memcached" => Ok(Self::Memcached),
+            other => Err(format!("unknown cache backend: {}", other)),
+        }
+    }
+}
+
+/// How refreshes of the same key are coordinated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockStrategy {
+    /// RedLock across every Redis server.
+    RedLock,
+    /// `SET NX PX` on the first Redis server.
+    SetNx,
+    /// Only lock within this instance, ignoring any locks in the backend.
+    None,
+}
+
+impl std::str::FromStr for LockStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "redlock" => Ok(Self::RedLock),
+            "setnx" => Ok(Self::SetNx),
+            "none" => Ok(Self::None),
+            other => Err(format!("unknown lock strategy: {}", other)),
+        }
+    }
+}
+
+/// Wraps another store, prefixing every key and lock resource so multiple
+/// deployments can share the same backend without colliding.
+pub struct PrefixedStore {
+    prefix: String,
+    inner: Arc<dyn CacheStore>,
+}
+
+impl PrefixedStore {
+    /// Create a new store where each key is prefixed with `prefix` and a colon.
+    pub fn new(prefix: &str, inner: Arc<dyn CacheStore>) -> Self {
+        Self {
+            prefix: format!("{}:", prefix),
+            inner,
+        }
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+
+    fn resource(&self, resource: &[u8]) -> Vec<u8> {
+        let mut prefixed = self.prefix.as_bytes().to_vec();
+        prefixed.extend(resource);
+        prefixed
+    }
+}
+
+#[async_trait]
+impl CacheStore for PrefixedStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        self.inner.get(&self.key(key)).await
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: u32) -> Result<(), Error> {
+        self.inner.set(&self.key(key), value, ttl).await
+    }
+
+    async fn ttl(&self, key: &str) -> Result<Option<u64>, Error> {
+        self.inner.ttl(&self.key(key)).await
+    }
+
+    async fn keys(&self, pattern: &str, count: usize) -> Result<Vec<String>, Error> {
+        let pattern = format!("{}{}", escape_glob(&self.prefix), pattern);
+        let keys = self.inner.keys(&pattern, count).await?;
+
+        Ok(keys
+            .into_iter()
+            .filter_map(|key| key.strip_prefix(&self.prefix).map(ToString::to_string))
+            .collect())
+    }
+
+    async fn lock(&self, resource: &[u8], ttl: Duration) -> Result<Option<CacheLock>, Error> {
+        self.inner.lock(&self.resource(resource), ttl).await
+    }
+
+    async fn unlock(&self, lock: &CacheLock) -> Result<(), Error> {
+        // The lock already contains the prefixed resource.
+        self.inner.unlock(lock).await
+    }
+
+    async fn increment_score(
+        &self,
+        key: &str,
+        member: &str,
+        amount: f64,
+        max: usize,
+    ) -> Result<(), Error> {
+        self.inner
+            .increment_score(&self.key(key), member, amount, max)
+            .await
+    }
+
+    async fn top_scores(&self, key: &str, count: usize) -> Result<Vec<(String, f64)>, Error> {
+        self.inner.top_scores(&self.key(key), count).await
+    }
+
+    async fn decay_scores(&self, key: &str, factor: f64, keep: usize) -> Result<(), Error> {
+        self.inner.decay_scores(&self.key(key), factor, keep).await
+    }
+
+    async fn increment_counters(
+        &self,
+        key: &str,
+        counters: &[(&str, u64)],
+        ttl: u32,
+    ) -> Result<(), Error> {
+        self.inner
+            .increment_counters(&self.key(key), counters, ttl)
+            .await
+    }
+
+    async fn counters(&self, key: &str) -> Result<HashMap<String, u64>, Error> {
+        self.inner.counters(&self.key(key)).await
+    }
+
+    async fn add_unique(&self, key: &str, member: &str, ttl: u32) -> Result<(), Error> {
+        self.inner.add_unique(&self.key(key), member, ttl).await
+    }
+
+    async fn unique_count(&self, key: &str) -> Result<u64, Error> {
+        self.inner.unique_count(&self.key(key)).await
+    }
+
+    async fn increment_many(&self, increments: &[Increment<'_>]) -> Result<(), Error> {
+        let keys: Vec<_> = increments
+            .iter()
+            .map(|increment| self.key(increment.key()))
+            .collect();
+        let increments: Vec<_> = increments
+            .iter()
+            .zip(&keys)
+            .map(|(increment, key)| increment.with_key(key))
+            .collect();
+
+        self.inner.increment_many(&increments).await
+    }
+
+    async fn set_field(&self, key: &str, field: &str, value: Vec<u8>) -> Result<(), Error> {
+        self.inner.set_field(&self.key(key), field, value).await
+    }
+
+    async fn remove_field(&self, key: &str, field: &str) -> Result<bool, Error> {
+        self.inner.remove_field(&self.key(key), field).await
+    }
+
+    async fn fields(&self, key: &str) -> Result<HashMap<String, Vec<u8>>, Error> {
+        self.inner.fields(&self.key(key)).await
+    }
+
+    async fn ping(&self) -> Result<(), Error> {
+        self.inner.ping().await
+    }
+}
+
+/// Wraps another store, keeping locks in memory instead of in the store.
+///
+/// Refreshes are only coordinated between requests to the same instance, so
+/// each instance refreshes a key at most once at a time.
+pub struct LocalLockStore {
+    inner: Arc<dyn CacheStore>,
+    locks: Mutex<MemoryLocks>,
+}
+
+impl LocalLockStore {
+    pub fn new(inner: Arc<dyn CacheStore>) -> Self {
+        Self {
+            inner,
+            locks: Default::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl CacheStore for LocalLockStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        self.inner.get(key).await
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: u32) -> Result<(), Error> {
+        self.inner.set(key, value, ttl).await
+    }
+
+    async fn ttl(&self, key: &str) -> Result<Option<u64>, Error> {
+        self.inner.ttl(key).await
+    }
+
+    async fn keys(&self, pattern: &str, count: usize) -> Result<Vec<String>, Error> {
+        self.inner.keys(pattern, count).await
+    }
+
+    async fn lock(&self, resource: &[u8], ttl: Duration) -> Result<Option<CacheLock>, Error> {
+        Ok(lock_memory(&self.locks, resource, ttl).await)
+    }
+
+    async fn unlock(&self, lock: &CacheLock) -> Result<(), Error> {
+        unlock_memory(&self.locks, lock).await;
+        Ok(())
+    }
+
+    async fn increment_score(
+        &self,
+        key: &str,
+        member: &str,
+        amount: f64,
+        max: usize,
+    ) -> Result<(), Error> {
+        self.inner.increment_score(key, member, amount, max).await
+    }
+
+    async fn top_scores(&self, key: &str, count: usize) -> Result<Vec<(String, f64)>, Error> {
+        self.inner.top_scores(key, count).await
+    }
+
+    async fn decay_scores(&self, key: &str, factor: f64, keep: usize) -> Result<(), Error> {
+        self.inner.decay_scores(key, factor, keep).await
+    }
+
+    async fn increment_counters(
+        &self,
+        key: &str,
+        counters: &[(&str, u64)],
+        ttl: u32,
+    ) -> Result<(), Error> {
+        self.inner.increment_counters(key, counters, ttl).await
+    }
+
+    async fn counters(&self, key: &str) -> Result<HashMap<String, u64>, Error> {
+        self.inner.counters(key).await
+    }
+
+    async fn add_unique(&self, key: &str, member: &str, ttl: u32) -> Result<(), Error> {
+        self.inner.add_unique(key, member, ttl).await
+    }
+
+    async fn unique_count(&self, key: &str) -> Result<u64, Error> {
+        self.inner.unique_count(key).await
+    }
+
+    async fn increment_many(&self, increments: &[Increment<'_>]) -> Result<(), Error> {
+        self.inner.increment_many(increments).await
+    }
+
+    async fn set_field(&self, key: &str, field: &str, value: Vec<u8>) -> Result<(), Error> {
+        self.inner.set_field(key, field, value).await
+    }
+
+    async fn remove_field(&self, key: &str, field: &str) -> Result<bool, Error> {
+        self.inner.remove_field(key, field).await
+    }
+
+    async fn fields(&self, key: &str) -> Result<HashMap<String, Vec<u8>>, Error> {
+        self.inner.fields(key).await
+    }
+
+    async fn ping(&self) -> Result<(), Error> {
+        self.inner.ping().await
+    }
+}
+
+/// How a Redis store locks resources.
+enum RedisLocker {
+    RedLock(RedLock),
+    /// Locks with `SET NX PX` over the store's connection.
+    SetNx,
+}
+
+/// Cache stored in Redis, locked with RedLock or `SET NX PX`.
+pub struct RedisStore {
+    client: RedisClient,
+    /// Connection shared by every operation, opened on first use and again
+    /// after it is dropped.
+    connection: Mutex<Option<MultiplexedConnection>>,
+    locker: RedisLocker,
+}
+
+impl RedisStore {
+    /// Create a new store from a list of Redis servers. The first server is
+    /// used for data, all of them are used for locking with RedLock.
+    pub fn new(servers: Vec<&str>, strategy: LockStrategy) -> Result<Self, Error> {
+        let client = RedisClient::open(servers[0])?;
+
+        let locker = match strategy {
+            LockStrategy::RedLock => RedisLocker::RedLock(RedLock::new(servers)),
+            LockStrategy::SetNx | LockStrategy::None => RedisLocker::SetNx,
+        };
+
+        Ok(Self {
+            client,
+            connection: Mutex::new(None),
+            locker,
+        })
+    }
+
+    /// Get the shared connection, opening it if needed.
+    async fn connection(&self) -> Result<MultiplexedConnection, Error> {
+        let mut shared = self.connection.lock().await;
+
+        if let Some(con) = shared.as_ref() {
+            return Ok(con.clone());
+        }
+
+        let con = self.client.get_multiplexed_tokio_connection().await?;
+        *shared = Some(con.clone());
+
+        Ok(con)
+    }
+
+    /// Forget the shared connection if an error means it was dropped, so the
+    /// next operation opens a new one.
+    async fn check<T>(&self, result: redis::RedisResult<T>) -> Result<T, Error> {
+        match result {
+            Ok(value) => Ok(value),
+            Err(err) => {
+                if err.is_connection_dropped() || err.is_io_error() {
+                    self.connection.lock().await.take();
+                }
+
+                Err(err.into())
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl CacheStore for RedisStore {
+    #[tracing::instrument(name = "redis_get", skip(self))]
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        let _timer = REDIS_DURATION.with_label_values(&["get"]).start_timer();
+
+        let mut con = self.connection().await?;
+        self.check(con.get(key).await).await
+    }
+
+    #[tracing::instrument(name = "redis_set", skip(self, value))]
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: u32) -> Result<(), Error> {
+        let _timer = REDIS_DURATION.with_label_values(&["set"]).start_timer();
+
+        let mut con = self.connection().await?;
+
+        // Expirations of 0 are rejected, so the key is removed instead.
+        if ttl == 0 {
+            return self.check(con.del::<_, ()>(key).await).await;
+        }
+
+        self.check(con.set_ex::<_, _, ()>(key, value, ttl as usize).await)
+            .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "redis_ttl", skip(self))]
+    async fn ttl(&self, key: &str) -> Result<Option<u64>, Error> {
+        let _timer = REDIS_DURATION.with_label_values(&["ttl"]).start_timer();
+
+        let mut con = self.connection().await?;
+        let ttl: i64 = self.check(con.ttl(key).await).await?;
+
+        // Missing keys are -2 and keys without an expiration are -1.
+        Ok(u64::try_from(ttl).ok())
+    }
+
+    #[tracing::instrument(name = "redis_keys", skip(self))]
+    async fn keys(&self, pattern: &str, count: usize) -> Result<Vec<String>, Error> {
+        let _timer = REDIS_DURATION.with_label_values(&["keys"]).start_timer();
+
+        let mut con = self.connection().await?;
+        let mut iter: redis::AsyncIter<String> = self.check(con.scan_match(pattern).await).await?;
+
+        // Scans may return the same key more than once, so duplicates are
+        // removed before counting.
+        let mut keys = BTreeSet::new();
+        while keys.len() < count {
+            match iter.next_item().await {
+                Some(key) => keys.insert(key),
+                None => break,
+            };
+        }
+
+        Ok(keys.into_iter().collect())
+    }
+
+    #[tracing::instrument(name = "redis_lock", skip(self, resource))]
+    async fn lock(&self, resource: &[u8], ttl: Duration) -> Result<Option<CacheLock>, Error> {
+        let _timer = REDIS_DURATION.with_label_values(&["lock"]).start_timer();
+
+        let redlock = match &self.locker {
+            RedisLocker::RedLock(redlock) => redlock,
+            RedisLocker::SetNx => {
+                let mut con = self.connection().await?;
+
+                for _ in 0..LOCK_RETRY_COUNT {
+                    let lock = CacheLock::new(resource);
+
+                    let set: Option<String> = self
+                        .check(
+                            redis::cmd("SET")
+                                .arg(resource)
+                                .arg(&lock.value)
+                                .arg("NX")
+                                .arg("PX")
+                                .arg(ttl.as_millis() as u64)
+                                .query_async(&mut con)
+                                .await,
+                        )
+                        .await?;
+
+                    if set.is_some() {
+                        return Ok(Some(lock));
+                    }
+
+                    tokio::time::sleep(LOCK_RETRY_DELAY).await;
+                }
+
+                return Ok(None);
+            }
+        };
+
+        Ok(redlock
+            .lock(resource, ttl.as_millis() as usize)
+            .await
+            .map(|lock| CacheLock {
+                resource: lock.resource,
+                value: lock.val,
+            }))
+    }
+
+    #[tracing::instrument(name = "redis_unlock", skip(self, lock))]
+    async fn unlock(&self, lock: &CacheLock) -> Result<(), Error> {
+        let _timer = REDIS_DURATION.with_label_values(&["unlock"]).start_timer();
+
+        let redlock = match &self.locker {
+            RedisLocker::RedLock(redlock) => redlock,
+            RedisLocker::SetNx => {
+                let mut con = self.connection().await?;
+
+                self.check(
+                    UNLOCK_SCRIPT
+                        .key(&lock.resource)
+                        .arg(&lock.value)
+                        .invoke_async::<_, i64>(&mut con)
+                        .await,
+                )
+                .await?;
+
+                return Ok(());
+            }
+        };
+
+        let lock = redlock::Lock {
+            lock: redlock,
+            resource: lock.resource.clone(),
+            val: lock.value.clone(),
+            validity_time: 0,
+        };
+
+        redlock.unlock(&lock).await;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "redis_increment_score", skip(self))]
+    async fn increment_score(
+        &self,
+        key: &str,
+        member: &str,
+        amount: f64,
+        max: usize,
+    ) -> Result<(), Error> {
+        let _timer = REDIS_DURATION
+            .with_label_values(&["increment_score"])
+            .start_timer();
+
+        let mut con = self.connection().await?;
+        self.check(
+            redis::pipe()
+                .atomic()
+                .zincr(key, member, amount)
+                .ignore()
+                .zremrangebyrank(key, 0, -(max as isize) - 1)
+                .ignore()
+                .query_async::<_, ()>(&mut con)
+                .await,
+        )
+        .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "redis_top_scores", skip(self))]
+    async fn top_scores(&self, key: &str, count: usize) -> Result<Vec<(String, f64)>, Error> {
+        let _timer = REDIS_DURATION
+            .with_label_values(&["top_scores"])
+            .start_timer();
+
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut con = self.connection().await?;
+        self.check(con.zrevrange_withscores(key, 0, count as isize - 1).await)
+            .await
+    }
+
+    #[tracing::instrument(name = "redis_decay_scores", skip(self))]
+    async fn decay_scores(&self, key: &str, factor: f64, keep: usize) -> Result<(), Error> {
+        let _timer = REDIS_DURATION
+            .with_label_values(&["decay_scores"])
+            .start_timer();
+
+        let mut con = self.connection().await?;
+        self.check(
+            redis::pipe()
+                .atomic()
+                .cmd("ZUNIONSTORE")
+                .arg(key)
+                .arg(1)
+                .arg(key)
+                .arg("WEIGHTS")
+                .arg(factor)
+                .ignore()
+                .zremrangebyrank(key, 0, -(keep as isize) - 1)
+                .ignore()
+                .query_async::<_, ()>(&mut con)
+                .await,
+        )
+        .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "redis_increment_counters", skip(self))]
+    async fn increment_counters(
+        &self,
+        key: &str,
+        counters: &[(&str, u64)],
+        ttl: u32,
+    ) -> Result<(), Error> {
+        let _timer = REDIS_DURATION
+            .with_label_values(&["increment_counters"])
+            .start_timer();
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+
+        for (field, amount) in counters {
+            pipe.hincr(key, *field, *amount).ignore();
+        }
+
+        let mut con = self.connection().await?;
+        self.check(
+            pipe.expire(key, ttl as usize)
+                .ignore()
+                .query_async::<_, ()>(&mut con)
+                .await,
+        )
+        .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "redis_counters", skip(self))]
+    async fn counters(&self, key: &str) -> Result<HashMap<String, u64>, Error> {
+        let _timer = REDIS_DURATION
+            .with_label_values(&["counters"])
+            .start_timer();
+
+        let mut con = self.connection().await?;
+        self.check(con.hgetall(key).await).await
+    }
+
+    #[tracing::instrument(name = "redis_add_unique", skip(self))]
+    async fn add_unique(&self, key: &str, member: &str, ttl: u32) -> Result<(), Error> {
+        let _timer = REDIS_DURATION
+            .with_label_values(&["add_unique"])
+            .start_timer();
+
+        let mut con = self.connection().await?;
+        self.check(
+            redis::pipe()
+                .atomic()
+                .pfadd(key, member)
+                .ignore()
+                .expire(key, ttl as usize)
+                .ignore()
+                .query_async::<_, ()>(&mut con)
+                .await,
+        )
+        .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "redis_unique_count", skip(self))]
+    async fn unique_count(&self, key: &str) -> Result<u64, Error> {
+        let _timer = REDIS_DURATION
+            .with_label_values(&["unique_count"])
+            .start_timer();
+
+        let mut con = self.connection().await?;
+        self.check(con.pfcount(key).await).await
+    }
+
+    #[tracing::instrument(name = "redis_increment_many", skip(self))]
+    async fn increment_many(&self, increments: &[Increment<'_>]) -> Result<(), Error> {
+        let _timer = REDIS_DURATION
+            .with_label_values(&["increment_many"])
+            .start_timer();
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+
+        for increment in increments {
+            match *increment {
+                Increment::Counters { key, counters, ttl } => {
+                    for (field, amount) in counters {
+                        pipe.hincr(key, *field, *amount).ignore();
+                    }
+                    pipe.expire(key, ttl as usize).ignore();
+                }
+                Increment::Unique { key, member, ttl } => {
+                    pipe.pfadd(key, member)
+                        .ignore()
+                        .expire(key, ttl as usize)
+                        .ignore();
+                }
+            }
+        }
+
+        let mut con = self.connection().await?;
+        self.check(pipe.query_async::<_, ()>(&mut con).await)
+            .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "redis_set_field", skip(self, value))]
+    async fn set_field(&self, key: &str, field: &str, value: Vec<u8>) -> Result<(), Error> {
+        let _timer = REDIS_DURATION
+            .with_label_values(&["set_field"])
+            .start_timer();
+
+        let mut con = self.connection().await?;
+        self.check(con.hset::<_, _, _, ()>(key, field, value).await)
+            .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "redis_remove_field", skip(self))]
+    async fn remove_field(&self, key: &str, field: &str) -> Result<bool, Error> {
+        let _timer = REDIS_DURATION
+            .with_label_values(&["remove_field"])
+            .start_timer();
+
+        let mut con = self.connection().await?;
+        let removed: u64 = self.check(con.hdel(key, field).await).await?;
+        Ok(removed > 0)
+    }
+
+    #[tracing::instrument(name = "redis_fields", skip(self))]
+    async fn fields(&self, key: &str) -> Result<HashMap<String, Vec<u8>>, Error> {
+        let _timer = REDIS_DURATION.with_label_values(&["fields"]).start_timer();
+
+        let mut con = self.connection().await?;
+        self.check(con.hgetall(key).await).await
+    }
+
+    #[tracing::instrument(name = "redis_ping", skip(self))]
+    async fn ping(&self) -> Result<(), Error> {
+        let _timer = REDIS_DURATION.with_label_values(&["ping"]).start_timer();
+
+        let mut con = self.connection().await?;
+        self.check(redis::cmd("PING").query_async::<_, ()>(&mut con).await)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Held locks, mapping a resource to the lock value and when it expires.
+type MemoryLocks = HashMap<Vec<u8>, (Vec<u8>, Instant)>;
+
+/// Sorted sets, mapping a key to the score of each member.
+type MemoryScores = HashMap<String, HashMap<String, f64>>;
+
+/// Hashes of counters, mapping a key to each counter and when it expires.
+type MemoryCounters = HashMap<String, (HashMap<String, u64>, Instant)>;
+
+/// Hashes that never expire, mapping a key to the value of each field.
+type MemoryFields = HashMap<String, HashMap<String, Vec<u8>>>;
+
+/// Sets counted by size, mapping a key to its members and when it expires.
+type MemoryUniques = HashMap<String, (HashSet<String>, Instant)>;
+
+/// Attempt to obtain a lock held in memory, retrying a few times.
+async fn lock_memory(
+    locks: &Mutex<MemoryLocks>,
+    resource: &[u8],
+    ttl: Duration,
+) -> Option<CacheLock> {
+    for _ in 0..LOCK_RETRY_COUNT {
+        {
+            let mut locks = locks.lock().await;
+            let now = Instant::now();
+            locks.retain(|_resource, (_value, expires)| *expires > now);
+
+            if !locks.contains_key(resource) {
+                let lock = CacheLock::new(resource);
+                locks.insert(lock.resource.clone(), (lock.value.clone(), now + ttl));
+                return Some(lock);
+            }
+        }
+
+        tokio::time::sleep(LOCK_RETRY_DELAY).await;
+    }
+
+    None
+}
+
+/// Release a lock held in memory if it has not expired and been taken by
+/// someone else.
+async fn unlock_memory(locks: &Mutex<MemoryLocks>, lock: &CacheLock) {
+    let mut locks = locks.lock().await;
+
+    if matches!(locks.get(&lock.resource), Some((value, _expires)) if value == &lock.value) {
+        locks.remove(&lock.resource);
+    }
+}
+
+/// Cache stored in process memory. Data is lost on restart and is not shared
+/// between instances.
+pub struct MemoryStore {
+    data: Mutex<LruCache<String, (Vec<u8>, Instant)>>,
+    locks: Mutex<MemoryLocks>,
+    scores: Mutex<MemoryScores>,
+    counters: Mutex<MemoryCounters>,
+    fields: Mutex<MemoryFields>,
+    uniques: Mutex<MemoryUniques>,
+}
+
+impl MemoryStore {
+    /// Create a new store holding up to `capacity` entries.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            data: Mutex::new(LruCache::new(capacity)),
+            locks: Default::default(),
+            scores: Default::default(),
+            counters: Default::default(),
+            fields: Default::default(),
+            uniques: Default::default(),
+        }
+    }
+}
+
+/// Sort members from highest to lowest score.
+fn sort_scores(scores: &HashMap<String, f64>) -> Vec<(String, f64)> {
+    let mut scores: Vec<_> = scores
+        .iter()
+        .map(|(member, score)| (member.clone(), *score))
+        .collect();
+
+    scores.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| b.0.cmp(&a.0)));
+    scores
+}
+
+/// Check if a key matches a glob pattern, like Redis does for `SCAN`.
+fn glob_match(pattern: &str, key: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let key: Vec<char> = key.chars().collect();
+
+    // Position after the most recent `*` in the pattern, and the position in
+    // the key it has matched up to, to backtrack to on a mismatch.
+    let (mut p, mut k) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while k < key.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                p += 1;
+                star = Some((p, k));
+            }
+            Some('\\') if pattern.get(p + 1) == Some(&key[k]) => {
+                p += 2;
+                k += 1;
+            }
+            Some(c) if *c != '\\' && (*c == '?' || *c == key[k]) => {
+                p += 1;
+                k += 1;
+            }
+            _ => match star {
+                Some((star_p, star_k)) => {
+                    p = star_p;
+                    k = star_k + 1;
+                    star = Some((star_p, k));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Escape characters with special meaning in glob patterns, so the value only
+/// matches itself.
+fn escape_glob(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+#[async_trait]
+impl CacheStore for MemoryStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        let mut data = self.data.lock().await;
+
+        match data.get(key) {
+            Some((value, expires)) if *expires > Instant::now() => Ok(Some(value.clone())),
+            Some(_) => {
+                data.pop(key);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: u32) -> Result<(), Error> {
+        let expires = Instant::now() + Duration::from_secs(ttl as u64);
+        self.data.lock().await.put(key.to_string(), (value, expires));
+        Ok(())
+    }
+
+    async fn ttl(&self, key: &str) -> Result<Option<u64>, Error> {
+        let now = Instant::now();
+
+        Ok(match self.data.lock().await.peek(key) {
+            Some((_value, expires)) if *expires > now => Some((*expires - now).as_secs()),
+            _ => None,
+        })
+    }
+
+    async fn keys(&self, pattern: &str, count: usize) -> Result<Vec<String>, Error> {
+        let now = Instant::now();
+
+        let mut keys: Vec<_> = self
+            .data
+            .lock()
+            .await
+            .iter()
+            .filter(|(key, (_value, expires))| *expires > now && glob_match(pattern, key))
+            .map(|(key, _entry)| key.clone())
+            .collect();
+
+        keys.sort();
+        keys.truncate(count);
+
+        Ok(keys)
+    }
+
+    async fn lock(&self, resource: &[u8], ttl: Duration) -> Result<Option<CacheLock>, Error> {
+        Ok(lock_memory(&self.locks, resource, ttl).await)
+    }
+
+    async fn unlock(&self, lock: &CacheLock) -> Result<(), Error> {
+        unlock_memory(&self.locks, lock).await;
+        Ok(())
+    }
+
+    async fn increment_score(
+        &self,
+        key: &str,
+        member: &str,
+        amount: f64,
+        max: usize,
+    ) -> Result<(), Error> {
+        let mut scores = self.scores.lock().await;
+        let members = scores.entry(key.to_string()).or_default();
+
+        *members.entry(member.to_string()).or_default() += amount;
+
+        while members.len() > max {
+            let lowest = members
+                .iter()
+                .min_by(|a, b| a.1.total_cmp(b.1).then_with(|| a.0.cmp(b.0)))
+                .map(|(member, _score)| member.clone());
+
+            match lowest {
+                Some(lowest) => members.remove(&lowest),
+                None => break,
+            };
+        }
+
+        Ok(())
+    }
+
+    async fn top_scores(&self, key: &str, count: usize) -> Result<Vec<(String, f64)>, Error> {
+        let mut top = match self.scores.lock().await.get(key) {
+            Some(scores) => sort_scores(scores),
+            None => return Ok(Vec::new()),
+        };
+
+        top.truncate(count);
+        Ok(top)
+    }
+
+    async fn decay_scores(&self, key: &str, factor: f64, keep: usize) -> Result<(), Error> {
+        let mut scores = self.scores.lock().await;
+
+        let members = match scores.get_mut(key) {
+            Some(members) => members,
+            None => return Ok(()),
+        };
+
+        let mut top = sort_scores(members);
+        top.truncate(keep);
+
+        *members = top
+            .into_iter()
+            .map(|(member, score)| (member, score * factor))
+            .collect();
+
+        Ok(())
+    }
+
+    async fn increment_counters(
+        &self,
+        key: &str,
+        counters: &[(&str, u64)],
+        ttl: u32,
+    ) -> Result<(), Error> {
+        let now = Instant::now();
+
+        let mut hashes = self.counters.lock().await;
+        hashes.retain(|_key, (_counters, expires)| *expires > now);
+
+        let (values, expires) = hashes
+            .entry(key.to_string())
+            .or_insert_with(|| (HashMap::new(), now));
+
+        for (field, amount) in counters {
+            *values.entry(field.to_string()).or_default() += amount;
+        }
+        *expires = now + Duration::from_secs(ttl as u64);
+
+        Ok(())
+    }
+
+    async fn counters(&self, key: &str) -> Result<HashMap<String, u64>, Error> {
+        Ok(match self.counters.lock().await.get(key) {
+            Some((counters, expires)) if *expires > Instant::now() => counters.clone(),
+            _ => HashMap::new(),
+        })
+    }
+
+    async fn add_unique(&self, key: &str, member: &str, ttl: u32) -> Result<(), Error> {
+        let now = Instant::now();
+
+        let mut sets = self.uniques.lock().await;
+        sets.retain(|_key, (_members, expires)| *expires > now);
+
+        let (members, expires) = sets
+            .entry(key.to_string())
+            .or_insert_with(|| (HashSet::new(), now));
+
+        members.insert(member.to_string());
+        *expires = now + Duration::from_secs(ttl as u64);
+
+        Ok(())
+    }
+
+    async fn unique_count(&self, key: &str) -> Result<u64, Error> {
+        Ok(match self.uniques.lock().await.get(key) {
+            Some((members, expires)) if *expires > Instant::now() => members.len() as u64,
+            _ => 0,
+        })
+    }
+
+    async fn increment_many(&self, increments: &[Increment<'_>]) -> Result<(), Error> {
+        for increment in increments {
+            match *increment {
+                Increment::Counters { key, counters, ttl } => {
+                    self.increment_counters(key, counters, ttl).await?
+                }
+                Increment::Unique { key, member, ttl } => self.add_unique(key, member, ttl).await?,
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn set_field(&self, key: &str, field: &str, value: Vec<u8>) -> Result<(), Error> {
+        self.fields
+            .lock()
+            .await
+            .entry(key.to_string())
+            .or_default()
+            .insert(field.to_string(), value);
+
+        Ok(())
+    }
+
+    async fn remove_field(&self, key: &str, field: &str) -> Result<bool, Error> {
+        let mut hashes = self.fields.lock().await;
+
+        let removed = match hashes.get_mut(key) {
+            Some(fields) => fields.remove(field).is_some(),
+            None => false,
+        };
+
+        if matches!(hashes.get(key), Some(fields) if fields.is_empty()) {
+            hashes.remove(key);
+        }
+
+        Ok(removed)
+    }
+
+    async fn fields(&self, key: &str) -> Result<HashMap<String, Vec<u8>>, Error> {
+        Ok(self
+            .fields
+            .lock()
+            .await
+            .get(key)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn ping(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Cache stored in memcached, using the text protocol. Locks are implemented
+/// with `add`, which only succeeds if the key does not already exist.
+pub struct MemcachedStore {
+    addr: SocketAddr,
+}
+
+impl MemcachedStore {
+    /// Create a new store connecting to the given memcached server.
+    pub fn new(addr: SocketAddr) -> Self {
+        Self { addr }
+    }
+
+    /// Ensure a key is usable with the memcached text protocol.
+    fn validate_key(key: &[u8]) -> Result<(), Error> {
+        if key.is_empty()
+            || key.len() > 250
+            || key.iter().any(|b| b.is_ascii_whitespace() || b.is_ascii_control())
+        {
+            return Err(Error::Memcached("invalid key".to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Send a command and read back the first line of the response.
+    async fn command(
+        &self,
+        con: &mut BufReader<TcpStream>,
+        command: &[u8],
+    ) -> Result<String, Error> {
+        con.get_mut().write_all(command).await?;
+
+        let mut line = String::new();
+        con.read_line(&mut line).await?;
+
+        Ok(line.trim_end().to_string())
+    }
+
+    /// Store a value with the given command, either `set` or `add`. Returns if
+    /// the value was stored.
+    async fn store(&self, verb: &str, key: &[u8], value: &[u8], ttl: u32) -> Result<bool, Error> {
+        Self::validate_key(key)?;
+
+        let mut con = BufReader::new(TcpStream::connect(self.addr).await?);
+
+        let mut command = Vec::with_capacity(key.len() + value.len() + 32);
+        command.extend(format!("{} ", verb).as_bytes());
+        command.extend(key);
+        command.extend(format!(" 0 {} {}\r\n", ttl, value.len()).as_bytes());
+        command.extend(value);
+        command.extend(b"\r\n");
+
+        match self.command(&mut con, &command).await?.as_str() {
+            "STORED" => Ok(true),
+            "NOT_STORED" => Ok(false),
+            other => Err(Error::Memcached(other.to_string())),
+        }
+    }
+
+    /// Load the value for a key.
+    async fn load(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        Self::validate_key(key)?;
+
+        let mut con = BufReader::new(TcpStream::connect(self.addr).await?);
+
+        let mut command = b"get ".to_vec();
+        command.extend(key);
+        command.extend(b"\r\n");
+
+        // A hit looks like `VALUE <key> <flags> <bytes>`, followed by the
+        // data and `END`. A miss is only `END`.
+        let line = self.command(&mut con, &command).await?;
+        if line == "END" {
+            return Ok(None);
+        }
+
+        let len: usize = match line.strip_prefix("VALUE ") {
+            Some(header) => header
+                .rsplit(' ')
+                .next()
+                .and_then(|len| len.parse().ok())
+                .ok_or_else(|| Error::Memcached(line.clone()))?,
+            None => return Err(Error::Memcached(line)),
+        };
+
+        // Read the data along with the trailing CRLF.
+        let mut data = vec![0; len + 2];
+        con.read_exact(&mut data).await?;
+        data.truncate(len);
+
+        Ok(Some(data))
+    }
+
+    /// Remove a key.
+    async fn delete(&self, key: &[u8]) -> Result<(), Error> {
+        Self::validate_key(key)?;
+
+        let mut con = BufReader::new(TcpStream::connect(self.addr).await?);
+
+        let mut command = b"delete ".to_vec();
+        command.extend(key);
+        command.extend(b"\r\n");
+
+        match self.command(&mut con, &command).await?.as_str() {
+            "DELETED" | "NOT_FOUND" => Ok(()),
+            other => Err(Error::Memcached(other.to_string())),
+        }
+    }
+}
+
+#[async_trait]
+impl CacheStore for MemcachedStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        self.load(key.as_bytes()).await
+    }
+
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: u32) -> Result<(), Error> {
+        // Memcached keeps values with an expiration of 0 forever, so the key is
+        // removed instead.
+        if ttl == 0 {
+            return self.delete(key.as_bytes()).await;
+        }
+
+        self.store("set", key.as_bytes(), &value, ttl).await?;
+        Ok(())
+    }
+
+    async fn ttl(&self, _key: &str) -> Result<Option<u64>, Error> {
+        Err(Error::Memcached(
+            "reading expirations is not supported".to_string(),
+        ))
+    }
+
+    async fn keys(&self, _pattern: &str, _count: usize) -> Result<Vec<String>, Error> {
+        Err(Error::Memcached(
+            "listing keys is not supported".to_string(),
+        ))
+    }
+
+    async fn lock(&self, resource: &[u8], ttl: Duration) -> Result<Option<CacheLock>, Error> {
+        // Memcached expirations have a resolution of one second.
+        let ttl = std::cmp::max(ttl.as_secs(), 1) as u32;
+
+        for _ in 0..LOCK_RETRY_COUNT {
+            let lock = CacheLock::new(resource);
+            if self.store("add", resource, &lock.value, ttl).await? {
+                return Ok(Some(lock));
+            }
+
+            tokio::time::sleep(LOCK_RETRY_DELAY).await;
+        }
+
+        Ok(None)
+    }
+
+    async fn unlock(&self, lock: &CacheLock) -> Result<(), Error> {
+        // This is not atomic, but at worst it releases a lock that was about
+        // to expire anyway.
+        if self.load(&lock.resource).await?.as_deref() == Some(lock.value.as_slice()) {
+            self.delete(&lock.resource).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn increment_score(
+        &self,
+        _key: &str,
+        _member: &str,
+        _amount: f64,
+        _max: usize,
+    ) -> Result<(), Error> {
+        Err(Error::Memcached(
+            "sorted sets are not supported".to_string(),
+        ))
+    }
+
+    async fn top_scores(&self, _key: &str, _count: usize) -> Result<Vec<(String, f64)>, Error> {
+        Err(Error::Memcached(
+            "sorted sets are not supported".to_string(),
+        ))
+    }
+
+    async fn decay_scores(&self, _key: &str, _factor: f64, _keep: usize) -> Result<(), Error> {
+        Err(Error::Memcached(
+            "sorted sets are not supported".to_string(),
+        ))
+    }
+
+    async fn increment_counters(
+        &self,
+        _key: &str,
+        _counters: &[(&str, u64)],
+        _ttl: u32,
+    ) -> Result<(), Error> {
+        Err(Error::Memcached("hashes are not supported".to_string()))
+    }
+
+    async fn counters(&self, _key: &str) -> Result<HashMap<String, u64>, Error> {
+        Err(Error::Memcached("hashes are not supported".to_string()))
+    }
+
+    async fn add_unique(&self, _key: &str, _member: &str, _ttl: u32) -> Result<(), Error> {
+        Err(Error::Memcached("sets are not supported".to_string()))
+    }
+
+    async fn unique_count(&self, _key: &str) -> Result<u64, Error> {
+        Err(Error::Memcached("sets are not supported".to_string()))
+    }
+
+    async fn increment_many(&self, _increments: &[Increment<'_>]) -> Result<(), Error> {
+        Err(Error::Memcached("hashes are not supported".to_string()))
+    }
+
+    async fn set_field(&self, _key: &str, _field: &str, _value: Vec<u8>) -> Result<(), Error> {
+        Err(Error::Memcached("hashes are not supported".to_string()))
+    }
+
+    async fn remove_field(&self, _key: &str, _field: &str) -> Result<bool, Error> {
+        Err(Error::Memcached("hashes are not supported".to_string()))
+    }
+
+    async fn fields(&self, _key: &str) -> Result<HashMap<String, Vec<u8>>, Error> {
+        Err(Error::Memcached("hashes are not supported".to_string()))
+    }
+
+    async fn ping(&self) -> Result<(), Error> {
+        let mut con = BufReader::new(TcpStream::connect(self.addr).await?);
+
+        let line = self.command(&mut con, b"version\r\n").await?;
+        if !line.starts_with("VERSION") {
+            return Err(Error::Memcached(line));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_store_expires() {
+        let store = MemoryStore::new(10);
+
+        store.set("key", b"value".to_vec(), 60).await.unwrap();
+        assert_eq!(store.get("key").await.unwrap(), Some(b"value".to_vec()));
+
+        store.set("key", b"value".to_vec(), 0).await.unwrap();
+        assert_eq!(store.get("key").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_keys() {
+        let store = MemoryStore::new(10);
+
+        store.set("ping:b:1", b"value".to_vec(), 60).await.unwrap();
+        store.set("ping:a:1", b"value".to_vec(), 60).await.unwrap();
+        store.set("query:a:1", b"value".to_vec(), 60).await.unwrap();
+        store.set("ping:c:1", b"value".to_vec(), 0).await.unwrap();
+
+        assert_eq!(
+            store.keys("ping:*", 10).await.unwrap(),
+            vec!["ping:a:1".to_string(), "ping:b:1".to_string()]
+        );
+        assert_eq!(
+            store.keys("*", 1).await.unwrap(),
+            vec!["ping:a:1".to_string()]
+        );
+
+        assert!(matches!(
+            store.ttl("ping:a:1").await.unwrap(),
+            Some(59..=60)
+        ));
+        assert_eq!(store.ttl("ping:c:1").await.unwrap(), None);
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("ping:*", "ping:example.com:25565"));
+        assert!(glob_match("*:example.com:*", "query:example.com:25565"));
+        assert!(glob_match("ping:?:1", "ping:a:1"));
+        assert!(!glob_match("ping:?:1", "ping:ab:1"));
+        assert!(!glob_match("ping:*", "query:example.com:25565"));
+        assert!(!glob_match("*.com", "example.net"));
+        assert!(glob_match("a\\*:*", "a*:ping"));
+        assert!(!glob_match("a\\*:*", "ab:ping"));
+        assert!(glob_match(&escape_glob("a?[b]\\"), "a?[b]\\"));
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_lock() {
+        let store = MemoryStore::new(10);
+
+        let lock = store
+            .lock(b"lock:key", Duration::from_secs(5))
+            .await
+            .unwrap()
+            .expect("lock should be available");
+
+        assert!(store
+            .lock(b"lock:key", Duration::from_secs(5))
+            .await
+            .unwrap()
+            .is_none());
+
+        store.unlock(&lock).await.unwrap();
+
+        assert!(store
+            .lock(b"lock:key", Duration::from_secs(5))
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_scores() {
+        let store = MemoryStore::new(10);
+
+        store.increment_score("top", "a", 1.0, 10).await.unwrap();
+        store.increment_score("top", "b", 3.0, 10).await.unwrap();
+        store.increment_score("top", "c", 2.0, 10).await.unwrap();
+        store.increment_score("top", "a", 3.0, 10).await.unwrap();
+
+        assert_eq!(
+            store.top_scores("top", 2).await.unwrap(),
+            vec![("a".to_string(), 4.0), ("b".to_string(), 3.0)]
+        );
+
+        store.decay_scores("top", 0.5, 2).await.unwrap();
+        assert_eq!(
+            store.top_scores("top", 10).await.unwrap(),
+            vec![("a".to_string(), 2.0), ("b".to_string(), 1.5)]
+        );
+        assert!(store.top_scores("other", 10).await.unwrap().is_empty());
+
+        // The lowest scores are removed once there are too many members.
+        store.increment_score("top", "d", 1.0, 2).await.unwrap();
+        assert_eq!(
+            store.top_scores("top", 10).await.unwrap(),
+            vec![("a".to_string(), 2.0), ("b".to_string(), 1.5)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_counters() {
+        let store = MemoryStore::new(10);
+
+        store
+            .increment_counters("usage", &[("requests", 1), ("bytes", 100)], 60)
+            .await
+            .unwrap();
+        store
+            .increment_counters("usage", &[("requests", 1), ("bytes", 50)], 60)
+            .await
+            .unwrap();
+
+        let counters = store.counters("usage").await.unwrap();
+        assert_eq!(counters.get("requests"), Some(&2));
+        assert_eq!(counters.get("bytes"), Some(&150));
+        assert!(store.counters("other").await.unwrap().is_empty());
+
+        store
+            .increment_counters("usage", &[("requests", 1)], 0)
+            .await
+            .unwrap();
+        assert!(store.counters("usage").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_uniques() {
+        let store = MemoryStore::new(10);
+
+        store.add_unique("seen", "a", 60).await.unwrap();
+        store.add_unique("seen", "b", 60).await.unwrap();
+        store.add_unique("seen", "a", 60).await.unwrap();
+
+        assert_eq!(store.unique_count("seen").await.unwrap(), 2);
+        assert_eq!(store.unique_count("other").await.unwrap(), 0);
+
+        store.add_unique("seen", "c", 0).await.unwrap();
+        assert_eq!(store.unique_count("seen").await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_fields() {
+        let store = MemoryStore::new(10);
+
+        store.set_field("bans", "a", b"1".to_vec()).await.unwrap();
+        store.set_field("bans", "b", b"2".to_vec()).await.unwrap();
+        store.set_field("bans", "a", b"3".to_vec()).await.unwrap();
+
+        let fields = store.fields("bans").await.unwrap();
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields.get("a"), Some(&b"3".to_vec()));
+
+        assert!(store.remove_field("bans", "a").await.unwrap());
+        assert!(!store.remove_field("bans", "a").await.unwrap());
+        assert!(!store.remove_field("other", "a").await.unwrap());
+        assert_eq!(store.fields("bans").await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_prefixed_store() {
+        let inner: Arc<dyn CacheStore> = Arc::new(MemoryStore::new(10));
+        let store = PrefixedStore::new("staging", inner.clone());
+
+        store.set("ping:a:1", b"value".to_vec(), 60).await.unwrap();
+        assert_eq!(
+            inner.get("staging:ping:a:1").await.unwrap(),
+            Some(b"value".to_vec())
+        );
+        assert_eq!(inner.get("ping:a:1").await.unwrap(), None);
+        assert_eq!(
+            store.keys("ping:*", 10).await.unwrap(),
+            vec!["ping:a:1".to_string()]
+        );
+
+        // Glob characters in the prefix only match themselves.
+        let wildcard = PrefixedStore::new("stag*", inner.clone());
+        assert!(wildcard.keys("*", 10).await.unwrap().is_empty());
+
+        let lock = store
+            .lock(b"lock:ping:a:1", Duration::from_secs(5))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(lock.resource, b"staging:lock:ping:a:1");
+
+        store
+            .increment_many(&[
+                Increment::Counters {
+                    key: "stats:total",
+                    counters: &[("lookups", 2)],
+                    ttl: 60,
+                },
+                Increment::Unique {
+                    key: "stats:servers",
+                    member: "a:1",
+                    ttl: 60,
+                },
+            ])
+            .await
+            .unwrap();
+        assert_eq!(
+            inner.counters("staging:stats:total").await.unwrap()["lookups"],
+            2
+        );
+        assert_eq!(
+            inner.unique_count("staging:stats:servers").await.unwrap(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_local_lock_store() {
+        let inner: Arc<dyn CacheStore> = Arc::new(MemoryStore::new(10));
+        let store = LocalLockStore::new(inner.clone());
+
+        store.set("key", b"value".to_vec(), 60).await.unwrap();
+        assert_eq!(inner.get("key").await.unwrap(), Some(b"value".to_vec()));
+
+        let lock = store
+            .lock(b"lock:key", Duration::from_secs(5))
+            .await
+            .unwrap()
+            .expect("lock should be available");
+
+        assert!(store
+            .lock(b"lock:key", Duration::from_secs(5))
+            .await
+            .unwrap()
+            .is_none());
+
+        // Locks are not shared with the inner store.
+        assert!(inner
+            .lock(b"lock:key", Duration::from_secs(5))
+            .await
+            .unwrap()
+            .is_some());
+
+        store.unlock(&lock).await.unwrap();
+
+        assert!(store
+            .lock(b"lock:key", Duration::from_secs(5))
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn test_memcached_validate_key() {
+        assert!(MemcachedStore::validate_key(b"ping:example.com:25565").is_ok());
+        assert!(MemcachedStore::validate_key(b"").is_err());
+        assert!(MemcachedStore::validate_key(b"ping:bad host:25565").is_err());
+        assert!(MemcachedStore::validate_key(&[b'a'; 251]).is_err());
+    }
+}
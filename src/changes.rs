@@ -0,0 +1,208 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use mcapi_rs::{
+    resolver::{self, Resolver},
+    types::ServerPing,
+};
+
+use crate::{cache::CacheStore, config::Config, monitor::Monitor, ServerAddr, ServerRequest};
+
+/// Key holding the data that was replaced by the most recent refresh of a
+/// cached key.
+pub fn previous_key(key: &str) -> String {
+    format!("previous:{}", key)
+}
+
+/// A value that is different between two checks.
+#[derive(Debug, PartialEq, Eq, Serialize, ToSchema)]
+#[aliases(
+    OnlineChange = Change<bool>,
+    MotdChange = Change<String>,
+    VersionChange = Change<Option<String>>
+)]
+pub struct Change<T> {
+    pub from: T,
+    pub to: T,
+}
+
+impl<T: PartialEq> Change<T> {
+    /// Describe a change if the values are different.
+    fn between(from: T, to: T) -> Option<Self> {
+        (from != to).then_some(Self { from, to })
+    }
+}
+
+/// What changed between the previous and current status of a server.
+#[derive(Debug, Default, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ServerChanges {
+    /// When the previous status was checked, if there was one.
+    pub previous_updated: Option<u64>,
+    /// When the current status was checked.
+    pub last_updated: u64,
+    /// If anything is different from the previous status.
+    pub changed: bool,
+
+    #[schema(value_type = Option<OnlineChange>)]
+    pub online: Option<Change<bool>>,
+    /// Difference in the number of online players.
+    pub players: i64,
+    #[schema(value_type = Option<MotdChange>)]
+    pub motd: Option<Change<String>>,
+    #[schema(value_type = Option<VersionChange>)]
+    pub version: Option<Change<Option<String>>>,
+    /// If the favicon is different, without including either favicon.
+    pub icon: bool,
+}
+
+impl ServerChanges {
+    /// Compare a server's current status to its previous status.
+    pub fn between(previous: Option<&ServerPing>, current: &ServerPing) -> Self {
+        let previous = match previous {
+            Some(previous) => previous,
+            None => {
+                return Self {
+                    last_updated: current.last_updated,
+                    ..Default::default()
+                }
+            }
+        };
+
+        let online = Change::between(previous.online, current.online);
+        let players = i64::from(current.players.now) - i64::from(previous.players.now);
+        let motd = Change::between(previous.motd.clone(), current.motd.clone());
+        let version = Change::between(previous.server.name.clone(), current.server.name.clone());
+        let icon = previous.favicon != current.favicon;
+
+        Self {
+            previous_updated: Some(previous.last_updated),
+            last_updated: current.last_updated,
+            changed: online.is_some()
+                || players != 0
+                || motd.is_some()
+                || version.is_some()
+                || icon,
+            online,
+            players,
+            motd,
+            version,
+            icon,
+        }
+    }
+}
+
+/// Get the status that was replaced by the most recent ping of a server.
+async fn previous_ping(cache: &dyn CacheStore, host: &str, port: u16) -> Option<ServerPing> {
+    let host = resolver::normalize_host(host).ok()?;
+    let key = previous_key(&format!("ping:{}:{}", host, port));
+
+    match cache.get(&key).await {
+        Ok(value) => serde_json::from_slice(&value?).ok(),
+        Err(err) => {
+            tracing::warn!("could not load previous ping: {:?}", err);
+            None
+        }
+    }
+}
+
+/// Show what changed in a server's status since it was last checked.
+#[utoipa::path(
+    get,
+    path = "/server/changes",
+    tag = "server",
+    params(ServerRequest),
+    responses((status = 200, description = "Differences between the current and previous status", body = ServerChanges))
+)]
+#[get("/server/changes")]
+pub async fn server_changes(
+    resolver: web::Data<Resolver>,
+    cache: web::Data<dyn CacheStore>,
+    config: web::Data<Config>,
+    monitor: web::Data<Monitor>,
+    web::Query(addr): web::Query<ServerRequest>,
+) -> impl Responder {
+    let (host, port) = addr.parse_host();
+
+    tracing::info!("attempting to get status changes for {}:{}", host, port);
+
+    // Getting the current status first moves it to the previous status if it
+    // needed to be refreshed.
+    let current = crate::get_ping(&cache, &resolver, &config, &monitor, host, port).await;
+    let previous = previous_ping(cache.get_ref(), host, port).await;
+
+    HttpResponse::Ok()
+        .insert_header(config.ping_ttl().cache_control())
+        .json(ServerChanges::between(previous.as_ref(), &current))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ping(online: bool, players: i32, motd: &str, version: &str, updated: u64) -> ServerPing {
+        let mut ping = ServerPing {
+            online,
+            motd: motd.to_string(),
+            last_updated: updated,
+            ..Default::default()
+        };
+        ping.players.now = players;
+        ping.server.name = Some(version.to_string());
+
+        ping
+    }
+
+    #[test]
+    fn test_server_changes() {
+        let previous = ping(true, 5, "A server", "1.20.4", 100);
+
+        let changes = ServerChanges::between(None, &previous);
+        assert_eq!(changes.previous_updated, None);
+        assert_eq!(changes.last_updated, 100);
+        assert!(!changes.changed);
+
+        let current = ping(true, 5, "A server", "1.20.4", 200);
+        let changes = ServerChanges::between(Some(&previous), &current);
+        assert_eq!(changes.previous_updated, Some(100));
+        assert!(!changes.changed);
+
+        let mut current = ping(true, 2, "A new server", "1.21", 200);
+        current.favicon = Some("data:image/png;base64,AAAA".to_string());
+        let changes = ServerChanges::between(Some(&previous), &current);
+        assert!(changes.changed);
+        assert_eq!(changes.online, None);
+        assert_eq!(changes.players, -3);
+        assert_eq!(
+            changes.motd,
+            Some(Change {
+                from: "A server".to_string(),
+                to: "A new server".to_string(),
+            })
+        );
+        assert_eq!(
+            changes.version,
+            Some(Change {
+                from: Some("1.20.4".to_string()),
+                to: Some("1.21".to_string()),
+            })
+        );
+        assert!(changes.icon);
+
+        let current = ping(false, 0, "", "1.20.4", 200);
+        let changes = ServerChanges::between(Some(&previous), &current);
+        assert_eq!(
+            changes.online,
+            Some(Change {
+                from: true,
+                to: false,
+            })
+        );
+        assert_eq!(changes.players, -5);
+
+        let previous = ping(true, i32::MIN, "A server", "1.20.4", 100);
+        let current = ping(true, i32::MAX, "A server", "1.20.4", 200);
+        let changes = ServerChanges::between(Some(&previous), &current);
+        assert_eq!(changes.players, i64::from(u32::MAX));
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    future::Future,
+    net::{AddrParseError, IpAddr},
+};
+
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::header::{self, HeaderMap, HeaderName, HeaderValue},
+    web, HttpMessage,
+};
+use ipnet::IpNet;
+
+/// Header used by proxies to provide the addresses a request passed through.
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+/// Header some proxies use to provide only the client address.
+const X_REAL_IP: HeaderName = HeaderName::from_static("x-real-ip");
+
+/// The address of the client that made a request, after removing any trusted
+/// proxies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+/// Parse a network, or a single address as a network containing only that
+/// address.
+pub fn parse_network(network: &str) -> Result<IpNet, AddrParseError> {
+    network
+        .parse()
+        .or_else(|_err| network.parse::<IpAddr>().map(IpNet::from))
+}
+
+/// Networks containing proxies allowed to provide client addresses.
+#[derive(Debug, Default)]
+pub struct TrustedProxies {
+    pub networks: Vec<IpNet>,
+}
+
+impl TrustedProxies {
+    /// Create trusted networks from a list of networks or single addresses.
+    pub fn new(networks: &[String]) -> Result<Self, AddrParseError> {
+        let networks = networks
+            .iter()
+            .map(|network| parse_network(network))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { networks })
+    }
+
+    /// If an address belongs to a trusted proxy.
+    fn is_trusted(&self, ip: &IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(ip))
+    }
+
+    /// Determine the client address for a request from a peer.
+    ///
+    /// Forwarded addresses are only considered when the peer is trusted. They
+    /// are then checked from the closest proxy backwards, returning the first
+    /// address not belonging to a trusted proxy.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.is_trusted(&peer) {
+            return peer;
+        }
+
+        let forwarded = headers
+            .get_all(X_FORWARDED_FOR)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect::<Vec<_>>();
+
+        let mut client = peer;
+
+        for addr in forwarded.into_iter().rev() {
+            let ip = match addr.parse() {
+                Ok(ip) => ip,
+                Err(_err) => break,
+            };
+
+            client = ip;
+
+            if !self.is_trusted(&ip) {
+                break;
+            }
+        }
+
+        client
+    }
+}
+
+/// Middleware to determine the client address for each request.
+///
+/// Forwarded headers are replaced with only the determined address, so
+/// anything else reading them, such as the request logger, can not be given
+/// an address by an untrusted peer.
+pub fn middleware<S, B>(
+    mut req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    if let Some(peer) = req.peer_addr().map(|addr| addr.ip()) {
+        let client_ip = match req.app_data::<web::Data<TrustedProxies>>() {
+            Some(trusted) => trusted.client_ip(peer, req.headers()),
+            None => peer,
+        };
+
+        let headers = req.head_mut().headers_mut();
+        headers.remove(header::FORWARDED);
+        headers.remove(X_REAL_IP);
+        headers.insert(
+            X_FORWARDED_FOR,
+            HeaderValue::from_str(&client_ip.to_string()).unwrap(),
+        );
+
+        req.extensions_mut().insert(ClientIp(client_ip));
+    }
+
+    srv.call(req)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trusted(networks: &[&str]) -> TrustedProxies {
+        TrustedProxies {
+            networks: networks.iter().map(|net| net.parse().unwrap()).collect(),
+        }
+    }
+
+    fn forwarded_for(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(X_FORWARDED_FOR, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let proxies = trusted(&["10.0.0.0/8", "2001:db8::/32"]);
+        let headers = forwarded_for("198.51.100.7, 192.0.2.1, 10.1.1.1");
+
+        // Untrusted peers can not provide an address.
+        assert_eq!(
+            proxies.client_ip("203.0.113.5".parse().unwrap(), &headers),
+            "203.0.113.5".parse::<IpAddr>().unwrap()
+        );
+
+        // Trusted proxies are skipped, and addresses before the first
+        // untrusted address are ignored.
+        assert_eq!(
+            proxies.client_ip("10.0.0.1".parse().unwrap(), &headers),
+            "192.0.2.1".parse::<IpAddr>().unwrap()
+        );
+
+        assert_eq!(
+            proxies.client_ip("2001:db8::1".parse().unwrap(), &HeaderMap::new()),
+            "2001:db8::1".parse::<IpAddr>().unwrap()
+        );
+
+        // Invalid entries stop processing at the last valid address.
+        let headers = forwarded_for("unknown, 10.2.2.2");
+        assert_eq!(
+            proxies.client_ip("10.0.0.1".parse().unwrap(), &headers),
+            "10.2.2.2".parse::<IpAddr>().unwrap()
+        );
+    }
+}
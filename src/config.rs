@@ -0,0 +1,1258 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
+
+use actix_web::http::header::{CacheControl, CacheDirective};
+use figment::{
+    providers::{Env, Format, Toml},
+    Figment,
+};
+use serde::{Deserialize, Deserializer};
+
+use mcapi_rs::{
+    image::{ImageStyle, LayoutPreset, Theme},
+    motd::Color,
+    protocol::{self, Limits, Outbound, Retry, Timeouts},
+    socks5::Proxy,
+};
+
+use crate::{
+    cache::{CacheBackend, LockStrategy},
+    history::Retention,
+    usage::ApiKey,
+    warmup::Seed,
+};
+
+/// Environment variable with the path of the config file.
+const CONFIG_PATH_VAR: &str = "MCAPI_CONFIG";
+
+/// Prefix of environment variables overriding settings, so unrelated
+/// variables are never loaded.
+const ENV_PREFIX: &str = "MCAPI_";
+
+/// Settings that were read from unprefixed environment variables before
+/// `MCAPI_` was required, which are still read but log a warning.
+const DEPRECATED_ENV: &[&str] = &["http_host", "redis_server"];
+
+/// Config file used when no path is provided.
+const DEFAULT_CONFIG_PATH: &str = "mcapi.toml";
+
+/// Endpoints that may be disabled.
+pub const ENDPOINTS: &[&str] = &[
+    "status", "query", "multi", "image", "icon", "debug", "history", "stats", "metrics", "openapi",
+    "page", "widget", "changes", "wait",
+];
+
+/// Longest duration settings in seconds may be, well before they could not
+/// be represented.
+const MAX_SECONDS: f64 = u32::MAX as f64;
+
+/// Longest history may be kept, well before it could not be represented.
+const MAX_RETENTION_DAYS: u64 = 100 * 365;
+
+const DEFAULT_MAX_AGE: u32 = 60 * 5;
+const DEFAULT_MAX_STALE_AGE: u32 = 60;
+
+/// How long data for an endpoint may be cached.
+#[derive(Clone, Copy, Debug)]
+pub struct CacheTtl {
+    /// Seconds before data is considered stale.
+    pub max_age: u32,
+    /// Seconds that stale data may be served while revalidating.
+    pub max_stale_age: u32,
+}
+
+impl CacheTtl {
+    /// Get cache-control directives for these TTLs.
+    pub fn cache_control(&self) -> CacheControl {
+        CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(self.max_age),
+            CacheDirective::Extension(
+                "stale-while-revalidate".to_string(),
+                Some(self.max_stale_age.to_string()),
+            ),
+        ])
+    }
+}
+
+/// A named theme for images, selected with the `theme` parameter. Request
+/// parameters replace the theme's values.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ThemePreset {
+    /// Built-in theme providing colors the preset does not set.
+    pub base: Theme,
+    pub background: Option<Color>,
+    pub foreground: Option<Color>,
+    pub accent: Option<Color>,
+    /// Name of a font from `image_font_dir`.
+    pub font: Option<String>,
+    pub layout: Option<LayoutPreset>,
+    pub style: Option<ImageStyle>,
+}
+
+impl From<Theme> for ThemePreset {
+    fn from(base: Theme) -> Self {
+        Self {
+            base,
+            ..Default::default()
+        }
+    }
+}
+
+/// A link on the landing page, configured like `Docs=/docs`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SiteLink {
+    pub name: String,
+    pub url: String,
+}
+
+impl FromStr for SiteLink {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, url) = s
+            .split_once('=')
+            .ok_or_else(|| format!("site link must be formatted as name=url: {}", s))?;
+        let (name, url) = (name.trim(), url.trim());
+
+        if name.is_empty() || url.is_empty() {
+            return Err(format!("site link is missing a name or url: {}", s));
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            url: url.to_string(),
+        })
+    }
+}
+
+/// What `/server/icon` responds with for servers without a favicon.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MissingIcon {
+    /// The built-in grass block.
+    Placeholder,
+    /// A 404 error.
+    NotFound,
+    /// The icon from `missing_icon_file`.
+    File,
+    /// A pattern generated from the hostname.
+    Identicon,
+}
+
+impl FromStr for MissingIcon {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "placeholder" => Ok(Self::Placeholder),
+            "not_found" => Ok(Self::NotFound),
+            "file" => Ok(Self::File),
+            "identicon" => Ok(Self::Identicon),
+            other => Err(format!("unknown missing icon behavior: {}", other)),
+        }
+    }
+}
+
+/// Another instance that checks servers from a different region, configured
+/// like `eu=https://eu.mcapi.example.com`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProbeRegion {
+    pub name: String,
+    /// Base URL of the instance's API.
+    pub url: String,
+}
+
+impl FromStr for ProbeRegion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, url) = s
+            .split_once('=')
+            .ok_or_else(|| format!("probe region must be formatted as name=url: {}", s))?;
+        let (name, url) = (name.trim(), url.trim());
+
+        if name.is_empty() {
+            return Err(format!("probe region is missing a name: {}", s));
+        }
+
+        crate::upstream::validate_url(url)
+            .map_err(|err| format!("invalid url for probe region {}: {}", name, err))?;
+
+        Ok(Self {
+            name: name.to_string(),
+            url: url.to_string(),
+        })
+    }
+}
+
+/// All tunable settings.
+///
+/// Settings are loaded from a TOML file, then overridden by environment
+/// variables with the uppercase name of each field after `MCAPI_`. Lists may
+/// be provided as comma separated strings.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Address to listen for HTTP requests on.
+    pub http_host: SocketAddr,
+
+    /// Which cache backend to use.
+    #[serde(deserialize_with = "from_str")]
+    pub cache_backend: CacheBackend,
+    /// Redis servers for the `redis` backend.
+    #[serde(deserialize_with = "list")]
+    pub redis_server: Vec<String>,
+    /// Maximum entries for the `memory` backend.
+    pub memory_cache_size: usize,
+    /// Memcached server for the `memcached` backend.
+    pub memcached_server: Option<SocketAddr>,
+    /// Prefix added to all cache keys.
+    #[serde(deserialize_with = "optional_text")]
+    pub cache_prefix: Option<String>,
+    /// How refreshes of the same key are coordinated between instances.
+    #[serde(deserialize_with = "from_str")]
+    pub lock_strategy: LockStrategy,
+
+    pub ping_max_age: u32,
+    pub ping_max_stale_age: u32,
+    pub query_max_age: u32,
+    pub query_max_stale_age: u32,
+    pub image_max_age: u32,
+    pub image_max_stale_age: u32,
+    /// Seconds to keep rendered images in the cache, or 0 to render every
+    /// image.
+    pub image_cache_ttl: u32,
+    /// Directory of fonts that images may be rendered with.
+    pub image_font_dir: Option<PathBuf>,
+    /// Fonts from the font directory to use, in order, for characters missing
+    /// from the selected font. None are bundled, so this must be set to draw
+    /// characters the default font lacks.
+    #[serde(deserialize_with = "list")]
+    pub image_fallback_fonts: Vec<String>,
+    /// Threads rendering images, or 0 for one for each CPU.
+    pub image_render_threads: usize,
+    /// Most images that may wait for a render thread before new image
+    /// requests are rejected.
+    pub image_render_queue: usize,
+    /// Status line of images of offline servers, replacing the translated
+    /// offline message.
+    #[serde(deserialize_with = "optional_text")]
+    pub offline_message: Option<String>,
+    /// Colors of images of offline servers, replacing the theme's colors.
+    #[serde(deserialize_with = "optional_from_str")]
+    pub offline_background: Option<Color>,
+    #[serde(deserialize_with = "optional_from_str")]
+    pub offline_foreground: Option<Color>,
+    #[serde(deserialize_with = "optional_from_str")]
+    pub offline_accent: Option<Color>,
+    /// Image covering the background of images of offline servers.
+    pub offline_background_image: Option<PathBuf>,
+    /// Themes images may be rendered with, by name, in addition to the
+    /// built-in `light` and `dark` themes.
+    pub image_themes: BTreeMap<String, ThemePreset>,
+    pub icon_max_age: u32,
+    pub icon_max_stale_age: u32,
+    /// What to respond with when a server has no favicon.
+    #[serde(deserialize_with = "from_str")]
+    pub missing_icon: MissingIcon,
+    /// Icon for the `file` missing icon behavior.
+    pub missing_icon_file: Option<PathBuf>,
+    /// Secret image URLs must be signed with, or unset to allow any request.
+    #[serde(deserialize_with = "optional_text")]
+    pub image_signing_secret: Option<String>,
+
+    /// Seconds to spend resolving a server's address.
+    pub dns_timeout: f64,
+    /// Seconds to spend opening a connection to a server.
+    pub connect_timeout: f64,
+    /// Seconds to spend sending a request and reading the response.
+    pub read_timeout: f64,
+    /// Seconds to spend checking a server, including every phase.
+    pub server_timeout: f64,
+    /// Times to retry checking a server after a transient error.
+    pub server_retries: usize,
+    /// Seconds to wait before the first retry, doubling for each retry after.
+    pub server_retry_delay: f64,
+    /// Largest status response to accept from a server.
+    pub max_status_bytes: usize,
+    /// Largest favicon to keep, which is removed from larger responses.
+    pub max_favicon_bytes: usize,
+    /// Most players to keep from the sample.
+    pub max_player_sample: usize,
+    /// Most bytes of other status fields to keep.
+    pub max_extra_bytes: usize,
+
+    /// Base URL of another instance to forward cache misses to, instead of
+    /// checking servers directly.
+    #[serde(deserialize_with = "optional_text")]
+    pub upstream_url: Option<String>,
+    /// Seconds to wait for the upstream instance to respond.
+    pub upstream_timeout: f64,
+
+    /// Name of the region this instance checks servers from.
+    #[serde(deserialize_with = "text")]
+    pub region: String,
+    /// Instances in other regions to check servers from for multi-region
+    /// status requests.
+    #[serde(deserialize_with = "parsed_list")]
+    pub probe_regions: Vec<ProbeRegion>,
+
+    /// Local address for outbound IPv4 connections.
+    pub bind_address_v4: Option<Ipv4Addr>,
+    /// Local address for outbound IPv6 connections.
+    pub bind_address_v6: Option<Ipv6Addr>,
+
+    /// SOCKS5 proxy for outbound connections to servers.
+    pub socks5_proxy: Option<SocketAddr>,
+    #[serde(deserialize_with = "optional_text")]
+    pub socks5_username: Option<String>,
+    #[serde(deserialize_with = "optional_text")]
+    pub socks5_password: Option<String>,
+
+    /// Seconds to wait for in-flight requests when shutting down.
+    pub shutdown_timeout: u64,
+
+    /// Origins allowed to make cross-origin requests, any if empty.
+    #[serde(deserialize_with = "list")]
+    pub cors_origins: Vec<String>,
+    /// Seconds browsers may cache preflight responses.
+    pub cors_max_age: usize,
+
+    /// Seconds a request or refresh may take before it is logged as slow, or 0
+    /// to never log slow requests.
+    pub slow_threshold: f64,
+
+    /// Longest timeout in seconds clients may request with `X-Timeout-Ms`.
+    pub max_client_timeout: f64,
+    /// If requests should keep running after the client disconnects or its
+    /// timeout passes, so data they were refreshing is still cached.
+    pub finish_cancelled_refreshes: bool,
+
+    /// Most servers that may be requested at once in a comma separated list.
+    pub max_hosts_per_request: usize,
+
+    /// Longest time in seconds `/server/wait` may hold a request open.
+    pub max_wait_timeout: u64,
+
+    /// Most requests that may be handled at once before new requests are
+    /// rejected, or 0 for no limit.
+    pub max_in_flight_requests: usize,
+    /// Most refreshes that may be waiting for locks before new requests are
+    /// rejected, or 0 for no limit.
+    pub max_queued_refreshes: usize,
+    /// Seconds clients are told to wait before retrying rejected requests.
+    pub load_shed_retry_after: u64,
+
+    /// Most requests each client may make in a window, or 0 for no limit.
+    pub rate_limit: u64,
+    /// Seconds in each rate limit window.
+    pub rate_limit_window: u64,
+
+    /// Endpoints that should not be served.
+    #[serde(deserialize_with = "list")]
+    pub disabled_endpoints: Vec<String>,
+    /// If Swagger UI should be served for the OpenAPI document.
+    pub swagger_ui: bool,
+
+    /// URL the site is served from, used for absolute links in pages.
+    #[serde(deserialize_with = "optional_text")]
+    pub public_url: Option<String>,
+    /// Name of the site shown on the landing page.
+    #[serde(deserialize_with = "text")]
+    pub site_title: String,
+    /// Server used in examples on the landing page.
+    #[serde(deserialize_with = "text")]
+    pub site_example_server: String,
+    /// Links added to the navigation bar of the landing page.
+    #[serde(deserialize_with = "parsed_list")]
+    pub site_links: Vec<SiteLink>,
+    /// HTML shown at the bottom of the landing page.
+    #[serde(deserialize_with = "optional_text")]
+    pub site_footer: Option<String>,
+    /// HTML added to the end of the landing page, such as an analytics
+    /// script.
+    #[serde(deserialize_with = "optional_text")]
+    pub site_analytics: Option<String>,
+
+    /// Where to send metrics.
+    #[serde(deserialize_with = "list")]
+    pub metrics_sinks: Vec<String>,
+    /// Hosts to emit individual metrics for.
+    #[serde(deserialize_with = "list")]
+    pub metrics_hosts: Vec<String>,
+    pub statsd_server: SocketAddr,
+    pub statsd_interval: u64,
+    #[serde(deserialize_with = "list")]
+    pub statsd_tags: Vec<String>,
+
+    /// Hosts to record the history of and publish changes for.
+    #[serde(alias = "history_hosts", deserialize_with = "list")]
+    pub monitored_hosts: Vec<String>,
+
+    /// Postgres database to record server history in, disabled if unset.
+    #[serde(deserialize_with = "optional_text")]
+    pub database_url: Option<String>,
+    /// Days to keep every check before combining them into hourly rollups.
+    pub history_raw_retention: u64,
+    /// Days to keep hourly rollups.
+    pub history_rollup_retention: u64,
+    /// Seconds between compacting history.
+    pub history_compaction_interval: u64,
+
+    /// Most servers to count lookups for, disabled if zero.
+    pub leaderboard_size: usize,
+    /// Seconds for a lookup to count for half as much on the leaderboard.
+    pub leaderboard_half_life: u64,
+    /// If lookups and checks are counted for the aggregate stats.
+    pub aggregate_stats: bool,
+
+    /// File of servers to check when starting, one `host:port` on each line.
+    pub warmup_file: Option<PathBuf>,
+    /// Redis set of `host:port` servers to check when starting.
+    #[serde(deserialize_with = "optional_text")]
+    pub warmup_redis_set: Option<String>,
+    /// Most servers to check each second when starting.
+    pub warmup_rate: f64,
+
+    /// Tokens allowed to use the admin API, which is disabled if empty.
+    #[serde(deserialize_with = "list")]
+    pub admin_tokens: Vec<String>,
+    /// Keys clients may identify themselves with to have their usage
+    /// recorded.
+    #[serde(deserialize_with = "parsed_list")]
+    pub api_keys: Vec<ApiKey>,
+    /// Days to keep the daily usage of each API key.
+    pub api_key_usage_retention: u64,
+    /// Fewest seconds between refreshes of a registered server.
+    pub monitor_min_interval: u32,
+    /// If `/server/debug` requires an admin token, as it connects to a server
+    /// for every request.
+    pub debug_require_admin: bool,
+
+    /// Where to publish status changes, `nats` or `kafka`, disabled if unset.
+    #[serde(deserialize_with = "optional_text")]
+    pub events_sink: Option<String>,
+    /// NATS servers or Kafka brokers to connect to.
+    #[serde(deserialize_with = "list")]
+    pub events_servers: Vec<String>,
+    /// NATS subject or Kafka topic to publish status changes to.
+    #[serde(deserialize_with = "text")]
+    pub events_subject: String,
+
+    #[serde(deserialize_with = "text")]
+    pub ready_dns_name: String,
+    #[serde(deserialize_with = "optional_text")]
+    pub ready_reference_server: Option<String>,
+
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub proxy_protocol: bool,
+    #[serde(deserialize_with = "list")]
+    pub trusted_proxies: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            http_host: ([0, 0, 0, 0], 8080).into(),
+
+            cache_backend: CacheBackend::Redis,
+            redis_server: Vec::new(),
+            memory_cache_size: 10_000,
+            memcached_server: None,
+            cache_prefix: None,
+            lock_strategy: LockStrategy::RedLock,
+
+            ping_max_age: DEFAULT_MAX_AGE,
+            ping_max_stale_age: DEFAULT_MAX_STALE_AGE,
+            query_max_age: DEFAULT_MAX_AGE,
+            query_max_stale_age: DEFAULT_MAX_STALE_AGE,
+            image_max_age: DEFAULT_MAX_AGE,
+            image_max_stale_age: DEFAULT_MAX_STALE_AGE,
+            image_cache_ttl: 60,
+            image_font_dir: None,
+            image_fallback_fonts: Vec::new(),
+            image_render_threads: 0,
+            image_render_queue: 64,
+            offline_message: None,
+            offline_background: None,
+            offline_foreground: None,
+            offline_accent: None,
+            offline_background_image: None,
+            image_themes: BTreeMap::new(),
+            icon_max_age: DEFAULT_MAX_AGE,
+            icon_max_stale_age: DEFAULT_MAX_STALE_AGE,
+            missing_icon: MissingIcon::Placeholder,
+            missing_icon_file: None,
+            image_signing_secret: None,
+
+            dns_timeout: 5.0,
+            connect_timeout: 5.0,
+            read_timeout: 5.0,
+            server_timeout: 5.0,
+            server_retries: 1,
+            server_retry_delay: 0.25,
+            max_status_bytes: protocol::DEFAULT_MAX_STATUS_BYTES,
+            max_favicon_bytes: protocol::DEFAULT_MAX_FAVICON_BYTES,
+            max_player_sample: protocol::DEFAULT_MAX_PLAYER_SAMPLE,
+            max_extra_bytes: protocol::DEFAULT_MAX_EXTRA_BYTES,
+
+            upstream_url: None,
+            upstream_timeout: 10.0,
+
+            region: "local".to_string(),
+            probe_regions: Vec::new(),
+
+            bind_address_v4: None,
+            bind_address_v6: None,
+
+            socks5_proxy: None,
+            socks5_username: None,
+            socks5_password: None,
+
+            shutdown_timeout: 30,
+
+            cors_origins: Vec::new(),
+            cors_max_age: 86400,
+
+            slow_threshold: 5.0,
+
+            max_client_timeout: 30.0,
+            finish_cancelled_refreshes: false,
+
+            max_hosts_per_request: 10,
+            max_wait_timeout: 60,
+
+            max_in_flight_requests: 0,
+            max_queued_refreshes: 0,
+            load_shed_retry_after: 5,
+
+            rate_limit: 0,
+            rate_limit_window: 60,
+
+            disabled_endpoints: Vec::new(),
+            swagger_ui: false,
+
+            public_url: None,
+            site_title: "Minecraft API".to_string(),
+            site_example_server: "s.nerd.nu".to_string(),
+            site_links: Vec::new(),
+            site_footer: None,
+            site_analytics: None,
+
+            metrics_sinks: vec!["prometheus".to_string()],
+            metrics_hosts: Vec::new(),
+            statsd_server: ([127, 0, 0, 1], 8125).into(),
+            statsd_interval: 10,
+            statsd_tags: Vec::new(),
+
+            monitored_hosts: Vec::new(),
+
+            database_url: None,
+            history_raw_retention: 7,
+            history_rollup_retention: 90,
+            history_compaction_interval: 60 * 60,
+
+            leaderboard_size: 0,
+            leaderboard_half_life: 60 * 60 * 24,
+            aggregate_stats: false,
+
+            warmup_file: None,
+            warmup_redis_set: None,
+            warmup_rate: 10.0,
+
+            admin_tokens: Vec::new(),
+            api_keys: Vec::new(),
+            api_key_usage_retention: 90,
+            monitor_min_interval: 60,
+            debug_require_admin: true,
+
+            events_sink: None,
+            events_servers: Vec::new(),
+            events_subject: "mcapi.status_changes".to_string(),
+
+            ready_dns_name: "minecraft.net".to_string(),
+            ready_reference_server: None,
+
+            tls_cert: None,
+            tls_key: None,
+            proxy_protocol: false,
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Load config from the file at `MCAPI_CONFIG`, or `mcapi.toml` if unset,
+    /// then apply overrides from `MCAPI_` environment variables. A missing
+    /// file is ignored.
+    pub fn load() -> Result<Self, Box<figment::Error>> {
+        let path =
+            std::env::var(CONFIG_PATH_VAR).unwrap_or_else(|_err| DEFAULT_CONFIG_PATH.to_string());
+
+        for name in DEPRECATED_ENV {
+            let name = name.to_uppercase();
+            if std::env::var_os(&name).is_some() {
+                tracing::warn!("{} is deprecated, use {}{} instead", name, ENV_PREFIX, name);
+            }
+        }
+
+        let config: Self = Figment::new()
+            .merge(Toml::file(path))
+            .merge(Env::raw().only(DEPRECATED_ENV))
+            .merge(Env::prefixed(ENV_PREFIX).ignore(&["config"]))
+            .extract()
+            .map_err(Box::new)?;
+
+        config
+            .validate()
+            .map_err(|err| Box::new(figment::Error::from(err)))?;
+
+        Ok(config)
+    }
+
+    /// Check for settings that could not be used together or would fail
+    /// once the server is running.
+    fn validate(&self) -> Result<(), String> {
+        if let Some(endpoint) = self
+            .disabled_endpoints
+            .iter()
+            .find(|endpoint| !ENDPOINTS.contains(&endpoint.as_str()))
+        {
+            return Err(format!(
+                "unknown endpoint in disabled_endpoints: {}",
+                endpoint
+            ));
+        }
+
+        if self.missing_icon == MissingIcon::File && self.missing_icon_file.is_none() {
+            return Err("missing_icon_file is required for file missing_icon".to_string());
+        }
+
+        if let Some(url) = &self.public_url {
+            let scheme = reqwest::Url::parse(url).map(|url| url.scheme().to_string());
+            if !matches!(scheme.as_deref(), Ok("http" | "https")) {
+                return Err("public_url must be an http or https url".to_string());
+            }
+        }
+
+        if self.proxy_protocol && (self.tls_cert.is_some() || self.tls_key.is_some()) {
+            return Err("proxy_protocol cannot be used with tls_cert and tls_key".to_string());
+        }
+
+        for (name, value) in [
+            ("dns_timeout", self.dns_timeout),
+            ("connect_timeout", self.connect_timeout),
+            ("read_timeout", self.read_timeout),
+            ("server_timeout", self.server_timeout),
+            ("upstream_timeout", self.upstream_timeout),
+            ("max_client_timeout", self.max_client_timeout),
+        ] {
+            if !(value > 0.0 && value <= MAX_SECONDS) {
+                return Err(format!("{} must be a positive number of seconds", name));
+            }
+        }
+
+        for (name, value) in [
+            ("server_retry_delay", self.server_retry_delay),
+            ("slow_threshold", self.slow_threshold),
+        ] {
+            if !(0.0..=MAX_SECONDS).contains(&value) {
+                return Err(format!(
+                    "{} must be zero or a positive number of seconds",
+                    name
+                ));
+            }
+        }
+
+        if self.leaderboard_size > 0 && self.cache_backend == CacheBackend::Memcached {
+            return Err(
+                "the leaderboard cannot be used with the memcached cache backend".to_string(),
+            );
+        }
+
+        if self.aggregate_stats && self.cache_backend == CacheBackend::Memcached {
+            return Err(
+                "aggregate_stats cannot be used with the memcached cache backend".to_string(),
+            );
+        }
+
+        if !(self.warmup_rate > 0.0 && self.warmup_rate.is_finite()) {
+            return Err("warmup_rate must be a positive number".to_string());
+        }
+
+        if let Some(server) = &self.ready_reference_server {
+            if !server.trim().is_empty() && Seed::parse(server).is_none() {
+                return Err(format!(
+                    "ready_reference_server must be formatted like host:port: {}",
+                    server
+                ));
+            }
+        }
+
+        if self.warmup_redis_set.is_some() && self.redis_server.is_empty() {
+            return Err("redis_server is required for warmup_redis_set".to_string());
+        }
+
+        if self.history_compaction_interval == 0 {
+            return Err("history_compaction_interval must be greater than 0".to_string());
+        }
+
+        for (name, value) in [
+            ("history_raw_retention", self.history_raw_retention),
+            ("history_rollup_retention", self.history_rollup_retention),
+        ] {
+            if value > MAX_RETENTION_DAYS {
+                return Err(format!(
+                    "{} must be at most {} days",
+                    name, MAX_RETENTION_DAYS
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get a theme by name, from `image_themes` or the built-in themes, or
+    /// the default theme if no name was provided.
+    pub fn image_theme(&self, name: Option<&str>) -> Option<ThemePreset> {
+        let name = match name {
+            Some(name) => name,
+            None => return Some(Theme::default().into()),
+        };
+
+        if let Some(preset) = self.image_themes.get(name) {
+            return Some(preset.clone());
+        }
+
+        match name {
+            "light" => Some(Theme::Light.into()),
+            "dark" => Some(Theme::Dark.into()),
+            _ => None,
+        }
+    }
+
+    /// Names of all themes images may be rendered with, in order.
+    pub fn image_theme_names(&self) -> Vec<&str> {
+        let mut names = vec!["light", "dark"];
+        names.extend(
+            self.image_themes
+                .keys()
+                .map(String::as_str)
+                .filter(|name| !matches!(*name, "light" | "dark")),
+        );
+        names
+    }
+
+    pub fn ping_ttl(&self) -> CacheTtl {
+        CacheTtl {
+            max_age: self.ping_max_age,
+            max_stale_age: self.ping_max_stale_age,
+        }
+    }
+
+    pub fn query_ttl(&self) -> CacheTtl {
+        CacheTtl {
+            max_age: self.query_max_age,
+            max_stale_age: self.query_max_stale_age,
+        }
+    }
+
+    pub fn image_ttl(&self) -> CacheTtl {
+        CacheTtl {
+            max_age: self.image_max_age,
+            max_stale_age: self.image_max_stale_age,
+        }
+    }
+
+    pub fn icon_ttl(&self) -> CacheTtl {
+        CacheTtl {
+            max_age: self.icon_max_age,
+            max_stale_age: self.icon_max_stale_age,
+        }
+    }
+
+    pub fn timeouts(&self) -> Timeouts {
+        Timeouts {
+            dns: Duration::from_secs_f64(self.dns_timeout),
+            connect: Duration::from_secs_f64(self.connect_timeout),
+            read: Duration::from_secs_f64(self.read_timeout),
+            total: Duration::from_secs_f64(self.server_timeout),
+        }
+    }
+
+    /// Longest a refresh of cached data may take, which depends on if servers
+    /// are checked directly or through the upstream instance.
+    pub fn refresh_timeout(&self) -> Duration {
+        if self.upstream_url.is_some() {
+            self.upstream_timeout()
+        } else {
+            self.timeouts().total
+        }
+    }
+
+    /// How long to wait for the upstream instance or other regions to respond.
+    pub fn upstream_timeout(&self) -> Duration {
+        Duration::from_secs_f64(self.upstream_timeout)
+    }
+
+    /// The server `/health/ready` pings, if one is set.
+    pub fn reference_server(&self) -> Option<Seed> {
+        self.ready_reference_server.as_deref().and_then(Seed::parse)
+    }
+
+    /// How long requests and refreshes may take before being logged as slow,
+    /// if they should be logged.
+    pub fn slow_threshold(&self) -> Option<Duration> {
+        if self.slow_threshold > 0.0 {
+            Some(Duration::from_secs_f64(self.slow_threshold))
+        } else {
+            None
+        }
+    }
+
+    pub fn max_client_timeout(&self) -> Duration {
+        Duration::from_secs_f64(self.max_client_timeout)
+    }
+
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_status_bytes: self.max_status_bytes,
+            max_favicon_bytes: self.max_favicon_bytes,
+            max_player_sample: self.max_player_sample,
+            max_extra_bytes: self.max_extra_bytes,
+        }
+    }
+
+    pub fn retry(&self) -> Retry {
+        Retry {
+            retries: self.server_retries,
+            delay: Duration::from_secs_f64(self.server_retry_delay),
+        }
+    }
+
+    pub fn outbound(&self) -> Outbound {
+        Outbound {
+            bind_v4: self.bind_address_v4,
+            bind_v6: self.bind_address_v6,
+            proxy: self.socks5_proxy.map(|addr| Proxy {
+                addr,
+                credentials: self
+                    .socks5_username
+                    .clone()
+                    .map(|username| (username, self.socks5_password.clone().unwrap_or_default())),
+            }),
+        }
+    }
+
+    pub fn history_retention(&self) -> Retention {
+        Retention {
+            raw: Duration::from_secs(self.history_raw_retention * 24 * 60 * 60),
+            rollups: Duration::from_secs(self.history_rollup_retention * 24 * 60 * 60),
+        }
+    }
+
+    /// If an endpoint should be served.
+    pub fn endpoint_enabled(&self, endpoint: &str) -> bool {
+        !self
+            .disabled_endpoints
+            .iter()
+            .any(|disabled| disabled == endpoint)
+    }
+
+    /// If metrics should be sent to the given sink.
+    pub fn metrics_sink_enabled(&self, sink: &str) -> bool {
+        self.metrics_sinks.iter().any(|enabled| enabled == sink)
+    }
+}
+
+/// A string that may have been parsed as another type.
+///
+/// Environment variables that look like numbers or booleans are provided as
+/// those types, so they must be converted back into strings.
+struct Text(String);
+
+impl<'de> Deserialize<'de> for Text {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct TextVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for TextVisitor {
+            type Value = Text;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a string")
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Text, E> {
+                Ok(Text(value.to_string()))
+            }
+
+            fn visit_bool<E>(self, value: bool) -> Result<Text, E> {
+                Ok(Text(value.to_string()))
+            }
+
+            fn visit_i64<E>(self, value: i64) -> Result<Text, E> {
+                Ok(Text(value.to_string()))
+            }
+
+            fn visit_u64<E>(self, value: u64) -> Result<Text, E> {
+                Ok(Text(value.to_string()))
+            }
+
+            fn visit_f64<E>(self, value: f64) -> Result<Text, E> {
+                Ok(Text(value.to_string()))
+            }
+        }
+
+        deserializer.deserialize_any(TextVisitor)
+    }
+}
+
+/// A list provided either as an array or as a comma separated string.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum List {
+    Items(Vec<Text>),
+    Joined(Text),
+}
+
+/// Deserialize a list, trimming and skipping empty items.
+fn list<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let items: Vec<String> = match List::deserialize(deserializer)? {
+        List::Items(items) => items.into_iter().map(|item| item.0).collect(),
+        List::Joined(joined) => joined.0.split(',').map(ToString::to_string).collect(),
+    };
+
+    Ok(items
+        .into_iter()
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect())
+}
+
+/// Deserialize a list of values using their `FromStr` implementation.
+fn parsed_list<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    list(deserializer)?
+        .iter()
+        .map(|item| item.parse().map_err(serde::de::Error::custom))
+        .collect()
+}
+
+/// Deserialize a string, even if it was provided as another type.
+fn text<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Text::deserialize(deserializer).map(|text| text.0)
+}
+
+/// Deserialize an optional string, even if it was provided as another type.
+fn optional_text<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<Text>::deserialize(deserializer).map(|text| text.map(|text| text.0))
+}
+
+/// Deserialize a value using its `FromStr` implementation.
+fn from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let value = Text::deserialize(deserializer)?;
+    value.0.parse().map_err(serde::de::Error::custom)
+}
+
+/// Deserialize an optional value using its `FromStr` implementation.
+fn optional_from_str<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    optional_text(deserializer)?
+        .map(|value| value.parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let config: Config = Figment::new()
+            .merge(Toml::string(
+                r#"
+                cache_backend = "memory"
+                lock_strategy = "setnx"
+                ping_max_age = 60
+                metrics_sinks = ["prometheus", "statsd"]
+                trusted_proxies = "10.0.0.0/8, 192.168.0.0/16"
+                disabled_endpoints = "query"
+                missing_icon = "identicon"
+                probe_regions = "eu=https://eu.example.com, asia = https://asia.example.com/mcapi"
+                offline_accent = "f55"
+                "#,
+            ))
+            .merge(("ping_max_age", 120))
+            .merge(("cache_prefix", 123u32))
+            .merge(("connect_timeout", 1.5))
+            .extract()
+            .unwrap();
+
+        assert_eq!(config.cache_backend, CacheBackend::Memory);
+        assert_eq!(config.lock_strategy, LockStrategy::SetNx);
+        assert_eq!(config.missing_icon, MissingIcon::Identicon);
+        assert_eq!(config.ping_ttl().max_age, 120);
+        assert_eq!(config.query_ttl().max_age, 300);
+        assert_eq!(config.cache_prefix.as_deref(), Some("123"));
+        assert_eq!(config.timeouts().connect, Duration::from_millis(1500));
+        assert_eq!(config.timeouts().total, Duration::from_secs(5));
+        assert!(config.metrics_sink_enabled("statsd"));
+        assert!(!config.endpoint_enabled("query"));
+        assert!(config.endpoint_enabled("status"));
+        assert_eq!(
+            config.trusted_proxies,
+            vec!["10.0.0.0/8".to_string(), "192.168.0.0/16".to_string()]
+        );
+        assert_eq!(config.region, "local");
+        assert_eq!(config.offline_accent, Some(Color(0xFF, 0x55, 0x55)));
+        assert_eq!(config.offline_background, None);
+        assert_eq!(config.slow_threshold(), Some(Duration::from_secs(5)));
+        assert_eq!(
+            config.probe_regions,
+            vec![
+                ProbeRegion {
+                    name: "eu".to_string(),
+                    url: "https://eu.example.com".to_string(),
+                },
+                ProbeRegion {
+                    name: "asia".to_string(),
+                    url: "https://asia.example.com/mcapi".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_image_theme() {
+        let config: Config = Figment::new()
+            .merge(Toml::string(
+                r##"
+                [image_themes.brand]
+                base = "dark"
+                background = "#123"
+                layout = "compact"
+
+                [image_themes.dark]
+                base = "dark"
+                accent = "f55"
+                "##,
+            ))
+            .extract()
+            .unwrap();
+
+        let brand = config.image_theme(Some("brand")).unwrap();
+        assert_eq!(brand.base, Theme::Dark);
+        assert_eq!(brand.background, Some(Color(0x11, 0x22, 0x33)));
+        assert_eq!(brand.layout, Some(LayoutPreset::Compact));
+        assert_eq!(brand.style, None);
+
+        // Configured themes replace built-in themes with the same name.
+        let dark = config.image_theme(Some("dark")).unwrap();
+        assert_eq!(dark.base, Theme::Dark);
+        assert_eq!(dark.accent, Some(Color(0xFF, 0x55, 0x55)));
+
+        assert_eq!(
+            config.image_theme(Some("light")).unwrap().base,
+            Theme::Light
+        );
+        assert_eq!(config.image_theme(None).unwrap().base, Theme::Light);
+        assert!(config.image_theme(Some("missing")).is_none());
+        assert_eq!(config.image_theme_names(), vec!["light", "dark", "brand"]);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Config::default().validate().is_ok());
+
+        let config = Config {
+            disabled_endpoints: vec!["missing".to_string()],
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        for endpoint in ["page", "widget", "changes", "wait"] {
+            let config = Config {
+                disabled_endpoints: vec![endpoint.to_string()],
+                ..Default::default()
+            };
+            assert!(config.validate().is_ok(), "{}", endpoint);
+        }
+
+        let config = Config {
+            missing_icon: MissingIcon::File,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = Config {
+            public_url: Some("mcapi.us".to_string()),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = Config {
+            ready_reference_server: Some("example.com:port".to_string()),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = Config {
+            proxy_protocol: true,
+            tls_cert: Some("cert.pem".into()),
+            tls_key: Some("key.pem".into()),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        for timeout in [-1.0, 0.0, f64::NAN, f64::INFINITY, 1e20] {
+            let config = Config {
+                connect_timeout: timeout,
+                ..Default::default()
+            };
+            assert!(config.validate().is_err(), "{}", timeout);
+        }
+
+        let config = Config {
+            max_client_timeout: -1.0,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = Config {
+            server_retry_delay: 0.0,
+            slow_threshold: 0.0,
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+
+        let config = Config {
+            server_retry_delay: -0.5,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = Config {
+            cache_backend: CacheBackend::Memcached,
+            leaderboard_size: 100,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = Config {
+            cache_backend: CacheBackend::Memcached,
+            aggregate_stats: true,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        for rate in [0.0, f64::NAN, f64::INFINITY] {
+            let config = Config {
+                warmup_rate: rate,
+                ..Default::default()
+            };
+            assert!(config.validate().is_err(), "{}", rate);
+        }
+
+        let config = Config {
+            warmup_redis_set: Some("servers".to_string()),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = Config {
+            history_compaction_interval: 0,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+
+        let config = Config {
+            history_rollup_retention: u64::MAX / 2,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_reference_server() {
+        let server = |server: &str| {
+            Config {
+                ready_reference_server: Some(server.to_string()),
+                ..Default::default()
+            }
+            .reference_server()
+        };
+        let seed = |host: &str, port| Seed {
+            host: host.to_string(),
+            port,
+        };
+
+        assert_eq!(server(""), None);
+        assert_eq!(server("example.com"), Some(seed("example.com", 25565)));
+        assert_eq!(
+            server("example.com:25566"),
+            Some(seed("example.com", 25566))
+        );
+        assert_eq!(
+            server("[2001:db8::1]:25566"),
+            Some(seed("2001:db8::1", 25566))
+        );
+        assert_eq!(Config::default().reference_server(), None);
+    }
+
+    #[test]
+    fn test_history_retention() {
+        let config = Config {
+            history_raw_retention: 2,
+            history_rollup_retention: MAX_RETENTION_DAYS,
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+
+        let retention = config.history_retention();
+        assert_eq!(retention.raw, Duration::from_secs(2 * 24 * 60 * 60));
+        assert_eq!(
+            retention.rollups,
+            Duration::from_secs(MAX_RETENTION_DAYS * 24 * 60 * 60)
+        );
+    }
+
+    #[test]
+    fn test_probe_region() {
+        assert!("https://eu.example.com".parse::<ProbeRegion>().is_err());
+        assert!("=https://eu.example.com".parse::<ProbeRegion>().is_err());
+        assert!("eu=eu.example.com".parse::<ProbeRegion>().is_err());
+    }
+}
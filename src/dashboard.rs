@@ -0,0 +1,283 @@
+//! A page for operators showing how the service is doing, built from the
+//! same metrics exported to Prometheus.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Display,
+    sync::Mutex,
+};
+
+use actix_web::{
+    http::header::{self, CacheControl, CacheDirective, ContentType},
+    web, HttpResponse, Resource, Responder,
+};
+use lazy_static::lazy_static;
+use prometheus::proto::{Metric, MetricFamily};
+
+use crate::{
+    admin::Admin,
+    cache::CacheStore,
+    stats::{Leaderboard, TopServer},
+    status_page::{escape_html, fill},
+};
+
+/// Page filled in with the current metrics.
+const DASHBOARD_TEMPLATE: &str = include_str!("../static/dashboard.html");
+
+/// Seconds between reloads of the page.
+const REFRESH_INTERVAL: u32 = 10;
+
+/// Most refresh errors kept to show on the page.
+const MAX_RECENT_ERRORS: usize = 20;
+
+/// Servers shown from the leaderboard.
+const TOP_SERVERS: usize = 10;
+
+lazy_static! {
+    static ref RECENT_ERRORS: Mutex<VecDeque<RecentError>> = Default::default();
+}
+
+/// A refresh that failed, which is usually a server that could not be
+/// reached.
+#[derive(Debug)]
+struct RecentError {
+    at: u64,
+    method: &'static str,
+    key: String,
+    error: String,
+}
+
+/// Remember an error refreshing data to show on the dashboard, forgetting
+/// the oldest error once enough are kept.
+pub fn record_error(method: &'static str, key: &str, error: &impl Display) {
+    let mut errors = RECENT_ERRORS.lock().unwrap();
+
+    if errors.len() >= MAX_RECENT_ERRORS {
+        errors.pop_back();
+    }
+
+    errors.push_front(RecentError {
+        at: crate::unix_timestamp(),
+        method,
+        key: key.to_string(),
+        error: error.to_string(),
+    });
+}
+
+/// Counters from the metrics, totaled across instances of each method.
+#[derive(Debug, Default)]
+struct Summary {
+    /// Requests handled for each method.
+    requests: BTreeMap<String, u64>,
+    cache_hits: f64,
+    cache_stale: f64,
+    cache_misses: f64,
+    online: f64,
+    offline: f64,
+    refresh_failures: f64,
+}
+
+impl Summary {
+    fn from_metrics(families: &[MetricFamily]) -> Self {
+        let mut summary = Self::default();
+
+        for family in families {
+            for metric in family.get_metric() {
+                let counter = metric.get_counter().get_value();
+
+                match family.get_name() {
+                    crate::REQUEST_DURATION_NAME => {
+                        let method = label(metric, "method").unwrap_or_default();
+                        *summary.requests.entry(method.to_string()).or_default() +=
+                            metric.get_histogram().get_sample_count();
+                    }
+                    "mcapi_cache_lookups_total" => match label(metric, "result") {
+                        Some("hit") => summary.cache_hits += counter,
+                        Some("stale") => summary.cache_stale += counter,
+                        Some("miss") => summary.cache_misses += counter,
+                        _ => (),
+                    },
+                    "mcapi_server_online_total" => summary.online += counter,
+                    "mcapi_server_offline_total" => summary.offline += counter,
+                    "mcapi_cache_refresh_failures_total" => summary.refresh_failures += counter,
+                    _ => (),
+                }
+            }
+        }
+
+        summary
+    }
+
+    /// Portion of cache lookups that found data, even if it was stale.
+    fn cache_hit_rate(&self) -> Option<f64> {
+        ratio(
+            self.cache_hits + self.cache_stale,
+            self.cache_hits + self.cache_stale + self.cache_misses,
+        )
+    }
+
+    /// Portion of checked servers that were online.
+    fn online_rate(&self) -> Option<f64> {
+        ratio(self.online, self.online + self.offline)
+    }
+}
+
+fn label<'a>(metric: &'a Metric, name: &str) -> Option<&'a str> {
+    metric
+        .get_label()
+        .iter()
+        .find(|pair| pair.get_name() == name)
+        .map(|pair| pair.get_value())
+}
+
+fn ratio(part: f64, total: f64) -> Option<f64> {
+    (total > 0.0).then_some(part / total)
+}
+
+fn percent(rate: Option<f64>) -> String {
+    match rate {
+        Some(rate) => format!("{:.1}%", rate * 100.0),
+        None => "-".to_string(),
+    }
+}
+
+/// Fill in the page with the metrics, most requested servers, and recent
+/// errors.
+fn dashboard_html(summary: &Summary, top: &[TopServer], now: u64) -> String {
+    let requests: String = summary
+        .requests
+        .iter()
+        .map(|(method, count)| {
+            format!(
+                "<tr><td>{}</td><td>{}</td></tr>",
+                escape_html(method),
+                count
+            )
+        })
+        .collect();
+
+    let top: String = top
+        .iter()
+        .map(|server| {
+            format!(
+                "<tr><td>{}:{}</td><td>{:.1}</td></tr>",
+                escape_html(&server.host),
+                server.port,
+                server.score
+            )
+        })
+        .collect();
+
+    let errors: String = RECENT_ERRORS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|error| {
+            format!(
+                "<tr><td>{}s ago</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                now.saturating_sub(error.at),
+                error.method,
+                escape_html(&error.key),
+                escape_html(&error.error)
+            )
+        })
+        .collect();
+
+    fill(
+        DASHBOARD_TEMPLATE,
+        &[
+            ("{{refresh}}", REFRESH_INTERVAL.to_string()),
+            (
+                "{{total_requests}}",
+                summary.requests.values().sum::<u64>().to_string(),
+            ),
+            ("{{cache_hit_rate}}", percent(summary.cache_hit_rate())),
+            ("{{online_rate}}", percent(summary.online_rate())),
+            ("{{refresh_failures}}", summary.refresh_failures.to_string()),
+            ("{{requests}}", requests),
+            ("{{top_servers}}", top),
+            ("{{errors}}", errors),
+        ],
+    )
+}
+
+/// Page showing metrics to operators.
+pub fn dashboard() -> Resource {
+    web::resource("/admin/dashboard").route(web::get().to(show_dashboard))
+}
+
+/// Show the dashboard, asking browsers to log in with an admin token as the
+/// password when one was not provided.
+async fn show_dashboard(
+    admin: Option<Admin>,
+    cache: web::Data<dyn CacheStore>,
+    leaderboard: web::Data<Leaderboard>,
+) -> impl Responder {
+    if admin.is_none() {
+        return HttpResponse::Unauthorized()
+            .insert_header((header::WWW_AUTHENTICATE, r#"Basic realm="mcapi admin""#))
+            .body("missing or invalid admin token");
+    }
+
+    let summary = Summary::from_metrics(&prometheus::gather());
+
+    let top = if leaderboard.is_enabled() {
+        match leaderboard.top(cache.get_ref(), TOP_SERVERS).await {
+            Ok(top) => top,
+            Err(err) => {
+                tracing::warn!("could not load leaderboard for dashboard: {}", err);
+                Vec::new()
+            }
+        }
+    } else {
+        Vec::new()
+    };
+
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .insert_header(ContentType::html())
+        .body(dashboard_html(&summary, &top, crate::unix_timestamp()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use prometheus::{CounterVec, HistogramOpts, HistogramVec, Opts, Registry};
+
+    #[test]
+    fn test_summary() {
+        let registry = Registry::new();
+
+        let requests = HistogramVec::new(
+            HistogramOpts::new(crate::REQUEST_DURATION_NAME, "requests"),
+            &["method"],
+        )
+        .unwrap();
+        let lookups = CounterVec::new(
+            Opts::new("mcapi_cache_lookups_total", "lookups"),
+            &["method", "result"],
+        )
+        .unwrap();
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry.register(Box::new(lookups.clone())).unwrap();
+
+        requests.with_label_values(&["ping"]).observe(0.1);
+        requests.with_label_values(&["ping"]).observe(0.2);
+        requests.with_label_values(&["image"]).observe(0.3);
+        lookups.with_label_values(&["ping", "hit"]).inc_by(2.0);
+        lookups.with_label_values(&["query", "hit"]).inc();
+        lookups.with_label_values(&["ping", "miss"]).inc();
+
+        let summary = Summary::from_metrics(&registry.gather());
+        assert_eq!(summary.requests.get("ping"), Some(&2));
+        assert_eq!(summary.requests.get("image"), Some(&1));
+        assert_eq!(summary.cache_hit_rate(), Some(0.75));
+        assert_eq!(summary.online_rate(), None);
+
+        let page = dashboard_html(&summary, &[], 0);
+        assert!(page.contains("<tr><td>image</td><td>1</td></tr>"));
+        assert!(page.contains("75.0%"));
+        assert!(!page.contains("{{"));
+    }
+}
@@ -0,0 +1,16 @@
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions},
+    ConnectOptions, PgPool,
+};
+
+/// Connect to the database and apply any pending migrations.
+pub async fn connect(database_url: &str) -> Result<PgPool, sqlx::Error> {
+    // Statements run on every refresh, which is far too noisy to log.
+    let mut options: PgConnectOptions = database_url.parse()?;
+    options.disable_statement_logging();
+
+    let pool = PgPoolOptions::new().connect_with(options).await?;
+    sqlx::migrate!().run(&pool).await?;
+
+    Ok(pool)
+}
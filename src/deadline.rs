@@ -0,0 +1,147 @@
+use std::{future::Future, time::Duration};
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse},
+    error::InternalError,
+    http::header::{CacheControl, CacheDirective, HeaderName},
+    web, HttpResponse,
+};
+use futures::future::LocalBoxFuture;
+use lazy_static::lazy_static;
+use prometheus::{register_counter_vec, CounterVec};
+
+use crate::config::Config;
+
+/// Header clients may use to set how many milliseconds they will wait.
+pub const X_TIMEOUT_MS: HeaderName = HeaderName::from_static("x-timeout-ms");
+
+lazy_static! {
+    static ref REQUESTS_CANCELLED: CounterVec = register_counter_vec!(
+        "mcapi_requests_cancelled_total",
+        "Number of requests ended before a response by reason, either deadline or disconnect",
+        &["reason"]
+    )
+    .unwrap();
+}
+
+type ResponseFuture<B> = LocalBoxFuture<'static, Result<ServiceResponse<B>, actix_web::Error>>;
+
+/// Get the timeout requested by a client, limited to between 1 millisecond
+/// and the longest timeout allowed.
+fn requested_timeout(value: &str, max: Duration) -> Option<Duration> {
+    let millis: u64 = value.trim().parse().ok()?;
+
+    Some(Duration::from_millis(millis.max(1)).min(max))
+}
+
+/// Counts a request as cancelled if it is dropped before finishing, which
+/// happens when the client disconnects.
+struct DisconnectGuard {
+    finished: bool,
+}
+
+impl Drop for DisconnectGuard {
+    fn drop(&mut self) {
+        if !self.finished {
+            tracing::debug!("client disconnected before response was sent");
+            REQUESTS_CANCELLED.with_label_values(&["disconnect"]).inc();
+        }
+    }
+}
+
+/// Middleware to end requests after the timeout in the `X-Timeout-Ms` header,
+/// responding with a 504 error.
+///
+/// When configured to finish cancelled refreshes, requests keep running after
+/// the client disconnects or the timeout passes so the result is still cached,
+/// otherwise all work for the request is stopped.
+pub fn middleware<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<BoxBody>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    let (max_timeout, finish) = req
+        .app_data::<web::Data<Config>>()
+        .map(|config| {
+            (
+                config.max_client_timeout(),
+                config.finish_cancelled_refreshes,
+            )
+        })
+        .unwrap_or_default();
+
+    let timeout = req
+        .headers()
+        .get(X_TIMEOUT_MS)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| requested_timeout(value, max_timeout));
+
+    let fut = srv.call(req);
+
+    let fut: ResponseFuture<B> = if finish {
+        let handle = actix_web::rt::spawn(fut);
+        Box::pin(async move {
+            handle
+                .await
+                .unwrap_or_else(|err| Err(actix_web::error::ErrorInternalServerError(err)))
+        })
+    } else {
+        Box::pin(fut)
+    };
+
+    async move {
+        let mut guard = DisconnectGuard { finished: false };
+
+        let res = match timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, fut).await {
+                Ok(res) => res.map(ServiceResponse::map_into_boxed_body),
+                Err(_elapsed) => {
+                    tracing::debug!("request did not finish within {:?}", timeout);
+                    REQUESTS_CANCELLED.with_label_values(&["deadline"]).inc();
+
+                    let res = HttpResponse::GatewayTimeout()
+                        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+                        .json(serde_json::json!({
+                            "status": "error",
+                            "error": "request did not finish within X-Timeout-Ms",
+                        }));
+
+                    // The request was moved into the service, so the response is
+                    // returned as an error instead.
+                    Err(InternalError::from_response("request deadline exceeded", res).into())
+                }
+            },
+            None => fut.await.map(ServiceResponse::map_into_boxed_body),
+        };
+
+        guard.finished = true;
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requested_timeout() {
+        let max = Duration::from_secs(30);
+
+        assert_eq!(
+            requested_timeout("1500", max),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(
+            requested_timeout(" 0 ", max),
+            Some(Duration::from_millis(1))
+        );
+        assert_eq!(requested_timeout("60000", max), Some(max));
+        assert_eq!(requested_timeout("-1", max), None);
+        assert_eq!(requested_timeout("soon", max), None);
+    }
+}
@@ -0,0 +1,264 @@
+use serde::{Deserialize, Serialize};
+
+use mcapi_rs::types::ServerPing;
+
+use crate::cache::CacheStore;
+
+/// Seconds to remember the last state of a server, so changes can still be
+/// detected for servers that are rarely refreshed.
+const STATE_TTL: u32 = 60 * 60 * 24 * 30;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("cache error: {0}")]
+    Cache(#[from] mcapi_rs::types::Error),
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("events sink {0} is unknown or was not enabled at compile time")]
+    UnsupportedSink(String),
+    #[cfg(feature = "nats")]
+    #[error("nats error: {0}")]
+    NatsConnect(#[from] async_nats::ConnectError),
+    #[cfg(feature = "nats")]
+    #[error("nats error: {0}")]
+    NatsPublish(#[from] async_nats::PublishError),
+    #[cfg(feature = "kafka")]
+    #[error("kafka error: {0}")]
+    Kafka(#[from] rdkafka::error::KafkaError),
+}
+
+/// Parts of a server's status that produce an event when changed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerState {
+    pub online: bool,
+    pub version: Option<String>,
+    pub motd: String,
+}
+
+impl From<&ServerPing> for ServerState {
+    fn from(data: &ServerPing) -> Self {
+        Self {
+            online: data.online,
+            version: data.server.name.clone(),
+            motd: data.motd.clone(),
+        }
+    }
+}
+
+/// A change to a server's status.
+#[derive(Debug, Serialize)]
+pub struct StatusChange<'a> {
+    pub host: &'a str,
+    pub port: u16,
+    pub timestamp: u64,
+    /// Names of each field that changed, from `online`, `version`, and `motd`.
+    pub changes: Vec<&'static str>,
+    pub previous: ServerState,
+    pub current: ServerState,
+}
+
+/// Where events are published.
+#[derive(Clone)]
+enum Publisher {
+    /// Write events to the log, useful for debugging.
+    Log,
+    #[cfg(feature = "nats")]
+    Nats(async_nats::Client),
+    #[cfg(feature = "kafka")]
+    Kafka(rdkafka::producer::FutureProducer),
+}
+
+// Servers are only used by some sinks.
+#[cfg_attr(not(any(feature = "nats", feature = "kafka")), allow(unused_variables))]
+impl Publisher {
+    async fn connect(sink: &str, servers: &[String]) -> Result<Self, Error> {
+        match sink {
+            "log" => Ok(Self::Log),
+            #[cfg(feature = "nats")]
+            "nats" => {
+                let servers = servers
+                    .iter()
+                    .map(|server| server.parse())
+                    .collect::<Result<Vec<async_nats::ServerAddr>, _>>()?;
+
+                Ok(Self::Nats(async_nats::connect(servers).await?))
+            }
+            #[cfg(feature = "kafka")]
+            "kafka" => Ok(Self::Kafka(
+                rdkafka::ClientConfig::new()
+                    .set("bootstrap.servers", servers.join(","))
+                    .create()?,
+            )),
+            _ => Err(Error::UnsupportedSink(sink.to_string())),
+        }
+    }
+
+    async fn publish(&self, subject: &str, key: &str, payload: Vec<u8>) -> Result<(), Error> {
+        match *self {
+            Self::Log => {
+                tracing::info!(subject, key, "{}", String::from_utf8_lossy(&payload));
+                Ok(())
+            }
+            #[cfg(feature = "nats")]
+            Self::Nats(ref client) => {
+                client.publish(subject.to_string(), payload.into()).await?;
+                Ok(())
+            }
+            #[cfg(feature = "kafka")]
+            Self::Kafka(ref producer) => {
+                let record = rdkafka::producer::FutureRecord::to(subject)
+                    .key(key)
+                    .payload(&payload);
+
+                producer
+                    .send(record, std::time::Duration::from_secs(5))
+                    .await
+                    .map_err(|(err, _message)| err)?;
+
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Publishes an event whenever a server's state changes.
+///
+/// Without a sink, nothing is published or stored.
+#[derive(Default)]
+pub struct Events {
+    sink: Option<(Publisher, String)>,
+}
+
+impl Events {
+    /// Connect to a sink, publishing events to the given NATS subject or
+    /// Kafka topic.
+    pub async fn connect(sink: &str, servers: &[String], subject: &str) -> Result<Self, Error> {
+        let publisher = Publisher::connect(sink, servers).await?;
+
+        Ok(Self {
+            sink: Some((publisher, subject.to_string())),
+        })
+    }
+
+    /// Compare a refreshed server's state to its previous state, publishing an
+    /// event if it changed.
+    ///
+    /// The previous state is kept in the cache, so changes are only published
+    /// once across instances sharing a cache.
+    pub async fn observe(
+        &self,
+        cache: &dyn CacheStore,
+        host: &str,
+        port: u16,
+        data: &ServerPing,
+    ) -> Result<(), Error> {
+        let (publisher, subject) = match &self.sink {
+            Some(sink) => sink,
+            None => return Ok(()),
+        };
+
+        let key = format!("state:{}:{}", host, port).to_lowercase();
+        let current = ServerState::from(data);
+
+        let previous: Option<ServerState> = match cache.get(&key).await? {
+            Some(value) => serde_json::from_slice(&value).ok(),
+            None => None,
+        };
+
+        cache
+            .set(&key, serde_json::to_vec(&current)?, STATE_TTL)
+            .await?;
+
+        let previous = match previous {
+            Some(previous) => previous,
+            None => return Ok(()),
+        };
+
+        let changes = changes(&previous, &current);
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        let change = StatusChange {
+            host,
+            port,
+            timestamp: crate::unix_timestamp(),
+            changes,
+            previous,
+            current,
+        };
+
+        tracing::info!("{}:{} changed {:?}", host, port, change.changes);
+
+        let payload = serde_json::to_vec(&change)?;
+        let key = format!("{}:{}", host, port);
+        let publisher = publisher.clone();
+        let subject = subject.clone();
+
+        actix_web::rt::spawn(async move {
+            if let Err(err) = publisher.publish(&subject, &key, payload).await {
+                tracing::error!("could not publish status change for {}: {}", key, err);
+            }
+        });
+
+        Ok(())
+    }
+}
+
+/// Get the names of each field that differs between two states.
+///
+/// Offline servers have no version or MOTD, so those are only compared when
+/// the server was online for both.
+fn changes(previous: &ServerState, current: &ServerState) -> Vec<&'static str> {
+    let mut changes = Vec::new();
+
+    if previous.online != current.online {
+        changes.push("online");
+    }
+
+    if !previous.online || !current.online {
+        return changes;
+    }
+
+    if previous.version != current.version {
+        changes.push("version");
+    }
+
+    if previous.motd != current.motd {
+        changes.push("motd");
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes() {
+        let previous = ServerState {
+            online: true,
+            version: Some("1.18.2".to_string()),
+            motd: "A Minecraft Server".to_string(),
+        };
+
+        assert!(changes(&previous, &previous.clone()).is_empty());
+
+        let current = ServerState {
+            version: Some("1.19".to_string()),
+            ..previous.clone()
+        };
+        assert_eq!(changes(&previous, &current), vec!["version"]);
+
+        let offline = ServerState {
+            online: false,
+            version: None,
+            motd: String::new(),
+        };
+        assert_eq!(changes(&previous, &offline), vec!["online"]);
+        assert_eq!(changes(&offline, &current), vec!["online"]);
+    }
+}
@@ -0,0 +1,200 @@
+use std::time::Duration;
+
+use serde::Serialize;
+use sqlx::PgPool;
+
+use mcapi_rs::types::{Metadata, ServerPing};
+
+/// Percentage of checks where a server was online over the last day, week,
+/// and month, including checks that were combined into hourly rollups.
+const UPTIME_QUERY: &str = "
+    WITH checks AS (
+        SELECT checked_at AS at, 1 AS checks, online::int AS online_checks
+        FROM server_history
+        WHERE host = $1 AND port = $2 AND checked_at > now() - interval '30 days'
+        UNION ALL
+        SELECT hour, checks, online_checks
+        FROM server_history_hourly
+        WHERE host = $1 AND port = $2 AND hour > now() - interval '30 days'
+    )
+    SELECT
+        sum(online_checks) FILTER (WHERE at > now() - interval '24 hours')::float8
+            / nullif(sum(checks) FILTER (WHERE at > now() - interval '24 hours'), 0) * 100,
+        sum(online_checks) FILTER (WHERE at > now() - interval '7 days')::float8
+            / nullif(sum(checks) FILTER (WHERE at > now() - interval '7 days'), 0) * 100,
+        sum(online_checks)::float8 / nullif(sum(checks), 0) * 100
+    FROM checks
+";
+
+/// Combine checks older than the raw retention into hourly rollups.
+///
+/// `now()` is fixed for the duration of a transaction, so the same checks are
+/// combined and then deleted.
+const ROLLUP_QUERY: &str = "
+    INSERT INTO server_history_hourly (host, port, hour, checks, online_checks, players_sum)
+    SELECT host, port, date_trunc('hour', checked_at), count(*), count(*) FILTER (WHERE online), sum(players)
+    FROM server_history
+    WHERE checked_at < now() - make_interval(secs => $1)
+    GROUP BY 1, 2, 3
+    ON CONFLICT (host, port, hour) DO UPDATE SET
+        checks = server_history_hourly.checks + excluded.checks,
+        online_checks = server_history_hourly.online_checks + excluded.online_checks,
+        players_sum = server_history_hourly.players_sum + excluded.players_sum
+";
+
+/// Key for the advisory lock held while compacting, so only one instance
+/// compacts at a time.
+const COMPACTION_LOCK: i64 = 0x6d63_6170_6869_7374;
+
+/// How long history is kept.
+#[derive(Clone, Copy, Debug)]
+pub struct Retention {
+    /// Every check is kept for this long, then combined into hourly rollups.
+    pub raw: Duration,
+    /// Hourly rollups are kept for this long.
+    pub rollups: Duration,
+}
+
+/// Records the result of each refresh for monitored servers.
+///
+/// Without a database, nothing is recorded and no history is available.
+#[derive(Debug, Default)]
+pub struct History {
+    pool: Option<PgPool>,
+}
+
+/// Percentage of checks where a server was online within each window, or
+/// none if it was not checked during the window.
+#[derive(Debug, Serialize, utoipa::ToSchema)]
+pub struct Uptime {
+    #[serde(rename = "24h")]
+    pub day: Option<f64>,
+    #[serde(rename = "7d")]
+    pub week: Option<f64>,
+    #[serde(rename = "30d")]
+    pub month: Option<f64>,
+}
+
+impl History {
+    /// Record history in a database.
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool: Some(pool) }
+    }
+
+    /// If history is being recorded.
+    pub fn is_enabled(&self) -> bool {
+        self.pool.is_some()
+    }
+
+    /// Record the result of refreshing a server.
+    ///
+    /// Data is written in the background so the refresh is not delayed by the
+    /// database.
+    pub fn record(&self, host: &str, port: u16, data: &ServerPing) {
+        let pool = match &self.pool {
+            Some(pool) => pool.clone(),
+            None => return,
+        };
+
+        let host = host.to_lowercase();
+        let online = data.is_online();
+        let players = data.players_online() as i32;
+
+        actix_web::rt::spawn(async move {
+            let result = sqlx::query(
+                "INSERT INTO server_history (host, port, online, players) VALUES ($1, $2, $3, $4)",
+            )
+            .bind(&host)
+            .bind(i32::from(port))
+            .bind(online)
+            .bind(players)
+            .execute(&pool)
+            .await;
+
+            if let Err(err) = result {
+                tracing::error!("could not record history for {}:{}: {}", host, port, err);
+            }
+        });
+    }
+
+    /// Calculate uptime for a server over each window, or none if history is
+    /// not being recorded.
+    pub async fn uptime(&self, host: &str, port: u16) -> Result<Option<Uptime>, sqlx::Error> {
+        let pool = match &self.pool {
+            Some(pool) => pool,
+            None => return Ok(None),
+        };
+
+        let (day, week, month) = sqlx::query_as(UPTIME_QUERY)
+            .bind(host.to_lowercase())
+            .bind(i32::from(port))
+            .fetch_one(pool)
+            .await?;
+
+        Ok(Some(Uptime { day, week, month }))
+    }
+
+    /// Periodically compact history according to the retention policy.
+    pub async fn run_compaction(&self, retention: Retention, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(err) = self.compact(retention).await {
+                tracing::error!("could not compact history: {}", err);
+            }
+        }
+    }
+
+    /// Combine old checks into hourly rollups and delete expired rollups.
+    #[tracing::instrument(skip(self))]
+    pub async fn compact(&self, retention: Retention) -> Result<(), sqlx::Error> {
+        let pool = match &self.pool {
+            Some(pool) => pool,
+            None => return Ok(()),
+        };
+
+        let mut tx = pool.begin().await?;
+
+        let (locked,): (bool,) = sqlx::query_as("SELECT pg_try_advisory_xact_lock($1)")
+            .bind(COMPACTION_LOCK)
+            .fetch_one(&mut tx)
+            .await?;
+
+        if !locked {
+            tracing::debug!("history is already being compacted");
+            return Ok(());
+        }
+
+        let rolled_up = sqlx::query(ROLLUP_QUERY)
+            .bind(retention.raw.as_secs_f64())
+            .execute(&mut tx)
+            .await?;
+
+        let deleted = sqlx::query(
+            "DELETE FROM server_history WHERE checked_at < now() - make_interval(secs => $1)",
+        )
+        .bind(retention.raw.as_secs_f64())
+        .execute(&mut tx)
+        .await?;
+
+        let expired = sqlx::query(
+            "DELETE FROM server_history_hourly WHERE hour < now() - make_interval(secs => $1)",
+        )
+        .bind(retention.rollups.as_secs_f64())
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        tracing::info!(
+            "combined {} checks into {} hourly rollups, deleted {} expired rollups",
+            deleted.rows_affected(),
+            rolled_up.rows_affected(),
+            expired.rows_affected()
+        );
+
+        Ok(())
+    }
+}
@@ -1,130 +1,2062 @@
-use image::{ImageBuffer, ImageEncoder, Rgba, RgbaImage};
-use imageproc::{
-    drawing::{draw_filled_rect_mut, draw_text_mut},
-    rect::Rect,
+use std::{collections::BTreeMap, ops::RangeInclusive, path::Path, sync::Arc};
+
+use image::{
+    codecs::gif::{GifEncoder, Repeat},
+    Delay, Frame, ImageBuffer, ImageEncoder, Rgba, RgbaImage,
+};
+use imageproc::{drawing::draw_filled_rect_mut, rect::Rect};
+use rusttype::{point, Font, GlyphId, PositionedGlyph, Scale};
+
+use crate::{
+    locale::Strings,
+    motd::{self, Color, Span},
+    types::ServerPing,
 };
-use rusttype::{Font, Scale};
 
-/// Theme for generated image. Defaults to light.
-#[derive(Debug, Clone, Copy, serde::Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum Theme {
-    Light,
-    Dark,
+/// Font used when no other font is selected.
+const FONT_DATA: &[u8] = include_bytes!("../static/assets/Inconsolata-Regular.ttf");
+
+/// Height of the header containing the icon and status.
+const HEADER_HEIGHT: u32 = 64;
+
+/// Most lines of the MOTD to draw, matching the server list.
+const MOTD_LINES: u32 = 2;
+
+/// Height of each line of the MOTD.
+const MOTD_LINE_HEIGHT: u32 = 16;
+
+/// Space taken by a line of text at the MOTD size between the status and
+/// update lines.
+const SMALL_LINE_HEIGHT: u32 = 12;
+
+/// Size of text other than the MOTD.
+const TEXT_SIZE: f32 = 16.0;
+
+/// Size of the icon in the compact layout.
+const COMPACT_ICON_SIZE: u32 = 32;
+
+/// Rows of player names in the tall layout, including a row for how many
+/// players were left out.
+const TALL_PLAYER_ROWS: u32 = 10;
+
+/// Size of the MOTD text, slightly smaller than other text so a full line from
+/// the server list fits across the image.
+const MOTD_TEXT_SIZE: f32 = 14.0;
+
+/// Width of generated images with the default layout, before scaling.
+pub const DEFAULT_WIDTH: u32 = 325;
+
+/// Height of generated images with the default layout and elements, before
+/// scaling.
+pub const DEFAULT_HEIGHT: u32 = HEADER_HEIGHT + 2 + MOTD_LINE_HEIGHT * MOTD_LINES + 2;
+
+/// Allowed widths, before scaling.
+pub const WIDTH_RANGE: RangeInclusive<u32> = 200..=1000;
+
+/// Allowed heights, before scaling.
+pub const HEIGHT_RANGE: RangeInclusive<u32> = HEADER_HEIGHT..=400;
+
+/// Largest allowed scale.
+pub const MAX_SCALE: u32 = 4;
+
+/// Most pixels in an image after scaling, keeping each render under 4MB. The
+/// scale is lowered for large images to stay within this.
+pub const MAX_PIXELS: u32 = 1000 * 1000;
+
+/// Width and height of Open Graph preview images.
+pub const OG_IMAGE_SIZE: (u32, u32) = (1200, 630);
+
+/// Scale preview images are drawn at.
+const OG_IMAGE_SCALE: u32 = 3;
+
+/// Allowed qualities of lossy images.
+pub const QUALITY_RANGE: RangeInclusive<u8> = 1..=100;
+
+/// Quality of lossy images when none is given, balancing quality and size.
+pub const DEFAULT_QUALITY: u8 = 80;
+
+/// Number of bars showing latency.
+const LATENCY_BAR_COUNT: u32 = 5;
+
+/// Colors of filled and empty latency bars.
+const LATENCY_FILLED_COLOR: Color = Color(0x00, 0xAA, 0x00);
+const LATENCY_EMPTY_COLOR: Color = Color(0xAA, 0xAA, 0xAA);
+
+/// Fonts used to draw text in vector images, where the viewer's fonts are
+/// used instead of the selected font.
+const SVG_FONT_FAMILY: &str = "Inconsolata, monospace";
+
+/// Largest size any image is drawn at, which offline background images are
+/// scaled down to cover.
+const MAX_BACKGROUND_SIZE: (u32, u32) = (
+    *WIDTH_RANGE.end() * MAX_SCALE,
+    *HEIGHT_RANGE.end() * MAX_SCALE,
+);
+
+/// How much smaller the icon is blurred at than the cover background.
+const COVER_DOWNSCALE: u32 = 8;
+
+/// Blur applied to the downscaled icon for cover backgrounds.
+const COVER_BLUR_SIGMA: f32 = 2.0;
+
+/// Opacity of the background color over cover backgrounds, from 0 to 255.
+const COVER_TINT_ALPHA: u8 = 170;
+
+/// Milliseconds each frame of a banner is shown for.
+const BANNER_FRAME_MS: u32 = 3000;
+
+/// Speed of quantizing colors for GIFs, from 1 to 30, trading quality for
+/// speed.
+const GIF_ENCODE_SPEED: i32 = 10;
+
+/// Horizontal offset of the top of italic text, per pixel of height.
+const ITALIC_SKEW: f32 = 0.2;
+
+/// Prefix of favicons, which must be PNGs encoded as a data URI.
+const FAVICON_PREFIX: &str = "data:image/png;base64,";
+
+/// Most bytes a decoded favicon may contain.
+pub const MAX_FAVICON_BYTES: usize = 256 * 1024;
+
+/// Largest width or height of a favicon, which is 64 pixels in vanilla.
+pub const MAX_FAVICON_SIZE: u32 = 512;
+
+/// Cells on each side of an identicon, mirrored around the middle column.
+const IDENTICON_CELLS: u32 = 5;
+
+/// Size of each identicon cell, leaving a margin in a 64 pixel icon.
+const IDENTICON_CELL_SIZE: u32 = 12;
+
+#[derive(Debug, thiserror::Error)]
+pub enum FontError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid font: {0}")]
+    Invalid(String),
+    #[error("unknown font: {0}")]
+    Unknown(String),
+}
+
+/// Reasons a server's favicon could not be used.
+#[derive(Debug, thiserror::Error)]
+pub enum FaviconError {
+    #[error("favicon is not a PNG data URI")]
+    InvalidPrefix,
+    #[error("favicon is larger than {} bytes", MAX_FAVICON_BYTES)]
+    TooManyBytes,
+    #[error("favicon is not valid base64: {0}")]
+    Base64(#[from] base64::DecodeError),
+    #[error("favicon is {0}x{1}, larger than {max}x{max}", max = MAX_FAVICON_SIZE)]
+    TooLarge(u32, u32),
+    #[error("favicon is not a valid PNG: {0}")]
+    Image(#[from] image::ImageError),
+}
+
+/// Get the font used when no other font is selected.
+pub fn default_font() -> Font<'static> {
+    Font::try_from_bytes(FONT_DATA).unwrap()
+}
+
+/// Fonts that may be selected for images, by name.
+#[derive(Debug, Clone, Default)]
+pub struct Fonts {
+    fonts: BTreeMap<String, Font<'static>>,
+    fallbacks: Vec<Font<'static>>,
+}
+
+impl Fonts {
+    /// Load every TrueType or OpenType font in a directory, named by the file
+    /// name without its extension.
+    pub fn load_dir(path: &Path) -> Result<Self, FontError> {
+        let mut fonts = BTreeMap::new();
+
+        for entry in std::fs::read_dir(path)? {
+            let path = entry?.path();
+
+            let is_font = path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .map(|ext| matches!(ext.to_ascii_lowercase().as_str(), "ttf" | "otf"))
+                .unwrap_or(false);
+            let name = match path.file_stem().and_then(|name| name.to_str()) {
+                Some(name) if is_font => name.to_lowercase(),
+                _ => continue,
+            };
+
+            let font = Font::try_from_vec(std::fs::read(&path)?)
+                .ok_or_else(|| FontError::Invalid(path.display().to_string()))?;
+
+            fonts.insert(name, font);
+        }
+
+        Ok(Self {
+            fonts,
+            fallbacks: Vec::new(),
+        })
+    }
+
+    /// Use fonts, in order, for characters missing from the selected font.
+    pub fn with_fallbacks(mut self, names: &[String]) -> Result<Self, FontError> {
+        self.fallbacks = names
+            .iter()
+            .map(|name| {
+                self.get(name)
+                    .cloned()
+                    .ok_or_else(|| FontError::Unknown(name.to_string()))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(self)
+    }
+
+    /// Fonts to use for characters missing from the selected font.
+    pub fn fallbacks(&self) -> &[Font<'static>] {
+        &self.fallbacks
+    }
+
+    /// Get a font by name, ignoring case.
+    pub fn get(&self, name: &str) -> Option<&Font<'static>> {
+        self.fonts.get(&name.to_lowercase())
+    }
+
+    /// Names of all available fonts, in order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.fonts.keys().map(String::as_str)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fonts.is_empty()
+    }
+}
+
+/// Theme for generated image. Defaults to light.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    Light,
+    Dark,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::Light
+    }
+}
+
+/// Style of the background of generated images. Defaults to flat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageStyle {
+    /// Filled with the background color.
+    Flat,
+    /// The server's icon, scaled to cover the image and blurred, under a
+    /// partly transparent layer of the background color.
+    Cover,
+}
+
+impl Default for ImageStyle {
+    fn default() -> Self {
+        Self::Flat
+    }
+}
+
+/// Format of generated images. Defaults to PNG.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    Png,
+    /// An animated banner, see [`server_banner`].
+    Gif,
+    /// A vector image, see [`server_svg`].
+    Svg,
+    /// A lossless WebP image.
+    Webp,
+    /// A JPEG image, with a quality from [`QUALITY_RANGE`].
+    #[serde(alias = "jpg")]
+    Jpeg,
+}
+
+impl Default for ImageFormat {
+    fn default() -> Self {
+        Self::Png
+    }
+}
+
+impl ImageFormat {
+    /// Formats that may be picked from an `Accept` header, in order of
+    /// preference when they are accepted equally.
+    ///
+    /// Other formats change how the image looks, so must be requested
+    /// explicitly.
+    const NEGOTIABLE: [(Self, &'static str); 3] = [
+        (Self::Webp, "image/webp"),
+        (Self::Png, "image/png"),
+        (Self::Jpeg, "image/jpeg"),
+    ];
+
+    /// Media type of images in this format.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Gif => "image/gif",
+            Self::Svg => "image/svg+xml",
+            Self::Webp => "image/webp",
+            Self::Jpeg => "image/jpeg",
+        }
+    }
+
+    /// Pick the most preferred format explicitly listed in an `Accept`
+    /// header.
+    ///
+    /// Wildcards are ignored, as nearly every client accepts any image.
+    pub fn negotiate(accept: &str) -> Option<Self> {
+        let mut best: Option<(Self, f32)> = None;
+
+        for (format, content_type) in Self::NEGOTIABLE {
+            let quality = accept
+                .split(',')
+                .filter_map(|range| {
+                    let mut parts = range.split(';');
+                    let media_type = parts.next()?.trim();
+
+                    media_type.eq_ignore_ascii_case(content_type).then(|| {
+                        parts
+                            .filter_map(|param| param.trim().strip_prefix("q="))
+                            .find_map(|quality| quality.parse().ok())
+                            .unwrap_or(1.0)
+                    })
+                })
+                .max_by(f32::total_cmp);
+
+            // Ties keep the earlier format.
+            if let Some(quality) = quality.filter(|quality| *quality > 0.0) {
+                if best.map_or(true, |(_format, best)| quality > best) {
+                    best = Some((format, quality));
+                }
+            }
+        }
+
+        best.map(|(format, _quality)| format)
+    }
+}
+
+/// Elements of an image that may be hidden. Other elements move into the
+/// space left by hidden elements.
+#[derive(Debug, Clone, Copy)]
+pub struct Elements {
+    pub icon: bool,
+    /// Player counts on the status line and any player names.
+    pub players: bool,
+    pub motd: bool,
+    /// The server's version, shown by default on banners but not on other
+    /// images.
+    pub version: Option<bool>,
+}
+
+impl Default for Elements {
+    fn default() -> Self {
+        Self {
+            icon: true,
+            players: true,
+            motd: true,
+            version: None,
+        }
+    }
+}
+
+/// Arrangement of the elements of an image. Defaults to wide.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LayoutPreset {
+    /// The icon next to the status, with the MOTD below.
+    Wide,
+    /// A single small row with the icon, title, and status.
+    Compact,
+    /// The icon above centered text.
+    Square,
+    /// The wide layout with a list of player names below.
+    Tall,
+}
+
+impl Default for LayoutPreset {
+    fn default() -> Self {
+        Self::Wide
+    }
+}
+
+impl LayoutPreset {
+    /// Width of images using this layout, before scaling.
+    pub fn default_width(self) -> u32 {
+        match self {
+            Self::Wide | Self::Tall => DEFAULT_WIDTH,
+            Self::Compact => 250,
+            Self::Square => 200,
+        }
+    }
+}
+
+/// Where each part of an image is drawn, before scaling.
+#[derive(Debug, PartialEq, Eq)]
+struct Layout {
+    width: u32,
+    height: u32,
+    parts: Vec<Placed>,
+}
+
+impl Layout {
+    fn place(&mut self, part: Part, x: u32, y: u32, anchor: Anchor) {
+        self.parts.push(Placed { part, x, y, anchor });
+    }
+
+    /// Width available for text placed at a position.
+    fn available_width(&self, placed: &Placed) -> u32 {
+        match placed.anchor {
+            Anchor::Start => self.width.saturating_sub(placed.x),
+            Anchor::Middle => self.width.saturating_sub(8),
+            Anchor::End => placed.x,
+        }
+    }
+}
+
+/// A part of an image at a position. Text is positioned by its top.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Placed {
+    part: Part,
+    x: u32,
+    y: u32,
+    anchor: Anchor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Part {
+    /// The icon, centered in a square.
+    Icon {
+        size: u32,
+    },
+    Title,
+    Status,
+    /// Player names on a single line.
+    Players,
+    Version,
+    Updated,
+    /// Lines of the MOTD.
+    Motd,
+    /// Player names, one on each row.
+    PlayerList {
+        rows: u32,
+    },
+    /// Latency bars, with the latency to their left.
+    Latency,
+}
+
+/// Which part of text is at its position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Anchor {
+    Start,
+    Middle,
+    End,
+}
+
+/// Options for generating a server image.
+#[derive(Debug, Clone, Default)]
+pub struct ImageOptions {
+    /// Text displayed at the top of the image, usually the server address.
+    /// Placeholders are replaced as described by [`format_title`].
+    pub title: String,
+    pub theme: Theme,
+    pub style: ImageStyle,
+    pub layout: LayoutPreset,
+    /// Width before scaling, defaults to the layout's width.
+    pub width: Option<u32>,
+    /// Height before scaling, defaults to fitting the shown elements, which
+    /// is [`DEFAULT_HEIGHT`] for the default layout and elements.
+    pub height: Option<u32>,
+    /// Multiplier for the size of the image and everything drawn on it, for
+    /// high density displays. Defaults to 1.
+    pub scale: Option<u32>,
+    /// Background color, replacing the theme's background.
+    pub background: Option<Color>,
+    /// Text color, replacing the theme's text color.
+    pub foreground: Option<Color>,
+    /// Color of the status line, defaults to the text color.
+    pub accent: Option<Color>,
+    /// If names from the player sample are listed under the status line.
+    pub players: bool,
+    /// If the server's latency is shown in the top right corner.
+    pub latency: bool,
+    /// Elements to show.
+    pub show: Elements,
+    /// Font for all text, defaults to [`default_font`].
+    pub font: Option<Font<'static>>,
+    /// Fonts to use, in order, for characters missing from the font, such as
+    /// CJK characters or emoji.
+    pub fallback_fonts: Vec<Font<'static>>,
+    /// Language of text drawn on the image, defaults to
+    /// [`crate::locale::DEFAULT_LANGUAGE`].
+    pub language: Option<String>,
+    /// How the image looks when the server is offline.
+    pub offline: Arc<OfflineCard>,
+}
+
+/// Changes to images of offline servers, so they can match the branding of
+/// wherever they are embedded.
+///
+/// Colors from [`ImageOptions`] are still used over these colors.
+#[derive(Debug, Clone, Default)]
+pub struct OfflineCard {
+    /// Status line, replacing the translated offline message.
+    pub message: Option<String>,
+    pub background: Option<Color>,
+    pub foreground: Option<Color>,
+    pub accent: Option<Color>,
+    /// Image covering the background, replacing the style's background.
+    pub background_image: Option<RgbaImage>,
+}
+
+impl ImageOptions {
+    /// Width, height, and scale of the image, clamped to allowed values.
+    pub fn dimensions(&self) -> (u32, u32, u32) {
+        let layout = self.arrange();
+        let mut scale = self.scale.unwrap_or(1).clamp(1, MAX_SCALE);
+
+        let pixels = layout.width * layout.height;
+        while scale > 1 && pixels * scale * scale > MAX_PIXELS {
+            scale -= 1;
+        }
+
+        (layout.width, layout.height, scale)
+    }
+
+    /// Place each shown element for the layout preset, moving elements into
+    /// the space left by hidden elements.
+    fn arrange(&self) -> Layout {
+        let width = self
+            .width
+            .unwrap_or_else(|| self.layout.default_width())
+            .clamp(*WIDTH_RANGE.start(), *WIDTH_RANGE.end());
+        let height = self
+            .height
+            .map(|height| height.clamp(*HEIGHT_RANGE.start(), *HEIGHT_RANGE.end()));
+
+        let mut layout = Layout {
+            width,
+            height: 0,
+            parts: Vec::new(),
+        };
+
+        let content_height = match self.layout {
+            LayoutPreset::Wide => self.arrange_wide(&mut layout, false),
+            LayoutPreset::Tall => self.arrange_wide(&mut layout, true),
+            LayoutPreset::Compact => self.arrange_compact(&mut layout),
+            LayoutPreset::Square => self.arrange_square(&mut layout, height),
+        };
+
+        if self.latency {
+            layout.place(
+                Part::Latency,
+                width - 4 - LATENCY_BAR_COUNT * 3,
+                2,
+                Anchor::Start,
+            );
+        }
+
+        layout.height = height.unwrap_or(content_height);
+        layout
+    }
+
+    /// Place the icon next to the text, with the MOTD and optionally a list
+    /// of players below, returning the height of the content.
+    fn arrange_wide(&self, layout: &mut Layout, player_list: bool) -> u32 {
+        let show = &self.show;
+
+        let x = if show.icon {
+            layout.place(
+                Part::Icon {
+                    size: HEADER_HEIGHT,
+                },
+                0,
+                0,
+                Anchor::Start,
+            );
+            68
+        } else {
+            4
+        };
+
+        // The icon sets the smallest height for the header.
+        let min_updated_y = if show.icon { HEADER_HEIGHT - 16 - 2 } else { 0 };
+        let names = self.players && show.players && !player_list;
+        let mut y = self.stack_text(layout, x, 2, Anchor::Start, names, min_updated_y);
+
+        if show.motd {
+            layout.place(Part::Motd, 4, y + 2, Anchor::Start);
+            y += 2 + MOTD_LINE_HEIGHT * MOTD_LINES + 2;
+        }
+
+        if player_list && show.players {
+            let rows = TALL_PLAYER_ROWS;
+            layout.place(Part::PlayerList { rows }, 4, y + 2, Anchor::Start);
+            y += 2 + MOTD_LINE_HEIGHT * rows + 2;
+        }
+
+        y
+    }
+
+    /// Place a small icon next to the title and status, returning the height
+    /// of the content.
+    fn arrange_compact(&self, layout: &mut Layout) -> u32 {
+        let x = if self.show.icon {
+            let size = COMPACT_ICON_SIZE;
+            layout.place(Part::Icon { size }, 2, 2, Anchor::Start);
+            size + 6
+        } else {
+            4
+        };
+
+        layout.place(Part::Title, x, 1, Anchor::Start);
+        layout.place(Part::Status, x, 18, Anchor::Start);
+
+        COMPACT_ICON_SIZE + 4
+    }
+
+    /// Place the icon above centered text, with the content centered
+    /// vertically in an image as tall as it is wide, returning the height.
+    fn arrange_square(&self, layout: &mut Layout, height: Option<u32>) -> u32 {
+        let show = &self.show;
+        let center = layout.width / 2;
+
+        let mut y = 8;
+        if show.icon {
+            let size = HEADER_HEIGHT;
+            layout.place(Part::Icon { size }, center - size / 2, y, Anchor::Start);
+            y += size + 8;
+        }
+
+        let names = self.players && show.players;
+        let mut y = self.stack_text(layout, center, y, Anchor::Middle, names, 0);
+
+        if show.motd {
+            layout.place(Part::Motd, center, y + 2, Anchor::Middle);
+            y += 2 + MOTD_LINE_HEIGHT * MOTD_LINES + 2;
+        }
+
+        let content_height = y + 6;
+        let square_height = content_height.max(layout.width);
+
+        let offset = height
+            .unwrap_or(square_height)
+            .saturating_sub(content_height)
+            / 2;
+        for placed in &mut layout.parts {
+            placed.y += offset;
+        }
+
+        square_height
+    }
+
+    /// Stack the title, status, optional lines, and update time from `y`,
+    /// returning where they end.
+    fn stack_text(
+        &self,
+        layout: &mut Layout,
+        x: u32,
+        y: u32,
+        anchor: Anchor,
+        names: bool,
+        min_updated_y: u32,
+    ) -> u32 {
+        layout.place(Part::Title, x, y, anchor);
+        layout.place(Part::Status, x, y + 16, anchor);
+
+        let mut y = y + 32;
+        let version = self.show.version.unwrap_or(false);
+        for (part, shown) in [(Part::Players, names), (Part::Version, version)] {
+            if shown {
+                layout.place(part, x, y - 1, anchor);
+                y += SMALL_LINE_HEIGHT;
+            }
+        }
+
+        let updated_y = y.max(min_updated_y);
+        layout.place(Part::Updated, x, updated_y, anchor);
+
+        updated_y + 16 + 2
+    }
+}
+
+/// Everything needed to draw an image, resolved from the image options.
+struct Renderer {
+    style: ImageStyle,
+    show: Elements,
+    layout: Layout,
+    background_color: Rgba<u8>,
+    text_color: Rgba<u8>,
+    accent_color: Rgba<u8>,
+    /// Changes to the image if the server is offline.
+    offline: Option<Arc<OfflineCard>>,
+    strings: &'static Strings,
+    fonts: Vec<Font<'static>>,
+    width: u32,
+    height: u32,
+    scale: u32,
+    text_scale: Scale,
+    motd_scale: Scale,
+}
+
+/// Something to draw on an image, before scaling.
+enum Item {
+    /// The server's icon, fit and centered in a square.
+    Icon {
+        x: u32,
+        y: u32,
+        size: u32,
+    },
+    /// Styled text, using the color for spans without a color.
+    Text {
+        x: u32,
+        y: u32,
+        size: f32,
+        color: Rgba<u8>,
+        anchor: Anchor,
+        spans: Vec<Span>,
+    },
+    LatencyBars {
+        x: u32,
+        y: u32,
+        latency: u64,
+    },
+}
+
+impl Renderer {
+    fn new(options: &ImageOptions, ping: &ServerPing) -> Self {
+        let (background_color, text_color) = match options.theme {
+            Theme::Light => (
+                Rgba([255u8, 255u8, 255u8, 255u8]),
+                Rgba([0u8, 0u8, 0u8, 255u8]),
+            ),
+            Theme::Dark => (
+                Rgba([0u8, 0u8, 0u8, 255u8]),
+                Rgba([255u8, 255u8, 255u8, 255u8]),
+            ),
+        };
+
+        let offline = (!ping.online).then(|| options.offline.clone());
+        let card = offline.as_deref();
+
+        let background_color = options
+            .background
+            .or(card.and_then(|card| card.background))
+            .map(rgba)
+            .unwrap_or(background_color);
+        let text_color = options
+            .foreground
+            .or(card.and_then(|card| card.foreground))
+            .map(rgba)
+            .unwrap_or(text_color);
+        let accent_color = options
+            .accent
+            .or(card.and_then(|card| card.accent))
+            .map(rgba)
+            .unwrap_or(text_color);
+
+        let strings = options
+            .language
+            .as_deref()
+            .and_then(Strings::get)
+            .unwrap_or_else(Strings::default_language);
+
+        let mut fonts = vec![options.font.clone().unwrap_or_else(default_font)];
+        fonts.extend(options.fallback_fonts.iter().cloned());
+
+        // Selected fonts may be missing characters used in the image itself.
+        if options.font.is_some() {
+            fonts.push(default_font());
+        }
+
+        let layout = options.arrange();
+        let (width, height, scale) = options.dimensions();
+
+        Self {
+            style: options.style,
+            show: options.show,
+            layout,
+            background_color,
+            text_color,
+            accent_color,
+            offline,
+            strings,
+            fonts,
+            width,
+            height,
+            scale,
+            text_scale: Scale::uniform(TEXT_SIZE * scale as f32),
+            motd_scale: Scale::uniform(MOTD_TEXT_SIZE * scale as f32),
+        }
+    }
+
+    /// Image replacing the background, if the server is offline.
+    fn background_image(&self) -> Option<&RgbaImage> {
+        self.offline.as_ref()?.background_image.as_ref()
+    }
+
+    /// Convert an unscaled position or size into pixels.
+    fn px(&self, value: u32) -> i32 {
+        (value * self.scale) as i32
+    }
+
+    /// Create an image with the background for the style.
+    fn canvas(&self, height: u32, ping: &ServerPing) -> RgbaImage {
+        let (width, height) = (self.width * self.scale, height * self.scale);
+
+        if let Some(background) = self.background_image() {
+            return fill_background(background, width, height);
+        }
+
+        match self.style {
+            ImageStyle::Flat => {
+                let mut image = RgbaImage::new(width, height);
+
+                let fill = Rect::at(0, 0).of_size(width, height);
+                draw_filled_rect_mut(&mut image, fill, self.background_color);
+
+                image
+            }
+            ImageStyle::Cover => cover_background(
+                &server_icon(&ping.favicon),
+                width,
+                height,
+                self.background_color,
+            ),
+        }
+    }
+
+    /// Everything to draw for each part of the layout.
+    fn items(&self, title: &str, ping: &ServerPing) -> Vec<Item> {
+        let mut items = Vec::new();
+
+        for placed in &self.layout.parts {
+            let Placed { part, x, y, anchor } = *placed;
+            let available_width = self.layout.available_width(placed);
+            let text = |y, size, color, spans| Item::Text {
+                x,
+                y,
+                size,
+                color,
+                anchor,
+                spans,
+            };
+
+            match part {
+                Part::Icon { size } => items.push(Item::Icon { x, y, size }),
+                Part::Title => {
+                    items.push(text(y, TEXT_SIZE, self.text_color, vec![plain_span(title)]))
+                }
+                Part::Status => items.push(text(
+                    y,
+                    TEXT_SIZE,
+                    self.accent_color,
+                    vec![plain_span(&self.status(ping))],
+                )),
+                Part::Players if ping.online => items.push(text(
+                    y,
+                    MOTD_TEXT_SIZE,
+                    self.text_color,
+                    self.players(ping, available_width),
+                )),
+                Part::Version if ping.online => {
+                    items.push(text(y, MOTD_TEXT_SIZE, self.text_color, self.version(ping)))
+                }
+                Part::Updated => items.push(text(
+                    y,
+                    TEXT_SIZE,
+                    self.text_color,
+                    vec![plain_span(&self.updated(ping, available_width))],
+                )),
+                Part::Motd => {
+                    let motd = motd::parse(&ping.motd_json);
+
+                    for (index, line) in (0..).zip(motd::lines(&motd)) {
+                        if index == MOTD_LINES {
+                            break;
+                        }
+
+                        let y = y + MOTD_LINE_HEIGHT * index;
+                        items.push(text(y, MOTD_TEXT_SIZE, self.text_color, line));
+                    }
+                }
+                Part::PlayerList { rows } if ping.online => {
+                    for (index, row) in (0..).zip(self.player_rows(ping, rows)) {
+                        let y = y + MOTD_LINE_HEIGHT * index;
+                        items.push(text(y, MOTD_TEXT_SIZE, self.text_color, row));
+                    }
+                }
+                Part::Latency => {
+                    if let Some(latency) = ping.latency {
+                        items.push(Item::LatencyBars { x, y, latency });
+                        items.push(Item::Text {
+                            x: x - 4,
+                            y,
+                            size: MOTD_TEXT_SIZE,
+                            color: self.text_color,
+                            anchor: Anchor::End,
+                            spans: vec![plain_span(&format!("{}ms", latency))],
+                        });
+                    }
+                }
+                Part::Players | Part::Version | Part::PlayerList { .. } => (),
+            }
+        }
+
+        items
+    }
+
+    /// Draw an item on an image.
+    fn draw(&self, image: &mut RgbaImage, item: &Item, ping: &ServerPing) {
+        match item {
+            Item::Icon { x, y, size } => self.draw_icon(image, ping, *x, *y, *size),
+            Item::Text {
+                x,
+                y,
+                size,
+                color,
+                anchor,
+                spans,
+            } => {
+                let scale = Scale::uniform(size * self.scale as f32);
+                let text: String = spans.iter().map(|span| span.text.as_str()).collect();
+                let x = match anchor {
+                    Anchor::Start => self.px(*x),
+                    Anchor::Middle => {
+                        self.px(*x) - text_width(&self.fonts, scale, &text) as i32 / 2
+                    }
+                    Anchor::End => self.px(*x) - text_width(&self.fonts, scale, &text) as i32,
+                };
+
+                draw_spans(image, *color, x, self.px(*y), scale, &self.fonts, spans);
+            }
+            Item::LatencyBars { x, y, latency } => {
+                draw_latency_bars(image, self.px(*x), self.px(*y), self.scale, *latency);
+            }
+        }
+    }
+
+    /// Draw a line of text at the normal size.
+    fn text(&self, image: &mut RgbaImage, color: Rgba<u8>, x: u32, y: u32, text: &str) {
+        draw_text(
+            image,
+            color,
+            self.px(x),
+            self.px(y),
+            self.text_scale,
+            &self.fonts,
+            text,
+        );
+    }
+
+    /// Draw styled text at the MOTD size.
+    fn spans(&self, image: &mut RgbaImage, x: u32, y: u32, spans: &[Span]) {
+        draw_spans(
+            image,
+            self.text_color,
+            self.px(x),
+            self.px(y),
+            self.motd_scale,
+            &self.fonts,
+            spans,
+        );
+    }
+
+    fn status(&self, ping: &ServerPing) -> String {
+        if ping.online && self.show.players {
+            self.strings.online(ping.players.now, ping.players.max)
+        } else if ping.online {
+            self.strings.online_without_players()
+        } else if let Some(message) = self.offline.as_ref().and_then(|card| card.message.clone()) {
+            message
+        } else {
+            self.strings.offline()
+        }
+    }
+
+    /// When the data was updated, including the site name if it fits.
+    fn updated(&self, ping: &ServerPing, available_width: u32) -> String {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let mins = now.saturating_sub(ping.last_updated) / 60;
+
+        // Some translations are too long to also fit the site name.
+        let updated = format!("{} · mcapi.us", self.strings.updated(mins));
+        if text_width(&self.fonts, self.text_scale, &updated) > self.px(available_width) as f32 {
+            return self.strings.updated(mins);
+        }
+
+        updated
+    }
+
+    /// Names of players that fit on a single line.
+    fn players(&self, ping: &ServerPing, available_width: u32) -> Vec<Span> {
+        let names: Vec<&str> = ping
+            .players
+            .sample
+            .iter()
+            .map(|player| player.name.as_str())
+            .collect();
+        let total = ping.players.now.max(0) as usize;
+
+        player_spans(
+            &self.fonts,
+            self.motd_scale,
+            self.strings,
+            &names,
+            total,
+            self.px(available_width.saturating_sub(4)) as f32,
+        )
+    }
+
+    /// Names of players, one for each row, with the last row showing how many
+    /// players were left out if they do not all fit.
+    fn player_rows(&self, ping: &ServerPing, rows: u32) -> Vec<Vec<Span>> {
+        let names = &ping.players.sample;
+        let total = (ping.players.now.max(0) as usize).max(names.len());
+        let rows = rows as usize;
+
+        let shown = if total > rows { rows - 1 } else { rows }.min(names.len());
+        let mut lines: Vec<_> = names[..shown]
+            .iter()
+            .map(|player| {
+                let name = player.name.replace('\n', " ");
+                motd::parse(&serde_json::Value::String(name))
+            })
+            .collect();
+
+        if total > shown {
+            lines.push(vec![plain_span(&self.strings.more_players(total - shown))]);
+        }
+
+        lines
+    }
+
+    /// The server's version, which may use formatting codes.
+    fn version(&self, ping: &ServerPing) -> Vec<Span> {
+        let version = self
+            .strings
+            .version(ping.server.name.as_deref().unwrap_or_default());
+
+        motd::parse(&serde_json::Value::String(version))
+    }
+
+    /// Draw the server's icon, centered in a square.
+    ///
+    /// Icons larger than the square are shrunk to fit it.
+    fn draw_icon(&self, image: &mut RgbaImage, ping: &ServerPing, x: u32, y: u32, size: u32) {
+        let favicon = server_icon(&ping.favicon);
+        let (width, height) = icon_size(&favicon, size);
+
+        // Icons are pixel art, so keep the pixels sharp when they are only
+        // being scaled up.
+        let filter = if width >= favicon.width() {
+            image::imageops::FilterType::Nearest
+        } else {
+            image::imageops::FilterType::Triangle
+        };
+        let favicon = if (width, height) == favicon.dimensions() && self.scale == 1 {
+            favicon
+        } else {
+            image::imageops::resize(&favicon, width * self.scale, height * self.scale, filter)
+        };
+
+        let (x, y) = (
+            x + size.saturating_sub(width) / 2,
+            y + size.saturating_sub(height) / 2,
+        );
+
+        image::imageops::overlay(image, &favicon, self.px(x) as i64, self.px(y) as i64);
+    }
+}
+
+/// Size of an icon shrunk to fit a square, keeping its aspect ratio.
+fn icon_size(icon: &RgbaImage, size: u32) -> (u32, u32) {
+    let (width, height) = icon.dimensions();
+
+    if width <= size && height <= size {
+        (width, height)
+    } else if width >= height {
+        (size, (height * size / width).max(1))
+    } else {
+        ((width * size / height).max(1), size)
+    }
+}
+
+/// Replace placeholders in a title with data from a ping:
+///
+/// * `{online}` and `{max}`, the number of players online and allowed
+/// * `{version}`, the name of the server's version
+/// * `{protocol}`, the server's protocol version
+/// * `{motd}`, the MOTD as plain text, on a single line
+/// * `{latency}`, milliseconds for the server to answer a ping
+///
+/// Anything else in braces is kept as it was. Placeholders for data an offline
+/// server does not have are replaced with nothing, except the player counts
+/// which are 0.
+pub fn format_title(template: &str, ping: &ServerPing) -> String {
+    let mut title = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        title.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = match rest.find('}') {
+            Some(end) => end,
+            None => break,
+        };
+
+        let value = match &rest[1..end] {
+            "online" => ping.players.now.to_string(),
+            "max" => ping.players.max.to_string(),
+            "version" => ping.server.name.clone().unwrap_or_default(),
+            "protocol" => ping.server.protocol.to_string(),
+            "motd" => ping.motd.split_whitespace().collect::<Vec<_>>().join(" "),
+            "latency" => ping
+                .latency
+                .map(|latency| latency.to_string())
+                .unwrap_or_default(),
+            _ => {
+                title.push('{');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+
+        title.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+
+    title.push_str(rest);
+    title
+}
+
+/// Generate an image for a server given image options and valid ping data,
+/// encoded as a PNG.
+pub fn server_image(options: &ImageOptions, ping: ServerPing) -> Vec<u8> {
+    encode_png(render_server_image(options, ping))
+}
+
+/// Draw an image for a server given image options and valid ping data, to be
+/// encoded in any raster format.
+pub fn render_server_image(options: &ImageOptions, ping: ServerPing) -> RgbaImage {
+    let renderer = Renderer::new(options, &ping);
+
+    let mut image = renderer.canvas(renderer.height, &ping);
+
+    for item in renderer.items(&format_title(&options.title, &ping), &ping) {
+        renderer.draw(&mut image, &item, &ping);
+    }
+
+    image
+}
+
+/// Draw a preview card for links to a server, at the [`OG_IMAGE_SIZE`] social
+/// sites show previews at.
+///
+/// Cards always use the square layout, scaled up so text is readable when the
+/// preview is shrunk, and show the version unless it was hidden.
+pub fn render_og_image(options: &ImageOptions, ping: ServerPing) -> RgbaImage {
+    let (width, height) = OG_IMAGE_SIZE;
+
+    let options = ImageOptions {
+        layout: LayoutPreset::Square,
+        width: Some(width / OG_IMAGE_SCALE),
+        height: Some(height / OG_IMAGE_SCALE),
+        scale: Some(OG_IMAGE_SCALE),
+        show: Elements {
+            version: Some(options.show.version.unwrap_or(true)),
+            ..options.show
+        },
+        ..options.clone()
+    };
+
+    render_server_image(&options, ping)
+}
+
+/// Generate an animated banner for a server, cycling between its MOTD, the
+/// players online, and its version.
+///
+/// Banners are only as tall as the icon, and offline servers only have a
+/// single frame. Hidden elements remove their frames, except the status.
+/// Banners always use the wide layout.
+pub fn server_banner(options: &ImageOptions, ping: ServerPing) -> Vec<u8> {
+    let renderer = Renderer::new(options, &ping);
+    let x = if renderer.show.icon { 68 } else { 4 };
+    let available_width = renderer.width.saturating_sub(x);
+
+    let background = renderer.canvas(HEADER_HEIGHT, &ping);
+    let title = format_title(&options.title, &ping);
+
+    let frame = |draw: &dyn Fn(&mut RgbaImage)| {
+        let mut image = background.clone();
+        renderer.text(&mut image, renderer.text_color, x, 2, &title);
+        draw(&mut image);
+
+        if renderer.show.icon {
+            renderer.draw_icon(&mut image, &ping, 0, 0, HEADER_HEIGHT);
+        }
+
+        image
+    };
+
+    let updated = renderer.updated(&ping, available_width);
+    let status = renderer.status(&ping);
+    let updated_y = HEADER_HEIGHT - 16 - 2;
+
+    if !ping.online {
+        return encode_gif(vec![frame(&|image| {
+            renderer.text(image, renderer.accent_color, x, 18, &status);
+            renderer.text(image, renderer.text_color, x, updated_y, &updated);
+        })]);
+    }
+
+    let motd = motd::parse(&ping.motd_json);
+    let lines = motd::lines(&motd);
+    let players = renderer.players(&ping, available_width);
+    let version = renderer.version(&ping);
+
+    let mut frames = Vec::new();
+
+    if renderer.show.motd {
+        frames.push(frame(&|image| {
+            for (index, line) in (0..).zip(lines.iter().take(MOTD_LINES as usize)) {
+                renderer.spans(image, x, 22 + MOTD_LINE_HEIGHT * index, line);
+            }
+        }));
+    }
+
+    // Without player names, the update time fits under the status.
+    frames.push(frame(&|image| {
+        renderer.text(image, renderer.accent_color, x, 18, &status);
+
+        if renderer.show.players {
+            renderer.spans(image, x, 36, &players);
+        } else {
+            renderer.text(image, renderer.text_color, x, updated_y, &updated);
+        }
+    }));
+
+    if renderer.show.version.unwrap_or(true) {
+        frames.push(frame(&|image| {
+            renderer.spans(image, x, 22, &version);
+            renderer.text(image, renderer.text_color, x, updated_y, &updated);
+        }));
+    }
+
+    encode_gif(frames)
+}
+
+/// Generate a vector image for a server given image options and valid ping
+/// data, with the same layout as [`server_image`].
+///
+/// Text is drawn by the viewer, so it uses the default font when the viewer
+/// has it installed and any monospace font otherwise.
+pub fn server_svg(options: &ImageOptions, ping: ServerPing) -> String {
+    let renderer = Renderer::new(options, &ping);
+    let (width, height, scale) = (renderer.width, renderer.height, renderer.scale);
+
+    let mut svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 {} {}" font-family="{}">"#,
+        width * scale,
+        height * scale,
+        width,
+        height,
+        SVG_FONT_FAMILY,
+    );
+
+    svg.push_str(&format!(
+        r#"<rect width="100%" height="100%" fill="{}"/>"#,
+        hex(renderer.background_color)
+    ));
+
+    let favicon = server_icon(&ping.favicon);
+    let favicon_uri = format!(
+        "data:image/png;base64,{}",
+        base64::encode(encode_png(favicon.clone()))
+    );
+
+    if let Some(background) = renderer.background_image() {
+        svg.push_str(&format!(
+            r#"<image width="100%" height="100%" preserveAspectRatio="xMidYMid slice" href="data:image/png;base64,{}"/>"#,
+            base64::encode(encode_png(background.clone()))
+        ));
+    } else if renderer.style == ImageStyle::Cover {
+        svg.push_str(&format!(
+            r#"<filter id="cover"><feGaussianBlur stdDeviation="{}"/></filter><image width="100%" height="100%" preserveAspectRatio="xMidYMid slice" filter="url(#cover)" href="{}"/><rect width="100%" height="100%" fill="{}" fill-opacity="{:.2}"/>"#,
+            COVER_BLUR_SIGMA * COVER_DOWNSCALE as f32,
+            favicon_uri,
+            hex(renderer.background_color),
+            COVER_TINT_ALPHA as f32 / 255.0,
+        ));
+    }
+
+    for item in renderer.items(&format_title(&options.title, &ping), &ping) {
+        match item {
+            Item::Icon { x, y, size } => {
+                let (icon_width, icon_height) = icon_size(&favicon, size);
+
+                // Only pixelate icons that are not being shrunk.
+                let rendering = if icon_width >= favicon.width() {
+                    "pixelated"
+                } else {
+                    "auto"
+                };
+
+                svg.push_str(&format!(
+                    r#"<image x="{}" y="{}" width="{}" height="{}" style="image-rendering:{}" href="{}"/>"#,
+                    x + size.saturating_sub(icon_width) / 2,
+                    y + size.saturating_sub(icon_height) / 2,
+                    icon_width,
+                    icon_height,
+                    rendering,
+                    favicon_uri,
+                ));
+            }
+            Item::Text {
+                x,
+                y,
+                size,
+                color,
+                anchor,
+                spans,
+            } => svg_text(
+                &mut svg,
+                &renderer.fonts[0],
+                color,
+                x,
+                y,
+                size,
+                anchor,
+                &spans,
+            ),
+            Item::LatencyBars { x, y, latency } => {
+                let filled = latency_bars(latency);
+
+                for bar in 0..LATENCY_BAR_COUNT {
+                    let bar_height = 2 * (bar + 1);
+                    let color = if bar < filled {
+                        LATENCY_FILLED_COLOR
+                    } else {
+                        LATENCY_EMPTY_COLOR
+                    };
+
+                    svg.push_str(&format!(
+                        r#"<rect x="{}" y="{}" width="2" height="{}" fill="{}"/>"#,
+                        x + bar * 3,
+                        y + LATENCY_BAR_COUNT * 2 - bar_height,
+                        bar_height,
+                        hex(rgba(color)),
+                    ));
+                }
+            }
+        }
+    }
+
+    svg.push_str("</svg>");
+
+    svg
+}
+
+/// Add a line of styled text to a vector image, positioned by the top of the
+/// text like text drawn on other images.
+#[allow(clippy::too_many_arguments)]
+fn svg_text(
+    svg: &mut String,
+    font: &Font<'static>,
+    color: Rgba<u8>,
+    x: u32,
+    y: u32,
+    size: f32,
+    anchor: Anchor,
+    spans: &[Span],
+) {
+    let anchor = match anchor {
+        Anchor::Start => "",
+        Anchor::Middle => r#" text-anchor="middle""#,
+        Anchor::End => r#" text-anchor="end""#,
+    };
+
+    svg.push_str(&format!(
+        r#"<text x="{}" y="{}" font-size="{}" fill="{}"{} xml:space="preserve">"#,
+        x,
+        y as f32 + ascent(font, size),
+        size,
+        hex(color),
+        anchor,
+    ));
+
+    for span in spans {
+        svg.push_str("<tspan");
+
+        if let Some(color) = span.style.color {
+            svg.push_str(&format!(r#" fill="{}""#, hex(rgba(color))));
+        }
+
+        if span.style.bold {
+            svg.push_str(r#" font-weight="bold""#);
+        }
+
+        if span.style.italic {
+            svg.push_str(r#" font-style="italic""#);
+        }
+
+        let decorations: Vec<_> = [
+            (span.style.underlined, "underline"),
+            (span.style.strikethrough, "line-through"),
+        ]
+        .iter()
+        .filter_map(|(enabled, decoration)| enabled.then_some(*decoration))
+        .collect();
+        if !decorations.is_empty() {
+            svg.push_str(&format!(r#" text-decoration="{}""#, decorations.join(" ")));
+        }
+
+        svg.push('>');
+        svg.push_str(&escape_xml(&span.text));
+        svg.push_str("</tspan>");
+    }
+
+    svg.push_str("</text>");
+}
+
+/// Distance from the top of text to its baseline.
+fn ascent(font: &Font<'static>, size: f32) -> f32 {
+    font.v_metrics(Scale::uniform(size)).ascent.round()
+}
+
+/// Format a pixel as a hex color.
+fn hex(color: Rgba<u8>) -> String {
+    format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+/// Escape text for XML, removing control characters that are not allowed in
+/// XML documents.
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c if c.is_control() => (),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// List as many player names as fit within a width, followed by how many
+/// players were left out.
+///
+/// Names may use formatting codes, as servers often put messages in the player
+/// sample.
+fn player_spans(
+    fonts: &[Font<'static>],
+    scale: Scale,
+    strings: &Strings,
+    names: &[&str],
+    total: usize,
+    max_width: f32,
+) -> Vec<Span> {
+    let total = total.max(names.len());
+
+    // Each name is parsed and measured once, with the separator before it.
+    let names: Vec<(Vec<Span>, f32)> = names
+        .iter()
+        .enumerate()
+        .map(|(index, name)| {
+            let mut spans = Vec::new();
+            if index > 0 {
+                spans.push(plain_span(", "));
+            }
+
+            let name = name.replace('\n', " ");
+            spans.extend(motd::parse(&serde_json::Value::String(name)));
+
+            let width = text_width(fonts, scale, &spans_text(&spans));
+            (spans, width)
+        })
+        .collect();
+
+    let widths: Vec<f32> = names
+        .iter()
+        .scan(0.0, |width, (_spans, name_width)| {
+            *width += name_width;
+            Some(*width)
+        })
+        .collect();
+
+    for shown in (1..=names.len()).rev() {
+        let remaining = total - shown;
+        let more =
+            (remaining > 0).then(|| plain_span(&format!(" {}", strings.more_players(remaining))));
+        let more_width = more
+            .as_ref()
+            .map(|more| text_width(fonts, scale, &more.text))
+            .unwrap_or_default();
+
+        // Adding widths rounds differently than measuring the whole line, so
+        // only lines clearly too wide are skipped before measuring them.
+        if widths[shown - 1] + more_width > max_width + 1.0 {
+            continue;
+        }
+
+        let spans: Vec<Span> = names[..shown]
+            .iter()
+            .flat_map(|(spans, _width)| spans.iter().cloned())
+            .chain(more)
+            .collect();
+
+        // Kerning between names can make the whole line slightly wider than
+        // the sum of each name.
+        if text_width(fonts, scale, &spans_text(&spans)) <= max_width {
+            return spans;
+        }
+    }
+
+    Vec::new()
 }
 
-impl Default for Theme {
-    fn default() -> Self {
-        Self::Light
+fn spans_text(spans: &[Span]) -> String {
+    spans.iter().map(|span| span.text.as_str()).collect()
+}
+
+fn plain_span(text: &str) -> Span {
+    Span {
+        text: text.to_string(),
+        style: Default::default(),
     }
 }
 
-/// Generate an image for a server given request information and valid ping
-/// data.
-pub fn server_image(
-    request: &crate::ServerImageRequest,
-    ping: crate::types::ServerPing,
-) -> Vec<u8> {
-    let (background_color, text_color) = match request.theme.unwrap_or_default() {
-        Theme::Light => (
-            Rgba([255u8, 255u8, 255u8, 255u8]),
-            Rgba([0u8, 0u8, 0u8, 255u8]),
-        ),
-        Theme::Dark => (
-            Rgba([0u8, 0u8, 0u8, 255u8]),
-            Rgba([255u8, 255u8, 255u8, 255u8]),
-        ),
-    };
+/// Width of text drawn without any styles.
+fn text_width(fonts: &[Font<'static>], scale: Scale, text: &str) -> f32 {
+    layout_line(fonts, scale, 0.0, 0.0, text).1
+}
 
-    let mut image = RgbaImage::new(325, 64);
+/// Lay out a line of text, taking each character from the first font with a
+/// glyph for it, and return the glyphs and where the text ended.
+///
+/// Characters missing from every font are drawn with the first font.
+fn layout_line(
+    fonts: &[Font<'static>],
+    scale: Scale,
+    x: f32,
+    baseline: f32,
+    text: &str,
+) -> (Vec<PositionedGlyph<'static>>, f32) {
+    let mut glyphs = Vec::new();
+    let mut caret = x;
+    let mut previous: Option<(usize, GlyphId)> = None;
 
-    let font_data: &[u8] = include_bytes!("../static/assets/Inconsolata-Regular.ttf");
-    let font: Font<'static> = Font::try_from_bytes(font_data).unwrap();
+    for c in text.chars().filter(|c| !c.is_control()) {
+        let index = fonts
+            .iter()
+            .position(|font| font.glyph(c).id() != GlyphId(0))
+            .unwrap_or(0);
+        let font = &fonts[index];
+        let glyph = font.glyph(c).scaled(scale);
 
-    let fill = Rect::at(0, 0).of_size(325, 64);
-    draw_filled_rect_mut(&mut image, fill, background_color);
+        // Kerning only applies between glyphs from the same font.
+        if let Some((previous_index, previous_id)) = previous {
+            if previous_index == index {
+                caret += font.pair_kerning(scale, previous_id, glyph.id());
+            }
+        }
+        previous = Some((index, glyph.id()));
 
-    let height = 16.0;
-    let scale = Scale {
-        x: height,
-        y: height,
-    };
+        let advance = glyph.h_metrics().advance_width;
+        glyphs.push(glyph.positioned(point(caret, baseline)));
+        caret += advance;
+    }
 
-    let title = if let Some(title) = &request.title {
-        title.to_owned()
-    } else if let Some(port) = request.port {
-        format!("{}:{}", request.host, port)
-    } else {
-        request.host.to_owned()
-    };
+    (glyphs, caret)
+}
 
-    draw_text_mut(&mut image, text_color, 68, 2, scale, &font, &title);
+/// Draw bars showing the strength of the connection to a server, using the
+/// same thresholds as the server list.
+fn draw_latency_bars(image: &mut RgbaImage, x: i32, y: i32, scale: u32, latency: u64) {
+    let filled = latency_bars(latency);
 
-    let status = if ping.online {
-        format!("Online! {}/{} players", ping.players.now, ping.players.max)
-    } else {
-        "Offline".to_owned()
-    };
+    let filled_color = rgba(LATENCY_FILLED_COLOR);
+    let empty_color = rgba(LATENCY_EMPTY_COLOR);
+
+    for bar in 0..LATENCY_BAR_COUNT {
+        let height = 2 * (bar + 1);
+        let rect = Rect::at(
+            x + (bar * 3 * scale) as i32,
+            y + ((LATENCY_BAR_COUNT * 2 - height) * scale) as i32,
+        )
+        .of_size(2 * scale, height * scale);
 
-    draw_text_mut(&mut image, text_color, 68, 18, scale, &font, &status);
+        let color = if bar < filled {
+            filled_color
+        } else {
+            empty_color
+        };
+        draw_filled_rect_mut(image, rect, color);
+    }
+}
 
-    let now = std::time::SystemTime::now()
-        .duration_since(std::time::SystemTime::UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-    let mins = (now - ping.last_updated) / 60;
+/// Scale an image to cover a size, cropping the center of anything that does
+/// not fit.
+fn fill_background(background: &RgbaImage, width: u32, height: u32) -> RgbaImage {
+    use image::imageops::{self, FilterType};
 
-    let updated = format!("Updated {} mins ago · mcapi.us", mins);
+    let (background_width, background_height) = background.dimensions();
+    let ratio = f64::max(
+        width as f64 / background_width.max(1) as f64,
+        height as f64 / background_height.max(1) as f64,
+    );
+    let (scaled_width, scaled_height) = (
+        ((background_width as f64 * ratio).ceil() as u32).max(width),
+        ((background_height as f64 * ratio).ceil() as u32).max(height),
+    );
 
-    draw_text_mut(
-        &mut image,
-        text_color,
-        68,
-        64 - 16 - 2,
-        scale,
-        &font,
-        &updated,
+    let scaled = imageops::resize(
+        background,
+        scaled_width,
+        scaled_height,
+        FilterType::Triangle,
     );
+    imageops::crop_imm(
+        &scaled,
+        (scaled_width - width) / 2,
+        (scaled_height - height) / 2,
+        width,
+        height,
+    )
+    .to_image()
+}
 
-    let favicon = server_icon(&ping.favicon);
+/// Scale an icon to cover an image, blur it, and tint it with a color so text
+/// drawn on it stays readable.
+fn cover_background(icon: &RgbaImage, width: u32, height: u32, tint: Rgba<u8>) -> RgbaImage {
+    use image::imageops::{self, FilterType};
+
+    // Blurring a smaller image is much faster and looks the same once scaled
+    // up, as the blur removes any detail.
+    let (small_width, small_height) = (
+        (width / COVER_DOWNSCALE).max(1),
+        (height / COVER_DOWNSCALE).max(1),
+    );
+    let size = small_width.max(small_height);
+    let icon = imageops::resize(icon, size, size, FilterType::Triangle);
+    let icon = imageops::crop_imm(
+        &icon,
+        (size - small_width) / 2,
+        (size - small_height) / 2,
+        small_width,
+        small_height,
+    )
+    .to_image();
 
-    let (x, y) = ((64 - favicon.width()) / 2, (64 - favicon.height()) / 2);
+    let blurred = imageops::blur(&icon, COVER_BLUR_SIGMA);
+    let mut image = imageops::resize(&blurred, width, height, FilterType::Triangle);
 
-    image::imageops::overlay(&mut image, &favicon, x as i64, y as i64);
+    let alpha = COVER_TINT_ALPHA as u16;
+    for pixel in image.pixels_mut() {
+        for channel in 0..3 {
+            pixel[channel] = ((pixel[channel] as u16 * (255 - alpha)
+                + tint[channel] as u16 * alpha)
+                / 255) as u8;
+        }
+        pixel[3] = 255;
+    }
 
-    encode_png(image)
+    image
 }
 
-/// Convert a base64-encoded server favicon into an image buffer.
-pub fn server_icon(favicon: &Option<String>) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
-    favicon
-        .as_deref()
-        .and_then(|favicon| {
-            // Some server seemed to be returning the base64 data with newlines
-            // like it had been word wrapped in a text editor. We can replace
-            // each newline with nothing to fix the issue.
-            let b64 = &favicon[22..].replace('\n', "");
-
-            let data = match base64::decode(b64) {
-                Ok(data) => data,
-                Err(err) => {
-                    tracing::warn!("favicon could not be decoded as base64: {:?}", err);
-                    return None;
-                }
-            };
+/// Number of latency bars to fill, using the same thresholds as the server
+/// list.
+fn latency_bars(latency: u64) -> u32 {
+    match latency {
+        0..=149 => 5,
+        150..=299 => 4,
+        300..=599 => 3,
+        600..=999 => 2,
+        _ => 1,
+    }
+}
+
+/// Convert a color into an opaque pixel.
+fn rgba(color: Color) -> Rgba<u8> {
+    Rgba([color.0, color.1, color.2, 255])
+}
 
-            match image::load_from_memory(&data) {
-                Ok(image) => Some(image.into_rgba8()),
-                Err(err) => {
-                    tracing::warn!("favicon could not be loaded as image: {:?}", err);
-                    None
+/// Draw a line of styled text, using the default color for spans without a
+/// color.
+///
+/// Only a regular font is available, so bold text is drawn twice with an
+/// offset and italic text is skewed.
+fn draw_spans(
+    image: &mut RgbaImage,
+    default_color: Rgba<u8>,
+    x: i32,
+    y: i32,
+    scale: Scale,
+    fonts: &[Font<'static>],
+    spans: &[Span],
+) {
+    let ascent = fonts[0].v_metrics(scale).ascent;
+    let mut x = x as f32;
+
+    // Offsets are one pixel at the unscaled MOTD size.
+    let pixel = (scale.y / MOTD_TEXT_SIZE).round().max(1.0);
+
+    for span in spans {
+        let color = span.style.color.map(rgba).unwrap_or(default_color);
+        let skew = if span.style.italic { ITALIC_SKEW } else { 0.0 };
+        let offsets: &[f32] = if span.style.bold {
+            &[0.0, pixel]
+        } else {
+            &[0.0]
+        };
+
+        // Text in the server list has a shadow a quarter of its brightness,
+        // which also keeps light colors readable on light backgrounds. Text in
+        // the image's own color is already readable.
+        let shadow = Rgba([color.0[0] / 4, color.0[1] / 4, color.0[2] / 4, 255]);
+        let passes: &[(Rgba<u8>, f32)] = if span.style.color.is_some() {
+            &[(shadow, pixel), (color, 0.0)]
+        } else {
+            &[(color, 0.0)]
+        };
+
+        let start = x;
+        for &(draw_color, shift) in passes {
+            for offset in offsets {
+                let text_x = start + offset + shift;
+                let text_y = y + shift as i32;
+                let end = draw_glyphs(
+                    image, draw_color, text_x, text_y, scale, fonts, &span.text, skew,
+                );
+
+                if shift == 0.0 && *offset == 0.0 {
+                    x = end;
                 }
             }
-        })
-        .unwrap_or_else(|| {
-            let grass = Vec::from(include_bytes!("../static/assets/grass_sm.png") as &[u8]);
-            image::load_from_memory(&grass).unwrap().into_rgba8()
-        })
+        }
+
+        let mut draw_line = |line_y: f32| {
+            let rect = Rect::at(start as i32, y + line_y as i32)
+                .of_size((x - start).max(1.0) as u32, pixel as u32);
+            draw_filled_rect_mut(image, rect, color);
+        };
+
+        if span.style.underlined {
+            draw_line(ascent + pixel);
+        }
+
+        if span.style.strikethrough {
+            draw_line(ascent / 2.0 + pixel);
+        }
+    }
+}
+
+/// Draw a line of text with the first font in the chain that has each
+/// character.
+fn draw_text(
+    image: &mut RgbaImage,
+    color: Rgba<u8>,
+    x: i32,
+    y: i32,
+    scale: Scale,
+    fonts: &[Font<'static>],
+    text: &str,
+) {
+    draw_glyphs(image, color, x as f32, y, scale, fonts, text, 0.0);
+}
+
+/// Draw text, shifting each row of pixels right by `skew` for every pixel above
+/// the baseline, and return where the text ended.
+#[allow(clippy::too_many_arguments)]
+fn draw_glyphs(
+    image: &mut RgbaImage,
+    color: Rgba<u8>,
+    x: f32,
+    y: i32,
+    scale: Scale,
+    fonts: &[Font<'static>],
+    text: &str,
+    skew: f32,
+) -> f32 {
+    let ascent = fonts[0].v_metrics(scale).ascent;
+    let (glyphs, end) = layout_line(fonts, scale, x, ascent, text);
+
+    for glyph in glyphs {
+        let bb = match glyph.pixel_bounding_box() {
+            Some(bb) => bb,
+            None => continue,
+        };
+
+        glyph.draw(|gx, gy, coverage| {
+            let row = bb.min.y + gy as i32;
+            let px = bb.min.x + gx as i32 + ((ascent - row as f32) * skew).round() as i32;
+            let py = y + row;
+
+            if px < 0 || py < 0 || px as u32 >= image.width() || py as u32 >= image.height() {
+                return;
+            }
+
+            let pixel = image.get_pixel_mut(px as u32, py as u32);
+            for channel in 0..3 {
+                pixel.0[channel] = (f32::from(pixel.0[channel]) * (1.0 - coverage)
+                    + f32::from(color.0[channel]) * coverage)
+                    .round() as u8;
+            }
+        });
+    }
+
+    end
+}
+
+/// Convert a base64-encoded server favicon into an image buffer, using the
+/// placeholder icon if the server has no valid favicon.
+pub fn server_icon(favicon: &Option<String>) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    favicon_image(favicon).unwrap_or_else(placeholder_icon)
+}
+
+/// Convert a base64-encoded server favicon into an image buffer, if the
+/// server has a valid favicon.
+pub fn favicon_image(favicon: &Option<String>) -> Option<RgbaImage> {
+    match decode_favicon(favicon.as_deref()?) {
+        Ok(image) => Some(image),
+        Err(err) => {
+            tracing::warn!("favicon could not be used: {}", err);
+            None
+        }
+    }
+}
+
+/// Get the PNG of a base64-encoded server favicon exactly as the server sent
+/// it, if the server has a valid favicon.
+pub fn favicon_png(favicon: &Option<String>) -> Option<Vec<u8>> {
+    match validate_favicon(favicon.as_deref()?) {
+        Ok(data) => Some(data),
+        Err(err) => {
+            tracing::warn!("favicon could not be used: {}", err);
+            None
+        }
+    }
+}
+
+/// Decode a favicon, checking its size before decoding the image so servers
+/// cannot send images that take too much memory to decode.
+pub fn decode_favicon(favicon: &str) -> Result<RgbaImage, FaviconError> {
+    let data = favicon_data(favicon)?;
+    let decoder = png_decoder(&data)?;
+
+    Ok(image::DynamicImage::from_decoder(decoder)?.into_rgba8())
+}
+
+/// Get the bytes of a favicon's PNG after checking its header, without
+/// decoding the image.
+pub fn validate_favicon(favicon: &str) -> Result<Vec<u8>, FaviconError> {
+    let data = favicon_data(favicon)?;
+    png_decoder(&data)?;
+
+    Ok(data)
+}
+
+/// Read the header of a PNG, checking that it is not too large to decode.
+fn png_decoder(data: &[u8]) -> Result<image::codecs::png::PngDecoder<&[u8]>, FaviconError> {
+    let decoder = image::codecs::png::PngDecoder::new(data)?;
+
+    let (width, height) = image::ImageDecoder::dimensions(&decoder);
+    if width > MAX_FAVICON_SIZE || height > MAX_FAVICON_SIZE {
+        return Err(FaviconError::TooLarge(width, height));
+    }
+
+    Ok(decoder)
+}
+
+/// Get the bytes of a favicon's PNG from its data URI.
+fn favicon_data(favicon: &str) -> Result<Vec<u8>, FaviconError> {
+    let b64 = favicon
+        .strip_prefix(FAVICON_PREFIX)
+        .ok_or(FaviconError::InvalidPrefix)?;
+
+    // Some server seemed to be returning the base64 data with newlines
+    // like it had been word wrapped in a text editor. We can remove
+    // the whitespace to fix the issue.
+    let b64: String = b64.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+
+    // Every 4 characters of base64 are 3 bytes, so check the length before
+    // decoding anything.
+    if b64.len() / 4 * 3 > MAX_FAVICON_BYTES {
+        return Err(FaviconError::TooManyBytes);
+    }
+
+    Ok(base64::decode(b64)?)
+}
+
+/// The grass block used for servers without a favicon.
+pub fn placeholder_icon() -> RgbaImage {
+    let grass = include_bytes!("../static/assets/grass_sm.png");
+    image::load_from_memory(grass).unwrap().into_rgba8()
+}
+
+/// Load an icon from a file in any supported format.
+pub fn load_icon(path: &Path) -> Result<RgbaImage, image::ImageError> {
+    Ok(image::open(path)?.into_rgba8())
+}
+
+/// Load a background image for offline servers from a file in any supported
+/// format, scaled down to the largest size it is drawn at so renders don't
+/// resize a larger image each time.
+pub fn load_background(path: &Path) -> Result<RgbaImage, image::ImageError> {
+    Ok(shrink_background(load_icon(path)?, MAX_BACKGROUND_SIZE))
+}
+
+/// Scale an image down until it only just covers a size, keeping images that
+/// are already smaller.
+fn shrink_background(background: RgbaImage, (max_width, max_height): (u32, u32)) -> RgbaImage {
+    let (width, height) = background.dimensions();
+    let ratio = f64::max(
+        max_width as f64 / width.max(1) as f64,
+        max_height as f64 / height.max(1) as f64,
+    );
+    if ratio >= 1.0 {
+        return background;
+    }
+
+    image::imageops::resize(
+        &background,
+        (width as f64 * ratio).ceil() as u32,
+        (height as f64 * ratio).ceil() as u32,
+        image::imageops::FilterType::Triangle,
+    )
+}
+
+/// Generate a 64 pixel icon with a symmetric pattern of colored cells that is
+/// always the same for the same seed, such as a hostname.
+pub fn identicon(seed: &str) -> RgbaImage {
+    // FNV-1a, as the standard library's hasher may change between releases
+    // and icons should stay the same.
+    let hash = seed.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+
+    let color = hsl_to_rgb((hash >> 48) as f32 / 65536.0 * 360.0, 0.6, 0.55);
+    let columns = IDENTICON_CELLS / 2 + IDENTICON_CELLS % 2;
+    let margin = (HEADER_HEIGHT - IDENTICON_CELLS * IDENTICON_CELL_SIZE) / 2;
+
+    let mut image = RgbaImage::new(HEADER_HEIGHT, HEADER_HEIGHT);
+
+    for row in 0..IDENTICON_CELLS {
+        for column in 0..columns {
+            if hash >> (row * columns + column) & 1 == 0 {
+                continue;
+            }
+
+            for x in [column, IDENTICON_CELLS - 1 - column] {
+                let cell = Rect::at(
+                    (margin + x * IDENTICON_CELL_SIZE) as i32,
+                    (margin + row * IDENTICON_CELL_SIZE) as i32,
+                )
+                .of_size(IDENTICON_CELL_SIZE, IDENTICON_CELL_SIZE);
+                draw_filled_rect_mut(&mut image, cell, color);
+            }
+        }
+    }
+
+    image
+}
+
+/// Convert a hue in degrees with saturation and lightness from 0 to 1 into
+/// an opaque color.
+fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> Rgba<u8> {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let sector = hue / 60.0;
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+
+    let (r, g, b) = match sector as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+
+    let m = lightness - chroma / 2.0;
+    let channel = |value: f32| ((value + m) * 255.0).round() as u8;
+
+    Rgba([channel(r), channel(g), channel(b), 255])
+}
+
+/// Encode frames into a looping animated GIF, showing each frame for the
+/// same time.
+pub fn encode_gif(frames: Vec<RgbaImage>) -> Vec<u8> {
+    let mut buf: Vec<u8> = vec![];
+
+    {
+        let mut encoder = GifEncoder::new_with_speed(&mut buf, GIF_ENCODE_SPEED);
+        encoder
+            .set_repeat(Repeat::Infinite)
+            .expect("Unable to encode GIF");
+
+        let delay = Delay::from_numer_denom_ms(BANNER_FRAME_MS, 1);
+        encoder
+            .encode_frames(
+                frames
+                    .into_iter()
+                    .map(|image| Frame::from_parts(image, 0, 0, delay)),
+            )
+            .expect("Unable to encode GIF");
+    }
+
+    buf
+}
+
+/// Encode an image buffer into a JPEG, with a quality clamped to
+/// [`QUALITY_RANGE`].
+///
+/// JPEGs do not support transparency, so images should be opaque.
+pub fn encode_jpeg(image: RgbaImage, quality: u8) -> Vec<u8> {
+    let quality = quality.clamp(*QUALITY_RANGE.start(), *QUALITY_RANGE.end());
+    let image = image::DynamicImage::ImageRgba8(image).into_rgb8();
+
+    let mut buf: Vec<u8> = vec![];
+    let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buf, quality);
+    encoder
+        .encode(
+            &image,
+            image.width(),
+            image.height(),
+            image::ColorType::Rgb8,
+        )
+        .expect("Unable to encode JPEG");
+
+    buf
+}
+
+/// Encode an image buffer into a lossless WebP.
+pub fn encode_webp(image: RgbaImage) -> Vec<u8> {
+    let mut buf: Vec<u8> = vec![];
+    let encoder = image::codecs::webp::WebPEncoder::new_lossless(&mut buf);
+    encoder
+        .write_image(
+            &image,
+            image.width(),
+            image.height(),
+            image::ColorType::Rgba8,
+        )
+        .expect("Unable to encode WebP");
+
+    buf
 }
 
 /// Encode an image buffer into a PNG.
@@ -142,3 +2074,608 @@ pub fn encode_png(image: ImageBuffer<Rgba<u8>, Vec<u8>>) -> Vec<u8> {
 
     buf
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dimensions() {
+        assert_eq!(
+            ImageOptions::default().dimensions(),
+            (DEFAULT_WIDTH, DEFAULT_HEIGHT, 1)
+        );
+
+        let options = ImageOptions {
+            width: Some(10_000),
+            height: Some(1),
+            scale: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(options.dimensions(), (1000, HEADER_HEIGHT, 1));
+
+        // Large images are scaled less to limit their size.
+        let options = ImageOptions {
+            width: Some(1000),
+            height: Some(400),
+            scale: Some(MAX_SCALE),
+            ..Default::default()
+        };
+        assert_eq!(options.dimensions(), (1000, 400, 1));
+
+        let options = ImageOptions {
+            scale: Some(MAX_SCALE),
+            ..Default::default()
+        };
+        let (width, height, scale) = options.dimensions();
+        assert_eq!(scale, MAX_SCALE);
+        assert!(width * height * scale * scale <= MAX_PIXELS);
+    }
+
+    /// Position of a part in a layout, if it was placed.
+    fn position(layout: &Layout, part: Part) -> Option<(u32, u32)> {
+        layout
+            .parts
+            .iter()
+            .find(|placed| placed.part == part)
+            .map(|placed| (placed.x, placed.y))
+    }
+
+    #[test]
+    fn test_layout() {
+        let layout = ImageOptions::default().arrange();
+        assert_eq!(
+            position(
+                &layout,
+                Part::Icon {
+                    size: HEADER_HEIGHT
+                }
+            ),
+            Some((0, 0))
+        );
+        assert_eq!(position(&layout, Part::Updated), Some((68, 46)));
+        assert_eq!(position(&layout, Part::Motd), Some((4, 66)));
+        assert_eq!(position(&layout, Part::Players), None);
+        assert_eq!(layout.height, DEFAULT_HEIGHT);
+
+        // Extra lines push the update time and MOTD down.
+        let options = ImageOptions {
+            players: true,
+            show: Elements {
+                version: Some(true),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let layout = options.arrange();
+        assert_eq!(position(&layout, Part::Players), Some((68, 33)));
+        assert_eq!(position(&layout, Part::Version), Some((68, 45)));
+        assert_eq!(position(&layout, Part::Updated), Some((68, 58)));
+        assert_eq!(options.dimensions().1, layout.height);
+
+        // Without the icon or MOTD, the image is only as tall as its text.
+        let options = ImageOptions {
+            players: true,
+            show: Elements {
+                icon: false,
+                players: false,
+                motd: false,
+                version: None,
+            },
+            ..Default::default()
+        };
+        let layout = options.arrange();
+        assert_eq!(position(&layout, Part::Title), Some((4, 2)));
+        assert_eq!(position(&layout, Part::Updated), Some((4, 34)));
+        assert_eq!(position(&layout, Part::Motd), None);
+        assert_eq!(layout.height, 52);
+    }
+
+    #[test]
+    fn test_layout_presets() {
+        let arrange = |layout| {
+            ImageOptions {
+                layout,
+                ..Default::default()
+            }
+            .arrange()
+        };
+
+        let compact = arrange(LayoutPreset::Compact);
+        assert_eq!((compact.width, compact.height), (250, 36));
+        assert_eq!(position(&compact, Part::Motd), None);
+        assert_eq!(position(&compact, Part::Status), Some((38, 18)));
+
+        // Everything is centered in a square.
+        let square = arrange(LayoutPreset::Square);
+        assert_eq!((square.width, square.height), (200, 200));
+        assert!(square
+            .parts
+            .iter()
+            .filter(|placed| placed.part
+                != Part::Icon {
+                    size: HEADER_HEIGHT
+                })
+            .all(|placed| placed.x == 100 && placed.anchor == Anchor::Middle));
+        let (_, icon_y) = position(
+            &square,
+            Part::Icon {
+                size: HEADER_HEIGHT,
+            },
+        )
+        .unwrap();
+        let (_, motd_y) = position(&square, Part::Motd).unwrap();
+        let bottom = motd_y + MOTD_LINE_HEIGHT * MOTD_LINES;
+        assert!(icon_y.abs_diff(200 - bottom) <= 8);
+
+        // The player list goes below the MOTD, replacing names on one line.
+        let tall = ImageOptions {
+            layout: LayoutPreset::Tall,
+            players: true,
+            ..Default::default()
+        }
+        .arrange();
+        let rows = TALL_PLAYER_ROWS;
+        assert_eq!(position(&tall, Part::Players), None);
+        assert_eq!(
+            position(&tall, Part::PlayerList { rows }),
+            Some((4, DEFAULT_HEIGHT + 2))
+        );
+        assert_eq!(tall.height, DEFAULT_HEIGHT + 4 + MOTD_LINE_HEIGHT * rows);
+    }
+
+    #[test]
+    fn test_format_title() {
+        let mut ping = ServerPing {
+            online: true,
+            motd: "A Minecraft\n  Server".to_string(),
+            latency: Some(42),
+            ..Default::default()
+        };
+        ping.players.now = 5;
+        ping.players.max = 20;
+        ping.server.name = Some("1.20.4".to_string());
+        ping.server.protocol = 765;
+
+        assert_eq!(
+            format_title("{online}/{max} on {version} ({protocol})", &ping),
+            "5/20 on 1.20.4 (765)"
+        );
+        assert_eq!(
+            format_title("{motd} - {latency}ms", &ping),
+            "A Minecraft Server - 42ms"
+        );
+        assert_eq!(
+            format_title("example.com:25565", &ping),
+            "example.com:25565"
+        );
+        assert_eq!(format_title("{unknown} {{online}", &ping), "{unknown} {5");
+        assert_eq!(format_title("unclosed {online", &ping), "unclosed {online");
+
+        assert_eq!(
+            format_title("{online}/{max} {version}", &ServerPing::default()),
+            "0/0 "
+        );
+    }
+
+    #[test]
+    fn test_shrink_background() {
+        let background = RgbaImage::new(400, 100);
+        assert_eq!(
+            shrink_background(background, (100, 40)).dimensions(),
+            (160, 40)
+        );
+
+        let background = RgbaImage::new(100, 50);
+        assert_eq!(
+            shrink_background(background, (200, 40)).dimensions(),
+            (100, 50)
+        );
+    }
+
+    #[test]
+    fn test_offline_card() {
+        let options = ImageOptions {
+            foreground: Some(Color(0x12, 0x34, 0x56)),
+            offline: Arc::new(OfflineCard {
+                message: Some("Down for maintenance".to_string()),
+                background: Some(Color(0xAA, 0x00, 0x00)),
+                foreground: Some(Color(0x00, 0xAA, 0x00)),
+                background_image: Some(RgbaImage::from_pixel(4, 2, Rgba([0, 0, 255, 255]))),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let offline = ServerPing::default();
+        let renderer = Renderer::new(&options, &offline);
+        assert_eq!(renderer.status(&offline), "Down for maintenance");
+        assert_eq!(renderer.background_color, Rgba([0xAA, 0x00, 0x00, 255]));
+        // Colors from the request are used over the card's colors.
+        assert_eq!(renderer.text_color, Rgba([0x12, 0x34, 0x56, 255]));
+
+        let image = renderer.canvas(renderer.height, &offline);
+        assert_eq!(image.dimensions(), (renderer.width, renderer.height));
+        assert_eq!(*image.get_pixel(0, 0), Rgba([0, 0, 255, 255]));
+
+        let online = ServerPing {
+            online: true,
+            ..Default::default()
+        };
+        let renderer = Renderer::new(&options, &online);
+        assert_eq!(renderer.status(&online), renderer.strings.online(0, 0));
+        assert_eq!(renderer.background_color, Rgba([255, 255, 255, 255]));
+        assert!(renderer.background_image().is_none());
+    }
+
+    #[test]
+    fn test_player_rows() {
+        let renderer = Renderer::new(&ImageOptions::default(), &ServerPing::default());
+        let ping = |now, names: &[&str]| ServerPing {
+            online: true,
+            players: crate::types::ServerPingPlayers {
+                now,
+                sample: names
+                    .iter()
+                    .map(|name| crate::protocol::PlayerSample {
+                        name: name.to_string(),
+                        id: String::new(),
+                    })
+                    .collect(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let text = |rows: Vec<Vec<Span>>| -> Vec<String> {
+            rows.iter()
+                .map(|row| row.iter().map(|span| span.text.as_str()).collect())
+                .collect()
+        };
+
+        assert_eq!(
+            text(renderer.player_rows(&ping(2, &["a", "b"]), 3)),
+            ["a", "b"]
+        );
+        assert_eq!(
+            text(renderer.player_rows(&ping(10, &["a", "b", "c", "d"]), 3)),
+            ["a", "b", "and 8 more"]
+        );
+    }
+
+    #[test]
+    fn test_server_image_scale() {
+        let options = ImageOptions {
+            title: "example.com".to_string(),
+            width: Some(400),
+            scale: Some(2),
+            ..Default::default()
+        };
+
+        let png = server_image(&options, ServerPing::default());
+        let image = image::load_from_memory(&png).unwrap();
+
+        assert_eq!((image.width(), image.height()), (800, DEFAULT_HEIGHT * 2));
+    }
+
+    #[test]
+    fn test_og_image() {
+        let options = ImageOptions {
+            title: "example.com".to_string(),
+            width: Some(250),
+            ..Default::default()
+        };
+
+        let image = render_og_image(&options, ServerPing::default());
+        assert_eq!(image.dimensions(), OG_IMAGE_SIZE);
+    }
+
+    #[test]
+    fn test_server_banner() {
+        let frame_count = |ping, show| {
+            let options = ImageOptions {
+                title: "example.com".to_string(),
+                show,
+                ..Default::default()
+            };
+
+            let gif = server_banner(&options, ping);
+            let decoder = image::codecs::gif::GifDecoder::new(gif.as_slice()).unwrap();
+            let frames = image::AnimationDecoder::into_frames(decoder)
+                .collect_frames()
+                .unwrap();
+
+            let buffer = frames[0].buffer();
+            assert_eq!(
+                (buffer.width(), buffer.height()),
+                (DEFAULT_WIDTH, HEADER_HEIGHT)
+            );
+
+            frames.len()
+        };
+
+        let online = ServerPing {
+            online: true,
+            ..Default::default()
+        };
+
+        assert_eq!(frame_count(online.clone(), Elements::default()), 3);
+        assert_eq!(frame_count(ServerPing::default(), Elements::default()), 1);
+
+        let show = Elements {
+            motd: false,
+            version: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(frame_count(online, show), 1);
+    }
+
+    #[test]
+    fn test_server_svg() {
+        let options = ImageOptions {
+            title: "<script>alert(1)</script>".to_string(),
+            scale: Some(2),
+            latency: true,
+            ..Default::default()
+        };
+
+        let ping = ServerPing {
+            online: true,
+            motd_json: serde_json::json!({
+                "text": "A ",
+                "extra": [{ "text": "bold", "bold": true, "color": "red" }],
+            }),
+            latency: Some(200),
+            ..Default::default()
+        };
+
+        let svg = server_svg(&options, ping);
+
+        assert!(svg.starts_with(r#"<svg xmlns="http://www.w3.org/2000/svg" width="650" height="200" viewBox="0 0 325 100""#));
+        assert!(svg.ends_with("</svg>"));
+        assert!(svg.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+        assert!(!svg.contains("<script>"));
+        assert!(svg.contains(r##"<tspan fill="#ff5555" font-weight="bold">bold</tspan>"##));
+        assert!(svg.contains(r#"text-anchor="end""#));
+        assert!(svg.contains("200ms</tspan></text>"));
+        assert!(svg.contains("data:image/png;base64,"));
+    }
+
+    #[test]
+    fn test_identicon() {
+        let icon = identicon("example.com");
+
+        assert_eq!(icon.dimensions(), (HEADER_HEIGHT, HEADER_HEIGHT));
+        assert_eq!(icon, identicon("example.com"));
+        assert_ne!(icon, identicon("example.org"));
+
+        // The pattern is mirrored around the middle column.
+        for (x, y, pixel) in icon.enumerate_pixels() {
+            assert_eq!(pixel, icon.get_pixel(HEADER_HEIGHT - 1 - x, y));
+        }
+    }
+
+    #[test]
+    fn test_decode_favicon() {
+        let data_uri =
+            |image: RgbaImage| format!("{}{}", FAVICON_PREFIX, base64::encode(encode_png(image)));
+
+        let icon = placeholder_icon();
+        assert_eq!(decode_favicon(&data_uri(icon.clone())).unwrap(), icon);
+
+        // Word wrapped favicons are still accepted.
+        let wrapped = data_uri(icon.clone())
+            .chars()
+            .enumerate()
+            .flat_map(|(index, c)| {
+                (index > 0 && index % 76 == 0)
+                    .then_some('\n')
+                    .into_iter()
+                    .chain([c])
+            })
+            .collect::<String>();
+        assert_eq!(decode_favicon(&wrapped).unwrap(), icon);
+
+        assert!(matches!(
+            decode_favicon("data:image/jpeg;base64,AAAA"),
+            Err(FaviconError::InvalidPrefix)
+        ));
+        assert!(matches!(
+            decode_favicon("data:image/png;base64,!!!!"),
+            Err(FaviconError::Base64(_))
+        ));
+        assert!(matches!(
+            decode_favicon(&format!(
+                "{}{}",
+                FAVICON_PREFIX,
+                "A".repeat(MAX_FAVICON_BYTES * 2)
+            )),
+            Err(FaviconError::TooManyBytes)
+        ));
+        assert!(matches!(
+            decode_favicon(&format!(
+                "{}{}",
+                FAVICON_PREFIX,
+                base64::encode(b"not a png")
+            )),
+            Err(FaviconError::Image(_))
+        ));
+        assert!(matches!(
+            decode_favicon(&data_uri(RgbaImage::new(MAX_FAVICON_SIZE + 1, 1))),
+            Err(FaviconError::TooLarge(513, 1))
+        ));
+    }
+
+    #[test]
+    fn test_validate_favicon() {
+        let png = include_bytes!("../static/assets/grass_sm.png");
+        let favicon = format!("{}{}", FAVICON_PREFIX, base64::encode(png));
+        assert_eq!(validate_favicon(&favicon).unwrap(), png);
+
+        assert!(matches!(
+            validate_favicon(&format!(
+                "{}{}",
+                FAVICON_PREFIX,
+                base64::encode(b"not a png")
+            )),
+            Err(FaviconError::Image(_))
+        ));
+        assert!(matches!(
+            validate_favicon(&format!(
+                "{}{}",
+                FAVICON_PREFIX,
+                base64::encode(encode_png(RgbaImage::new(1, MAX_FAVICON_SIZE + 1)))
+            )),
+            Err(FaviconError::TooLarge(1, 513))
+        ));
+    }
+
+    #[test]
+    fn test_favicon_image() {
+        let favicon = format!(
+            "data:image/png;base64,{}",
+            base64::encode(encode_png(placeholder_icon()))
+        );
+        assert_eq!(favicon_image(&Some(favicon)), Some(placeholder_icon()));
+
+        assert_eq!(favicon_image(&None), None);
+        assert_eq!(favicon_image(&Some("data:".to_string())), None);
+        assert_eq!(
+            favicon_image(&Some("data:image/png;base64,invalid".to_string())),
+            None
+        );
+    }
+
+    #[test]
+    fn test_escape_xml() {
+        assert_eq!(
+            escape_xml(r#"a & "b" <c> 'd'"#),
+            "a &amp; &quot;b&quot; &lt;c&gt; &apos;d&apos;"
+        );
+        assert_eq!(escape_xml("line\u{0}\u{1b}break"), "linebreak");
+    }
+
+    #[test]
+    fn test_cover_background() {
+        let mut icon = RgbaImage::from_pixel(64, 64, Rgba([255, 0, 0, 255]));
+        for y in 32..64 {
+            for x in 0..64 {
+                icon.put_pixel(x, y, Rgba([0, 0, 255, 255]));
+            }
+        }
+
+        let image = cover_background(&icon, 325, 100, Rgba([0, 0, 0, 255]));
+        assert_eq!(image.dimensions(), (325, 100));
+
+        // Tinted darker, and the icon is cropped from its center so both
+        // colors are still visible at the top and bottom.
+        let (top, bottom) = (image.get_pixel(160, 0), image.get_pixel(160, 99));
+        assert!(top[0] > top[2] && top[0] < 255, "{:?}", top);
+        assert!(bottom[2] > bottom[0] && bottom[2] < 255, "{:?}", bottom);
+        assert!(image.pixels().all(|pixel| pixel[3] == 255));
+    }
+
+    #[test]
+    fn test_raster_formats() {
+        let image = render_server_image(&ImageOptions::default(), ServerPing::default());
+
+        let jpeg = encode_jpeg(image.clone(), 50);
+        assert_eq!(
+            image::guess_format(&jpeg).unwrap(),
+            image::ImageFormat::Jpeg
+        );
+        assert!(jpeg.len() < encode_jpeg(image.clone(), 100).len());
+
+        let webp = image::load_from_memory(&encode_webp(image.clone())).unwrap();
+        assert_eq!(webp.into_rgba8(), image);
+    }
+
+    #[test]
+    fn test_negotiate_format() {
+        let browser = "image/avif,image/webp,image/apng,image/svg+xml,image/*,*/*;q=0.8";
+        assert_eq!(ImageFormat::negotiate(browser), Some(ImageFormat::Webp));
+        assert_eq!(
+            ImageFormat::negotiate("image/png, image/webp;q=0.5"),
+            Some(ImageFormat::Png)
+        );
+        assert_eq!(
+            ImageFormat::negotiate("image/jpeg, image/webp;q=0"),
+            Some(ImageFormat::Jpeg)
+        );
+        assert_eq!(ImageFormat::negotiate("image/*, */*;q=0.8"), None);
+        assert_eq!(ImageFormat::negotiate("image/gif"), None);
+    }
+
+    #[test]
+    fn test_load_fonts() {
+        let dir = std::env::temp_dir().join(format!("mcapi-fonts-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("Inconsolata.TTF"), FONT_DATA).unwrap();
+        std::fs::write(dir.join("readme.txt"), "not a font").unwrap();
+
+        let fonts = Fonts::load_dir(&dir).unwrap();
+        assert_eq!(fonts.names().collect::<Vec<_>>(), vec!["inconsolata"]);
+        assert!(fonts.get("Inconsolata").is_some());
+        assert!(fonts.get("comic-sans").is_none());
+
+        let fonts = fonts.with_fallbacks(&["inconsolata".to_string()]).unwrap();
+        assert_eq!(fonts.fallbacks().len(), 1);
+        assert!(matches!(
+            fonts.with_fallbacks(&["noto-emoji".to_string()]),
+            Err(FontError::Unknown(_))
+        ));
+
+        std::fs::write(dir.join("broken.otf"), "not a font").unwrap();
+        assert!(matches!(Fonts::load_dir(&dir), Err(FontError::Invalid(_))));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_locales_have_glyphs() {
+        let font = default_font();
+
+        for language in crate::locale::languages() {
+            let strings = Strings::get(language).unwrap();
+            let text = [
+                strings.online(1, 20),
+                strings.online_without_players(),
+                strings.offline(),
+                strings.updated(5),
+                strings.more_players(3),
+                strings.version("1.20"),
+            ]
+            .concat();
+
+            for c in text.chars() {
+                assert_ne!(font.glyph(c).id(), GlyphId(0), "{} in {}", c, language);
+            }
+        }
+    }
+
+    #[test]
+    fn test_player_spans() {
+        let fonts = [default_font()];
+        let scale = Scale::uniform(MOTD_TEXT_SIZE);
+        let strings = Strings::default_language();
+        let names = ["Notch", "§ajeb_", "Dinnerbone"];
+
+        let text =
+            |spans: Vec<Span>| -> String { spans.iter().map(|span| span.text.as_str()).collect() };
+
+        assert_eq!(
+            text(player_spans(&fonts, scale, strings, &names, 3, 500.0)),
+            "Notch, jeb_, Dinnerbone"
+        );
+        assert_eq!(
+            text(player_spans(&fonts, scale, strings, &names, 10, 500.0)),
+            "Notch, jeb_, Dinnerbone and 7 more"
+        );
+
+        let width = text_width(&fonts, scale, "Notch, jeb_ and 8 more");
+        assert_eq!(
+            text(player_spans(&fonts, scale, strings, &names, 10, width)),
+            "Notch, jeb_ and 8 more"
+        );
+        assert!(player_spans(&fonts, scale, strings, &names, 10, 10.0).is_empty());
+    }
+}
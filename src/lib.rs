@@ -0,0 +1,20 @@
+//! Pinging, querying, and rendering Minecraft servers.
+//!
+//! This contains everything needed to talk to servers without running the
+//! API itself. Addresses can be resolved with [`resolver::Resolver`], then
+//! servers pinged with [`protocol::send_ping`] or queried with
+//! [`protocol::send_query`]. Responses can be converted into the
+//! [`types::ServerPing`] and [`types::ServerQuery`] types returned by the API
+//! and rendered with [`image::server_image`].
+//!
+//! With the `blocking` feature, servers can also be pinged and queried from
+//! synchronous code with `protocol::send_ping_blocking` and
+//! `protocol::send_query_blocking`.
+
+pub mod image;
+pub mod locale;
+pub mod motd;
+pub mod protocol;
+pub mod resolver;
+pub mod socks5;
+pub mod types;
@@ -0,0 +1,254 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::header::{CacheControl, CacheDirective, RETRY_AFTER},
+    web, HttpResponse,
+};
+use futures::future::LocalBoxFuture;
+use lazy_static::lazy_static;
+use prometheus::{
+    register_counter_vec, register_int_gauge, register_int_gauge_vec, CounterVec, IntGauge,
+    IntGaugeVec,
+};
+
+use crate::config::Config;
+
+/// Paths that are always served so the instance can still be monitored while
+/// overloaded.
+pub const EXEMPT_PATHS: &[&str] = &["/health", "/health/ready", "/metrics", "/version"];
+
+/// Paths that hold requests open while waiting for changes, which are not
+/// counted as in flight since they are mostly idle.
+pub const LONG_POLL_PATHS: &[&str] = &["/server/wait"];
+
+lazy_static! {
+    static ref REQUESTS_SHED: CounterVec = register_counter_vec!(
+        "mcapi_requests_shed_total",
+        "Number of requests rejected because the instance was overloaded by reason, either in_flight or queue",
+        &["reason"]
+    )
+    .unwrap();
+    static ref IN_FLIGHT_REQUESTS: IntGauge = register_int_gauge!(
+        "mcapi_requests_in_flight",
+        "Number of requests being handled, including ones still finishing after their client disconnected"
+    )
+    .unwrap();
+    static ref LOCK_WAITS: IntGauge = register_int_gauge!(
+        "mcapi_cache_lock_waits_in_progress",
+        "Number of refreshes waiting for another refresh of the same key to release its lock"
+    )
+    .unwrap();
+    static ref REFRESHES_IN_FLIGHT: IntGaugeVec = register_int_gauge_vec!(
+        "mcapi_refreshes_in_flight",
+        "Number of servers being checked, including through an upstream instance",
+        &["method"]
+    )
+    .unwrap();
+}
+
+/// Requests currently being handled.
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+/// Refreshes currently waiting for another refresh of the same key to release
+/// its lock.
+static QUEUED: AtomicUsize = AtomicUsize::new(0);
+
+/// Holds a place in a count and the gauge reporting it until dropped.
+struct CountGuard {
+    count: &'static AtomicUsize,
+    gauge: &'static IntGauge,
+}
+
+impl CountGuard {
+    /// Add to the count, returning the guard and the new count.
+    fn enter(count: &'static AtomicUsize, gauge: &'static IntGauge) -> (Self, usize) {
+        let current = count.fetch_add(1, Ordering::SeqCst) + 1;
+        gauge.inc();
+
+        (Self { count, gauge }, current)
+    }
+}
+
+impl Drop for CountGuard {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::SeqCst);
+        self.gauge.dec();
+    }
+}
+
+/// Marks a refresh as waiting for a lock until dropped.
+pub struct QueueGuard {
+    _count: CountGuard,
+}
+
+impl QueueGuard {
+    pub fn enter() -> Self {
+        Self {
+            _count: CountGuard::enter(&QUEUED, &LOCK_WAITS).0,
+        }
+    }
+}
+
+/// Marks a server as being checked until dropped.
+pub struct RefreshGuard {
+    gauge: IntGauge,
+}
+
+impl RefreshGuard {
+    pub fn enter(method: &str) -> Self {
+        let gauge = REFRESHES_IN_FLIGHT.with_label_values(&[method]);
+        gauge.inc();
+
+        Self { gauge }
+    }
+}
+
+impl Drop for RefreshGuard {
+    fn drop(&mut self) {
+        self.gauge.dec();
+    }
+}
+
+/// Determine if a request should be rejected, given the number of requests in
+/// flight including it, the number of queued refreshes, and the limits where 0
+/// is unlimited.
+fn shed_reason(
+    in_flight: usize,
+    queued: usize,
+    max_in_flight: usize,
+    max_queued: usize,
+) -> Option<&'static str> {
+    if max_in_flight > 0 && in_flight > max_in_flight {
+        Some("in_flight")
+    } else if max_queued > 0 && queued >= max_queued {
+        Some("queue")
+    } else {
+        None
+    }
+}
+
+/// Middleware to reject requests with a 503 error while too many requests are
+/// in flight or too many refreshes are waiting for locks, instead of letting
+/// every request get slower.
+///
+/// It must be inside the deadline middleware so requests that keep running
+/// after the client disconnects are still counted as in flight.
+pub fn middleware<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> LocalBoxFuture<'static, Result<ServiceResponse<BoxBody>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    let (max_in_flight, max_queued, retry_after) = req
+        .app_data::<web::Data<Config>>()
+        .map(|config| {
+            (
+                config.max_in_flight_requests,
+                config.max_queued_refreshes,
+                config.load_shed_retry_after,
+            )
+        })
+        .unwrap_or_default();
+
+    if LONG_POLL_PATHS.contains(&req.path()) {
+        let fut = srv.call(req);
+        return Box::pin(async move { fut.await.map(ServiceResponse::map_into_boxed_body) });
+    }
+
+    let exempt = EXEMPT_PATHS.contains(&req.path());
+    let (guard, in_flight) = CountGuard::enter(&IN_FLIGHT, &IN_FLIGHT_REQUESTS);
+    let queued = QUEUED.load(Ordering::SeqCst);
+
+    let call = match shed_reason(in_flight, queued, max_in_flight, max_queued) {
+        Some(reason) if !exempt => {
+            drop(guard);
+
+            tracing::debug!(in_flight, queued, "shedding request for {}", reason);
+            REQUESTS_SHED.with_label_values(&[reason]).inc();
+
+            let res = HttpResponse::ServiceUnavailable()
+                .insert_header((RETRY_AFTER, retry_after))
+                .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+                .json(serde_json::json!({
+                    "status": "error",
+                    "error": "too many requests are in progress, try again later",
+                }));
+
+            Err(req.into_response(res))
+        }
+        _ => Ok((guard, srv.call(req))),
+    };
+
+    Box::pin(async move {
+        match call {
+            Ok((_guard, fut)) => fut.await.map(ServiceResponse::map_into_boxed_body),
+            Err(res) => Ok(res),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{call_and_read_body, init_service, TestRequest};
+
+    use super::*;
+
+    #[test]
+    fn test_guards() {
+        let waiting = LOCK_WAITS.get();
+
+        let queued = QueueGuard::enter();
+        assert_eq!(LOCK_WAITS.get(), waiting + 1);
+        drop(queued);
+        assert_eq!(LOCK_WAITS.get(), waiting);
+
+        let refresh = RefreshGuard::enter("test");
+        assert_eq!(REFRESHES_IN_FLIGHT.with_label_values(&["test"]).get(), 1);
+        drop(refresh);
+        assert_eq!(REFRESHES_IN_FLIGHT.with_label_values(&["test"]).get(), 0);
+    }
+
+    #[test]
+    fn test_shed_reason() {
+        assert_eq!(shed_reason(500, 500, 0, 0), None);
+
+        assert_eq!(shed_reason(10, 0, 10, 0), None);
+        assert_eq!(shed_reason(11, 0, 10, 0), Some("in_flight"));
+
+        assert_eq!(shed_reason(1, 4, 10, 5), None);
+        assert_eq!(shed_reason(1, 5, 10, 5), Some("queue"));
+        assert_eq!(shed_reason(11, 5, 10, 5), Some("in_flight"));
+    }
+
+    #[tokio::test]
+    async fn test_long_poll_path() {
+        let app = init_service(
+            actix_web::App::new()
+                .app_data(web::Data::new(Config {
+                    max_in_flight_requests: 1,
+                    ..Default::default()
+                }))
+                .wrap_fn(middleware)
+                .wrap_fn(crate::path_routes::middleware)
+                .default_service(web::to(|| async {
+                    HttpResponse::Ok().body(IN_FLIGHT.load(Ordering::SeqCst).to_string())
+                })),
+        )
+        .await;
+
+        let req = TestRequest::get()
+            .uri("/server/example.com/wait")
+            .to_request();
+        assert_eq!(call_and_read_body(&app, req).await, "0");
+
+        let req = TestRequest::get()
+            .uri("/server/example.com/status")
+            .to_request();
+        assert_eq!(call_and_read_body(&app, req).await, "1");
+    }
+}
@@ -0,0 +1,200 @@
+//! Translations of text drawn on images and of error messages.
+//!
+//! Translations are bundled from `static/locales`, with one JSON file for each
+//! language. Placeholders in braces are replaced when formatting.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Deserialize;
+
+/// Language used when no other language is requested or available.
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+/// Bundled translation files, by language.
+const LOCALE_FILES: &[(&str, &str)] = &[
+    ("de", include_str!("../static/locales/de.json")),
+    ("en", include_str!("../static/locales/en.json")),
+    ("es", include_str!("../static/locales/es.json")),
+    ("fr", include_str!("../static/locales/fr.json")),
+    ("it", include_str!("../static/locales/it.json")),
+    ("nl", include_str!("../static/locales/nl.json")),
+    ("pl", include_str!("../static/locales/pl.json")),
+    ("pt", include_str!("../static/locales/pt.json")),
+];
+
+lazy_static::lazy_static! {
+    static ref LOCALES: BTreeMap<&'static str, Strings> = LOCALE_FILES
+        .iter()
+        .map(|(language, data)| (*language, serde_json::from_str(data).unwrap()))
+        .collect();
+}
+
+/// Text drawn on images and error messages in a single language.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Strings {
+    /// Status of an online server, with `{now}` and `{max}` players.
+    online: String,
+    /// Status of an online server, when players are hidden.
+    online_without_players: String,
+    /// Status of an offline server.
+    offline: String,
+    /// When data was updated, with `{mins}` since the update.
+    updated: String,
+    /// Players left out of a list, with the `{count}` of players.
+    more_players: String,
+    /// Version of a server, with the version `{name}`.
+    version: String,
+    /// Error messages, keyed by the English message.
+    #[serde(default)]
+    errors: HashMap<String, String>,
+}
+
+impl Strings {
+    /// Get the strings for a language, if it has been translated.
+    pub fn get(language: &str) -> Option<&'static Self> {
+        LOCALES.get(language.to_lowercase().as_str())
+    }
+
+    /// Get the strings for the default language.
+    pub fn default_language() -> &'static Self {
+        &LOCALES[DEFAULT_LANGUAGE]
+    }
+
+    pub fn online(&self, now: i32, max: i32) -> String {
+        self.online
+            .replace("{now}", &now.to_string())
+            .replace("{max}", &max.to_string())
+    }
+
+    pub fn online_without_players(&self) -> String {
+        self.online_without_players.clone()
+    }
+
+    pub fn offline(&self) -> String {
+        self.offline.clone()
+    }
+
+    pub fn updated(&self, mins: u64) -> String {
+        self.updated.replace("{mins}", &mins.to_string())
+    }
+
+    pub fn more_players(&self, count: usize) -> String {
+        self.more_players.replace("{count}", &count.to_string())
+    }
+
+    pub fn version(&self, name: &str) -> String {
+        self.version.replace("{name}", name)
+    }
+
+    /// Translate an English error message, if it has been translated.
+    ///
+    /// Messages with details after a colon, like `timeout: deadline has
+    /// elapsed`, only have the part before the colon translated.
+    pub fn error(&self, message: &str) -> Option<String> {
+        if let Some(translated) = self.errors.get(message) {
+            return Some(translated.clone());
+        }
+
+        let (prefix, details) = message.split_once(": ")?;
+        self.errors
+            .get(prefix)
+            .map(|translated| format!("{}: {}", translated, details))
+    }
+}
+
+/// All languages with translations, in order.
+pub fn languages() -> impl Iterator<Item = &'static str> {
+    LOCALES.keys().copied()
+}
+
+/// Pick the most preferred translated language from an `Accept-Language`
+/// header.
+///
+/// Regional variants match their base language, so `pt-BR` uses `pt`.
+pub fn negotiate(accept_language: &str) -> Option<&'static str> {
+    let mut preferences: Vec<(&str, f32)> = accept_language
+        .split(',')
+        .filter_map(|preference| {
+            let mut parts = preference.split(';');
+            let tag = parts.next()?.trim();
+
+            let quality = parts
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|quality| quality.parse().ok())
+                .unwrap_or(1.0);
+
+            (!tag.is_empty() && quality > 0.0).then_some((tag, quality))
+        })
+        .collect();
+
+    // Sorting is stable, so equal preferences keep the order they were sent.
+    preferences.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    preferences.into_iter().find_map(|(tag, _quality)| {
+        let base = tag.split('-').next().unwrap_or(tag).to_lowercase();
+
+        LOCALES
+            .get_key_value(base.as_str())
+            .map(|(language, _strings)| *language)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locales() {
+        for language in languages() {
+            let strings = Strings::get(language).unwrap();
+            assert!(strings.online(1, 20).contains("1/20"), "{}", language);
+            assert!(strings.updated(5).contains('5'), "{}", language);
+            assert!(strings.more_players(3).contains('3'), "{}", language);
+            assert!(strings.version("1.20").contains("1.20"), "{}", language);
+        }
+
+        assert_eq!(
+            Strings::default_language().online(2, 10),
+            "Online! 2/10 players"
+        );
+        assert!(Strings::get("DE").is_some());
+        assert!(Strings::get("xx").is_none());
+    }
+
+    #[test]
+    fn test_errors() {
+        let english = Strings::default_language();
+
+        for language in languages() {
+            let strings = Strings::get(language).unwrap();
+            for message in english.errors.keys() {
+                assert!(
+                    strings.errors.contains_key(message),
+                    "{} in {}",
+                    message,
+                    language
+                );
+            }
+        }
+
+        let german = Strings::get("de").unwrap();
+        assert_eq!(
+            german.error("could not resolve host").as_deref(),
+            Some("Host konnte nicht aufgelöst werden")
+        );
+        assert_eq!(
+            german.error("timeout: deadline has elapsed").as_deref(),
+            Some("Zeitüberschreitung: deadline has elapsed")
+        );
+        assert_eq!(german.error("something else"), None);
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate("de-DE,de;q=0.9,en;q=0.8"), Some("de"));
+        assert_eq!(negotiate("ja, pt-BR;q=0.5, en;q=0.7"), Some("en"));
+        assert_eq!(negotiate("fr;q=0, es"), Some("es"));
+        assert_eq!(negotiate("ja, *;q=0.1"), None);
+        assert_eq!(negotiate(""), None);
+    }
+}
@@ -0,0 +1,175 @@
+use actix_web::{
+    body::{self, BodySize, BoxBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::header::{self, HeaderValue},
+    web,
+};
+use futures::future::LocalBoxFuture;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use mcapi_rs::locale::{self, Strings};
+
+/// Largest response body to translate. Error responses are small, so larger
+/// bodies are passed through without being parsed.
+const MAX_BODY_SIZE: u64 = 64 * 1024;
+
+#[derive(Deserialize)]
+struct RawQuery {
+    #[serde(default)]
+    raw: bool,
+}
+
+/// Translate an object's `error` string, returning if it was translated.
+fn translate_error(object: &mut Map<String, Value>, strings: &Strings) -> bool {
+    match object.get_mut("error") {
+        Some(Value::String(message)) => match strings.error(message) {
+            Some(translation) => {
+                *message = translation;
+                true
+            }
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+/// Translate the error in a JSON response, returning if it was translated.
+///
+/// Only the top-level `error` is translated, or the `error` of each server
+/// when the response is keyed by server, so values from servers themselves
+/// are never changed.
+fn translate_errors(value: &mut Value, strings: &Strings) -> bool {
+    let object = match value {
+        Value::Object(object) => object,
+        _ => return false,
+    };
+
+    if object.contains_key("error") {
+        return translate_error(object, strings);
+    }
+
+    object
+        .values_mut()
+        .filter_map(Value::as_object_mut)
+        .fold(false, |translated, server| {
+            translate_error(server, strings) | translated
+        })
+}
+
+/// Middleware to translate error messages in JSON responses into the language
+/// preferred by the `Accept-Language` header.
+///
+/// JSON responses always include `Vary: Accept-Language`, even when nothing
+/// was translated. Raw responses and bodies larger than [`MAX_BODY_SIZE`] are
+/// never translated.
+pub fn middleware<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> LocalBoxFuture<'static, Result<ServiceResponse<BoxBody>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    let raw = web::Query::<RawQuery>::from_query(req.query_string())
+        .map(|query| query.raw)
+        .unwrap_or(false);
+
+    let strings = req
+        .headers()
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(locale::negotiate)
+        .filter(|language| *language != locale::DEFAULT_LANGUAGE)
+        .and_then(Strings::get);
+
+    let fut = srv.call(req);
+
+    Box::pin(async move {
+        let mut res = fut.await?;
+
+        let is_json = res
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.starts_with("application/json"))
+            .unwrap_or(false);
+        if !is_json {
+            return Ok(res.map_into_boxed_body());
+        }
+
+        res.headers_mut()
+            .append(header::VARY, HeaderValue::from_static("Accept-Language"));
+
+        let strings = match strings {
+            Some(strings) if !raw => strings,
+            _ => return Ok(res.map_into_boxed_body()),
+        };
+
+        match res.response().body().size() {
+            BodySize::Sized(size) if size <= MAX_BODY_SIZE => (),
+            _ => return Ok(res.map_into_boxed_body()),
+        }
+
+        let (req, res) = res.into_parts();
+        let (res, body) = res.into_parts();
+        let body = body::to_bytes(body).await.map_err(|err| {
+            let err: Box<dyn std::error::Error> = err.into();
+            actix_web::error::ErrorInternalServerError(err)
+        })?;
+
+        let mut value = match serde_json::from_slice::<Value>(&body) {
+            Ok(value) => value,
+            Err(_err) => return Ok(ServiceResponse::new(req, res.set_body(BoxBody::new(body)))),
+        };
+
+        let res = if translate_errors(&mut value, strings) {
+            res.set_body(BoxBody::new(serde_json::to_vec(&value)?))
+        } else {
+            res.set_body(BoxBody::new(body))
+        };
+
+        Ok(ServiceResponse::new(req, res))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translate_errors() {
+        let strings = Strings::get("de").unwrap();
+
+        let mut value = serde_json::json!({
+            "status": "error",
+            "error": "this endpoint has been disabled",
+        });
+        assert!(translate_errors(&mut value, strings));
+        assert_eq!(value["error"], "dieser Endpunkt wurde deaktiviert");
+
+        // Each server in a multi status response has its own error.
+        let mut value = serde_json::json!({
+            "a.example.com": { "online": false, "error": "could not resolve host" },
+            "b.example.com": { "online": true, "error": null },
+        });
+        assert!(translate_errors(&mut value, strings));
+        assert_eq!(
+            value["a.example.com"]["error"],
+            "Host konnte nicht aufgelöst werden"
+        );
+
+        let mut value = serde_json::json!({ "error": "not a known message" });
+        assert!(!translate_errors(&mut value, strings));
+
+        // Errors inside the server's own data are left alone.
+        let mut value = serde_json::json!({
+            "online": true,
+            "error": null,
+            "raw": { "error": "this endpoint has been disabled" },
+        });
+        assert!(!translate_errors(&mut value, strings));
+        assert_eq!(value["raw"]["error"], "this endpoint has been disabled");
+    }
+}
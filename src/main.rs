@@ -1,33 +1,90 @@
 use std::{
+    collections::{BTreeMap, HashSet},
     future::Future,
-    net::SocketAddr,
+    sync::Arc,
     time::{Duration, Instant},
 };
 
 use actix_cors::Cors;
+use actix_http::{error::DispatchError, HttpService, Protocol};
+use actix_service::{fn_service, map_config, ServiceFactoryExt};
 use actix_web::{
+    dev::{AppConfig, HttpServiceFactory},
     get,
-    http::header::{CacheControl, CacheDirective, ContentType},
-    web, App, HttpResponse, HttpServer, Responder,
+    http::{
+        header::{self, CacheControl, CacheDirective, ContentType},
+        Method,
+    },
+    route,
+    rt::net::TcpStream,
+    web, App, FromRequest, HttpRequest, HttpResponse, HttpServer, Responder,
 };
+use futures_retry::FutureRetry;
 use lazy_static::lazy_static;
-use prometheus::{register_counter_vec, register_histogram_vec, CounterVec, HistogramVec};
-use redis::{AsyncCommands, Client as RedisClient};
-use redlock::RedLock;
+use prometheus::{
+    register_counter_vec, register_gauge_vec, register_histogram_vec, CounterVec, GaugeVec,
+    HistogramVec,
+};
 use tokio::time::timeout;
 use tracing_actix_web::TracingLogger;
+use utoipa::{IntoParams, ToSchema};
+
+use mcapi_rs::{
+    image, locale,
+    motd::Color,
+    protocol,
+    resolver::{self, Resolver},
+    types::{self, Error},
+};
 
-use resolver::Resolver;
-use types::Error;
-
-const TIMEOUT_DURATION: Duration = Duration::from_secs(5);
-const MAX_AGE: u32 = 60 * 5;
-const MAX_STALE_AGE: u32 = 60;
-
-mod image;
-mod protocol;
-mod resolver;
-mod types;
+use cache::CacheStore;
+use config::{Config, MissingIcon, ThemePreset};
+use events::Events;
+use monitor::Monitor;
+use render_pool::{RenderError, RenderPool};
+use stats::Leaderboard;
+
+const MAX_METRIC_HOSTS: usize = 100;
+const MAX_TOP_SERVERS: usize = 100;
+
+mod admin;
+mod alerts;
+mod ban;
+mod cache;
+mod changes;
+mod client_ip;
+mod config;
+mod dashboard;
+mod database;
+mod deadline;
+mod events;
+mod history;
+mod load_shed;
+mod localize;
+mod monitor;
+mod openapi;
+mod openmetrics;
+mod path_routes;
+mod proxy_protocol;
+mod rate_limit;
+mod render_pool;
+mod request_id;
+mod runtime;
+mod signing;
+mod slow_log;
+mod stats;
+mod statsd;
+mod status_page;
+mod telemetry;
+mod tls;
+mod upstream;
+mod usage;
+mod wait;
+mod warmup;
+
+/// Name of the histogram of total request durations, also needed to attach
+/// exemplars to it.
+const REQUEST_DURATION_NAME: &str = "mcapi_request_duration_seconds";
 
 lazy_static! {
     static ref UPDATE_DURATION: HistogramVec = register_histogram_vec!(
@@ -37,7 +94,7 @@ lazy_static! {
     )
     .unwrap();
     static ref REQUEST_DURATION: HistogramVec = register_histogram_vec!(
-        "mcapi_request_duration_seconds",
+        REQUEST_DURATION_NAME,
         "Total duration for a request",
         &["method"]
     )
@@ -54,8 +111,65 @@ lazy_static! {
         &["method"]
     )
     .unwrap();
+    static ref CACHE_LOOKUPS: CounterVec = register_counter_vec!(
+        "mcapi_cache_lookups_total",
+        "Number of cache lookups by result, one of hit, miss, or stale",
+        &["method", "result"]
+    )
+    .unwrap();
+    static ref CACHE_LOCK_WAITS: CounterVec = register_counter_vec!(
+        "mcapi_cache_lock_waits_total",
+        "Number of times a lock could not be immediately obtained",
+        &["method"]
+    )
+    .unwrap();
+    static ref CACHE_STALE_RESPONSES: CounterVec = register_counter_vec!(
+        "mcapi_cache_stale_responses_total",
+        "Number of times stale data was used because a lock could not be obtained",
+        &["method"]
+    )
+    .unwrap();
+    static ref CACHE_REFRESH_FAILURES: CounterVec = register_counter_vec!(
+        "mcapi_cache_refresh_failures_total",
+        "Number of times data could not be refreshed",
+        &["method"]
+    )
+    .unwrap();
+    static ref HOST_ONLINE: GaugeVec = register_gauge_vec!(
+        "mcapi_host_online",
+        "If an allowed host was online when last checked",
+        &["method", "host"]
+    )
+    .unwrap();
+    static ref HOST_PLAYERS: GaugeVec = register_gauge_vec!(
+        "mcapi_host_players",
+        "Number of players on an allowed host when last checked",
+        &["method", "host"]
+    )
+    .unwrap();
+    static ref HOST_UPDATE_DURATION: GaugeVec = register_gauge_vec!(
+        "mcapi_host_update_duration_seconds",
+        "Duration of the last update for an allowed host",
+        &["method", "host"]
+    )
+    .unwrap();
+    static ref BUILD_INFO: GaugeVec = register_gauge_vec!(
+        "mcapi_build_info",
+        "Always 1, labeled with the version and commit of the running build",
+        &["version", "commit"]
+    )
+    .unwrap();
 }
 
+/// Version of the crate being built.
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Commit the running build was made from, or `unknown`.
+const GIT_COMMIT: &str = env!("MCAPI_GIT_COMMIT");
+
+/// Unix timestamp of when the running build was made.
+const BUILT_AT: &str = env!("MCAPI_BUILT_AT");
+
 trait ServerAddr {
     fn host(&self) -> &str;
     fn port(&self) -> Option<u16>;
@@ -75,11 +189,91 @@ trait ServerAddr {
     }
 }
 
-#[derive(Debug, serde::Deserialize)]
+/// Hosts to emit individual metrics for.
+///
+/// Only a limited number of hosts are allowed to keep label cardinality
+/// bounded.
+#[derive(Debug, Default)]
+struct HostMetrics {
+    hosts: HashSet<String>,
+}
+
+impl HostMetrics {
+    /// Create allowed hosts from a list of `host:port` entries.
+    fn new(hosts: &[String]) -> Self {
+        let hosts: Vec<String> = hosts.iter().map(|host| resolver::entry_key(host)).collect();
+
+        if hosts.len() > MAX_METRIC_HOSTS {
+            tracing::warn!(
+                "too many metrics hosts, only using first {} of {}",
+                MAX_METRIC_HOSTS,
+                hosts.len()
+            );
+        }
+
+        Self {
+            hosts: hosts.into_iter().take(MAX_METRIC_HOSTS).collect(),
+        }
+    }
+
+    /// Update metrics for a host, if it is allowed.
+    fn observe<D: types::Metadata>(&self, host: &str, port: u16, data: &D) {
+        let host = resolver::server_key(host, port);
+        if !self.hosts.contains(&host) {
+            return;
+        }
+
+        let labels = [D::NAME, host.as_str()];
+
+        HOST_ONLINE
+            .with_label_values(&labels)
+            .set(if data.is_online() { 1.0 } else { 0.0 });
+        HOST_PLAYERS
+            .with_label_values(&labels)
+            .set(data.players_online() as f64);
+        HOST_UPDATE_DURATION
+            .with_label_values(&labels)
+            .set(Duration::from_nanos(data.duration()).as_secs_f64());
+    }
+}
+
+/// Parameters for a request, from the query string or from a JSON body for
+/// `POST` requests.
+struct Lookup<T>(T);
+
+impl<T: serde::de::DeserializeOwned + 'static> FromRequest for Lookup<T> {
+    type Error = actix_web::Error;
+    type Future = futures::future::LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut actix_http::Payload) -> Self::Future {
+        if req.method() == Method::POST {
+            let json = web::Json::<T>::from_request(req, payload);
+            Box::pin(async move { json.await.map(|json| Lookup(json.into_inner())) })
+        } else {
+            let query = web::Query::<T>::from_request(req, payload);
+            Box::pin(async move { query.await.map(|query| Lookup(query.into_inner())) })
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct ServerRequest {
+    /// Address of the server, which may include the port.
     #[serde(rename = "ip")]
     pub host: String,
+    /// Port of the server, defaults to 25565.
     pub port: Option<u16>,
+
+    /// Include where the server was found in the response.
+    #[serde(default)]
+    pub resolved: bool,
+    /// Include the status exactly as the server sent it in the response.
+    #[serde(default)]
+    pub raw: bool,
+    /// Connect to the host itself without looking up its SRV record.
+    #[serde(default)]
+    pub no_srv: bool,
 }
 
 impl ServerAddr for ServerRequest {
@@ -92,161 +286,1661 @@ impl ServerAddr for ServerRequest {
     }
 }
 
-#[derive(Debug, serde::Deserialize)]
+#[derive(Clone, Debug, Hash, serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct ServerImageRequest {
+    /// Address of the server, which may include the port.
+    #[serde(rename = "ip")]
+    pub host: String,
+    /// Port of the server, defaults to 25565.
+    pub port: Option<u16>,
+
+    /// First line of text, defaults to the address. Placeholders like
+    /// `{online}`, `{max}`, and `{version}` are replaced with the server's
+    /// status.
+    pub title: Option<String>,
+    /// Either `light`, `dark`, or the name of a theme from the config.
+    pub theme: Option<String>,
+    /// Either `flat` or `cover`, which blurs the icon for the background.
+    #[param(value_type = Option<String>)]
+    pub style: Option<image::ImageStyle>,
+    /// One of `wide`, `compact`, `square`, or `tall`.
+    #[param(value_type = Option<String>)]
+    pub layout: Option<image::LayoutPreset>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Multiplier for the size of the image, for high density displays.
+    pub scale: Option<u32>,
+    /// Background color as hex.
+    #[param(value_type = Option<String>)]
+    pub bg: Option<Color>,
+    /// Text color as hex.
+    #[param(value_type = Option<String>)]
+    pub fg: Option<Color>,
+    /// Status line color as hex.
+    #[param(value_type = Option<String>)]
+    pub accent: Option<Color>,
+    /// List names of players online.
+    #[serde(default)]
+    pub players: bool,
+    /// Show the server's latency.
+    #[serde(default)]
+    pub latency: bool,
+    pub show_icon: Option<bool>,
+    pub show_players: Option<bool>,
+    pub show_version: Option<bool>,
+    pub show_motd: Option<bool>,
+    /// Name of a font loaded by the server.
+    pub font: Option<String>,
+    /// Language of text on the image, defaults to the browser's language.
+    pub lang: Option<String>,
+    /// One of `png`, `gif`, `svg`, `webp`, or `jpeg`.
+    #[param(value_type = Option<String>)]
+    pub format: Option<image::ImageFormat>,
+    /// Quality of JPEG images, from 1 to 100.
+    pub quality: Option<u8>,
+}
+
+impl ServerImageRequest {
+    /// Options for the image, using the address as the title if none was
+    /// provided and values from the theme for anything not requested.
+    fn options(&self, theme: &ThemePreset) -> image::ImageOptions {
+        image::ImageOptions {
+            title: self.title(),
+            theme: theme.base,
+            style: self.style.or(theme.style).unwrap_or_default(),
+            layout: self.layout.or(theme.layout).unwrap_or_default(),
+            width: self.width,
+            height: self.height,
+            scale: self.scale,
+            background: self.bg.or(theme.background),
+            foreground: self.fg.or(theme.foreground),
+            accent: self.accent.or(theme.accent),
+            players: self.players,
+            latency: self.latency,
+            show: image::Elements {
+                icon: self.show_icon.unwrap_or(true),
+                players: self.show_players.unwrap_or(true),
+                motd: self.show_motd.unwrap_or(true),
+                version: self.show_version,
+            },
+            font: None,
+            fallback_fonts: Vec::new(),
+            language: None,
+            offline: Default::default(),
+        }
+    }
+
+    /// Title of the image, before placeholders are replaced.
+    fn title(&self) -> String {
+        if let Some(title) = &self.title {
+            title.to_owned()
+        } else if let Some(port) = self.port {
+            format!("{}:{}", self.host, port)
+        } else {
+            self.host.to_owned()
+        }
+    }
+
+    /// If the rendered image may be cached.
+    ///
+    /// Titles and colors can be anything, so a client could create any number
+    /// of images for an online server with them. Those images are rendered
+    /// each time instead. Images of offline servers are shared by every server
+    /// with the same options, so they are always cached.
+    fn cacheable(&self, data: &types::ServerPing) -> bool {
+        !data.online
+            || (self.title.is_none()
+                && self.bg.is_none()
+                && self.fg.is_none()
+                && self.accent.is_none())
+    }
+}
+
+/// Fields [`Cached`] adds to the data in a response.
+const CACHED_FIELDS: &[&str] = &["cached", "stale", "age_seconds", "expires_in"];
+
+/// Data for a response, with when it was checked and how long it will be
+/// cached.
+#[derive(Debug, serde::Serialize, ToSchema)]
+#[aliases(
+    CachedServerPing = Cached<types::ServerPing>,
+    CachedServerQuery = Cached<types::ServerQuery>
+)]
+struct Cached<D> {
+    #[serde(flatten)]
+    data: D,
+    /// If the data was checked before this request was made.
+    cached: bool,
+    /// If the data is older than the max age, because it was being refreshed
+    /// by another request.
+    stale: bool,
+    /// Seconds since the data was checked.
+    age_seconds: u64,
+    /// Seconds until the data will be checked again.
+    expires_in: u64,
+}
+
+impl<D: types::Metadata> Cached<D> {
+    /// Describe data that is checked again after `max_age` seconds, where
+    /// `checked` is if it was checked for this request.
+    fn new(data: D, checked: bool, max_age: u32) -> Self {
+        let age_seconds = unix_timestamp().saturating_sub(data.updated_at());
+
+        Self {
+            cached: !checked,
+            stale: age_seconds > u64::from(max_age),
+            age_seconds,
+            expires_in: u64::from(max_age).saturating_sub(age_seconds),
+            data,
+        }
+    }
+
+    /// Seconds until data for an offline server is checked again, for the
+    /// `Retry-After` header.
+    fn retry_after(&self) -> Option<u64> {
+        (!self.data.is_online()).then(|| self.expires_in.max(1))
+    }
+}
+
+/// Response from `/server/status`, which is keyed by each address when a
+/// comma separated list of servers was requested.
+#[derive(Debug, serde::Serialize, ToSchema)]
+#[serde(untagged)]
+enum StatusResponse {
+    Server(CachedServerPing),
+    Servers(BTreeMap<String, CachedServerPing>),
+}
+
+#[derive(Debug, serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ServerIconRequest {
+    /// Address of the server, which may include the port.
     #[serde(rename = "ip")]
     pub host: String,
+    /// Port of the server, defaults to 25565.
     pub port: Option<u16>,
 
-    pub title: Option<String>,
-    pub theme: Option<image::Theme>,
+    /// Return the favicon exactly as the server sent it.
+    #[serde(default)]
+    pub raw: bool,
+}
+
+impl ServerAddr for ServerIconRequest {
+    fn host(&self) -> &str {
+        &self.host
+    }
+
+    fn port(&self) -> Option<u16> {
+        self.port
+    }
+}
+
+impl ServerAddr for ServerImageRequest {
+    fn host(&self) -> &str {
+        &self.host
+    }
+
+    fn port(&self) -> Option<u16> {
+        self.port
+    }
+}
+
+/// Get the status of a server from a ping, like the server list.
+#[utoipa::path(
+    get,
+    path = "/server/status",
+    tag = "server",
+    params(ServerRequest),
+    responses((status = 200, description = "Status of the server, with `status` set to `error` if it could not be reached, or of each server when `ip` is a comma separated list", body = StatusResponse))
+)]
+#[route("/server/status", method = "GET", method = "POST")]
+async fn server_status(
+    resolver: web::Data<Resolver>,
+    cache: web::Data<dyn CacheStore>,
+    config: web::Data<Config>,
+    monitor: web::Data<Monitor>,
+    host_metrics: web::Data<HostMetrics>,
+    leaderboard: web::Data<Leaderboard>,
+    Lookup(addr): Lookup<ServerRequest>,
+) -> impl Responder {
+    let _timer = openmetrics::HistogramTimer::new(
+        &REQUEST_DURATION,
+        REQUEST_DURATION_NAME,
+        &[("method", "ping")],
+    );
+
+    // A list of servers responds with an object of each server's status.
+    if addr.host.contains(',') {
+        let hosts = split_hosts(&addr.host);
+        if hosts.len() > config.max_hosts_per_request {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "status": "error",
+                "error": format!("too many servers, at most {} are allowed", config.max_hosts_per_request),
+            }));
+        }
+
+        tracing::info!(
+            "attempting to get server status for {} servers",
+            hosts.len()
+        );
+
+        let statuses = hosts.into_iter().map(|host| {
+            let addr = ServerRequest {
+                host: host.to_string(),
+                ..addr
+            };
+
+            let (cache, resolver, config) = (&cache, &resolver, &config);
+            let (monitor, host_metrics, leaderboard) = (&monitor, &host_metrics, &leaderboard);
+
+            async move {
+                let (host, port) = addr.parse_host();
+                leaderboard.record(cache, host, port);
+                stats::record_lookup(config, cache, host, port);
+
+                let (mut data, checked) = fetch_ping(
+                    cache,
+                    resolver,
+                    config,
+                    monitor,
+                    host,
+                    port,
+                    config.ping_max_age,
+                    !addr.no_srv,
+                )
+                .await;
+                host_metrics.observe(host, port, &data);
+
+                if !addr.resolved {
+                    data.resolved = None;
+                }
+                if !addr.raw {
+                    data.raw = None;
+                }
+
+                (addr.host, Cached::new(data, checked, config.ping_max_age))
+            }
+        });
+
+        let statuses = futures::future::join_all(statuses)
+            .await
+            .into_iter()
+            .collect();
+
+        return HttpResponse::Ok()
+            .insert_header(config.ping_ttl().cache_control())
+            .json(StatusResponse::Servers(statuses));
+    }
+
+    let (host, port) = addr.parse_host();
+    leaderboard.record(&cache, host, port);
+    stats::record_lookup(&config, &cache, host, port);
+
+    tracing::info!("attempting to get server status for {}:{}", host, port);
+
+    let (mut data, checked) = fetch_ping(
+        &cache,
+        &resolver,
+        &config,
+        &monitor,
+        host,
+        port,
+        config.ping_max_age,
+        !addr.no_srv,
+    )
+    .await;
+    host_metrics.observe(host, port, &data);
+
+    if !addr.resolved {
+        data.resolved = None;
+    }
+    if !addr.raw {
+        data.raw = None;
+    }
+
+    let data = Cached::new(data, checked, config.ping_max_age);
+
+    let mut res = HttpResponse::Ok();
+    res.insert_header(config.ping_ttl().cache_control());
+    if let Some(retry_after) = data.retry_after() {
+        res.insert_header((header::RETRY_AFTER, retry_after));
+    }
+
+    res.json(StatusResponse::Server(data))
+}
+
+/// Split a comma separated list of servers, skipping empty and repeated
+/// entries.
+fn split_hosts(hosts: &str) -> Vec<&str> {
+    let mut seen = HashSet::new();
+
+    hosts
+        .split(',')
+        .map(str::trim)
+        .filter(|host| !host.is_empty() && seen.insert(host.to_lowercase()))
+        .collect()
+}
+
+/// Get details about a server from a query, which must be enabled on the
+/// server.
+#[utoipa::path(
+    get,
+    path = "/server/query",
+    tag = "server",
+    params(ServerRequest),
+    responses((status = 200, description = "Query of the server, with `status` set to `error` if it could not be reached", body = CachedServerQuery))
+)]
+#[route("/server/query", method = "GET", method = "POST")]
+async fn server_query(
+    resolver: web::Data<Resolver>,
+    cache: web::Data<dyn CacheStore>,
+    config: web::Data<Config>,
+    host_metrics: web::Data<HostMetrics>,
+    leaderboard: web::Data<Leaderboard>,
+    Lookup(addr): Lookup<ServerRequest>,
+) -> impl Responder {
+    let _timer = openmetrics::HistogramTimer::new(
+        &REQUEST_DURATION,
+        REQUEST_DURATION_NAME,
+        &[("method", "query")],
+    );
+
+    let (host, port) = addr.parse_host();
+    leaderboard.record(&cache, host, port);
+    stats::record_lookup(&config, &cache, host, port);
+
+    tracing::info!("attempting to get server query for {}:{}", host, port);
+
+    let (mut data, checked) = get_query(&cache, &resolver, &config, host, port).await;
+    host_metrics.observe(host, port, &data);
+
+    if !addr.resolved {
+        data.resolved = None;
+    }
+
+    let data = Cached::new(data, checked, config.query_max_age);
+
+    let mut res = HttpResponse::Ok();
+    res.insert_header(config.query_ttl().cache_control());
+    if let Some(retry_after) = data.retry_after() {
+        res.insert_header((header::RETRY_AFTER, retry_after));
+    }
+
+    res.json(data)
+}
+
+/// Status of a server as seen from one region.
+#[derive(Debug, serde::Serialize, ToSchema)]
+struct RegionStatus {
+    region: String,
+    online: bool,
+    /// Milliseconds for the server to answer a ping from the region.
+    latency: Option<u64>,
+    error: Option<String>,
+    offline_reason: Option<types::OfflineReason>,
+}
+
+impl RegionStatus {
+    fn new(region: &str, data: types::ServerPing) -> Self {
+        Self {
+            region: region.to_string(),
+            online: data.online,
+            latency: data.latency,
+            error: data.error,
+            offline_reason: data.offline_reason,
+        }
+    }
+}
+
+/// Status of a server from each region.
+#[derive(Debug, serde::Serialize, ToSchema)]
+struct MultiStatus {
+    status: &'static str,
+    /// If the server is online from any region.
+    online: bool,
+    /// This instance's region, followed by each probe region.
+    regions: Vec<RegionStatus>,
+}
+
+/// Check a server from this instance and every probe region at once, to show
+/// if it can be reached globally or only from some networks.
+#[utoipa::path(
+    get,
+    path = "/server/status/multi",
+    tag = "server",
+    params(ServerRequest),
+    responses((status = 200, description = "Status of the server from each region", body = MultiStatus))
+)]
+#[get("/server/status/multi")]
+async fn server_status_multi(
+    resolver: web::Data<Resolver>,
+    cache: web::Data<dyn CacheStore>,
+    config: web::Data<Config>,
+    monitor: web::Data<Monitor>,
+    web::Query(addr): web::Query<ServerRequest>,
+) -> impl Responder {
+    let _timer = openmetrics::HistogramTimer::new(
+        &REQUEST_DURATION,
+        REQUEST_DURATION_NAME,
+        &[("method", "multi")],
+    );
+
+    let (host, port) = addr.parse_host();
+
+    tracing::info!(
+        "attempting to get multi-region status for {}:{}",
+        host,
+        port
+    );
+
+    let region_timeout = config.upstream_timeout();
+    let remote = config.probe_regions.iter().map(|region| async move {
+        let data = upstream::fetch(&region.url, "server/status", host, port, region_timeout)
+            .await
+            .unwrap_or_else(types::ServerPing::from);
+
+        RegionStatus::new(&region.name, data)
+    });
+
+    let (local, remote) = futures::future::join(
+        get_ping(&cache, &resolver, &config, &monitor, host, port),
+        futures::future::join_all(remote),
+    )
+    .await;
+
+    let regions: Vec<_> = std::iter::once(RegionStatus::new(&config.region, local))
+        .chain(remote)
+        .collect();
+
+    HttpResponse::Ok()
+        .insert_header(config.ping_ttl().cache_control())
+        .json(MultiStatus {
+            status: "success",
+            online: regions.iter().any(|region| region.online),
+            regions,
+        })
+}
+
+/// Ping a server without the cache, responding with the time taken by each
+/// phase, the status exactly as the server sent it, and dumps of the packets
+/// sent and received.
+#[utoipa::path(
+    get,
+    path = "/server/debug",
+    tag = "server",
+    params(ServerRequest),
+    responses(
+        (status = 200, description = "Milliseconds taken by each phase of the ping, with the resolved address, the status as the server sent it, and hex dumps of the packets"),
+        (status = 400, description = "Invalid host or port"),
+        (status = 401, description = "Missing or invalid admin token when `MCAPI_DEBUG_REQUIRE_ADMIN` is set")
+    )
+)]
+#[get("/server/debug")]
+async fn server_debug(
+    req: HttpRequest,
+    resolver: web::Data<Resolver>,
+    config: web::Data<Config>,
+    web::Query(addr): web::Query<ServerRequest>,
+) -> HttpResponse {
+    let _timer = openmetrics::HistogramTimer::new(
+        &REQUEST_DURATION,
+        REQUEST_DURATION_NAME,
+        &[("method", "debug")],
+    );
+
+    if config.debug_require_admin {
+        if let Err(err) = admin::Admin::extract(&req).await {
+            return err.error_response();
+        }
+    }
+
+    let (host, port) = addr.parse_host();
+    let host = match validate_port(port).and_then(|()| resolver::normalize_host(host)) {
+        Ok(host) => host,
+        Err(err) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "status": "error",
+                "error": err.to_string(),
+            }))
+        }
+    };
+    let host = host.as_str();
+
+    tracing::info!("debugging server {}:{}", host, port);
+
+    let timeouts = config.timeouts();
+    let start = Instant::now();
+
+    let lookup = async {
+        if addr.no_srv {
+            resolver
+                .lookup_target_without_srv(host.to_owned(), port)
+                .await
+        } else {
+            resolver.lookup_target(host.to_owned(), port).await
+        }
+    };
+
+    let target = timeout(timeouts.dns, lookup)
+        .await
+        .map_err(Error::from)
+        .and_then(|target| {
+            if target.addrs.is_empty() {
+                Err(Error::ResolveFailed)
+            } else {
+                Ok(target)
+            }
+        });
+    let dns = start.elapsed();
+
+    let (mut trace, srv_target, error) = match target {
+        Ok(target) => (
+            protocol::trace_ping(
+                &target.addrs,
+                host,
+                port,
+                &timeouts,
+                &config.limits(),
+                &config.outbound(),
+            )
+            .await,
+            target.srv_target,
+            None,
+        ),
+        Err(err) => (protocol::PingTrace::default(), None, Some(err)),
+    };
+    let error = error.or_else(|| trace.error.take().map(Error::from));
+
+    let millis =
+        |duration: Option<Duration>| duration.map(|duration| duration.as_secs_f64() * 1000.0);
+    let latency = trace.ping.as_ref().and_then(|ping| ping.latency);
+    let packets: Vec<_> = trace
+        .packets
+        .data
+        .iter()
+        .map(|captured| {
+            serde_json::json!({
+                "direction": captured.direction,
+                "bytes": captured.data.len(),
+                "hex": captured.hex_dump(),
+            })
+        })
+        .collect();
+
+    // Statuses may be much larger than anything useful to read.
+    let mut raw_status = trace.raw_status.take();
+    let raw_status_truncated = match &mut raw_status {
+        Some(status) if status.len() > protocol::MAX_CAPTURE_BYTES => {
+            let mut end = protocol::MAX_CAPTURE_BYTES;
+            while !status.is_char_boundary(end) {
+                end -= 1;
+            }
+
+            status.truncate(end);
+            true
+        }
+        _ => false,
+    };
+
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(serde_json::json!({
+            "status": if trace.ping.is_some() { "success" } else { "error" },
+            "online": trace.ping.is_some(),
+            "error": error.map(|err| err.to_string()),
+            "resolved": trace.addr.map(|addr| types::ResolvedAddress::new(addr, srv_target)),
+            "timings": {
+                "dns": millis(Some(dns)),
+                "connect": millis(trace.connect),
+                "handshake": millis(trace.handshake),
+                "read": millis(trace.read),
+                "parse": millis(trace.parse),
+                "latency": millis(latency),
+                "total": millis(Some(start.elapsed())),
+            },
+            "raw_status": raw_status,
+            "raw_status_truncated": raw_status_truncated,
+            "packets": packets,
+            "packets_truncated": trace.packets.truncated,
+        }))
+}
+
+/// Generate an image for a server, registered for both `/server/image` and
+/// `/server/image.gif` so the format can be set by the path.
+#[utoipa::path(
+    get,
+    path = "/server/image",
+    tag = "image",
+    params(ServerImageRequest),
+    responses(
+        (status = 200, description = "Image of the server's status, or an animated banner from `/server/image.gif`"),
+        (status = 400, description = "Unknown theme, font, or language"),
+        (status = 403, description = "Missing, invalid, or expired signature when `MCAPI_IMAGE_SIGNING_SECRET` is set"),
+        (status = 503, description = "Too many images are being rendered")
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+async fn server_image(
+    resolver: web::Data<Resolver>,
+    cache: web::Data<dyn CacheStore>,
+    config: web::Data<Config>,
+    monitor: web::Data<Monitor>,
+    host_metrics: web::Data<HostMetrics>,
+    leaderboard: web::Data<Leaderboard>,
+    fonts: web::Data<image::Fonts>,
+    offline_card: web::Data<image::OfflineCard>,
+    render_pool: web::Data<RenderPool>,
+    http_req: HttpRequest,
+    _signed: signing::Signed,
+    Lookup(req): Lookup<ServerImageRequest>,
+) -> impl Responder {
+    let _timer = openmetrics::HistogramTimer::new(
+        &REQUEST_DURATION,
+        REQUEST_DURATION_NAME,
+        &[("method", "image")],
+    );
+
+    let theme = match config.image_theme(req.theme.as_deref()) {
+        Some(theme) => theme,
+        None => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "status": "error",
+                "error": format!(
+                    "unknown theme, available themes: {}",
+                    config.image_theme_names().join(", ")
+                ),
+            }));
+        }
+    };
+
+    let mut options = req.options(&theme);
+    options.offline = offline_card.into_inner();
+    options.fallback_fonts = fonts.fallbacks().to_vec();
+    if let Some(name) = req.font.as_ref().or(theme.font.as_ref()) {
+        match fonts.get(name) {
+            Some(font) => options.font = Some(font.clone()),
+            None => {
+                let available: Vec<_> = fonts.names().collect();
+
+                return HttpResponse::BadRequest().json(serde_json::json!({
+                    "status": "error",
+                    "error": format!("unknown font, available fonts: {}", available.join(", ")),
+                }));
+            }
+        }
+    }
+
+    // An explicit language must be available, but the header is only a
+    // preference so falls back to the default language.
+    options.language = match &req.lang {
+        Some(lang) if locale::Strings::get(lang).is_some() => Some(lang.to_lowercase()),
+        Some(_lang) => {
+            let available: Vec<_> = locale::languages().collect();
+
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "status": "error",
+                "error": format!("unknown language, available languages: {}", available.join(", ")),
+            }));
+        }
+        None => http_req
+            .headers()
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(locale::negotiate)
+            .map(str::to_string),
+    };
+
+    let (host, port) = req.parse_host();
+    leaderboard.record(&cache, host, port);
+    stats::record_lookup(&config, &cache, host, port);
+
+    tracing::info!("attempting to get server image for {}:{}", host, port);
+
+    let mut data = get_ping(&cache, &resolver, &config, &monitor, host, port).await;
+    host_metrics.observe(host, port, &data);
+
+    // Pings only include a sample of players, but a query lists everyone. The
+    // server is not queried just for the image, as many do not enable queries.
+    if req.players {
+        if let Some(query) = cached_query(cache.get_ref(), host, port).await {
+            if query.online && query.players.list.len() > data.players.sample.len() {
+                data.players.sample = query
+                    .players
+                    .list
+                    .into_iter()
+                    .map(|name| protocol::PlayerSample {
+                        name,
+                        id: String::new(),
+                    })
+                    .collect();
+            }
+        }
+    }
+
+    // The path and the Accept header are only used when no format is given,
+    // and the header may only pick between formats that look the same.
+    let negotiated = req.format.is_none() && !http_req.path().ends_with(".gif");
+    let format = match req.format {
+        Some(format) => format,
+        None if http_req.path().ends_with(".gif") => image::ImageFormat::Gif,
+        None => http_req
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|value| value.to_str().ok())
+            .and_then(image::ImageFormat::negotiate)
+            .unwrap_or_default(),
+    };
+    let quality = req.quality.unwrap_or(image::DEFAULT_QUALITY);
+
+    // Rendering takes much longer than loading cached data, so images are
+    // cached too. Keys include when the data was updated, so a cached image
+    // never shows older data than a new image would.
+    let image_key = (config.image_cache_ttl > 0 && req.cacheable(&data)).then(|| {
+        image_cache_key(
+            &req,
+            host,
+            port,
+            format,
+            quality,
+            options.language.as_deref(),
+            &data,
+        )
+    });
+    let cached = match &image_key {
+        Some(key) => cached_image(cache.get_ref(), key).await,
+        None => None,
+    };
+
+    let image = match cached {
+        Some(image) => image,
+        None => {
+            let span = tracing::info_span!("render_image", ?format);
+            let image = render_pool.render(move || {
+                span.in_scope(|| match format {
+                    image::ImageFormat::Png => image::server_image(&options, data),
+                    image::ImageFormat::Gif => image::server_banner(&options, data),
+                    image::ImageFormat::Svg => image::server_svg(&options, data).into_bytes(),
+                    image::ImageFormat::Webp => {
+                        image::encode_webp(image::render_server_image(&options, data))
+                    }
+                    image::ImageFormat::Jpeg => {
+                        image::encode_jpeg(image::render_server_image(&options, data), quality)
+                    }
+                })
+            });
+            let image = match image.await {
+                Ok(image) => image,
+                Err(err) => return render_error(err, &config),
+            };
+
+            if let Some(key) = &image_key {
+                if let Err(err) = cache.set(key, image.clone(), config.image_cache_ttl).await {
+                    tracing::warn!("could not cache rendered image: {:?}", err);
+                }
+            }
+
+            image
+        }
+    };
+
+    let mut resp = HttpResponse::Ok();
+    resp.insert_header(config.image_ttl().cache_control())
+        .insert_header((header::CONTENT_TYPE, format.content_type()));
+
+    let vary: Vec<_> = [
+        (negotiated, "Accept"),
+        (req.lang.is_none(), "Accept-Language"),
+    ]
+    .iter()
+    .filter_map(|(varies, name)| varies.then_some(*name))
+    .collect();
+    if !vary.is_empty() {
+        resp.insert_header((header::VARY, vary.join(", ")));
+    }
+
+    resp.body(image)
+}
+
+/// Get a server's favicon as a PNG.
+#[utoipa::path(
+    get,
+    path = "/server/icon",
+    tag = "image",
+    params(ServerIconRequest),
+    responses(
+        (status = 200, description = "The server's favicon, or a replacement if it has none"),
+        (status = 403, description = "Missing, invalid, or expired signature when `MCAPI_IMAGE_SIGNING_SECRET` is set"),
+        (status = 404, description = "The server has no favicon and `MCAPI_MISSING_ICON` is `not_found`")
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+#[get("/server/icon")]
+async fn server_icon(
+    resolver: web::Data<Resolver>,
+    cache: web::Data<dyn CacheStore>,
+    config: web::Data<Config>,
+    monitor: web::Data<Monitor>,
+    host_metrics: web::Data<HostMetrics>,
+    leaderboard: web::Data<Leaderboard>,
+    default_icon: web::Data<DefaultIcon>,
+    _signed: signing::Signed,
+    web::Query(req): web::Query<ServerIconRequest>,
+) -> impl Responder {
+    let _timer = openmetrics::HistogramTimer::new(
+        &REQUEST_DURATION,
+        REQUEST_DURATION_NAME,
+        &[("method", "icon")],
+    );
+
+    let (host, port) = req.parse_host();
+    leaderboard.record(&cache, host, port);
+    stats::record_lookup(&config, &cache, host, port);
+
+    tracing::info!("attempting to get server icon for {}:{}", host, port);
+
+    let data = get_ping(&cache, &resolver, &config, &monitor, host, port).await;
+    host_metrics.observe(host, port, &data);
+
+    // Raw favicons are only validated, skipping decoding and re-encoding.
+    let favicon = if req.raw {
+        image::favicon_png(&data.favicon)
+    } else {
+        image::favicon_image(&data.favicon).map(image::encode_png)
+    };
+
+    let icon = match favicon {
+        Some(icon) => icon,
+        None => match config.missing_icon {
+            MissingIcon::Placeholder => image::encode_png(image::placeholder_icon()),
+            MissingIcon::NotFound => {
+                return HttpResponse::NotFound()
+                    .insert_header(config.icon_ttl().cache_control())
+                    .json(serde_json::json!({
+                        "status": "error",
+                        "error": "server has no favicon",
+                    }));
+            }
+            MissingIcon::File => default_icon.0.clone(),
+            MissingIcon::Identicon => image::encode_png(image::identicon(&host.to_lowercase())),
+        },
+    };
+
+    HttpResponse::Ok()
+        .insert_header(config.icon_ttl().cache_control())
+        .insert_header(ContentType::png())
+        .body(icon)
+}
+
+/// Generate a preview image for links to a server, like its status page.
+#[utoipa::path(
+    get,
+    path = "/server/og-image",
+    tag = "image",
+    params(ServerImageRequest),
+    responses(
+        (status = 200, description = "1200 by 630 PNG preview of the server's status"),
+        (status = 403, description = "Missing, invalid, or expired signature when `MCAPI_IMAGE_SIGNING_SECRET` is set"),
+        (status = 503, description = "Too many images are being rendered")
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+#[get("/server/og-image")]
+async fn server_og_image(
+    resolver: web::Data<Resolver>,
+    cache: web::Data<dyn CacheStore>,
+    config: web::Data<Config>,
+    monitor: web::Data<Monitor>,
+    host_metrics: web::Data<HostMetrics>,
+    fonts: web::Data<image::Fonts>,
+    offline_card: web::Data<image::OfflineCard>,
+    render_pool: web::Data<RenderPool>,
+    _signed: signing::Signed,
+    web::Query(req): web::Query<ServerImageRequest>,
+) -> impl Responder {
+    let _timer = openmetrics::HistogramTimer::new(
+        &REQUEST_DURATION,
+        REQUEST_DURATION_NAME,
+        &[("method", "og_image")],
+    );
+
+    // Previews are linked from pages that can't show errors, so an unknown
+    // theme or font uses the default instead.
+    let theme = config.image_theme(req.theme.as_deref()).unwrap_or_default();
+
+    let mut options = req.options(&theme);
+    options.offline = offline_card.into_inner();
+    options.fallback_fonts = fonts.fallbacks().to_vec();
+    options.font = req
+        .font
+        .as_ref()
+        .or(theme.font.as_ref())
+        .and_then(|name| fonts.get(name))
+        .cloned();
+    options.language = req
+        .lang
+        .as_deref()
+        .filter(|lang| locale::Strings::get(lang).is_some())
+        .map(str::to_lowercase);
+
+    let (host, port) = req.parse_host();
+
+    tracing::info!("attempting to get preview image for {}:{}", host, port);
+
+    let data = get_ping(&cache, &resolver, &config, &monitor, host, port).await;
+    host_metrics.observe(host, port, &data);
+
+    // Previews are fetched by every site a status page is shared on, so they
+    // are cached like other images.
+    let image_key = (config.image_cache_ttl > 0 && req.cacheable(&data)).then(|| {
+        let key = image_cache_key(
+            &req,
+            host,
+            port,
+            image::ImageFormat::Png,
+            0,
+            options.language.as_deref(),
+            &data,
+        );
+
+        format!("og_{}", key)
+    });
+    let cached = match &image_key {
+        Some(key) => cached_image(cache.get_ref(), key).await,
+        None => None,
+    };
+
+    let image = match cached {
+        Some(image) => image,
+        None => {
+            let span = tracing::info_span!("render_og_image");
+            let image = render_pool.render(move || {
+                span.in_scope(|| image::encode_png(image::render_og_image(&options, data)))
+            });
+            let image = match image.await {
+                Ok(image) => image,
+                Err(err) => return render_error(err, &config),
+            };
+
+            if let Some(key) = &image_key {
+                if let Err(err) = cache.set(key, image.clone(), config.image_cache_ttl).await {
+                    tracing::warn!("could not cache rendered image: {:?}", err);
+                }
+            }
+
+            image
+        }
+    };
+
+    HttpResponse::Ok()
+        .insert_header(config.image_ttl().cache_control())
+        .insert_header(ContentType::png())
+        .body(image)
+}
+
+/// Respond to an image that could not be rendered, asking clients to try again
+/// later if too many images were waiting to be rendered.
+fn render_error(err: RenderError, config: &Config) -> HttpResponse {
+    match err {
+        RenderError::Full => HttpResponse::ServiceUnavailable()
+            .insert_header((header::RETRY_AFTER, config.load_shed_retry_after))
+            .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+            .json(serde_json::json!({
+                "status": "error",
+                "error": "too many images are being rendered, try again later",
+            })),
+        RenderError::Failed => HttpResponse::InternalServerError().json(serde_json::json!({
+            "status": "error",
+            "error": "could not render image",
+        })),
+    }
+}
+
+/// PNG returned from `/server/icon` for servers without a favicon when the
+/// missing icon behavior is `file`.
+struct DefaultIcon(Vec<u8>);
+
+/// Percentage of checks where a server was online within each window.
+#[derive(Debug, serde::Serialize, ToSchema)]
+struct UptimeResponse {
+    status: &'static str,
+    uptime: history::Uptime,
+}
+
+/// Get the uptime of a monitored server over the last day, week, and month.
+#[utoipa::path(
+    get,
+    path = "/server/history/uptime",
+    tag = "server",
+    params(ServerRequest),
+    responses(
+        (status = 200, description = "Uptime of the server", body = UptimeResponse),
+        (status = 404, description = "The server is not monitored, so no history is recorded")
+    )
+)]
+#[get("/server/history/uptime")]
+async fn server_uptime(
+    monitor: web::Data<Monitor>,
+    web::Query(addr): web::Query<ServerRequest>,
+) -> impl Responder {
+    let (host, port) = addr.parse_host();
+
+    let uptime = if monitor.is_monitored(host, port) {
+        monitor.history.uptime(host, port).await
+    } else {
+        Ok(None)
+    };
+
+    match uptime {
+        Ok(Some(uptime)) => HttpResponse::Ok().json(UptimeResponse {
+            status: "success",
+            uptime,
+        }),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({
+            "status": "error",
+            "error": "history is not recorded for this server",
+        })),
+        Err(err) => {
+            tracing::error!("could not load uptime for {}:{}: {}", host, port, err);
+
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "status": "error",
+                "error": "could not load history",
+            }))
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TopRequest {
+    /// Most servers to include, defaults to 10.
+    pub limit: Option<usize>,
+}
+
+/// The most requested servers, in order.
+#[derive(Debug, serde::Serialize, ToSchema)]
+struct TopResponse {
+    status: &'static str,
+    servers: Vec<stats::TopServer>,
+}
+
+/// Get the servers with the most recent lookups.
+#[utoipa::path(
+    get,
+    path = "/stats/top",
+    tag = "stats",
+    params(TopRequest),
+    responses(
+        (status = 200, description = "The most requested servers", body = TopResponse),
+        (status = 404, description = "The leaderboard is disabled")
+    )
+)]
+#[get("/stats/top")]
+async fn stats_top(
+    cache: web::Data<dyn CacheStore>,
+    leaderboard: web::Data<Leaderboard>,
+    web::Query(req): web::Query<TopRequest>,
+) -> impl Responder {
+    let limit = req.limit.unwrap_or(10).min(MAX_TOP_SERVERS);
+
+    match leaderboard.top(cache.get_ref(), limit).await {
+        Ok(servers) => HttpResponse::Ok()
+            .insert_header(CacheControl(vec![
+                CacheDirective::Public,
+                CacheDirective::MaxAge(60),
+            ]))
+            .json(TopResponse {
+                status: "success",
+                servers,
+            }),
+        Err(err) => {
+            tracing::error!("could not load leaderboard: {}", err);
+
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "status": "error",
+                "error": "could not load leaderboard",
+            }))
+        }
+    }
+}
+
+/// Totals across every instance.
+#[derive(Debug, serde::Serialize, ToSchema)]
+struct StatsResponse {
+    status: &'static str,
+    stats: stats::AggregateStats,
+}
+
+/// Totals across every instance, for the landing page and status
+/// aggregators.
+#[utoipa::path(
+    get,
+    path = "/stats",
+    tag = "stats",
+    responses((status = 200, description = "Lookups and checks across every instance", body = StatsResponse))
+)]
+#[get("/stats")]
+async fn stats_summary(cache: web::Data<dyn CacheStore>) -> impl Responder {
+    match stats::aggregate(cache.get_ref()).await {
+        Ok(stats) => HttpResponse::Ok()
+            .insert_header(CacheControl(vec![
+                CacheDirective::Public,
+                CacheDirective::MaxAge(60),
+            ]))
+            .json(StatsResponse {
+                status: "success",
+                stats,
+            }),
+        Err(err) => {
+            tracing::error!("could not load stats: {}", err);
+
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "status": "error",
+                "error": "could not load stats",
+            }))
+        }
+    }
+}
+
+#[get("/health")]
+async fn health() -> impl Responder {
+    "OK"
+}
+
+/// Which build is serving requests.
+#[derive(Debug, serde::Serialize, ToSchema)]
+struct VersionResponse {
+    version: &'static str,
+    /// Git commit the build was made from.
+    commit: &'static str,
+    /// Unix timestamp of when the build was made, if known.
+    built_at: Option<u64>,
+}
+
+/// Which build is serving requests.
+#[utoipa::path(
+    get,
+    path = "/version",
+    tag = "meta",
+    responses((status = 200, description = "Version of the server", body = VersionResponse))
+)]
+#[get("/version")]
+async fn version() -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+        .json(VersionResponse {
+            version: VERSION,
+            commit: GIT_COMMIT,
+            built_at: BUILT_AT.parse().ok(),
+        })
+}
+
+/// Dependencies to check for readiness.
+#[derive(Debug)]
+struct ReadinessChecks {
+    /// A name that should always resolve.
+    dns_name: String,
+    /// An optional server that should always respond to pings.
+    reference_server: Option<(String, u16)>,
+    /// How long to wait for each check.
+    timeouts: protocol::Timeouts,
+    limits: protocol::Limits,
+    /// How to connect to the reference server.
+    outbound: protocol::Outbound,
+}
+
+impl ReadinessChecks {
+    fn new(config: &Config) -> Self {
+        Self {
+            dns_name: config.ready_dns_name.clone(),
+            reference_server: config
+                .reference_server()
+                .map(|server| (server.host, server.port)),
+            timeouts: config.timeouts(),
+            limits: config.limits(),
+            outbound: config.outbound(),
+        }
+    }
+}
+
+/// Convert the result of a readiness check into JSON.
+fn check_status<E: std::fmt::Display>(result: &Result<(), E>) -> serde_json::Value {
+    match result {
+        Ok(()) => serde_json::json!({ "status": "ok" }),
+        Err(err) => serde_json::json!({ "status": "error", "error": err.to_string() }),
+    }
+}
+
+#[get("/health/ready")]
+async fn health_ready(
+    resolver: web::Data<Resolver>,
+    cache: web::Data<dyn CacheStore>,
+    checks: web::Data<ReadinessChecks>,
+) -> impl Responder {
+    let cache_check = timeout(checks.timeouts.total, cache.ping())
+        .await
+        .map_err(Error::from)
+        .and_then(|result| result);
+
+    let dns_check = timeout(checks.timeouts.dns, resolver.check(&checks.dns_name))
+        .await
+        .map_err(Error::from)
+        .and_then(|result| result.map_err(|_err| Error::ResolveFailed));
+
+    let mut statuses = serde_json::json!({
+        "cache": check_status(&cache_check),
+        "dns": check_status(&dns_check),
+    });
+
+    let mut ready = cache_check.is_ok() && dns_check.is_ok();
+
+    if let Some((host, port)) = &checks.reference_server {
+        let reference_check = async {
+            let addrs =
+                timeout(checks.timeouts.dns, resolver.lookup(host.to_owned(), *port)).await?;
+            if addrs.is_empty() {
+                return Err(Error::ResolveFailed);
+            }
+
+            timeout(
+                checks.timeouts.total,
+                protocol::send_ping(
+                    &addrs,
+                    host,
+                    *port,
+                    &checks.timeouts,
+                    &checks.limits,
+                    &checks.outbound,
+                ),
+            )
+            .await??;
+
+            Ok::<_, Error>(())
+        }
+        .await;
+
+        ready &= reference_check.is_ok();
+        statuses["reference_server"] = check_status(&reference_check);
+    }
+
+    let body = serde_json::json!({
+        "status": if ready { "ok" } else { "error" },
+        "checks": statuses,
+    });
+
+    if ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+#[get("/metrics")]
+async fn metrics(req: HttpRequest) -> impl Responder {
+    use prometheus::Encoder;
+
+    let metric_families = prometheus::gather();
+
+    // Prefer OpenMetrics, which includes exemplars, when the client accepts it.
+    let wants_openmetrics = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .map(|accept| accept.contains("application/openmetrics-text"))
+        .unwrap_or(false);
+
+    if wants_openmetrics {
+        return HttpResponse::Ok()
+            .content_type(openmetrics::CONTENT_TYPE)
+            .body(openmetrics::encode(&metric_families));
+    }
+
+    let encoder = prometheus::TextEncoder::new();
+    let mut buffer = Vec::new();
+    encoder.encode(&metric_families, &mut buffer).unwrap();
+
+    HttpResponse::Ok()
+        .content_type(encoder.format_type())
+        .body(buffer)
+}
+
+/// Register an endpoint if it is enabled, otherwise respond to its path with
+/// an error explaining it was disabled.
+fn endpoint<F>(cfg: &mut web::ServiceConfig, enabled: bool, path: &str, service: F)
+where
+    F: HttpServiceFactory + 'static,
+{
+    if enabled {
+        cfg.service(service);
+    } else {
+        cfg.route(path, web::route().to(endpoint_disabled));
+    }
+}
+
+async fn endpoint_disabled() -> impl Responder {
+    HttpResponse::NotFound().json(serde_json::json!({
+        "status": "error",
+        "error": "this endpoint has been disabled",
+    }))
 }
 
-impl ServerAddr for ServerImageRequest {
-    fn host(&self) -> &str {
-        &self.host
-    }
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    telemetry::init().expect("could not set up tracing");
+    runtime::register_collector();
+    runtime::register_current();
 
-    fn port(&self) -> Option<u16> {
-        self.port
-    }
-}
+    tracing::info!("starting mcapi-rs {} ({})", VERSION, GIT_COMMIT);
+    BUILD_INFO
+        .with_label_values(&[VERSION, GIT_COMMIT])
+        .set(1.0);
 
-#[get("/server/status")]
-async fn server_status(
-    resolver: web::Data<Resolver>,
-    redis: web::Data<RedisClient>,
-    redlock: web::Data<RedLock>,
-    web::Query(addr): web::Query<ServerRequest>,
-) -> impl Responder {
-    let _timer = REQUEST_DURATION.with_label_values(&["ping"]).start_timer();
+    let config = Config::load().expect("could not load config");
+    let listen = config.http_host;
 
-    let (host, port) = addr.parse_host();
+    tracing::info!("will listen on {}", listen);
+    tracing::info!("using {:?} cache backend", config.cache_backend);
 
-    tracing::info!("attempting to get server status for {}:{}", host, port);
+    let cache: Arc<dyn CacheStore> = match config.cache_backend {
+        cache::CacheBackend::Redis => {
+            if config.redis_server.is_empty() {
+                panic!("redis_server is required");
+            }
 
-    let data = get_ping(&redis, &redlock, &resolver, host, port).await;
+            let redis_servers: Vec<_> = config.redis_server.iter().map(String::as_str).collect();
+            Arc::new(cache::RedisStore::new(redis_servers, config.lock_strategy).unwrap())
+        }
+        cache::CacheBackend::Memory => Arc::new(cache::MemoryStore::new(config.memory_cache_size)),
+        cache::CacheBackend::Memcached => Arc::new(cache::MemcachedStore::new(
+            config
+                .memcached_server
+                .expect("memcached_server is required"),
+        )),
+    };
 
-    HttpResponse::Ok()
-        .insert_header(get_cache_control())
-        .json(data)
-}
+    let cache: Arc<dyn CacheStore> = if config.lock_strategy == cache::LockStrategy::None {
+        tracing::info!("only locking refreshes within this instance");
+        Arc::new(cache::LocalLockStore::new(cache))
+    } else {
+        cache
+    };
 
-#[get("/server/query")]
-async fn server_query(
-    resolver: web::Data<Resolver>,
-    redis: web::Data<RedisClient>,
-    redlock: web::Data<RedLock>,
-    web::Query(addr): web::Query<ServerRequest>,
-) -> impl Responder {
-    let _timer = REQUEST_DURATION.with_label_values(&["query"]).start_timer();
+    let cache: Arc<dyn CacheStore> = match &config.cache_prefix {
+        Some(prefix) if !prefix.is_empty() => {
+            tracing::info!("using cache prefix {}", prefix);
+            Arc::new(cache::PrefixedStore::new(prefix, cache))
+        }
+        _ => cache,
+    };
 
-    let (host, port) = addr.parse_host();
+    if !config.api_keys.is_empty() && config.cache_backend == cache::CacheBackend::Memcached {
+        tracing::warn!("api key usage cannot be recorded with the memcached cache backend");
+    }
 
-    tracing::info!("attempting to get server query for {}:{}", host, port);
+    let host_metrics = web::Data::new(HostMetrics::new(&config.metrics_hosts));
+    tracing::debug!("emitting metrics for hosts: {:?}", host_metrics.hosts);
+
+    let leaderboard = web::Data::new(Leaderboard::new(
+        config.leaderboard_size,
+        Duration::from_secs(config.leaderboard_half_life),
+    ));
+
+    let fonts = match &config.image_font_dir {
+        Some(dir) => image::Fonts::load_dir(dir)
+            .expect("could not load fonts from image_font_dir")
+            .with_fallbacks(&config.image_fallback_fonts)
+            .expect("image_fallback_fonts must be fonts in image_font_dir"),
+        None if !config.image_fallback_fonts.is_empty() => {
+            panic!("image_font_dir is required for image_fallback_fonts")
+        }
+        None => image::Fonts::default(),
+    };
+    tracing::debug!("image fonts: {:?}", fonts.names().collect::<Vec<_>>());
+    for (name, theme) in &config.image_themes {
+        if let Some(font) = &theme.font {
+            if fonts.get(font).is_none() {
+                panic!(
+                    "font {} of image theme {} is not in image_font_dir",
+                    font, name
+                );
+            }
+        }
+    }
+    tracing::debug!("image themes: {:?}", config.image_theme_names());
+    let fonts = web::Data::new(fonts);
+    let background_image = match &config.offline_background_image {
+        Some(path) => match image::load_background(path) {
+            Ok(background) => Some(background),
+            Err(err) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "could not load image from offline_background_image: {}",
+                        err
+                    ),
+                ))
+            }
+        },
+        None => None,
+    };
+    let offline_card = web::Data::new(image::OfflineCard {
+        message: config.offline_message.clone(),
+        background: config.offline_background,
+        foreground: config.offline_foreground,
+        accent: config.offline_accent,
+        background_image,
+    });
+    let render_pool = web::Data::new(RenderPool::new(
+        config.image_render_threads,
+        config.image_render_queue,
+    ));
+
+    let default_icon = match (config.missing_icon, &config.missing_icon_file) {
+        (MissingIcon::File, Some(path)) => match image::load_icon(path) {
+            Ok(icon) => image::encode_png(icon),
+            Err(err) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("could not load icon from missing_icon_file: {}", err),
+                ))
+            }
+        },
+        _ => Vec::new(),
+    };
+    let default_icon = web::Data::new(DefaultIcon(default_icon));
+
+    if let Some(url) = &config.upstream_url {
+        upstream::validate_url(url).expect("upstream_url must be a valid url");
+        tracing::info!("forwarding status and query requests to {}", url);
+    }
 
-    let data = get_query(&redis, &redlock, &resolver, host, port).await;
+    let database = match &config.database_url {
+        Some(database_url) if !database_url.is_empty() => Some(
+            database::connect(database_url)
+                .await
+                .expect("could not connect to database"),
+        ),
+        _ => None,
+    };
 
-    HttpResponse::Ok()
-        .insert_header(get_cache_control())
-        .json(data)
-}
+    let events = match &config.events_sink {
+        Some(sink) if !sink.is_empty() => {
+            tracing::info!("publishing status changes to {}", sink);
 
-#[get("/server/image")]
-async fn server_image(
-    resolver: web::Data<Resolver>,
-    redis: web::Data<RedisClient>,
-    redlock: web::Data<RedLock>,
-    web::Query(req): web::Query<ServerImageRequest>,
-) -> impl Responder {
-    let _timer = REQUEST_DURATION.with_label_values(&["image"]).start_timer();
+            Events::connect(sink, &config.events_servers, &config.events_subject)
+                .await
+                .expect("could not connect to events sink")
+        }
+        _ => Events::default(),
+    };
 
-    let (host, port) = req.parse_host();
+    let monitor = Monitor::new(&config.monitored_hosts, database, events);
+    tracing::debug!("monitoring hosts: {:?}", monitor.hosts);
 
-    tracing::info!("attempting to get server image for {}:{}", host, port);
+    // Servers may only be registered at runtime through the admin API.
+    let admin_enabled = !config.admin_tokens.is_empty();
+    if admin_enabled {
+        monitor
+            .load_registrations()
+            .await
+            .expect("could not load monitored servers");
+    }
 
-    let data = get_ping(&redis, &redlock, &resolver, host, port).await;
+    let history_enabled =
+        monitor.history.is_enabled() && (!monitor.hosts.is_empty() || admin_enabled);
+    let monitor = web::Data::new(monitor);
 
-    let image = actix_web::rt::task::spawn_blocking(move || image::server_image(&req, data))
-        .await
-        .unwrap();
+    if history_enabled {
+        let monitor = monitor.clone();
+        let retention = config.history_retention();
+        let interval = Duration::from_secs(config.history_compaction_interval);
 
-    HttpResponse::Ok()
-        .insert_header(get_cache_control())
-        .insert_header(ContentType::png())
-        .body(image)
-}
+        actix_web::rt::spawn(
+            async move { monitor.history.run_compaction(retention, interval).await },
+        );
+    }
 
-#[get("/server/icon")]
-async fn server_icon(
-    resolver: web::Data<Resolver>,
-    redis: web::Data<RedisClient>,
-    redlock: web::Data<RedLock>,
-    web::Query(addr): web::Query<ServerRequest>,
-) -> impl Responder {
-    let _timer = REQUEST_DURATION.with_label_values(&["icon"]).start_timer();
+    let prometheus_enabled = config.metrics_sink_enabled("prometheus");
 
-    let (host, port) = addr.parse_host();
+    let statsd = if config.metrics_sink_enabled("statsd") {
+        tracing::info!(
+            "sending statsd metrics to {} every {}s",
+            config.statsd_server,
+            config.statsd_interval
+        );
 
-    tracing::info!("attempting to get server icon for {}:{}", host, port);
+        let sink = statsd::StatsdSink::new(
+            config.statsd_server,
+            Duration::from_secs(config.statsd_interval),
+            config.statsd_tags.clone(),
+        );
+        let (shutdown, rx) = tokio::sync::oneshot::channel();
 
-    let data = get_ping(&redis, &redlock, &resolver, host, port).await;
+        Some((shutdown, actix_web::rt::spawn(sink.run(rx))))
+    } else {
+        None
+    };
 
-    let icon = image::encode_png(image::server_icon(&data.favicon));
+    let shutdown_timeout = config.shutdown_timeout;
+
+    let resolver = web::Data::new(Resolver::new(&config.outbound()));
+    let readiness_checks = web::Data::new(ReadinessChecks::new(&config));
+    let trusted_proxies = web::Data::new(
+        client_ip::TrustedProxies::new(&config.trusted_proxies)
+            .expect("trusted_proxies must contain networks or addresses"),
+    );
+    tracing::debug!("trusting proxies in: {:?}", trusted_proxies.networks);
+    let rate_limiter = web::Data::new(rate_limit::RateLimiter::new(
+        config.rate_limit,
+        Duration::from_secs(config.rate_limit_window),
+    ));
+    let cache: web::Data<dyn CacheStore> = web::Data::from(cache);
+
+    if leaderboard.is_enabled() {
+        let leaderboard = leaderboard.clone();
+        let cache = cache.clone();
+
+        actix_web::rt::spawn(async move { leaderboard.run_decay(cache).await });
+    }
 
-    HttpResponse::Ok()
-        .insert_header(get_cache_control())
-        .insert_header(ContentType::png())
-        .body(icon)
-}
+    let tls_config = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => {
+            let resolver = Arc::new(
+                tls::ReloadingResolver::new(cert, key).expect("could not load tls certificate"),
+            );
 
-#[get("/health")]
-async fn health() -> impl Responder {
-    "OK"
-}
+            tracing::info!("serving https with certificate {}", cert.display());
 
-#[get("/metrics")]
-async fn metrics() -> impl Responder {
-    use prometheus::Encoder;
+            #[cfg(unix)]
+            actix_web::rt::spawn(tls::reload_on_sighup(resolver.clone()));
 
-    let encoder = prometheus::TextEncoder::new();
-    let metric_families = prometheus::gather();
-    let mut buffer = Vec::new();
-    encoder.encode(&metric_families, &mut buffer).unwrap();
+            Some(tls::server_config(resolver))
+        }
+        _ => None,
+    };
 
-    HttpResponse::Ok().body(buffer)
-}
+    let seeds = warmup::load_seeds(&config)
+        .await
+        .expect("could not load warmup seeds");
+
+    let proxy_protocol = config.proxy_protocol;
+    let config = web::Data::new(config);
+
+    if !seeds.is_empty() {
+        actix_web::rt::spawn(warmup::run(
+            seeds,
+            cache.clone(),
+            resolver.clone(),
+            config.clone(),
+            monitor.clone(),
+        ));
+    }
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    tracing_subscriber::fmt::init();
+    // Bans may only be changed through the admin API.
+    let bans = web::Data::new(ban::BanList::default());
+    if admin_enabled {
+        if let Err(err) = bans.reload(cache.get_ref()).await {
+            tracing::error!("could not load bans: {}", err);
+        }
 
-    tracing::info!("starting mcapi-rs");
+        actix_web::rt::spawn({
+            let bans = bans.clone();
+            let cache = cache.clone();
 
-    let listen: SocketAddr = std::env::var("HTTP_HOST")
-        .unwrap_or_else(|_err| "0.0.0.0:8080".to_string())
-        .parse()
-        .unwrap();
+            async move { bans.run_reload(cache).await }
+        });
 
-    tracing::info!("will listen on {}", listen);
+        actix_web::rt::spawn(monitor::run_scheduler(
+            monitor.clone(),
+            cache.clone(),
+            resolver.clone(),
+            config.clone(),
+        ));
+    }
 
-    let redis_servers = std::env::var("REDIS_SERVER").expect("REDIS_SERVER is required");
-    let redis_servers: Vec<_> = redis_servers.split(',').collect();
+    let app = move || {
+        // Each worker runs its own runtime, and this is called once in each.
+        runtime::register_current();
 
-    let resolver = web::Data::new(Resolver::default());
-    let redis = web::Data::new(RedisClient::open(redis_servers[0]).unwrap());
-    let redlock = web::Data::new(RedLock::new(redis_servers));
+        let cors = config
+            .cors_origins
+            .iter()
+            .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin));
 
-    HttpServer::new(move || {
-        let cors = Cors::default()
-            .allow_any_origin()
-            .allowed_methods(["GET"])
-            .allow_any_header()
-            .max_age(86400);
+        let cors = if config.cors_origins.is_empty() {
+            cors.allow_any_origin()
+        } else {
+            cors
+        }
+        .allowed_methods(["GET", "POST"])
+        .allow_any_header()
+        .expose_headers([
+            request_id::X_REQUEST_ID,
+            rate_limit::X_RATELIMIT_LIMIT,
+            rate_limit::X_RATELIMIT_REMAINING,
+            rate_limit::X_RATELIMIT_RESET,
+        ])
+        .max_age(config.cors_max_age);
 
         let scripts = actix_files::Files::new("/scripts", "./static/scripts").show_files_listing();
         let site = actix_files::Files::new("/site", "./static/site");
 
+        // Bodies depend on the request, so unlike query errors these are not
+        // cached.
+        let json_cfg = actix_web::web::JsonConfig::default().error_handler(|err, _req| {
+            let body = serde_json::json!({
+                "status": "error",
+                "error": err.to_string(),
+            });
+
+            actix_web::error::InternalError::from_response(
+                err.to_string(),
+                HttpResponse::BadRequest().json(body),
+            )
+            .into()
+        });
+
         let query_cfg = actix_web::web::QueryConfig::default().error_handler(|err, _req| {
             // Create a new error response with a JSON body. Allow caching the
             // error for up to 1 hour, even though it should never change.
@@ -270,42 +1964,187 @@ async fn main() -> std::io::Result<()> {
         });
 
         App::new()
+            .wrap_fn(slow_log::middleware)
+            .wrap_fn(load_shed::middleware)
+            .wrap_fn(deadline::middleware)
+            .wrap_fn(usage::middleware)
+            .wrap_fn(rate_limit::middleware)
+            .wrap_fn(ban::middleware)
+            .wrap_fn(localize::middleware)
             .wrap(TracingLogger::default())
+            .wrap_fn(request_id::middleware)
             .wrap(cors)
+            .wrap_fn(client_ip::middleware)
+            .wrap_fn(path_routes::middleware)
             .app_data(resolver.clone())
-            .app_data(redis.clone())
-            .app_data(redlock.clone())
+            .app_data(cache.clone())
+            .app_data(config.clone())
+            .app_data(monitor.clone())
+            .app_data(host_metrics.clone())
+            .app_data(leaderboard.clone())
+            .app_data(fonts.clone())
+            .app_data(offline_card.clone())
+            .app_data(render_pool.clone())
+            .app_data(default_icon.clone())
+            .app_data(readiness_checks.clone())
+            .app_data(trusted_proxies.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(bans.clone())
             .app_data(query_cfg)
-            .service(server_status)
-            .service(server_query)
-            .service(server_image)
-            .service(server_icon)
+            .app_data(json_cfg)
+            .configure(|cfg| {
+                let enabled = |name| config.endpoint_enabled(name);
+
+                endpoint(cfg, enabled("status"), "/server/status", server_status);
+                endpoint(cfg, enabled("query"), "/server/query", server_query);
+                endpoint(
+                    cfg,
+                    enabled("multi"),
+                    "/server/status/multi",
+                    server_status_multi,
+                );
+                for path in ["/server/image", "/server/image.gif"] {
+                    let resource = web::resource(path)
+                        .route(web::get().to(server_image))
+                        .route(web::post().to(server_image));
+                    endpoint(cfg, enabled("image"), path, resource);
+                }
+                endpoint(cfg, enabled("icon"), "/server/icon", server_icon);
+                endpoint(cfg, enabled("image"), "/server/og-image", server_og_image);
+                endpoint(cfg, enabled("debug"), "/server/debug", server_debug);
+                endpoint(
+                    cfg,
+                    enabled("changes"),
+                    "/server/changes",
+                    changes::server_changes,
+                );
+                endpoint(cfg, enabled("wait"), "/server/wait", wait::server_wait);
+                endpoint(
+                    cfg,
+                    enabled("widget"),
+                    "/server/widget",
+                    status_page::server_widget,
+                );
+                endpoint(
+                    cfg,
+                    enabled("page"),
+                    "/status/{host}",
+                    status_page::status_page,
+                );
+                endpoint(
+                    cfg,
+                    history_enabled && enabled("history"),
+                    "/server/history/uptime",
+                    server_uptime,
+                );
+                endpoint(
+                    cfg,
+                    prometheus_enabled && enabled("metrics"),
+                    "/metrics",
+                    metrics,
+                );
+                endpoint(
+                    cfg,
+                    leaderboard.is_enabled() && enabled("stats"),
+                    "/stats/top",
+                    stats_top,
+                );
+                endpoint(
+                    cfg,
+                    config.aggregate_stats && enabled("stats"),
+                    "/stats",
+                    stats_summary,
+                );
+                endpoint(cfg, enabled("openapi"), "/openapi.json", openapi::spec);
+                endpoint(
+                    cfg,
+                    config.swagger_ui && enabled("openapi"),
+                    "/docs",
+                    openapi::swagger_ui,
+                );
+                endpoint(cfg, admin_enabled, "/admin/monitors", admin::monitors());
+                endpoint(
+                    cfg,
+                    admin_enabled,
+                    "/admin/dashboard",
+                    dashboard::dashboard(),
+                );
+                endpoint(cfg, admin_enabled, "/admin/bans", admin::bans());
+                endpoint(cfg, admin_enabled, "/admin/cache/keys", admin::cache_keys());
+                endpoint(
+                    cfg,
+                    admin_enabled,
+                    "/admin/cache/entry",
+                    admin::cache_entry(),
+                );
+                endpoint(
+                    cfg,
+                    admin_enabled,
+                    "/admin/keys/{id}/usage",
+                    admin::key_usage(),
+                );
+            })
             .service(health)
-            .service(metrics)
+            .service(version)
+            .service(health_ready)
             .service(scripts)
             .service(site)
-            .route(
-                "/",
-                web::get().to(|| async {
-                    HttpResponse::Ok().body(include_str!("../static/site/index.html"))
-                }),
-            )
-    })
-    .bind(listen)?
-    .run()
-    .await
-}
+            .route("/", web::get().to(status_page::landing_page))
+    };
 
-/// Get standard cache-control directives.
-fn get_cache_control() -> CacheControl {
-    CacheControl(vec![
-        CacheDirective::Public,
-        CacheDirective::MaxAge(MAX_AGE as u32),
-        CacheDirective::Extension(
-            "stale-while-revalidate".to_string(),
-            Some(MAX_STALE_AGE.to_string()),
-        ),
-    ])
+    let server = if proxy_protocol {
+        tracing::info!("expecting proxy protocol headers on connections");
+
+        actix_server::Server::build()
+            .bind("mcapi-rs", listen, move || {
+                fn_service(|mut stream: TcpStream| async move {
+                    let peer_addr = timeout(
+                        proxy_protocol::HEADER_TIMEOUT,
+                        proxy_protocol::read_header(&mut stream),
+                    )
+                    .await
+                    .map_err(|_err| DispatchError::SlowRequestTimeout)?
+                    .map_err(|err| {
+                        tracing::debug!("could not read proxy protocol header: {}", err);
+                        DispatchError::Io(err.into())
+                    })?;
+
+                    Ok((stream, Protocol::Http1, peer_addr))
+                })
+                .and_then(HttpService::build().finish(map_config(app(), |_| AppConfig::default())))
+            })?
+            .shutdown_timeout(shutdown_timeout)
+            .run()
+    } else {
+        let server = HttpServer::new(app).shutdown_timeout(shutdown_timeout);
+
+        let server = match tls_config {
+            Some(config) => server.bind_rustls(listen, config)?,
+            None => server.bind(listen)?,
+        };
+
+        server.run()
+    };
+
+    server.await?;
+
+    tracing::info!("server stopped, flushing remaining data");
+
+    // In-flight requests have completed or been dropped by now, only need to
+    // send any remaining telemetry.
+    if let Some((shutdown, handle)) = statsd {
+        let _ = shutdown.send(());
+        if timeout(Duration::from_secs(shutdown_timeout), handle)
+            .await
+            .is_err()
+        {
+            tracing::warn!("timed out flushing statsd metrics");
+        }
+    }
+
+    telemetry::shutdown();
+
+    Ok(())
 }
 
 /// Get the current unix timestamp, as seconds.
@@ -320,69 +2159,130 @@ fn unix_timestamp() -> u64 {
 /// If the key cannot be found or is older than the max age, it will call the
 /// function to calculate the value, then save that value into the same key.
 ///
-/// It locks the key so the value should only be updated exactly once.
+/// It locks the key so the value should only be updated exactly once. If
+/// another request holds the lock and stale data exists, the stale data is
+/// returned instead of waiting for the refresh to finish.
+///
+/// Along with the data, returns if this call refreshed it. Errors from the
+/// function are counted as failed refreshes and stored as the data.
+///
+/// Refreshes taking at least the slow threshold are logged with the time spent
+/// in each phase.
+#[tracing::instrument(skip(cache, f), fields(method = D::NAME))]
 async fn get_cached_data<D, F, Fut>(
-    redis: &RedisClient,
-    locker: &RedLock,
+    cache: &web::Data<dyn CacheStore>,
     key: &str,
     max_age: u32,
+    max_stale_age: u32,
+    lock_ttl: Duration,
+    slow_threshold: Option<Duration>,
     f: F,
-) -> Result<D, Error>
+) -> Result<(D, bool), Error>
 where
     D: Clone + From<Error> + types::Metadata + serde::Serialize + serde::de::DeserializeOwned,
     F: FnOnce() -> Fut,
     Fut: Future<Output = Result<D, Error>>,
 {
-    let mut con = redis.get_async_connection().await?;
+    let mut timings = slow_log::RefreshTimings::default();
+    let start = Instant::now();
 
     // Check if we already have fresh data in cache. If we do, return that.
-    if let Some(value) = con.get::<_, Option<Vec<u8>>>(key).await? {
+    let mut stale = None;
+    if let Some(value) = cache.get(key).await? {
         tracing::trace!("already had value for {} in cache", key);
         let data: D = serde_json::from_slice(&value)?;
 
         if data.updated_at() >= unix_timestamp() - (max_age as u64) {
             tracing::trace!("data is fresh");
-            return Ok(data);
+            CACHE_LOOKUPS.with_label_values(&[D::NAME, "hit"]).inc();
+            return Ok((data, false));
         }
+
+        CACHE_LOOKUPS.with_label_values(&[D::NAME, "stale"]).inc();
+        stale = Some(data);
+    } else {
+        CACHE_LOOKUPS.with_label_values(&[D::NAME, "miss"]).inc();
     }
 
+    timings.lookup = start.elapsed();
+    let start = Instant::now();
+
     // Get exclusive lock to try and update this key.
     let lock_key = format!("lock:{}", key);
     tracing::debug!("wanting to compute new value, requesting lock {}", lock_key);
 
+    // Only refreshes that have to wait for another refresh count as queued.
+    let mut queued = None;
+
     let lock = loop {
-        if let Some(lock) = locker
-            .lock(lock_key.as_bytes(), TIMEOUT_DURATION.as_millis() as usize)
-            .await
-        {
+        if let Some(lock) = cache.lock(lock_key.as_bytes(), lock_ttl).await? {
             break lock;
         }
+
+        CACHE_LOCK_WAITS.with_label_values(&[D::NAME]).inc();
+
+        // Someone else is refreshing the data, so use the stale data instead
+        // of waiting for them to finish.
+        if let Some(data) = stale.take() {
+            tracing::debug!("lock was not available, using stale data");
+            CACHE_STALE_RESPONSES.with_label_values(&[D::NAME]).inc();
+
+            timings.lock = start.elapsed();
+            timings.check(slow_threshold, D::NAME, key);
+
+            return Ok((data, false));
+        }
+
+        queued.get_or_insert_with(load_shed::QueueGuard::enter);
     };
 
+    drop(queued);
+
     tracing::trace!("obtained lock {}", lock_key);
+    let lock = LockGuard {
+        cache: cache.clone(),
+        lock: Some(lock),
+    };
 
     // Make sure potential previous lock owner did not already refresh data.
-    if let Some(value) = con.get::<_, Option<Vec<u8>>>(key).await? {
-        let data: D = serde_json::from_slice(&value)?;
+    let previous = cache.get(key).await?;
+    if let Some(value) = &previous {
+        let data: D = serde_json::from_slice(value)?;
 
         if data.updated_at() >= unix_timestamp() - (max_age as u64) {
             tracing::debug!("data was already updated");
-            locker.unlock(&lock).await;
-            return Ok(data);
+            lock.unlock().await?;
+
+            timings.lock = start.elapsed();
+            timings.check(slow_threshold, D::NAME, key);
+
+            return Ok((data, false));
         }
     }
 
+    timings.lock = start.elapsed();
+
     // Update data and store in cache.
     let now = Instant::now();
-    let data = f().await.unwrap_or_else(D::from);
+    let refreshing = load_shed::RefreshGuard::enter(D::NAME);
+    let data = f().await.unwrap_or_else(|err| {
+        CACHE_REFRESH_FAILURES.with_label_values(&[D::NAME]).inc();
+        dashboard::record_error(D::NAME, key, &err);
+        D::from(err)
+    });
+    drop(refreshing);
     let elapsed = now.elapsed();
+    timings.refresh = elapsed;
 
     // Set when this request was completed and how long it took to complete.
     let data = data.set_times(unix_timestamp(), elapsed.as_nanos() as u64);
 
-    UPDATE_DURATION
-        .with_label_values(&[D::NAME])
-        .observe(elapsed.as_secs_f64());
+    openmetrics::observe(
+        &UPDATE_DURATION,
+        "mcapi_update_duration_seconds",
+        &[("method", D::NAME)],
+        elapsed.as_secs_f64(),
+    );
 
     if data.is_online() {
         SERVER_ONLINE.with_label_values(&[D::NAME]).inc();
@@ -390,12 +2290,58 @@ where
         SERVER_OFFLINE.with_label_values(&[D::NAME]).inc();
     }
 
+    let start = Instant::now();
+
+    // Keep data after it is stale so it can be used while it is refreshed.
     let value = serde_json::to_vec(&data)?;
-    con.set_ex::<_, _, ()>(key, value, max_age as usize).await?;
+    let ttl = max_age.saturating_add(max_stale_age);
+    cache.set(key, value, ttl).await?;
 
-    locker.unlock(&lock).await;
+    lock.unlock().await?;
 
-    Ok(data)
+    // Keep the ping this replaced so changes between checks can be found.
+    if let Some(previous) = previous.filter(|_| D::NAME == "ping") {
+        if let Err(err) = cache.set(&changes::previous_key(key), previous, ttl).await {
+            tracing::warn!("could not store previous data: {:?}", err);
+        }
+    }
+
+    timings.store = start.elapsed();
+    timings.check(slow_threshold, D::NAME, key);
+
+    Ok((data, true))
+}
+
+/// Releases a lock when dropped before it was unlocked, such as when the
+/// request refreshing the data is cancelled, instead of leaving it held until
+/// it expires.
+struct LockGuard {
+    cache: web::Data<dyn CacheStore>,
+    lock: Option<cache::CacheLock>,
+}
+
+impl LockGuard {
+    async fn unlock(mut self) -> Result<(), Error> {
+        match self.lock.take() {
+            Some(lock) => self.cache.unlock(&lock).await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        if let Some(lock) = self.lock.take() {
+            tracing::debug!("refresh was cancelled while holding lock");
+
+            let cache = self.cache.clone();
+            actix_web::rt::spawn(async move {
+                if let Err(err) = cache.unlock(&lock).await {
+                    tracing::warn!("could not release cancelled lock: {}", err);
+                }
+            });
+        }
+    }
 }
 
 /// Ensure a port is something we should be attempting to connect to.
@@ -407,70 +2353,405 @@ fn validate_port(port: u16) -> Result<(), Error> {
     Ok(())
 }
 
-/// Perform a server ping if not already cached, using default ages and
-/// timeouts.
+/// Perform a server ping if not already cached.
 async fn get_ping(
-    redis: &RedisClient,
-    redlock: &RedLock,
+    cache: &web::Data<dyn CacheStore>,
     resolver: &Resolver,
+    config: &Config,
+    monitor: &Monitor,
     host: &str,
     port: u16,
 ) -> types::ServerPing {
+    fetch_ping(
+        cache,
+        resolver,
+        config,
+        monitor,
+        host,
+        port,
+        config.ping_max_age,
+        true,
+    )
+    .await
+    .0
+}
+
+/// Key for a rendered image, changing with anything that changes the image.
+///
+/// Images of offline servers look the same for every server with the same
+/// title and options, so they are shared by all of them instead of being
+/// rendered again for each server and refresh. How long ago the server was
+/// checked is shown as it was when the image was first rendered.
+///
+/// The standard hasher may change between Rust versions, which only causes
+/// images to be rendered again.
+fn image_cache_key(
+    req: &ServerImageRequest,
+    host: &str,
+    port: u16,
+    format: image::ImageFormat,
+    quality: u8,
+    language: Option<&str>,
+    data: &types::ServerPing,
+) -> String {
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+
+    if !data.online {
+        let options = ServerImageRequest {
+            host: String::new(),
+            port: None,
+            ..req.clone()
+        };
+        (options, req.title(), format, quality, language).hash(&mut hasher);
+
+        // Offline images show how many minutes ago the server was checked.
+        (data.last_updated / 60).hash(&mut hasher);
+
+        return format!("image:offline:{:016x}", hasher.finish());
+    }
+
+    let server = resolver::server_key(host, port);
+    (req, format, quality, language).hash(&mut hasher);
+
+    // Player names may come from query data, which is updated separately.
+    data.last_updated.hash(&mut hasher);
+    for player in &data.players.sample {
+        player.name.hash(&mut hasher);
+    }
+
+    format!("image:{}:{:016x}", server, hasher.finish())
+}
+
+/// Get a rendered image from the cache, if it exists.
+async fn cached_image(cache: &dyn CacheStore, key: &str) -> Option<Vec<u8>> {
+    let image = match cache.get(key).await {
+        Ok(image) => image,
+        Err(err) => {
+            tracing::warn!("could not load cached image: {:?}", err);
+            None
+        }
+    };
+
+    let result = if image.is_some() { "hit" } else { "miss" };
+    CACHE_LOOKUPS.with_label_values(&["image", result]).inc();
+
+    image
+}
+
+/// Get query data that is already cached, without querying the server.
+async fn cached_query(cache: &dyn CacheStore, host: &str, port: u16) -> Option<types::ServerQuery> {
+    let host = resolver::normalize_host(host).ok()?;
+    let value = cache
+        .get(&format!("query:{}:{}", host, port))
+        .await
+        .ok()??;
+
+    serde_json::from_slice(&value).ok()
+}
+
+/// Perform a server ping if the cached data is older than the max age,
+/// returning the data and if the server was checked for this call.
+///
+/// Without `srv`, the host's SRV record is ignored and the result is cached
+/// separately from normal pings.
+#[allow(clippy::too_many_arguments)]
+async fn fetch_ping(
+    cache: &web::Data<dyn CacheStore>,
+    resolver: &Resolver,
+    config: &Config,
+    monitor: &Monitor,
+    host: &str,
+    port: u16,
+    max_age: u32,
+    srv: bool,
+) -> (types::ServerPing, bool) {
     if let Err(err) = validate_port(port) {
         tracing::warn!("Got request for invalid port: {}", port);
-        return err.into();
+        return (err.into(), true);
     }
 
-    get_cached_data(
-        redis,
-        redlock,
-        &format!("ping:{}:{}", host, port),
-        MAX_AGE,
-        || async {
-            let addr = resolver
-                .lookup(host.to_owned(), port)
-                .await
-                .ok_or(Error::ResolveFailed)?;
+    let host = match resolver::normalize_host(host) {
+        Ok(host) => host,
+        Err(err) => {
+            tracing::warn!("Got request for invalid host: {}", host);
+            return (err.into(), true);
+        }
+    };
+    let host = host.as_str();
 
-            let data = timeout(TIMEOUT_DURATION, protocol::send_ping(addr, host, port)).await??;
+    let (key, path) = if srv {
+        (format!("ping:{}:{}", host, port), "server/status")
+    } else {
+        (
+            format!("ping:{}:{}:no_srv", host, port),
+            "server/status?no_srv=true",
+        )
+    };
 
-            Ok(types::ServerPing::from(data))
+    let result = get_cached_data(
+        cache,
+        &key,
+        max_age,
+        config.ping_max_stale_age,
+        config.refresh_timeout(),
+        config.slow_threshold(),
+        || async {
+            match &config.upstream_url {
+                Some(url) => upstream::fetch(url, path, host, port, config.refresh_timeout()).await,
+                None => ping_server(resolver, config, host, port, srv).await,
+            }
         },
     )
-    .await
-    .unwrap_or_else(From::from)
+    .await;
+
+    let (data, refreshed) = match result {
+        Ok(result) => result,
+        Err(err) => return (err.into(), true),
+    };
+
+    // Only after the data is stored does it have when it was checked and how
+    // long it took.
+    if refreshed {
+        // Monitors and history only follow the server as players see it.
+        if srv {
+            monitor.refreshed(cache.get_ref(), host, port, &data).await;
+        }
+        stats::record_check(config, cache.get_ref(), &data).await;
+    }
+
+    (data, refreshed)
+}
+
+/// Ping a server directly, without the cache, optionally ignoring its SRV
+/// record.
+async fn ping_server(
+    resolver: &Resolver,
+    config: &Config,
+    host: &str,
+    port: u16,
+    srv: bool,
+) -> Result<types::ServerPing, Error> {
+    let timeouts = config.timeouts();
+    let limits = config.limits();
+    let outbound = config.outbound();
+
+    let (ping, srv_target) = timeout(timeouts.total, async {
+        let lookup = async {
+            if srv {
+                resolver.lookup_target(host.to_owned(), port).await
+            } else {
+                resolver
+                    .lookup_target_without_srv(host.to_owned(), port)
+                    .await
+            }
+        };
+
+        let target = timeout(timeouts.dns, lookup).await?;
+        if target.addrs.is_empty() {
+            return Err(Error::ResolveFailed);
+        }
+
+        let ping = FutureRetry::new(
+            || protocol::send_ping(&target.addrs, host, port, &timeouts, &limits, &outbound),
+            config.retry(),
+        )
+        .await
+        .map(|(ping, _attempts)| ping)
+        .map_err(|(err, _attempts)| err)?;
+
+        Ok((ping, target.srv_target))
+    })
+    .await??;
+
+    let mut data = types::ServerPing::from(ping);
+    if let Some(resolved) = &mut data.resolved {
+        resolved.srv_target = srv_target;
+    }
+
+    Ok(data)
 }
 
-/// Perform a server query if not already cached, using default ages and
-/// timeouts.
+/// Perform a server query if not already cached, returning the data and if
+/// the server was queried for this call.
 async fn get_query(
-    redis: &RedisClient,
-    redlock: &RedLock,
+    cache: &web::Data<dyn CacheStore>,
     resolver: &Resolver,
+    config: &Config,
     host: &str,
     port: u16,
-) -> types::ServerQuery {
+) -> (types::ServerQuery, bool) {
     if let Err(err) = validate_port(port) {
         tracing::warn!("Got request for invalid port: {}", port);
-        return err.into();
+        return (err.into(), true);
     }
 
+    let host = match resolver::normalize_host(host) {
+        Ok(host) => host,
+        Err(err) => {
+            tracing::warn!("Got request for invalid host: {}", host);
+            return (err.into(), true);
+        }
+    };
+    let host = host.as_str();
+
     get_cached_data(
-        redis,
-        redlock,
+        cache,
         &format!("query:{}:{}", host, port),
-        MAX_AGE,
+        config.query_max_age,
+        config.query_max_stale_age,
+        config.refresh_timeout(),
+        config.slow_threshold(),
         || async {
-            let addr = resolver
-                .lookup(host.to_owned(), port)
+            if let Some(url) = &config.upstream_url {
+                return upstream::fetch(url, "server/query", host, port, config.refresh_timeout())
+                    .await;
+            }
+
+            let timeouts = config.timeouts();
+            let outbound = config.outbound();
+
+            let (data, resolved) = timeout(timeouts.total, async {
+                let target =
+                    timeout(timeouts.dns, resolver.lookup_target(host.to_owned(), port)).await?;
+                let addr = target.addrs.first().copied().ok_or(Error::ResolveFailed)?;
+
+                let query = FutureRetry::new(
+                    || protocol::send_query(addr, &timeouts, &outbound),
+                    config.retry(),
+                )
                 .await
-                .ok_or(Error::ResolveFailed)?;
+                .map(|(query, _attempts)| query)
+                .map_err(|(err, _attempts)| err)?;
 
-            let data = timeout(TIMEOUT_DURATION, protocol::send_query(addr)).await??;
+                Ok::<_, Error>((query, types::ResolvedAddress::new(addr, target.srv_target)))
+            })
+            .await??;
 
-            Ok(types::ServerQuery::from(data))
+            Ok(types::ServerQuery {
+                resolved: Some(resolved),
+                ..types::ServerQuery::from(data)
+            })
         },
     )
     .await
-    .unwrap_or_else(From::from)
+    .unwrap_or_else(|err| (err.into(), true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_cache_key() {
+        let req = |query: &str| {
+            web::Query::<ServerImageRequest>::from_query(query)
+                .unwrap()
+                .into_inner()
+        };
+        let key = |req: &ServerImageRequest, data: &types::ServerPing| {
+            let (host, port) = req.parse_host();
+            image_cache_key(req, host, port, image::ImageFormat::Png, 0, None, data)
+        };
+
+        let offline = types::ServerPing {
+            last_updated: 120,
+            ..Default::default()
+        };
+        let same_minute = types::ServerPing {
+            last_updated: 150,
+            ..Default::default()
+        };
+        let checked_again = types::ServerPing {
+            last_updated: 500,
+            ..Default::default()
+        };
+
+        // Offline images are shared by every server with the same title that
+        // was checked in the same minute.
+        let first = req("ip=a.example.com&title=Server&theme=dark");
+        let second = req("ip=b.example.com:25566&title=Server&theme=dark");
+        assert!(first.cacheable(&offline));
+        assert_eq!(key(&first, &offline), key(&second, &same_minute));
+        assert_ne!(key(&first, &offline), key(&second, &checked_again));
+        assert_ne!(
+            key(&first, &offline),
+            key(&req("ip=a.example.com&theme=dark"), &offline)
+        );
+        assert_ne!(
+            key(&first, &offline),
+            key(&req("ip=a.example.com&title=Server"), &offline)
+        );
+
+        let online = types::ServerPing {
+            online: true,
+            ..offline.clone()
+        };
+        let refreshed = types::ServerPing {
+            online: true,
+            ..checked_again
+        };
+        assert!(!first.cacheable(&online));
+        assert!(req("ip=a.example.com&theme=dark").cacheable(&online));
+        assert_ne!(key(&first, &online), key(&first, &refreshed));
+        assert_ne!(key(&first, &online), key(&second, &online));
+    }
+
+    #[tokio::test]
+    async fn test_get_cached_data_stale() {
+        let cache: web::Data<dyn CacheStore> =
+            web::Data::from(Arc::new(cache::MemoryStore::new(10)) as Arc<dyn CacheStore>);
+
+        let stale = types::ServerPing {
+            online: true,
+            last_updated: unix_timestamp() - 600,
+            ..Default::default()
+        };
+        cache
+            .set(
+                "ping:example.com:25565",
+                serde_json::to_vec(&stale).unwrap(),
+                3600,
+            )
+            .await
+            .unwrap();
+
+        // Another refresh holds the lock, so the stale data is used instead
+        // of checking the server.
+        let _lock = cache
+            .lock(b"lock:ping:example.com:25565", Duration::from_secs(60))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let (data, refreshed): (types::ServerPing, bool) = get_cached_data(
+            &cache,
+            "ping:example.com:25565",
+            60,
+            3600,
+            Duration::from_secs(1),
+            None,
+            || async { Err(Error::ResolveFailed) },
+        )
+        .await
+        .unwrap();
+
+        assert!(!refreshed);
+        assert!(data.online);
+        assert_eq!(data.last_updated, stale.last_updated);
+    }
+
+    #[test]
+    fn test_split_hosts() {
+        assert_eq!(
+            split_hosts(" example.com , mc.example.com:25566,,"),
+            vec!["example.com", "mc.example.com:25566"]
+        );
+        assert_eq!(
+            split_hosts("example.com,Example.com, example.com:25565"),
+            vec!["example.com", "example.com:25565"]
+        );
+        assert!(split_hosts(" , ").is_empty());
+    }
 }
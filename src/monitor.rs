@@ -0,0 +1,345 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use actix_web::web;
+use serde::Serialize;
+use sqlx::PgPool;
+
+use mcapi_rs::{
+    resolver::{self, Resolver},
+    types::ServerPing,
+};
+
+use crate::{
+    alerts::{AlertRule, Alerts},
+    cache::CacheStore,
+    config::Config,
+    events::Events,
+    history::History,
+};
+
+/// Longest interval a server may be registered with, in seconds.
+pub const MAX_INTERVAL: u32 = 60 * 60 * 24;
+
+/// How often the scheduler looks for servers that are due to be refreshed.
+const SCHEDULER_TICK: Duration = Duration::from_secs(1);
+
+/// How often the scheduler reloads registrations, to pick up changes made
+/// through other instances.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Seconds subtracted from a registration's interval when deciding if cached
+/// data should be refreshed.
+const SCHEDULE_SLACK: u32 = 5;
+
+/// A server registered to be refreshed on a schedule.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Registration {
+    pub host: String,
+    pub port: u16,
+    /// Seconds between refreshes.
+    pub interval: u32,
+    /// Rules to notify about when refreshed.
+    pub alerts: Vec<AlertRule>,
+}
+
+impl Registration {
+    fn key(&self) -> String {
+        resolver::server_key(&self.host, self.port)
+    }
+
+    /// Maximum age of cached data when the scheduler checks this server.
+    ///
+    /// Data is saved after the refresh finishes, so this is slightly shorter
+    /// than the interval to make sure data is stale by the next check.
+    fn max_age(&self, ping_max_age: u32) -> u32 {
+        self.interval
+            .min(ping_max_age)
+            .saturating_sub(SCHEDULE_SLACK)
+    }
+}
+
+/// Servers that have every refresh recorded and published.
+///
+/// Servers are either configured by host or registered at runtime. Registered
+/// servers are saved to the database if one is available, otherwise they are
+/// only kept until restarting.
+pub struct Monitor {
+    pub hosts: HashSet<String>,
+    pub history: History,
+    pub events: Events,
+    alerts: Alerts,
+    pool: Option<PgPool>,
+    registrations: RwLock<HashMap<String, Registration>>,
+}
+
+impl Monitor {
+    /// Create a monitor for a list of `host:port` entries.
+    pub fn new(hosts: &[String], pool: Option<PgPool>, events: Events) -> Self {
+        Self {
+            hosts: hosts.iter().map(|host| resolver::entry_key(host)).collect(),
+            history: pool.clone().map(History::new).unwrap_or_default(),
+            events,
+            alerts: Alerts::default(),
+            pool,
+            registrations: Default::default(),
+        }
+    }
+
+    /// If a server is monitored.
+    pub fn is_monitored(&self, host: &str, port: u16) -> bool {
+        let key = resolver::server_key(host, port);
+
+        self.hosts.contains(&key) || self.registrations.read().unwrap().contains_key(&key)
+    }
+
+    /// All registered servers, ordered by address.
+    pub fn registrations(&self) -> Vec<Registration> {
+        let mut registrations: Vec<_> = self
+            .registrations
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect();
+
+        registrations.sort_by(|a, b| (&a.host, a.port).cmp(&(&b.host, b.port)));
+        registrations
+    }
+
+    /// Register a server, or replace the interval and alerts of an existing
+    /// registration.
+    pub async fn register(&self, registration: Registration) -> Result<(), sqlx::Error> {
+        if let Some(pool) = &self.pool {
+            // Rules only contain strings and numbers, which always serialize.
+            let alerts = serde_json::to_string(&registration.alerts).unwrap();
+
+            sqlx::query(
+                "INSERT INTO monitored_servers (host, port, interval_secs, alerts)
+                VALUES ($1, $2, $3, $4::jsonb)
+                ON CONFLICT (host, port) DO UPDATE SET
+                    interval_secs = excluded.interval_secs,
+                    alerts = excluded.alerts",
+            )
+            .bind(&registration.host)
+            .bind(i32::from(registration.port))
+            .bind(registration.interval as i32)
+            .bind(alerts)
+            .execute(pool)
+            .await?;
+        }
+
+        self.registrations
+            .write()
+            .unwrap()
+            .insert(registration.key(), registration);
+
+        Ok(())
+    }
+
+    /// Remove a registered server, returning if it was registered.
+    pub async fn unregister(&self, host: &str, port: u16) -> Result<bool, sqlx::Error> {
+        let host = resolver::normalize_host(host).unwrap_or_else(|_err| host.to_lowercase());
+
+        let deleted = match &self.pool {
+            Some(pool) => {
+                sqlx::query("DELETE FROM monitored_servers WHERE host = $1 AND port = $2")
+                    .bind(&host)
+                    .bind(i32::from(port))
+                    .execute(pool)
+                    .await?
+                    .rows_affected()
+                    > 0
+            }
+            None => false,
+        };
+
+        let removed = self
+            .registrations
+            .write()
+            .unwrap()
+            .remove(&format!("{}:{}", host, port))
+            .is_some();
+
+        Ok(deleted || removed)
+    }
+
+    /// Replace registrations with those saved in the database.
+    pub async fn load_registrations(&self) -> Result<(), sqlx::Error> {
+        let pool = match &self.pool {
+            Some(pool) => pool,
+            None => return Ok(()),
+        };
+
+        let rows: Vec<(String, i32, i32, String)> =
+            sqlx::query_as("SELECT host, port, interval_secs, alerts::text FROM monitored_servers")
+                .fetch_all(pool)
+                .await?;
+
+        let registrations = rows
+            .into_iter()
+            .map(|(host, port, interval, alerts)| {
+                let alerts = serde_json::from_str(&alerts).unwrap_or_else(|err| {
+                    tracing::error!("invalid alerts for {}:{}: {}", host, port, err);
+                    Vec::new()
+                });
+
+                Registration {
+                    host,
+                    port: port as u16,
+                    interval: interval as u32,
+                    alerts,
+                }
+            })
+            .map(|registration| (registration.key(), registration))
+            .collect();
+
+        *self.registrations.write().unwrap() = registrations;
+
+        Ok(())
+    }
+
+    /// Handle the result of refreshing a server, if it is monitored.
+    pub async fn refreshed(
+        &self,
+        cache: &dyn CacheStore,
+        host: &str,
+        port: u16,
+        data: &ServerPing,
+    ) {
+        if !self.is_monitored(host, port) {
+            return;
+        }
+
+        self.history.record(host, port, data);
+
+        if let Err(err) = self.events.observe(cache, host, port, data).await {
+            tracing::error!("could not check {}:{} for changes: {}", host, port, err);
+        }
+
+        let rules = self
+            .registrations
+            .read()
+            .unwrap()
+            .get(&resolver::server_key(host, port))
+            .map(|registration| registration.alerts.clone())
+            .unwrap_or_default();
+
+        if let Err(err) = self.alerts.check(cache, host, port, &rules, data).await {
+            tracing::error!("could not check {}:{} for alerts: {}", host, port, err);
+        }
+    }
+}
+
+/// Refresh registered servers at their intervals, even when no requests are
+/// made for them.
+///
+/// Refreshes go through the cache, so instances sharing a cache only ping each
+/// server once per interval.
+pub async fn run_scheduler(
+    monitor: web::Data<Monitor>,
+    cache: web::Data<dyn CacheStore>,
+    resolver: web::Data<Resolver>,
+    config: web::Data<Config>,
+) {
+    let mut tick = tokio::time::interval(SCHEDULER_TICK);
+    let mut next_checks: HashMap<String, Instant> = HashMap::new();
+    let mut last_reload = Instant::now();
+
+    loop {
+        tick.tick().await;
+
+        if last_reload.elapsed() >= RELOAD_INTERVAL {
+            if let Err(err) = monitor.load_registrations().await {
+                tracing::error!("could not reload monitored servers: {}", err);
+            }
+
+            last_reload = Instant::now();
+        }
+
+        let now = Instant::now();
+        let registrations = monitor.registrations();
+
+        next_checks.retain(|key, _next| {
+            registrations
+                .iter()
+                .any(|registration| &registration.key() == key)
+        });
+
+        for registration in registrations {
+            let next = next_checks.entry(registration.key()).or_insert(now);
+            if *next > now {
+                continue;
+            }
+
+            *next = now + Duration::from_secs(registration.interval.into());
+
+            let monitor = monitor.clone();
+            let cache = cache.clone();
+            let resolver = resolver.clone();
+            let config = config.clone();
+
+            actix_web::rt::spawn(async move {
+                tracing::debug!(
+                    "refreshing scheduled server {}:{}",
+                    registration.host,
+                    registration.port
+                );
+
+                crate::fetch_ping(
+                    &cache,
+                    &resolver,
+                    &config,
+                    &monitor,
+                    &registration.host,
+                    registration.port,
+                    registration.max_age(config.ping_max_age),
+                    true,
+                )
+                .await;
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_registrations() {
+        let monitor = Monitor::new(&["Example.com:25565".to_string()], None, Events::default());
+        assert!(monitor.is_monitored("example.com", 25565));
+        assert!(!monitor.is_monitored("mc.example.com", 25566));
+
+        let registration = Registration {
+            host: "mc.example.com".to_string(),
+            port: 25566,
+            interval: 60,
+            alerts: Vec::new(),
+        };
+        monitor.register(registration.clone()).await.unwrap();
+        assert!(monitor.is_monitored("MC.example.com", 25566));
+        assert_eq!(monitor.registrations(), vec![registration]);
+
+        assert!(monitor.unregister("MC.example.com", 25566).await.unwrap());
+        assert!(!monitor.unregister("mc.example.com", 25566).await.unwrap());
+        assert!(!monitor.is_monitored("mc.example.com", 25566));
+    }
+
+    #[test]
+    fn test_registration_max_age() {
+        let registration = Registration {
+            host: "mc.example.com".to_string(),
+            port: 25565,
+            interval: 60,
+            alerts: Vec::new(),
+        };
+
+        assert_eq!(registration.max_age(300), 55);
+        assert_eq!(registration.max_age(30), 25);
+    }
+}
@@ -0,0 +1,371 @@
+//! Parse server descriptions into styled text, as shown in the server list.
+//!
+//! Descriptions are chat components, which may be plain strings, objects with
+//! styles and children, or arrays. Any text may also contain legacy formatting
+//! codes, a `§` followed by a color or style code.
+
+use std::str::FromStr;
+
+use serde_json::Value;
+
+/// An RGB color.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Color(pub u8, pub u8, pub u8);
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid color: {0}")]
+pub struct InvalidColor(String);
+
+/// Named colors, in the order of their legacy formatting codes.
+const COLORS: [(&str, Color); 16] = [
+    ("black", Color(0x00, 0x00, 0x00)),
+    ("dark_blue", Color(0x00, 0x00, 0xAA)),
+    ("dark_green", Color(0x00, 0xAA, 0x00)),
+    ("dark_aqua", Color(0x00, 0xAA, 0xAA)),
+    ("dark_red", Color(0xAA, 0x00, 0x00)),
+    ("dark_purple", Color(0xAA, 0x00, 0xAA)),
+    ("gold", Color(0xFF, 0xAA, 0x00)),
+    ("gray", Color(0xAA, 0xAA, 0xAA)),
+    ("dark_gray", Color(0x55, 0x55, 0x55)),
+    ("blue", Color(0x55, 0x55, 0xFF)),
+    ("green", Color(0x55, 0xFF, 0x55)),
+    ("aqua", Color(0x55, 0xFF, 0xFF)),
+    ("red", Color(0xFF, 0x55, 0x55)),
+    ("light_purple", Color(0xFF, 0x55, 0xFF)),
+    ("yellow", Color(0xFF, 0xFF, 0x55)),
+    ("white", Color(0xFF, 0xFF, 0xFF)),
+];
+
+impl Color {
+    /// Parse a named color or a hex color like `#55ff55`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        if let Some(hex) = name.strip_prefix('#') {
+            return Self::from_hex(hex);
+        }
+
+        COLORS
+            .iter()
+            .find(|(color_name, _color)| *color_name == name)
+            .map(|(_name, color)| *color)
+    }
+
+    /// Parse a six digit hex color, without a leading `#`.
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+
+        let channel = |index: usize| u8::from_str_radix(&hex[index..index + 2], 16).ok();
+
+        Some(Self(channel(0)?, channel(2)?, channel(4)?))
+    }
+
+    /// Parse a hex color with three or six digits and an optional leading `#`.
+    fn from_short_hex(value: &str) -> Option<Self> {
+        let hex = value.strip_prefix('#').unwrap_or(value);
+
+        if hex.len() == 3 && hex.is_ascii() {
+            let expanded: String = hex.chars().flat_map(|c| [c, c]).collect();
+            return Self::from_hex(&expanded);
+        }
+
+        Self::from_hex(hex)
+    }
+
+    /// Get the color for a legacy formatting code.
+    fn from_code(code: char) -> Option<Self> {
+        code.to_digit(16).map(|index| COLORS[index as usize].1)
+    }
+}
+
+impl FromStr for Color {
+    type Err = InvalidColor;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_short_hex(s).ok_or_else(|| InvalidColor(s.to_string()))
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Color {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// How a span of text is displayed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Style {
+    /// Color of the text, or the default color if none.
+    pub color: Option<Color>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underlined: bool,
+    pub strikethrough: bool,
+    pub obfuscated: bool,
+}
+
+impl Style {
+    /// Apply styles set on a chat component, keeping inherited styles that
+    /// were not set.
+    fn merge(mut self, component: &serde_json::Map<String, Value>) -> Self {
+        if let Some(color) = component
+            .get("color")
+            .and_then(Value::as_str)
+            .and_then(Color::from_name)
+        {
+            self.color = Some(color);
+        }
+
+        let flag = |name: &str, current: bool| {
+            component
+                .get(name)
+                .and_then(Value::as_bool)
+                .unwrap_or(current)
+        };
+
+        self.bold = flag("bold", self.bold);
+        self.italic = flag("italic", self.italic);
+        self.underlined = flag("underlined", self.underlined);
+        self.strikethrough = flag("strikethrough", self.strikethrough);
+        self.obfuscated = flag("obfuscated", self.obfuscated);
+
+        self
+    }
+}
+
+/// Text displayed with a single style.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub text: String,
+    pub style: Style,
+}
+
+/// Parse a description into styled spans of text.
+///
+/// Spans may contain newlines, see [`lines`] to split them.
+pub fn parse(description: &Value) -> Vec<Span> {
+    let mut spans = Vec::new();
+    parse_component(description, Style::default(), &mut spans);
+    spans
+}
+
+fn parse_component(component: &Value, parent: Style, spans: &mut Vec<Span>) {
+    match component {
+        Value::String(text) => parse_legacy(text, parent, spans),
+        Value::Array(components) => {
+            for component in components {
+                parse_component(component, parent, spans);
+            }
+        }
+        Value::Object(component) => {
+            let style = parent.merge(component);
+
+            if let Some(text) = component.get("text").and_then(Value::as_str) {
+                parse_legacy(text, style, spans);
+            }
+
+            if let Some(extra) = component.get("extra") {
+                parse_component(extra, style, spans);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Split text containing legacy formatting codes into spans.
+///
+/// A color code resets any other formatting, and `r` resets to the style the
+/// text started with.
+fn parse_legacy(text: &str, base: Style, spans: &mut Vec<Span>) {
+    let mut style = base;
+    let mut current = String::new();
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '§' {
+            current.push(c);
+            continue;
+        }
+
+        let code = match chars.next() {
+            Some(code) => code.to_ascii_lowercase(),
+            None => break,
+        };
+
+        let next = match code {
+            'k' => Style {
+                obfuscated: true,
+                ..style
+            },
+            'l' => Style {
+                bold: true,
+                ..style
+            },
+            'm' => Style {
+                strikethrough: true,
+                ..style
+            },
+            'n' => Style {
+                underlined: true,
+                ..style
+            },
+            'o' => Style {
+                italic: true,
+                ..style
+            },
+            'r' => base,
+            code => match Color::from_code(code) {
+                Some(color) => Style {
+                    color: Some(color),
+                    ..Style::default()
+                },
+                None => continue,
+            },
+        };
+
+        if next != style {
+            push_span(spans, std::mem::take(&mut current), style);
+            style = next;
+        }
+    }
+
+    push_span(spans, current, style);
+}
+
+/// Add a span, combining it with the previous span if they share a style.
+fn push_span(spans: &mut Vec<Span>, text: String, style: Style) {
+    if text.is_empty() {
+        return;
+    }
+
+    match spans.last_mut() {
+        Some(last) if last.style == style => last.text.push_str(&text),
+        _ => spans.push(Span { text, style }),
+    }
+}
+
+/// Split spans into lines at each newline.
+pub fn lines(spans: &[Span]) -> Vec<Vec<Span>> {
+    let mut lines = vec![Vec::new()];
+
+    for span in spans {
+        for (index, text) in span.text.split('\n').enumerate() {
+            if index > 0 {
+                lines.push(Vec::new());
+            }
+
+            if !text.is_empty() {
+                lines.last_mut().unwrap().push(Span {
+                    text: text.to_string(),
+                    style: span.style,
+                });
+            }
+        }
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(text: &str, style: Style) -> Span {
+        Span {
+            text: text.to_string(),
+            style,
+        }
+    }
+
+    #[test]
+    fn test_parse_legacy() {
+        let spans = parse(&Value::String("§aGreen §lbold§r plain§z".to_string()));
+
+        let green = Style {
+            color: Some(Color(0x55, 0xFF, 0x55)),
+            ..Style::default()
+        };
+
+        assert_eq!(
+            spans,
+            vec![
+                span("Green ", green),
+                span(
+                    "bold",
+                    Style {
+                        bold: true,
+                        ..green
+                    }
+                ),
+                span(" plain", Style::default()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_components() {
+        let description = serde_json::json!({
+            "text": "A ",
+            "color": "gold",
+            "extra": [
+                {"text": "Minecraft", "bold": true},
+                " Server",
+                {"text": "\n§7Line two", "color": "#123456"},
+            ],
+        });
+
+        let gold = Style {
+            color: Some(Color(0xFF, 0xAA, 0x00)),
+            ..Style::default()
+        };
+
+        let spans = parse(&description);
+        assert_eq!(
+            spans,
+            vec![
+                span("A ", gold),
+                span("Minecraft", Style { bold: true, ..gold }),
+                span(" Server", gold),
+                span(
+                    "\n",
+                    Style {
+                        color: Some(Color(0x12, 0x34, 0x56)),
+                        ..Style::default()
+                    }
+                ),
+                span(
+                    "Line two",
+                    Style {
+                        color: Some(Color(0xAA, 0xAA, 0xAA)),
+                        ..Style::default()
+                    }
+                ),
+            ]
+        );
+
+        let lines = lines(&spans);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].len(), 3);
+        assert_eq!(lines[1], vec![spans[4].clone()]);
+    }
+
+    #[test]
+    fn test_color_from_name() {
+        assert_eq!(Color::from_name("dark_red"), Some(Color(0xAA, 0x00, 0x00)));
+        assert_eq!(Color::from_name("#ff00AA"), Some(Color(0xFF, 0x00, 0xAA)));
+        assert_eq!(Color::from_name("#ff00"), None);
+        assert_eq!(Color::from_name("rainbow"), None);
+    }
+
+    #[test]
+    fn test_color_from_str() {
+        assert_eq!("23272a".parse::<Color>().unwrap(), Color(0x23, 0x27, 0x2A));
+        assert_eq!("#FFF".parse::<Color>().unwrap(), Color(0xFF, 0xFF, 0xFF));
+        assert!("gold".parse::<Color>().is_err());
+        assert!("12345g".parse::<Color>().is_err());
+    }
+}
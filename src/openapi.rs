@@ -0,0 +1,75 @@
+//! OpenAPI description of the public endpoints, generated from annotations on
+//! the handlers and the types they respond with.
+
+use actix_web::{get, http::header::ContentType, HttpResponse, Responder};
+use utoipa::OpenApi;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Minecraft API",
+        description = "Check the status of Minecraft servers and generate images of them."
+    ),
+    paths(
+        crate::server_status,
+        crate::server_query,
+        crate::server_status_multi,
+        crate::server_image,
+        crate::server_icon,
+        crate::server_og_image,
+        crate::server_debug,
+        crate::server_uptime,
+        crate::wait::server_wait,
+        crate::changes::server_changes,
+        crate::status_page::server_widget,
+        crate::stats_top,
+        crate::stats_summary,
+        crate::version,
+    ),
+    components(schemas(
+        mcapi_rs::types::ServerPing,
+        mcapi_rs::types::ServerPingPlayers,
+        mcapi_rs::types::ServerPingServer,
+        mcapi_rs::types::ServerQuery,
+        mcapi_rs::types::ServerQueryPlayers,
+        mcapi_rs::types::ResolvedAddress,
+        mcapi_rs::types::OfflineReason,
+        mcapi_rs::protocol::PlayerSample,
+        crate::CachedServerPing,
+        crate::CachedServerQuery,
+        crate::StatusResponse,
+        crate::MultiStatus,
+        crate::RegionStatus,
+        crate::UptimeResponse,
+        crate::history::Uptime,
+        crate::wait::WaitResponse,
+        crate::changes::ServerChanges,
+        crate::changes::OnlineChange,
+        crate::TopResponse,
+        crate::stats::TopServer,
+        crate::StatsResponse,
+        crate::stats::AggregateStats,
+        crate::VersionResponse,
+    )),
+    tags(
+        (name = "server", description = "Status of servers"),
+        (name = "image", description = "Images of servers to embed in pages"),
+        (name = "stats", description = "Usage across every instance"),
+        (name = "meta", description = "Information about the running build"),
+    )
+)]
+pub struct ApiDoc;
+
+#[get("/openapi.json")]
+pub async fn spec() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+/// Swagger UI for the document, loading its assets from a CDN so they do not
+/// need to be bundled.
+#[get("/docs")]
+pub async fn swagger_ui() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(include_str!("../static/docs.html"))
+}
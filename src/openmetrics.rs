@@ -0,0 +1,339 @@
+use std::{collections::HashMap, fmt::Write, sync::Mutex, time::Instant};
+
+use lazy_static::lazy_static;
+use opentelemetry::trace::TraceContextExt;
+use prometheus::{
+    proto::{MetricFamily, MetricType},
+    HistogramVec, DEFAULT_BUCKETS,
+};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Content type for OpenMetrics responses.
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// An example observation of a histogram bucket, linked to a trace.
+#[derive(Clone, Debug)]
+struct Exemplar {
+    trace_id: String,
+    value: f64,
+    timestamp: f64,
+}
+
+/// Identifies a single histogram bucket: the metric name, its sorted labels,
+/// and the bucket's upper bound.
+type BucketKey = (String, Vec<(String, String)>, u64);
+
+lazy_static! {
+    static ref EXEMPLARS: Mutex<HashMap<BucketKey, Exemplar>> = Default::default();
+}
+
+/// Get the trace ID of the current span, if it is being traced.
+fn current_trace_id() -> Option<String> {
+    let context = tracing::Span::current().context();
+    let span_context = context.span().span_context().clone();
+
+    if span_context.is_valid() {
+        Some(span_context.trace_id().to_string())
+    } else {
+        None
+    }
+}
+
+/// Sort labels by name, matching the order they are gathered in.
+fn sorted_labels(labels: &[(&str, &str)]) -> Vec<(String, String)> {
+    let mut labels: Vec<_> = labels
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    labels.sort();
+    labels
+}
+
+/// Observe a value for a histogram using the default buckets, saving an
+/// exemplar for the bucket if there is a current trace.
+pub fn observe(histogram: &HistogramVec, name: &str, labels: &[(&str, &str)], value: f64) {
+    let label_values: Vec<&str> = labels.iter().map(|(_name, value)| *value).collect();
+    histogram.with_label_values(&label_values).observe(value);
+
+    let trace_id = match current_trace_id() {
+        Some(trace_id) => trace_id,
+        None => return,
+    };
+
+    let upper_bound = DEFAULT_BUCKETS
+        .iter()
+        .copied()
+        .find(|bound| value <= *bound)
+        .unwrap_or(f64::INFINITY);
+
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64();
+
+    EXEMPLARS.lock().unwrap().insert(
+        (
+            name.to_string(),
+            sorted_labels(labels),
+            upper_bound.to_bits(),
+        ),
+        Exemplar {
+            trace_id,
+            value,
+            timestamp,
+        },
+    );
+}
+
+/// A timer that observes the elapsed time, with an exemplar, when dropped.
+pub struct HistogramTimer {
+    histogram: &'static HistogramVec,
+    name: &'static str,
+    labels: Vec<(&'static str, &'static str)>,
+    start: Instant,
+}
+
+impl HistogramTimer {
+    pub fn new(
+        histogram: &'static HistogramVec,
+        name: &'static str,
+        labels: &[(&'static str, &'static str)],
+    ) -> Self {
+        Self {
+            histogram,
+            name,
+            labels: labels.to_vec(),
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for HistogramTimer {
+    fn drop(&mut self) {
+        observe(
+            self.histogram,
+            self.name,
+            &self.labels,
+            self.start.elapsed().as_secs_f64(),
+        );
+    }
+}
+
+/// Escape a label value for the text format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+/// Format a float the way OpenMetrics expects.
+fn format_float(value: f64) -> String {
+    if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else if value.is_nan() {
+        "NaN".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Format labels, with an optional extra label, as `{name="value",...}`.
+fn format_labels(labels: &[(String, String)], extra: Option<(&str, &str)>) -> String {
+    let mut pairs: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| format!(r#"{}="{}""#, name, escape(value)))
+        .collect();
+
+    if let Some((name, value)) = extra {
+        pairs.push(format!(r#"{}="{}""#, name, escape(value)));
+    }
+
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+/// Encode gathered metrics in the OpenMetrics text format, including any
+/// exemplars for histogram buckets.
+pub fn encode(families: &[MetricFamily]) -> String {
+    let exemplars = EXEMPLARS.lock().unwrap().clone();
+    let mut buf = String::new();
+
+    for family in families {
+        let (kind, name) = match family.get_field_type() {
+            MetricType::COUNTER => (
+                "counter",
+                family
+                    .get_name()
+                    .strip_suffix("_total")
+                    .unwrap_or_else(|| family.get_name()),
+            ),
+            MetricType::GAUGE => ("gauge", family.get_name()),
+            MetricType::HISTOGRAM => ("histogram", family.get_name()),
+            MetricType::SUMMARY => ("summary", family.get_name()),
+            MetricType::UNTYPED => ("unknown", family.get_name()),
+        };
+
+        writeln!(buf, "# TYPE {} {}", name, kind).unwrap();
+        writeln!(buf, "# HELP {} {}", name, escape(family.get_help())).unwrap();
+
+        for metric in family.get_metric() {
+            let labels: Vec<(String, String)> = metric
+                .get_label()
+                .iter()
+                .map(|pair| (pair.get_name().to_string(), pair.get_value().to_string()))
+                .collect();
+            let formatted = format_labels(&labels, None);
+
+            match family.get_field_type() {
+                MetricType::COUNTER => {
+                    let value = metric.get_counter().get_value();
+                    writeln!(buf, "{}_total{} {}", name, formatted, format_float(value)).unwrap();
+                }
+                MetricType::GAUGE => {
+                    let value = metric.get_gauge().get_value();
+                    writeln!(buf, "{}{} {}", name, formatted, format_float(value)).unwrap();
+                }
+                MetricType::UNTYPED => {
+                    let value = metric.get_untyped().get_value();
+                    writeln!(buf, "{}{} {}", name, formatted, format_float(value)).unwrap();
+                }
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+                    for quantile in summary.get_quantile() {
+                        let quantile_value = format_float(quantile.get_quantile());
+                        writeln!(
+                            buf,
+                            "{}{} {}",
+                            name,
+                            format_labels(&labels, Some(("quantile", &quantile_value))),
+                            format_float(quantile.get_value())
+                        )
+                        .unwrap();
+                    }
+                    writeln!(
+                        buf,
+                        "{}_sum{} {}",
+                        name,
+                        formatted,
+                        summary.get_sample_sum()
+                    )
+                    .unwrap();
+                    writeln!(
+                        buf,
+                        "{}_count{} {}",
+                        name,
+                        formatted,
+                        summary.get_sample_count()
+                    )
+                    .unwrap();
+                }
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+
+                    let mut buckets: Vec<(f64, u64)> = histogram
+                        .get_bucket()
+                        .iter()
+                        .map(|bucket| (bucket.get_upper_bound(), bucket.get_cumulative_count()))
+                        .collect();
+                    if !matches!(buckets.last(), Some((bound, _)) if *bound == f64::INFINITY) {
+                        buckets.push((f64::INFINITY, histogram.get_sample_count()));
+                    }
+
+                    for (upper_bound, count) in buckets {
+                        let le = format_float(upper_bound);
+                        write!(
+                            buf,
+                            "{}_bucket{} {}",
+                            name,
+                            format_labels(&labels, Some(("le", &le))),
+                            count
+                        )
+                        .unwrap();
+
+                        let key = (name.to_string(), labels.clone(), upper_bound.to_bits());
+                        if let Some(exemplar) = exemplars.get(&key) {
+                            write!(
+                                buf,
+                                r#" # {{trace_id="{}"}} {} {}"#,
+                                exemplar.trace_id,
+                                format_float(exemplar.value),
+                                exemplar.timestamp
+                            )
+                            .unwrap();
+                        }
+
+                        buf.push('\n');
+                    }
+
+                    writeln!(
+                        buf,
+                        "{}_sum{} {}",
+                        name,
+                        formatted,
+                        histogram.get_sample_sum()
+                    )
+                    .unwrap();
+                    writeln!(
+                        buf,
+                        "{}_count{} {}",
+                        name,
+                        formatted,
+                        histogram.get_sample_count()
+                    )
+                    .unwrap();
+                }
+            }
+        }
+    }
+
+    buf.push_str("# EOF\n");
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let registry = prometheus::Registry::new();
+
+        let counter = prometheus::CounterVec::new(
+            prometheus::Opts::new("test_requests_total", "Test requests"),
+            &["method"],
+        )
+        .unwrap();
+        registry.register(Box::new(counter.clone())).unwrap();
+        counter.with_label_values(&["ping"]).inc();
+
+        let histogram = prometheus::HistogramVec::new(
+            prometheus::HistogramOpts::new("test_duration_seconds", "Test duration"),
+            &["method"],
+        )
+        .unwrap();
+        registry.register(Box::new(histogram.clone())).unwrap();
+        histogram.with_label_values(&["ping"]).observe(0.3);
+
+        let output = encode(&registry.gather());
+
+        assert!(output.contains("# TYPE test_requests counter\n"));
+        assert!(output.contains("test_requests_total{method=\"ping\"} 1\n"));
+        assert!(output.contains("# TYPE test_duration_seconds histogram\n"));
+        assert!(output.contains("test_duration_seconds_bucket{method=\"ping\",le=\"0.5\"} 1\n"));
+        assert!(output.contains("test_duration_seconds_bucket{method=\"ping\",le=\"+Inf\"} 1\n"));
+        assert!(output.contains("test_duration_seconds_count{method=\"ping\"} 1\n"));
+        assert!(output.ends_with("# EOF\n"));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape(r#"a"b\c"#), r#"a\"b\\c"#);
+        assert_eq!(escape("a\nb"), r"a\nb");
+    }
+}
@@ -0,0 +1,121 @@
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::{uri::PathAndQuery, Uri},
+};
+
+/// Endpoints that may be requested with the server address in the path, like
+/// `/server/example.com:25565/status`.
+const PATH_ENDPOINTS: &[&str] = &[
+    "status",
+    "status/multi",
+    "query",
+    "image",
+    "image.gif",
+    "icon",
+    "og-image",
+    "widget",
+    "changes",
+    "wait",
+    "debug",
+    "history/uptime",
+];
+
+/// Parameters replaced by the address in the path.
+const ADDRESS_PARAMS: &[&str] = &["ip", "port"];
+
+/// If a path segment only contains characters that may be in an address,
+/// including percent encoded internationalized domain names.
+fn is_address(segment: &str) -> bool {
+    !segment.is_empty()
+        && segment
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | ':' | '-' | '_' | '%'))
+}
+
+/// Rewrite a path with the server address in it to the same endpoint with the
+/// address as a query parameter, replacing any address already in the query.
+fn rewrite(path: &str, query: Option<&str>) -> Option<String> {
+    let (address, endpoint) = path.strip_prefix("/server/")?.split_once('/')?;
+
+    if !is_address(address) || !PATH_ENDPOINTS.contains(&endpoint) {
+        return None;
+    }
+
+    let params = query
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .filter(|param| {
+            let name = param.split('=').next().unwrap_or_default();
+            !param.is_empty() && !ADDRESS_PARAMS.contains(&name)
+        });
+
+    let query: Vec<_> = std::iter::once(format!("ip={}", address))
+        .chain(params.map(ToString::to_string))
+        .collect();
+
+    Some(format!("/server/{}?{}", endpoint, query.join("&")))
+}
+
+/// Middleware to route requests with the server address in the path, which
+/// are easier for CDNs to cache than query strings, to the usual endpoints.
+///
+/// It must wrap every other middleware so they all see the rewritten path,
+/// like long polls being exempt from load shedding.
+pub fn middleware<S, B>(mut req: ServiceRequest, srv: &S) -> S::Future
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    let uri = req.uri();
+
+    if let Some(path_and_query) = rewrite(uri.path(), uri.query())
+        .and_then(|path_and_query| path_and_query.parse::<PathAndQuery>().ok())
+    {
+        let mut parts = uri.clone().into_parts();
+        parts.path_and_query = Some(path_and_query);
+
+        if let Ok(uri) = Uri::from_parts(parts) {
+            req.match_info_mut().get_mut().update(&uri);
+            req.head_mut().uri = uri;
+        }
+    }
+
+    srv.call(req)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite() {
+        assert_eq!(
+            rewrite("/server/example.com/status", None).as_deref(),
+            Some("/server/status?ip=example.com")
+        );
+        assert_eq!(
+            rewrite("/server/example.com:25566/image.gif", Some("theme=dark")).as_deref(),
+            Some("/server/image.gif?ip=example.com:25566&theme=dark")
+        );
+        assert_eq!(
+            rewrite(
+                "/server/example.com/status/multi",
+                Some("ip=other.com&port=1&resolved=true")
+            )
+            .as_deref(),
+            Some("/server/status/multi?ip=example.com&resolved=true")
+        );
+        assert_eq!(
+            rewrite("/server/b%C3%BCcher.example/icon", Some("raw=true")).as_deref(),
+            Some("/server/icon?ip=b%C3%BCcher.example&raw=true")
+        );
+
+        assert_eq!(rewrite("/server/status", Some("ip=example.com")), None);
+        assert_eq!(
+            rewrite("/server/history/uptime", Some("ip=example.com")),
+            None
+        );
+        assert_eq!(rewrite("/server/example.com/unknown", None), None);
+        assert_eq!(rewrite("/server/example.com&ip=x/status", None), None);
+        assert_eq!(rewrite("/stats/example.com/status", None), None);
+    }
+}
@@ -1,10 +1,24 @@
-use std::net::SocketAddr;
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
 
+use futures::{
+    stream::{FuturesUnordered, StreamExt},
+    TryFutureExt,
+};
+use futures_retry::{ErrorHandler, RetryPolicy};
 use serde::{Deserialize, Serialize};
 use tokio::{
-    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
-    net::{TcpStream, UdpSocket},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+    net::{TcpSocket, TcpStream, UdpSocket},
+    time::timeout,
 };
+use tracing::Instrument;
+
+use crate::socks5;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -20,6 +34,326 @@ pub enum Error {
     Varint,
     #[error("packet too large")]
     PacketTooLarge,
+    #[error("packet too short")]
+    PacketTooShort,
+    #[error("timed out during {0}")]
+    Timeout(&'static str),
+    #[error("proxy error: {0}")]
+    Proxy(#[from] crate::socks5::Error),
+}
+
+impl Error {
+    /// If the error may be caused by a momentary network issue, so trying
+    /// again could succeed.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Timeout(_) => true,
+            Self::Io(err) => matches!(
+                err.kind(),
+                std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::BrokenPipe
+                    | std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::UnexpectedEof
+            ),
+            _ => false,
+        }
+    }
+}
+
+/// Retry method for checking servers.
+///
+/// It will retry transient errors up to `retries` times, doubling the delay
+/// after each attempt.
+#[derive(Clone, Copy, Debug)]
+pub struct Retry {
+    pub retries: usize,
+    pub delay: Duration,
+}
+
+impl ErrorHandler<Error> for Retry {
+    type OutError = Error;
+
+    fn handle(&mut self, attempt: usize, err: Error) -> RetryPolicy<Self::OutError> {
+        if attempt > self.retries || !err.is_transient() {
+            return RetryPolicy::ForwardError(err);
+        }
+
+        tracing::debug!("retrying after transient error: {}", err);
+
+        RetryPolicy::WaitRetry(self.delay * 2u32.saturating_pow(attempt as u32 - 1))
+    }
+}
+
+/// How long each phase of checking a server may take.
+#[derive(Clone, Copy, Debug)]
+pub struct Timeouts {
+    /// Resolving the server's address.
+    pub dns: Duration,
+    /// Opening a TCP connection.
+    pub connect: Duration,
+    /// Sending the request and reading the complete response.
+    pub read: Duration,
+    /// Every phase together.
+    pub total: Duration,
+}
+
+/// Largest status response accepted by default.
+///
+/// Statuses are buffered and then parsed, so this bounds the memory used by
+/// each ping. Even servers with large mod lists stay well below it.
+pub const DEFAULT_MAX_STATUS_BYTES: usize = 1024 * 1024;
+
+/// Largest favicon kept by default, including its data URI prefix.
+pub const DEFAULT_MAX_FAVICON_BYTES: usize = 256 * 1024;
+
+/// Most players kept from the sample by default. Vanilla servers send up to
+/// 12, but some servers send many lines of text as fake players.
+pub const DEFAULT_MAX_PLAYER_SAMPLE: usize = 100;
+
+/// Most bytes of other status fields kept by default. Mod lists may be large,
+/// and are still in the raw status when requested.
+pub const DEFAULT_MAX_EXTRA_BYTES: usize = 16 * 1024;
+
+/// Most bytes of a status response to read at once.
+const STATUS_CHUNK_SIZE: u64 = 16 * 1024;
+
+/// How much data servers may send.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Largest status response, which is rejected before reading it.
+    pub max_status_bytes: usize,
+    /// Largest favicon, which is removed from the status if it is larger.
+    pub max_favicon_bytes: usize,
+    /// Most players to keep from the sample, dropping any others.
+    pub max_player_sample: usize,
+    /// Most bytes of other fields to keep, dropping fields that don't fit.
+    pub max_extra_bytes: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_status_bytes: DEFAULT_MAX_STATUS_BYTES,
+            max_favicon_bytes: DEFAULT_MAX_FAVICON_BYTES,
+            max_player_sample: DEFAULT_MAX_PLAYER_SAMPLE,
+            max_extra_bytes: DEFAULT_MAX_EXTRA_BYTES,
+        }
+    }
+}
+
+impl Limits {
+    /// Remove anything from a ping that is over the limits, so it is not
+    /// kept in the cache.
+    ///
+    /// The raw status never keeps the favicon, which is already in the ping,
+    /// and its player sample is limited the same way.
+    fn apply(&self, ping: &mut Ping) {
+        if let Some(favicon) = &ping.favicon {
+            if favicon.len() > self.max_favicon_bytes {
+                tracing::debug!("removing favicon of {} bytes", favicon.len());
+                ping.favicon = None;
+            }
+        }
+
+        if let Some(sample) = &mut ping.players.sample {
+            sample.truncate(self.max_player_sample);
+        }
+
+        let mut extra_bytes = 0;
+        for (key, value) in std::mem::take(&mut ping.extra) {
+            let size = key.len() + value.to_string().len();
+            if extra_bytes + size > self.max_extra_bytes {
+                tracing::debug!("removing extra field {} of {} bytes", key, size);
+                continue;
+            }
+
+            extra_bytes += size;
+            ping.extra.insert(key, value);
+        }
+
+        if let Some(serde_json::Value::Object(raw)) = &mut ping.raw {
+            raw.remove("favicon");
+
+            if let Some(serde_json::Value::Array(sample)) = raw
+                .get_mut("players")
+                .and_then(|players| players.get_mut("sample"))
+            {
+                sample.truncate(self.max_player_sample);
+            }
+        }
+    }
+}
+
+/// How long to wait for a connection attempt before also trying the next
+/// address.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Longest to wait for a server to answer a ping packet when measuring latency.
+const LATENCY_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How long to wait for a server to answer a ping packet, given how long it
+/// took to get the status.
+///
+/// Both take about one round trip, so a server that does not answer only
+/// delays the response by about as long as the status took.
+fn latency_timeout(read: Duration, status: Duration) -> Duration {
+    read.min(LATENCY_TIMEOUT).min(status * 2)
+}
+
+/// Most bytes kept from a traced connection, in both directions together.
+pub const MAX_CAPTURE_BYTES: usize = 16 * 1024;
+
+/// Order addresses to alternate between IPv6 and IPv4, starting with IPv6,
+/// while keeping the order within each family.
+fn interleave_families(addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+    let (v6, v4): (Vec<SocketAddr>, Vec<SocketAddr>) =
+        addrs.iter().copied().partition(|addr| addr.is_ipv6());
+
+    let mut v6 = v6.into_iter();
+    let mut v4 = v4.into_iter();
+    let mut addrs = Vec::with_capacity(addrs.len());
+
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => break,
+            (a, b) => addrs.extend(a.into_iter().chain(b)),
+        }
+    }
+
+    addrs
+}
+
+/// How to open connections to servers.
+#[derive(Clone, Debug, Default)]
+pub struct Outbound {
+    /// Local address to use for IPv4 connections.
+    pub bind_v4: Option<Ipv4Addr>,
+    /// Local address to use for IPv6 connections.
+    pub bind_v6: Option<Ipv6Addr>,
+    /// Proxy to send all connections through.
+    pub proxy: Option<socks5::Proxy>,
+}
+
+impl Outbound {
+    /// Get the local address to use when connecting to a remote address.
+    ///
+    /// If no address was configured for the family, it uses the unspecified
+    /// address to let the system pick.
+    pub fn local_addr(&self, remote: &SocketAddr) -> SocketAddr {
+        let ip = match remote {
+            SocketAddr::V4(_) => IpAddr::V4(self.bind_v4.unwrap_or(Ipv4Addr::UNSPECIFIED)),
+            SocketAddr::V6(_) => IpAddr::V6(self.bind_v6.unwrap_or(Ipv6Addr::UNSPECIFIED)),
+        };
+
+        SocketAddr::new(ip, 0)
+    }
+
+    /// Open a TCP connection to an address without using the proxy.
+    async fn connect_direct(&self, addr: SocketAddr) -> std::io::Result<TcpStream> {
+        let socket = match addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+
+        socket.bind(self.local_addr(&addr))?;
+        socket.connect(addr).await
+    }
+
+    /// Open a TCP connection to an address.
+    async fn connect_tcp(&self, addr: SocketAddr) -> Result<TcpStream, Error> {
+        match &self.proxy {
+            Some(proxy) => {
+                let stream = self.connect_direct(proxy.addr).await?;
+                Ok(proxy.connect(stream, addr).await?)
+            }
+            None => Ok(self.connect_direct(addr).await?),
+        }
+    }
+
+    /// Open a TCP connection to the first of many addresses that responds,
+    /// returning the address it connected to.
+    ///
+    /// Connections are attempted in order, alternating between address
+    /// families, starting another attempt whenever one fails or has not
+    /// connected after [CONNECTION_ATTEMPT_DELAY] (Happy Eyeballs).
+    async fn connect_any(&self, addrs: &[SocketAddr]) -> Result<(TcpStream, SocketAddr), Error> {
+        let mut remaining = interleave_families(addrs).into_iter();
+        let mut next = remaining.next();
+
+        let mut attempts = FuturesUnordered::new();
+        let mut last_err = None;
+
+        loop {
+            if let Some(addr) = next.take() {
+                tracing::trace!("attempting connection to {}", addr);
+                attempts.push(self.connect_tcp(addr).map_ok(move |stream| (stream, addr)));
+            }
+
+            if attempts.is_empty() {
+                return Err(last_err.unwrap_or_else(|| {
+                    std::io::Error::new(std::io::ErrorKind::NotFound, "no addresses").into()
+                }));
+            }
+
+            tokio::select! {
+                Some(result) = attempts.next() => match result {
+                    Ok(connected) => return Ok(connected),
+                    Err(err) => {
+                        tracing::debug!("connection attempt failed: {}", err);
+                        last_err = Some(err);
+                        next = remaining.next();
+                    }
+                },
+                _ = tokio::time::sleep(CONNECTION_ATTEMPT_DELAY), if remaining.len() > 0 => {
+                    next = remaining.next();
+                }
+            }
+        }
+    }
+
+    /// Create a UDP socket connected to an address.
+    async fn connect_udp(&self, addr: SocketAddr) -> Result<Datagrams, Error> {
+        match &self.proxy {
+            Some(proxy) => {
+                let control = self.connect_direct(proxy.addr).await?;
+                let socket = UdpSocket::bind(self.local_addr(&proxy.addr)).await?;
+
+                Ok(Datagrams::Proxied(
+                    proxy.associate(control, socket, addr).await?,
+                ))
+            }
+            None => {
+                let socket = UdpSocket::bind(self.local_addr(&addr)).await?;
+                socket.connect(addr).await?;
+
+                Ok(Datagrams::Direct(socket))
+            }
+        }
+    }
+}
+
+/// A UDP socket connected to a single address, either directly or through a
+/// proxy.
+enum Datagrams {
+    Direct(UdpSocket),
+    Proxied(socks5::UdpAssociation),
+}
+
+impl Datagrams {
+    async fn send(&self, data: &[u8]) -> Result<usize, Error> {
+        match self {
+            Self::Direct(socket) => Ok(socket.send(data).await?),
+            Self::Proxied(association) => Ok(association.send(data).await?),
+        }
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        match self {
+            Self::Direct(socket) => Ok(socket.recv(buf).await?),
+            Self::Proxied(association) => Ok(association.recv(buf).await?),
+        }
+    }
 }
 
 /// Encode a u32 into a VarInt.
@@ -51,6 +385,11 @@ where
     let mut index = 0;
 
     loop {
+        // If length is greater than 5, something is wrong
+        if index >= 5 {
+            return Err(Error::Varint);
+        }
+
         // Read a single byte
         reader.read_exact(&mut buf).await?;
 
@@ -62,10 +401,6 @@ where
         result |= value << (7 * index);
 
         index += 1;
-        // If length is greater than 5, something is wrong
-        if index > 5 {
-            return Err(Error::Varint);
-        }
 
         // If top bit was zero, we're done
         if buf[0] & 0b1000_0000 == 0 {
@@ -120,7 +455,7 @@ pub struct Version {
 }
 
 /// A player on the server and their ID.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
 pub struct PlayerSample {
     pub name: String,
     pub id: String,
@@ -144,6 +479,19 @@ pub struct Ping {
     /// be parsed to get colors, etc.
     pub description: serde_json::Value,
     pub favicon: Option<String>,
+    /// Any other fields the server sent, such as from mods or plugins.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+    /// Round trip time of a ping packet, if the server answered one.
+    #[serde(skip)]
+    pub latency: Option<Duration>,
+    /// Address the ping was sent to, if it was sent by [`send_ping`].
+    #[serde(skip)]
+    pub addr: Option<SocketAddr>,
+    /// Status as the server sent it, without the favicon, if it was read by
+    /// [`ping_stream`].
+    #[serde(skip)]
+    pub raw: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -179,50 +527,417 @@ impl Ping {
 /// resolution or other failure. If it is false, the error was caused by not
 /// being able to communicate with the server.
 ///
-/// In order to avoid resource exhaustion it is advisable to wrap this in
-/// a timeout as none are implemented within the library.
-pub async fn send_ping(addr: SocketAddr, host: &str, port: u16) -> Result<Ping, Error> {
+/// The connect and read phases are limited by `timeouts`, but it is advisable
+/// to also wrap this in the total timeout.
+#[tracing::instrument]
+pub async fn send_ping(
+    addrs: &[SocketAddr],
+    host: &str,
+    port: u16,
+    timeouts: &Timeouts,
+    limits: &Limits,
+    outbound: &Outbound,
+) -> Result<Ping, Error> {
     // Resolve our host and port to a SocketAddr,
     // then open a TCP connection.
-    let mut stream = TcpStream::connect(&addr).await?;
-
-    // Create a handshake and write it.
-    let handshake = build_packet(build_handshake(host, port), 0x00);
-    stream.write_all(&handshake).await?;
-
-    // Send a request packet.
-    let request = build_packet(vec![], 0x00);
-    stream.write_all(&request).await?;
-
-    // Read the packet ID length and packet ID, discard values.
-    // We do not care about what they were.
-    let _packet_length = read_varint(&mut stream).await?;
-    let _packet_id = read_varint(&mut stream).await?;
-
-    // Read the data length and ensure it's of a reasonable size.
-    let string_len = read_varint(&mut stream).await? as usize;
-    if string_len > 1024 * 1024 * 10 {
-        tracing::error!(
-            "rejecting ping packet from {}:{}, desired size is {}",
-            host,
-            port,
-            string_len
-        );
-        return Err(Error::PacketTooLarge);
-    }
+    let (mut stream, addr) = timeout(timeouts.connect, outbound.connect_any(addrs))
+        .instrument(tracing::info_span!("connect"))
+        .await
+        .map_err(|_err| Error::Timeout("connect"))??;
+
+    // Sending the handshake and reading the response share a single timeout.
+    let start = Instant::now();
+    let mut ping = timeout(timeouts.read, ping_stream(&mut stream, host, port, limits))
+        .await
+        .map_err(|_err| Error::Timeout("read"))??;
+
+    // Latency is only informational, so a server that does not answer is
+    // still online. It gets a short timeout to avoid delaying the response.
+    ping.latency = timeout(
+        latency_timeout(timeouts.read, start.elapsed()),
+        measure_latency(&mut stream),
+    )
+    .await
+    .ok()
+    .and_then(Result::ok);
+    ping.addr = Some(addr);
 
-    // Attempt to allocate and read the packet.
-    let mut data: Vec<u8> = vec![0; string_len];
-    stream.read_exact(&mut data).await?;
+    Ok(ping)
+}
 
-    // Attempt to parse the data into a UTF8 string and deserialize its
-    // JSON contents.
-    let s = String::from_utf8(data)?;
-    let ping: Ping = serde_json::from_str(&s)?;
+/// Send a ping over an existing connection to a server.
+///
+/// The `host` and `port` are only used for the handshake, so this works with
+/// any transport, such as a TLS or proxied connection. No timeouts are
+/// applied.
+pub async fn ping_stream<S>(
+    stream: &mut S,
+    host: &str,
+    port: u16,
+    limits: &Limits,
+) -> Result<Ping, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    write_status_request(stream, host, port).await?;
+    let status = read_status(stream, host, port, limits).await?;
+
+    parse_status(&status, limits)
+}
+
+/// Parse the JSON of a status response, keeping the original JSON and
+/// removing anything over the limits.
+fn parse_status(status: &str, limits: &Limits) -> Result<Ping, Error> {
+    let raw: serde_json::Value = serde_json::from_str(status)?;
+    let mut ping: Ping = serde_json::from_value(raw.clone())?;
+    ping.raw = Some(raw);
+    limits.apply(&mut ping);
 
     Ok(ping)
 }
 
+/// Send the handshake and status request packets.
+async fn write_status_request<S>(stream: &mut S, host: &str, port: u16) -> Result<(), Error>
+where
+    S: AsyncWrite + Unpin,
+{
+    async {
+        // Create a handshake and write it.
+        let handshake = build_packet(build_handshake(host, port), 0x00);
+        stream.write_all(&handshake).await?;
+
+        // Send a request packet.
+        let request = build_packet(vec![], 0x00);
+        stream.write_all(&request).await
+    }
+    .instrument(tracing::info_span!("handshake"))
+    .await?;
+
+    Ok(())
+}
+
+/// Read the status response packet, returning its JSON without parsing it.
+///
+/// The response is read in chunks as it arrives, so a server claiming a large
+/// response without sending it does not use that much memory.
+async fn read_status<S>(
+    stream: &mut S,
+    host: &str,
+    port: u16,
+    limits: &Limits,
+) -> Result<String, Error>
+where
+    S: AsyncRead + Unpin,
+{
+    async {
+        // Read the packet ID length and packet ID, discard values.
+        // We do not care about what they were.
+        let _packet_length = read_varint(stream).await?;
+        let _packet_id = read_varint(stream).await?;
+
+        // Read the data length and ensure it's of a reasonable size.
+        let string_len = read_varint(stream).await? as usize;
+        if string_len > limits.max_status_bytes {
+            tracing::error!(
+                "rejecting ping packet from {}:{}, desired size is {}",
+                host,
+                port,
+                string_len
+            );
+            return Err(Error::PacketTooLarge);
+        }
+
+        let mut data = Vec::new();
+        while data.len() < string_len {
+            let remaining = (string_len - data.len()) as u64;
+            let read = (&mut *stream)
+                .take(remaining.min(STATUS_CHUNK_SIZE))
+                .read_to_end(&mut data)
+                .await?;
+
+            if read == 0 {
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+            }
+        }
+
+        // Attempt to parse the data into a UTF8 string.
+        Ok(String::from_utf8(data)?)
+    }
+    .instrument(tracing::info_span!("read"))
+    .await
+}
+
+/// Which way data went on a connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// Data sent or received without anything going the other way in between.
+#[derive(Debug)]
+pub struct CapturedData {
+    pub direction: Direction,
+    pub data: Vec<u8>,
+}
+
+impl CapturedData {
+    /// Format the data like `hexdump -C`, with 16 bytes on each line.
+    pub fn hex_dump(&self) -> String {
+        let mut dump = String::new();
+
+        for (index, line) in self.data.chunks(16).enumerate() {
+            let hex: Vec<_> = line.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let text: String = line
+                .iter()
+                .map(|&byte| {
+                    if byte.is_ascii_graphic() || byte == b' ' {
+                        byte as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+
+            dump.push_str(&format!(
+                "{:08x}  {:<47}  |{}|\n",
+                index * 16,
+                hex.join(" "),
+                text
+            ));
+        }
+
+        dump
+    }
+}
+
+/// Everything sent and received on a connection, up to a number of bytes.
+#[derive(Debug, Default)]
+pub struct PacketCapture {
+    pub data: Vec<CapturedData>,
+    /// If more data went over the connection than was kept.
+    pub truncated: bool,
+    len: usize,
+    max_len: usize,
+}
+
+impl PacketCapture {
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            max_len,
+            ..Default::default()
+        }
+    }
+
+    fn record(&mut self, direction: Direction, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+
+        let keep = data.len().min(self.max_len - self.len);
+        if keep < data.len() {
+            self.truncated = true;
+        }
+        if keep == 0 {
+            return;
+        }
+        self.len += keep;
+
+        match self.data.last_mut() {
+            Some(last) if last.direction == direction => last.data.extend(&data[..keep]),
+            _ => self.data.push(CapturedData {
+                direction,
+                data: data[..keep].to_vec(),
+            }),
+        }
+    }
+}
+
+/// A stream that records everything written to and read from it.
+struct CaptureStream<'a, S> {
+    inner: S,
+    capture: &'a mut PacketCapture,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CaptureStream<'_, S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let this = &mut *self;
+
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            this.capture
+                .record(Direction::Received, &buf.filled()[filled..]);
+        }
+
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CaptureStream<'_, S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = &mut *self;
+
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            this.capture.record(Direction::Sent, &buf[..written]);
+        }
+
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// How long each phase of a ping took and what the server sent, for
+/// diagnosing servers that appear offline.
+///
+/// Phases after one that failed are not attempted and have no duration.
+#[derive(Debug, Default)]
+pub struct PingTrace {
+    /// Address that was connected to.
+    pub addr: Option<SocketAddr>,
+    pub connect: Option<Duration>,
+    /// Writing the handshake and status request.
+    pub handshake: Option<Duration>,
+    /// Reading the status response.
+    pub read: Option<Duration>,
+    /// Parsing the status JSON.
+    pub parse: Option<Duration>,
+    /// Status JSON exactly as the server sent it.
+    pub raw_status: Option<String>,
+    /// Bytes sent and received on the connection, up to
+    /// [`MAX_CAPTURE_BYTES`].
+    pub packets: PacketCapture,
+    /// The parsed status, including latency if the server answered a ping.
+    pub ping: Option<Ping>,
+    /// Error that ended the ping early.
+    pub error: Option<Error>,
+}
+
+/// Ping a server like [`send_ping`], recording how long each phase took.
+///
+/// The connect and read phases are limited by `timeouts`. It is not retried.
+pub async fn trace_ping(
+    addrs: &[SocketAddr],
+    host: &str,
+    port: u16,
+    timeouts: &Timeouts,
+    limits: &Limits,
+    outbound: &Outbound,
+) -> PingTrace {
+    let mut trace = PingTrace {
+        packets: PacketCapture::new(MAX_CAPTURE_BYTES),
+        ..Default::default()
+    };
+
+    if let Err(err) = run_trace(&mut trace, addrs, host, port, timeouts, limits, outbound).await {
+        trace.error = Some(err);
+    }
+
+    trace
+}
+
+async fn run_trace(
+    trace: &mut PingTrace,
+    addrs: &[SocketAddr],
+    host: &str,
+    port: u16,
+    timeouts: &Timeouts,
+    limits: &Limits,
+    outbound: &Outbound,
+) -> Result<(), Error> {
+    let start = Instant::now();
+    let (stream, addr) = timeout(timeouts.connect, outbound.connect_any(addrs))
+        .await
+        .map_err(|_err| Error::Timeout("connect"))??;
+    trace.connect = Some(start.elapsed());
+    trace.addr = Some(addr);
+
+    let mut stream = CaptureStream {
+        inner: stream,
+        capture: &mut trace.packets,
+    };
+
+    // Writing and reading share a single timeout, like a normal ping.
+    let deadline = tokio::time::Instant::now() + timeouts.read;
+
+    let start = Instant::now();
+    tokio::time::timeout_at(deadline, write_status_request(&mut stream, host, port))
+        .await
+        .map_err(|_err| Error::Timeout("read"))??;
+    trace.handshake = Some(start.elapsed());
+
+    let start = Instant::now();
+    let status = tokio::time::timeout_at(deadline, read_status(&mut stream, host, port, limits))
+        .await
+        .map_err(|_err| Error::Timeout("read"))??;
+    trace.read = Some(start.elapsed());
+
+    let start = Instant::now();
+    let parsed = parse_status(&status, limits);
+    trace.parse = Some(start.elapsed());
+    trace.raw_status = Some(status);
+    let mut ping = parsed?;
+
+    let status = trace.handshake.unwrap_or_default() + trace.read.unwrap_or_default();
+    ping.latency = timeout(
+        latency_timeout(timeouts.read, status),
+        measure_latency(&mut stream),
+    )
+    .await
+    .ok()
+    .and_then(Result::ok);
+    ping.addr = Some(addr);
+    trace.ping = Some(ping);
+
+    Ok(())
+}
+
+/// Measure the round trip time to a server by sending a ping packet and
+/// waiting for the server to echo it, as the server list does.
+///
+/// This must be called after a ping on the same connection. No timeouts are
+/// applied.
+pub async fn measure_latency<S>(stream: &mut S) -> Result<Duration, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    async {
+        let start = Instant::now();
+
+        let payload = 1i64.to_be_bytes();
+        stream
+            .write_all(&build_packet(payload.to_vec(), 0x01))
+            .await?;
+
+        let _packet_length = read_varint(stream).await?;
+        let _packet_id = read_varint(stream).await?;
+
+        let mut pong = [0; 8];
+        stream.read_exact(&mut pong).await?;
+
+        Ok(start.elapsed())
+    }
+    .instrument(tracing::info_span!("latency"))
+    .await
+}
+
 /// Parse plugins from an optional string.
 fn parse_plugins(plugins: Option<String>) -> (String, Vec<String>) {
     // Ensure that we have plugins to parse. If not, return empty data.
@@ -317,11 +1032,15 @@ where
 /// See [send_ping] for more information about timeouts and errors.
 ///
 /// If data was missing, it is possible for fields to have empty values.
-pub async fn send_query(addr: SocketAddr) -> Result<Query, Error> {
+#[tracing::instrument]
+pub async fn send_query(
+    addr: SocketAddr,
+    timeouts: &Timeouts,
+    outbound: &Outbound,
+) -> Result<Query, Error> {
     // Resolve our host and port to a SocketAddr, bind a socket,
     // and open a UDP connection to the host.
-    let socket = UdpSocket::bind("0.0.0.0:0").await?;
-    socket.connect(addr).await?;
+    let socket = outbound.connect_udp(addr).await?;
 
     // Generate and send a random session ID for our packet.
     let session_id = rand::random::<u32>() & 0x0F0F_0F0F;
@@ -331,7 +1050,15 @@ pub async fn send_query(addr: SocketAddr) -> Result<Query, Error> {
 
     // Receive up to 2KiB from connection.
     let mut buf: Vec<u8> = vec![0; 65_535];
-    let len = socket.recv(&mut buf).await?;
+    let len = timeout(timeouts.read, socket.recv(&mut buf))
+        .await
+        .map_err(|_err| Error::Timeout("read"))??;
+
+    // Responses must have at least the type and session ID, and end with a
+    // null byte.
+    if len < 6 {
+        return Err(Error::PacketTooShort);
+    }
 
     // Get the challenge token from the response.
     let challenge_token: i32 = String::from_utf8_lossy(&buf[5..len - 1]).parse()?;
@@ -344,9 +1071,20 @@ pub async fn send_query(addr: SocketAddr) -> Result<Query, Error> {
     socket.send(&request).await?;
 
     // Receive data
-    let len = socket.recv(&mut buf).await?;
+    let len = timeout(timeouts.read, socket.recv(&mut buf))
+        .await
+        .map_err(|_err| Error::Timeout("read"))??;
+    if len < 17 {
+        return Err(Error::PacketTooShort);
+    }
+
     // Ignore type, session ID, and padding before trying to parse data.
-    let mut cursor = std::io::Cursor::new(&buf[16..len - 1]);
+    Ok(parse_query(&buf[16..len - 1]).await)
+}
+
+/// Parse the keys, values, and players from the data of a full stat response.
+async fn parse_query(data: &[u8]) -> Query {
+    let mut cursor = std::io::Cursor::new(data);
 
     let mut kv = std::collections::HashMap::new();
     let mut server = None;
@@ -372,17 +1110,180 @@ pub async fn send_query(addr: SocketAddr) -> Result<Query, Error> {
 
     let players = parse_players(&mut cursor, true).await;
 
-    Ok(Query {
+    Query {
         kv,
         players,
         server: server.unwrap_or_default(),
+    }
+}
+
+/// Run a future to completion on a new single threaded runtime, limited to
+/// the total timeout.
+#[cfg(feature = "blocking")]
+fn block_on<F, T>(total: Duration, fut: F) -> Result<T, Error>
+where
+    F: std::future::Future<Output = Result<T, Error>>,
+{
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    runtime.block_on(async {
+        timeout(total, fut)
+            .await
+            .map_err(|_err| Error::Timeout("total"))?
     })
 }
 
+/// Ping a server like [`send_ping`], blocking the current thread until it
+/// completes or the total timeout elapses.
+///
+/// This must not be called from within an async runtime.
+#[cfg(feature = "blocking")]
+pub fn send_ping_blocking(
+    addrs: &[SocketAddr],
+    host: &str,
+    port: u16,
+    timeouts: &Timeouts,
+    limits: &Limits,
+    outbound: &Outbound,
+) -> Result<Ping, Error> {
+    block_on(
+        timeouts.total,
+        send_ping(addrs, host, port, timeouts, limits, outbound),
+    )
+}
+
+/// Query a server like [`send_query`], blocking the current thread until it
+/// completes or the total timeout elapses.
+///
+/// This must not be called from within an async runtime.
+#[cfg(feature = "blocking")]
+pub fn send_query_blocking(
+    addr: SocketAddr,
+    timeouts: &Timeouts,
+    outbound: &Outbound,
+) -> Result<Query, Error> {
+    block_on(timeouts.total, send_query(addr, timeouts, outbound))
+}
+
+/// Entry points to parsers of data from servers, which are otherwise private,
+/// for fuzzing.
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing {
+    use std::io::Cursor;
+
+    use super::{Error, Limits, Ping, Query};
+
+    /// Read a VarInt from the start of the data.
+    pub async fn read_varint(data: &[u8]) -> Result<u32, Error> {
+        super::read_varint(&mut Cursor::new(data)).await
+    }
+
+    /// Read a status response packet.
+    pub async fn read_status(data: &[u8], limits: &Limits) -> Result<String, Error> {
+        super::read_status(&mut Cursor::new(data), "localhost", 25565, limits).await
+    }
+
+    /// Parse the JSON of a status response.
+    pub fn parse_status(status: &str, limits: &Limits) -> Result<Ping, Error> {
+        super::parse_status(status, limits)
+    }
+
+    /// Parse the data of a full stat query response.
+    pub async fn parse_query(data: &[u8]) -> Query {
+        super::parse_query(data).await
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
+
     use super::*;
 
+    #[test]
+    fn test_outbound_local_addr() {
+        let outbound = Outbound {
+            bind_v4: Some(Ipv4Addr::new(192, 0, 2, 10)),
+            bind_v6: None,
+            proxy: None,
+        };
+
+        assert_eq!(
+            outbound.local_addr(&"198.51.100.1:25565".parse().unwrap()),
+            "192.0.2.10:0".parse().unwrap()
+        );
+        assert_eq!(
+            outbound.local_addr(&"[2001:db8::1]:25565".parse().unwrap()),
+            "[::]:0".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_retry() {
+        let mut retry = Retry {
+            retries: 2,
+            delay: Duration::from_millis(250),
+        };
+
+        let reset = || Error::Io(std::io::ErrorKind::ConnectionReset.into());
+
+        assert!(matches!(
+            retry.handle(1, reset()),
+            RetryPolicy::WaitRetry(delay) if delay == Duration::from_millis(250)
+        ));
+        assert!(matches!(
+            retry.handle(2, Error::Timeout("read")),
+            RetryPolicy::WaitRetry(delay) if delay == Duration::from_millis(500)
+        ));
+        assert!(matches!(
+            retry.handle(3, reset()),
+            RetryPolicy::ForwardError(_)
+        ));
+        assert!(matches!(
+            retry.handle(1, Error::PacketTooLarge),
+            RetryPolicy::ForwardError(_)
+        ));
+    }
+
+    #[test]
+    fn test_interleave_families() {
+        let addrs: Vec<SocketAddr> = vec![
+            "192.0.2.1:25565".parse().unwrap(),
+            "192.0.2.2:25565".parse().unwrap(),
+            "192.0.2.3:25565".parse().unwrap(),
+            "[2001:db8::1]:25565".parse().unwrap(),
+        ];
+
+        assert_eq!(
+            interleave_families(&addrs),
+            vec![addrs[3], addrs[0], addrs[1], addrs[2]]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connect_any() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let open = listener.local_addr().unwrap();
+
+        // Find a port that refuses connections by closing a listener.
+        let closed = {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap()
+        };
+
+        let (stream, addr) = Outbound::default()
+            .connect_any(&[closed, open])
+            .await
+            .unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), open);
+        assert_eq!(addr, open);
+
+        assert!(Outbound::default().connect_any(&[closed]).await.is_err());
+        assert!(Outbound::default().connect_any(&[]).await.is_err());
+    }
+
     #[test]
     fn test_encode_varint() {
         assert_eq!(vec![0x00], encode_varint(0));
@@ -413,6 +1314,12 @@ mod tests {
             2_147_483_647,
             read_varint(&mut src.as_slice()).await.unwrap()
         );
+
+        let src: Vec<u8> = vec![0xFF; 6];
+        assert!(matches!(
+            read_varint(&mut src.as_slice()).await,
+            Err(Error::Varint)
+        ));
     }
 
     #[test]
@@ -443,6 +1350,190 @@ mod tests {
         assert!(msg.is_none());
     }
 
+    #[tokio::test]
+    async fn test_ping_stream() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+
+        let server = tokio::spawn(async move {
+            let mut expected = build_packet(build_handshake("localhost", 25565), 0x00);
+            expected.extend(build_packet(vec![], 0x00));
+
+            let mut request = vec![0; expected.len()];
+            server.read_exact(&mut request).await.unwrap();
+            assert_eq!(request, expected);
+
+            let json = br#"{"version":{"name":"1.18.2","protocol":758},"players":{"max":20,"online":1},"description":{"text":"A Minecraft Server"},"enforcesSecureChat":true}"#;
+            let mut data = encode_varint(json.len() as u32);
+            data.extend_from_slice(json);
+            server.write_all(&build_packet(data, 0x00)).await.unwrap();
+        });
+
+        let ping = ping_stream(&mut client, "localhost", 25565, &Limits::default())
+            .await
+            .unwrap();
+        server.await.unwrap();
+
+        assert_eq!(ping.version.protocol, 758);
+        assert_eq!(ping.players.online, 1);
+        assert_eq!(ping.get_motd(), Some("A Minecraft Server".to_string()));
+        assert_eq!(ping.extra["enforcesSecureChat"], true);
+        assert_eq!(ping.raw.unwrap()["version"]["name"], "1.18.2");
+    }
+
+    #[tokio::test]
+    async fn test_measure_latency() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+
+        let server = tokio::spawn(async move {
+            let expected = build_packet(1i64.to_be_bytes().to_vec(), 0x01);
+
+            let mut request = vec![0; expected.len()];
+            server.read_exact(&mut request).await.unwrap();
+            assert_eq!(request, expected);
+
+            server.write_all(&request).await.unwrap();
+        });
+
+        let latency = measure_latency(&mut client).await.unwrap();
+        server.await.unwrap();
+
+        assert!(latency < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_latency_timeout() {
+        let read = Duration::from_secs(5);
+
+        assert_eq!(
+            latency_timeout(read, Duration::from_millis(40)),
+            Duration::from_millis(80)
+        );
+        assert_eq!(latency_timeout(read, read), LATENCY_TIMEOUT);
+        assert_eq!(
+            latency_timeout(Duration::from_millis(100), Duration::from_millis(90)),
+            Duration::from_millis(100)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ping_stream_too_large() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+
+        let mut response = encode_varint(0);
+        response.extend(encode_varint(0x00));
+        response.extend(encode_varint(1024 * 1024 * 11));
+        server.write_all(&response).await.unwrap();
+
+        assert!(matches!(
+            ping_stream(&mut client, "localhost", 25565, &Limits::default()).await,
+            Err(Error::PacketTooLarge)
+        ));
+    }
+
+    #[test]
+    fn test_limits_apply() {
+        let player = |name: &str| PlayerSample {
+            name: name.to_string(),
+            id: String::new(),
+        };
+        let mut ping = Ping {
+            version: Version {
+                name: None,
+                protocol: 758,
+            },
+            players: Players {
+                max: 20,
+                online: 3,
+                sample: Some(vec![player("a"), player("b"), player("c")]),
+            },
+            description: serde_json::Value::Null,
+            favicon: Some(format!("data:image/png;base64,{}", "A".repeat(100))),
+            extra: serde_json::json!({
+                "enforcesSecureChat": true,
+                "forgeData": { "mods": "x".repeat(100) },
+            })
+            .as_object()
+            .unwrap()
+            .clone(),
+            latency: None,
+            addr: None,
+            raw: None,
+        };
+        ping.raw = Some(serde_json::json!({
+            "favicon": ping.favicon,
+            "players": { "sample": ["a", "b", "c"] },
+        }));
+
+        Limits::default().apply(&mut ping);
+        assert!(ping.favicon.is_some());
+        assert_eq!(ping.raw.as_ref().unwrap().get("favicon"), None);
+        assert_eq!(ping.players.sample.as_ref().unwrap().len(), 3);
+        assert_eq!(ping.extra.len(), 2);
+
+        let limits = Limits {
+            max_favicon_bytes: 100,
+            max_player_sample: 2,
+            max_extra_bytes: 100,
+            ..Default::default()
+        };
+        limits.apply(&mut ping);
+        assert_eq!(ping.favicon, None);
+        assert_eq!(
+            ping.raw.as_ref().unwrap()["players"]["sample"],
+            serde_json::json!(["a", "b"])
+        );
+        assert_eq!(ping.players.sample.unwrap()[1].name, "b");
+        assert_eq!(ping.extra.len(), 1);
+        assert_eq!(ping.extra["enforcesSecureChat"], true);
+        assert_eq!(ping.players.online, 3);
+    }
+
+    /// Read a status response from a server that sends `data` as the status
+    /// then closes the connection.
+    async fn read_response(data: Vec<u8>, limits: Limits) -> Result<String, Error> {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+
+        tokio::spawn(async move {
+            let _ = server.write_all(&build_packet(data, 0x00)).await;
+        });
+
+        read_status(&mut client, "localhost", 25565, &limits).await
+    }
+
+    #[tokio::test]
+    async fn test_read_status_chunks() {
+        let status = "x".repeat(STATUS_CHUNK_SIZE as usize * 2 + 10);
+        let mut data = encode_varint(status.len() as u32);
+        data.extend(status.as_bytes());
+
+        let limits = Limits {
+            max_status_bytes: status.len(),
+            ..Default::default()
+        };
+        assert_eq!(read_response(data, limits).await.unwrap(), status);
+
+        // Responses over the limit are rejected before reading them.
+        let mut data = encode_varint(101);
+        data.extend([b'x'; 101]);
+
+        let limits = Limits {
+            max_status_bytes: 100,
+            ..Default::default()
+        };
+        assert!(matches!(
+            read_response(data, limits).await,
+            Err(Error::PacketTooLarge)
+        ));
+
+        // Ending before the declared length is an error.
+        let mut data = encode_varint(50);
+        data.extend([b'x'; 10]);
+        assert!(matches!(
+            read_response(data, Limits::default()).await,
+            Err(Error::Io(_))
+        ));
+    }
+
     #[test]
     fn test_parse_plugins() {
         let plugins = parse_plugins(None);
@@ -467,4 +1558,174 @@ mod tests {
 
         assert_eq!(players, vec!["a", "b", "c"]);
     }
+
+    #[test]
+    fn test_packet_capture() {
+        let mut capture = PacketCapture::new(20);
+        capture.record(Direction::Sent, b"hello");
+        capture.record(Direction::Sent, b" ");
+        capture.record(Direction::Received, b"");
+        capture.record(Direction::Received, b"a longer response");
+
+        assert_eq!(capture.data.len(), 2);
+        assert_eq!(capture.data[0].data, b"hello ");
+        assert_eq!(capture.data[1].direction, Direction::Received);
+        assert_eq!(capture.data[1].data, b"a longer respo");
+        assert!(capture.truncated);
+
+        capture.record(Direction::Sent, b"more");
+        assert_eq!(capture.data.len(), 2);
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let captured = CapturedData {
+            direction: Direction::Received,
+            data: b"\x00\x10{\"version\":{\"name\":\"1.20\"}}".to_vec(),
+        };
+
+        assert_eq!(
+            captured.hex_dump(),
+            concat!(
+                "00000000  00 10 7b 22 76 65 72 73 69 6f 6e 22 3a 7b 22 6e  |..{\"version\":{\"n|\n",
+                "00000010  61 6d 65 22 3a 22 31 2e 32 30 22 7d 7d           |ame\":\"1.20\"}}|\n",
+            )
+        );
+    }
+
+    /// Arbitrary JSON values, for building status responses.
+    fn arb_json() -> impl Strategy<Value = serde_json::Value> {
+        let leaf = prop_oneof![
+            Just(serde_json::Value::Null),
+            any::<bool>().prop_map(serde_json::Value::from),
+            any::<i64>().prop_map(serde_json::Value::from),
+            any::<f64>().prop_map(serde_json::Value::from),
+            ".*".prop_map(serde_json::Value::from),
+        ];
+
+        leaf.prop_recursive(4, 64, 8, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..8).prop_map(serde_json::Value::from),
+                prop::collection::hash_map(".*", inner, 0..8)
+                    .prop_map(|map| serde_json::Value::Object(map.into_iter().collect())),
+            ]
+        })
+    }
+
+    /// Status responses shaped like a real server's, with arbitrary values.
+    fn arb_status() -> impl Strategy<Value = serde_json::Value> {
+        (arb_json(), arb_json(), arb_json(), arb_json()).prop_map(
+            |(version, players, description, favicon)| {
+                serde_json::json!({
+                    "version": version,
+                    "players": players,
+                    "description": description,
+                    "favicon": favicon,
+                })
+            },
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn prop_varint_round_trip(num: u32) {
+            let varint = encode_varint(num);
+            prop_assert!(varint.len() <= 5);
+
+            let decoded = futures::executor::block_on(read_varint(&mut varint.as_slice()));
+            prop_assert_eq!(decoded.unwrap(), num);
+        }
+
+        #[test]
+        fn prop_read_varint(data: Vec<u8>) {
+            let _ = futures::executor::block_on(read_varint(&mut data.as_slice()));
+        }
+
+        #[test]
+        fn prop_packet_round_trip(status in ".*", id: u32) {
+            let mut data = encode_varint(status.len() as u32);
+            data.extend(status.as_bytes());
+            let packet = build_packet(data, id);
+
+            let limits = Limits {
+                max_status_bytes: status.len(),
+                ..Default::default()
+            };
+            let read = futures::executor::block_on(read_status(
+                &mut packet.as_slice(),
+                "localhost",
+                25565,
+                &limits,
+            ));
+            prop_assert_eq!(read.unwrap(), status);
+        }
+
+        #[test]
+        fn prop_read_status(data: Vec<u8>) {
+            let _ = futures::executor::block_on(read_status(
+                &mut data.as_slice(),
+                "localhost",
+                25565,
+                &Limits::default(),
+            ));
+        }
+
+        #[test]
+        fn prop_parse_status(status in arb_status()) {
+            let limits = Limits {
+                max_favicon_bytes: 16,
+                max_player_sample: 2,
+                ..Default::default()
+            };
+
+            if let Ok(ping) = parse_status(&status.to_string(), &limits) {
+                prop_assert!(ping.favicon.iter().all(|favicon| favicon.len() <= 16));
+                prop_assert!(ping.players.sample.iter().all(|sample| sample.len() <= 2));
+
+                let _ = ping.get_motd();
+                crate::motd::lines(&crate::motd::parse(&ping.description));
+            }
+        }
+
+        #[test]
+        fn prop_parse_status_bytes(status in ".*") {
+            let _ = parse_status(&status, &Limits::default());
+        }
+
+        #[test]
+        fn prop_parse_query(data: Vec<u8>) {
+            let query = futures::executor::block_on(parse_query(&data));
+            prop_assert!(!query.kv.contains_key("plugins"));
+        }
+
+        #[test]
+        fn prop_parse_query_round_trip(
+            kv in prop::collection::hash_map("[^\\x00]+", "[^\\x00]+", 0..8),
+            players in prop::collection::vec("[^\\x00]+", 0..8),
+        ) {
+            let kv: std::collections::HashMap<String, String> = kv
+                .into_iter()
+                .filter(|(key, _)| key != "plugins")
+                .collect();
+
+            let mut data = Vec::new();
+            for (key, value) in &kv {
+                data.extend(key.as_bytes());
+                data.push(0);
+                data.extend(value.as_bytes());
+                data.push(0);
+            }
+            data.push(0);
+            data.extend(b"\x01player_\x00\x00");
+            for player in &players {
+                data.extend(player.as_bytes());
+                data.push(0);
+            }
+            data.push(0);
+
+            let query = futures::executor::block_on(parse_query(&data));
+            prop_assert_eq!(query.kv, kv);
+            prop_assert_eq!(query.players, players);
+        }
+    }
 }
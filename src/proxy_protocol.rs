@@ -0,0 +1,220 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// How long to wait for a client to send the header.
+pub const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Signature at the start of every version 2 header.
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Maximum length of a version 1 header, including the trailing CRLF.
+const V1_MAX_LENGTH: usize = 107;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("missing proxy protocol header")]
+    MissingHeader,
+    #[error("invalid proxy protocol header")]
+    InvalidHeader,
+}
+
+impl From<Error> for std::io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Io(err) => err,
+            err => std::io::Error::new(std::io::ErrorKind::InvalidData, err),
+        }
+    }
+}
+
+/// Read a version 1 or version 2 PROXY protocol header from the start of a
+/// connection, returning the original source address.
+///
+/// Exactly the bytes of the header are consumed, so the reader may be used
+/// for the proxied connection afterwards. Headers for health checks from the
+/// proxy itself or for unknown protocols return no address.
+pub async fn read_header<R>(reader: &mut R) -> Result<Option<SocketAddr>, Error>
+where
+    R: AsyncRead + Unpin,
+{
+    // Both versions have at least 12 bytes, so this is safe to read before
+    // knowing which version is being used.
+    let mut prefix = [0; 12];
+    reader.read_exact(&mut prefix).await?;
+
+    if &prefix == V2_SIGNATURE {
+        read_v2(reader).await
+    } else if prefix.starts_with(b"PROXY ") {
+        read_v1(reader, &prefix).await
+    } else {
+        Err(Error::MissingHeader)
+    }
+}
+
+/// Read the remainder of a version 1 header after the prefix.
+async fn read_v1<R>(reader: &mut R, prefix: &[u8]) -> Result<Option<SocketAddr>, Error>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut line = prefix.to_vec();
+    let mut buf = [0; 1];
+
+    // Read a byte at a time so nothing past the header is consumed.
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LENGTH {
+            return Err(Error::InvalidHeader);
+        }
+
+        reader.read_exact(&mut buf).await?;
+        line.push(buf[0]);
+    }
+
+    let line = std::str::from_utf8(&line[..line.len() - 2]).map_err(|_err| Error::InvalidHeader)?;
+    parse_v1(line)
+}
+
+/// Parse a version 1 header line, without the trailing CRLF.
+fn parse_v1(line: &str) -> Result<Option<SocketAddr>, Error> {
+    let parts: Vec<&str> = line.split(' ').collect();
+
+    match parts.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", source, _destination, source_port, _destination_port] => {
+            let ip: IpAddr = source.parse().map_err(|_err| Error::InvalidHeader)?;
+            let port: u16 = source_port.parse().map_err(|_err| Error::InvalidHeader)?;
+
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(Error::InvalidHeader),
+    }
+}
+
+/// Read the remainder of a version 2 header after the signature.
+async fn read_v2<R>(reader: &mut R) -> Result<Option<SocketAddr>, Error>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut header = [0; 4];
+    reader.read_exact(&mut header).await?;
+
+    let version = header[0] >> 4;
+    let command = header[0] & 0x0F;
+    let family = header[1];
+    let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+
+    if version != 2 {
+        return Err(Error::InvalidHeader);
+    }
+
+    // Length is at most 65535, so the rest of the header can always be read.
+    let mut addresses = vec![0; len];
+    reader.read_exact(&mut addresses).await?;
+
+    match command {
+        // LOCAL, connection was created by the proxy itself.
+        0x0 => Ok(None),
+        // PROXY, connection was on behalf of another host.
+        0x1 => parse_v2_addresses(family, &addresses),
+        _ => Err(Error::InvalidHeader),
+    }
+}
+
+/// Parse the source address from the address block of a version 2 header.
+fn parse_v2_addresses(family: u8, addresses: &[u8]) -> Result<Option<SocketAddr>, Error> {
+    match family {
+        // TCP or UDP over IPv4.
+        0x11 | 0x12 => {
+            if addresses.len() < 12 {
+                return Err(Error::InvalidHeader);
+            }
+
+            let ip = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        // TCP or UDP over IPv6.
+        0x21 | 0x22 => {
+            if addresses.len() < 36 {
+                return Err(Error::InvalidHeader);
+            }
+
+            let mut ip = [0; 16];
+            ip.copy_from_slice(&addresses[0..16]);
+            let ip = Ipv6Addr::from(ip);
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        // Unspecified or Unix sockets, which have no useful address.
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_v1() {
+        let mut data: &[u8] = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 8080\r\nGET / HTTP/1.1\r\n";
+        let addr = read_header(&mut data).await.unwrap();
+
+        assert_eq!(addr, Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(data, b"GET / HTTP/1.1\r\n");
+
+        let mut data: &[u8] = b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 8080\r\n";
+        let addr = read_header(&mut data).await.unwrap();
+        assert_eq!(addr, Some("[2001:db8::1]:56324".parse().unwrap()));
+
+        let mut data: &[u8] = b"PROXY UNKNOWN\r\n";
+        assert_eq!(read_header(&mut data).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_v2() {
+        let mut data = V2_SIGNATURE.to_vec();
+        data.extend([0x21, 0x11, 0x00, 0x0C]);
+        data.extend([192, 0, 2, 1, 198, 51, 100, 1]);
+        data.extend(56324u16.to_be_bytes());
+        data.extend(8080u16.to_be_bytes());
+        data.extend(b"GET / HTTP/1.1\r\n");
+
+        let mut data = data.as_slice();
+        let addr = read_header(&mut data).await.unwrap();
+
+        assert_eq!(addr, Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(data, b"GET / HTTP/1.1\r\n");
+
+        let mut data = V2_SIGNATURE.to_vec();
+        data.extend([0x20, 0x00, 0x00, 0x00]);
+        assert_eq!(read_header(&mut data.as_slice()).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_invalid() {
+        let mut data: &[u8] = b"GET / HTTP/1.1\r\nHost: localhost\r\n";
+        assert!(matches!(
+            read_header(&mut data).await,
+            Err(Error::MissingHeader)
+        ));
+
+        let mut data: &[u8] = b"PROXY TCP4 not-an-ip 198.51.100.1 56324 8080\r\n";
+        assert!(matches!(
+            read_header(&mut data).await,
+            Err(Error::InvalidHeader)
+        ));
+
+        let long = format!("PROXY {}\r\n", "A".repeat(200));
+        assert!(matches!(
+            read_header(&mut long.as_bytes()).await,
+            Err(Error::InvalidHeader)
+        ));
+    }
+}
@@ -0,0 +1,334 @@
+use std::{
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::header::{CacheControl, CacheDirective, HeaderMap, HeaderName, HeaderValue, RETRY_AFTER},
+    web, HttpMessage, HttpResponse,
+};
+use futures::future::LocalBoxFuture;
+use lazy_static::lazy_static;
+use lru::LruCache;
+use prometheus::{register_counter, Counter};
+
+use crate::{client_ip::ClientIp, config::Config, usage};
+
+/// Header with the most requests allowed in each window.
+pub const X_RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("x-ratelimit-limit");
+
+/// Header with the requests remaining in the current window.
+pub const X_RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("x-ratelimit-remaining");
+
+/// Header with the seconds until the current window ends.
+pub const X_RATELIMIT_RESET: HeaderName = HeaderName::from_static("x-ratelimit-reset");
+
+/// Most clients tracked at once. Once this many are tracked, the client that
+/// made a request least recently is forgotten to make room for a new one.
+const MAX_CLIENTS: usize = 100_000;
+
+lazy_static! {
+    static ref REQUESTS_LIMITED: Counter = register_counter!(
+        "mcapi_requests_rate_limited_total",
+        "Number of requests rejected because the client made too many requests"
+    )
+    .unwrap();
+}
+
+/// Requests a client has made in its current window.
+#[derive(Debug)]
+struct Window {
+    started: Instant,
+    requests: u64,
+}
+
+/// Windows for each client, and when expired windows were last removed.
+#[derive(Debug)]
+struct Windows {
+    clients: LruCache<String, Window>,
+    cleaned: Instant,
+}
+
+/// If a request is allowed, and the state of the client's window after it.
+#[derive(Debug, PartialEq, Eq)]
+struct Decision {
+    allowed: bool,
+    remaining: u64,
+    /// Time until the window ends.
+    reset: Duration,
+}
+
+/// Limits how many requests each client may make in a fixed window.
+///
+/// Clients are identified by their API key if they provided a valid one,
+/// otherwise by their address. Counts are kept in memory, so each instance
+/// limits clients separately.
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// Requests allowed in each window, disabled if zero.
+    limit: u64,
+    window: Duration,
+    windows: Mutex<Windows>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u64, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            windows: Mutex::new(Windows {
+                clients: LruCache::new(MAX_CLIENTS),
+                cleaned: Instant::now(),
+            }),
+        }
+    }
+
+    /// If requests are being limited.
+    pub fn is_enabled(&self) -> bool {
+        self.limit > 0
+    }
+
+    /// Count a request from a client made at `now`.
+    fn check(&self, client: &str, now: Instant) -> Decision {
+        let mut windows = self.windows.lock().unwrap();
+
+        // Expired windows are only removed once per window, so many clients
+        // making requests does not make every request scan all of them.
+        if now.duration_since(windows.cleaned) >= self.window {
+            let expired: Vec<String> = windows
+                .clients
+                .iter()
+                .filter(|(_client, window)| now.duration_since(window.started) >= self.window)
+                .map(|(client, _window)| client.clone())
+                .collect();
+            for client in expired {
+                windows.clients.pop(&client);
+            }
+            windows.cleaned = now;
+        }
+
+        let mut window = windows.clients.pop(client).unwrap_or(Window {
+            started: now,
+            requests: 0,
+        });
+
+        if now.duration_since(window.started) >= self.window {
+            window.started = now;
+            window.requests = 0;
+        }
+
+        let allowed = window.requests < self.limit;
+        if allowed {
+            window.requests += 1;
+        }
+
+        let decision = Decision {
+            allowed,
+            remaining: self.limit - window.requests,
+            reset: self.window - now.duration_since(window.started),
+        };
+
+        // Adding the window again marks the client as the most recent one.
+        windows.clients.put(client.to_string(), window);
+
+        decision
+    }
+
+    /// Add headers describing a decision to a response.
+    fn insert_headers(&self, headers: &mut HeaderMap, decision: &Decision) {
+        // Round up so clients never retry before the window ends.
+        let reset = (decision.reset.as_secs_f64().ceil() as u64).to_string();
+
+        headers.insert(X_RATELIMIT_LIMIT, HeaderValue::from(self.limit));
+        headers.insert(X_RATELIMIT_REMAINING, HeaderValue::from(decision.remaining));
+        headers.insert(X_RATELIMIT_RESET, HeaderValue::from_str(&reset).unwrap());
+    }
+}
+
+/// Identify a client by its address. IPv6 clients are usually given a whole
+/// /64 network, so they are identified by the network instead.
+fn client_network(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            format!(
+                "{:x}:{:x}:{:x}:{:x}::/64",
+                segments[0], segments[1], segments[2], segments[3]
+            )
+        }
+    }
+}
+
+/// Identify who made a request, by API key ID if valid or otherwise address.
+fn client_key(req: &ServiceRequest) -> Option<String> {
+    let api_key = req.app_data::<web::Data<Config>>().and_then(|config| {
+        let token = req.headers().get(usage::X_API_KEY)?.to_str().ok()?;
+        usage::key_id(&config.api_keys, token).map(|id| format!("key:{}", id))
+    });
+
+    api_key.or_else(|| {
+        req.extensions()
+            .get::<ClientIp>()
+            .map(|ip| ip.0)
+            .or_else(|| req.peer_addr().map(|addr| addr.ip()))
+            .map(|ip| format!("ip:{}", client_network(ip)))
+    })
+}
+
+/// Middleware to reject clients with a 429 error once they have made too many
+/// requests in the current window.
+///
+/// Every limited response includes `X-RateLimit-Limit`,
+/// `X-RateLimit-Remaining`, and `X-RateLimit-Reset` headers so clients can
+/// slow down before being rejected.
+pub fn middleware<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> LocalBoxFuture<'static, Result<ServiceResponse<BoxBody>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    let limiter = req
+        .app_data::<web::Data<RateLimiter>>()
+        .filter(|limiter| limiter.is_enabled())
+        .cloned();
+
+    let exempt = crate::load_shed::EXEMPT_PATHS.contains(&req.path());
+
+    let decision = match (&limiter, client_key(&req)) {
+        (Some(limiter), Some(client)) if !exempt => Some(limiter.check(&client, Instant::now())),
+        _ => None,
+    };
+
+    let call = match (&limiter, &decision) {
+        (Some(limiter), Some(decision)) if !decision.allowed => {
+            tracing::debug!("rate limiting request");
+            REQUESTS_LIMITED.inc();
+
+            let mut res = HttpResponse::TooManyRequests()
+                .insert_header((RETRY_AFTER, decision.reset.as_secs_f64().ceil() as u64))
+                .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+                .json(serde_json::json!({
+                    "status": "error",
+                    "error": "too many requests, try again later",
+                }));
+            limiter.insert_headers(res.headers_mut(), decision);
+
+            Err(req.into_response(res))
+        }
+        _ => Ok(srv.call(req)),
+    };
+
+    Box::pin(async move {
+        let fut = match call {
+            Ok(fut) => fut,
+            Err(res) => return Ok(res),
+        };
+
+        let mut res = fut.await?.map_into_boxed_body();
+
+        if let (Some(limiter), Some(decision)) = (limiter, decision) {
+            limiter.insert_headers(res.headers_mut(), &decision);
+        }
+
+        Ok(res)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        let start = Instant::now();
+
+        assert_eq!(
+            limiter.check("a", start),
+            Decision {
+                allowed: true,
+                remaining: 1,
+                reset: Duration::from_secs(60),
+            }
+        );
+        assert!(limiter.check("a", start + Duration::from_secs(10)).allowed);
+
+        let decision = limiter.check("a", start + Duration::from_secs(20));
+        assert!(!decision.allowed);
+        assert_eq!(decision.remaining, 0);
+        assert_eq!(decision.reset, Duration::from_secs(40));
+
+        // Clients are limited separately.
+        assert!(limiter.check("b", start + Duration::from_secs(20)).allowed);
+
+        // A new window starts once the previous one ends.
+        assert_eq!(
+            limiter.check("a", start + Duration::from_secs(60)),
+            Decision {
+                allowed: true,
+                remaining: 1,
+                reset: Duration::from_secs(60),
+            }
+        );
+    }
+
+    #[test]
+    fn test_cleanup() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        let start = Instant::now();
+
+        limiter.check("a", start);
+        limiter.check("b", start + Duration::from_secs(30));
+        assert_eq!(limiter.windows.lock().unwrap().clients.len(), 2);
+
+        // Only the window that ended is removed.
+        limiter.check("c", start + Duration::from_secs(70));
+        let windows = limiter.windows.lock().unwrap();
+        assert!(!windows.clients.contains("a"));
+        assert!(windows.clients.contains("b"));
+        assert!(windows.clients.contains("c"));
+    }
+
+    #[test]
+    fn test_max_clients() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60));
+        limiter.windows.lock().unwrap().clients.resize(2);
+        let start = Instant::now();
+
+        limiter.check("a", start);
+        limiter.check("b", start + Duration::from_secs(10));
+
+        // New clients are still limited once the most clients are tracked, by
+        // forgetting the client that made a request least recently.
+        assert!(limiter.check("c", start + Duration::from_secs(20)).allowed);
+        assert!(!limiter.check("c", start + Duration::from_secs(20)).allowed);
+
+        let windows = limiter.windows.lock().unwrap();
+        assert!(!windows.clients.contains("a"));
+        assert!(windows.clients.contains("b"));
+        assert!(windows.clients.contains("c"));
+    }
+
+    #[test]
+    fn test_client_network() {
+        assert_eq!(
+            client_network("203.0.113.7".parse().unwrap()),
+            "203.0.113.7"
+        );
+        assert_eq!(
+            client_network("2001:db8:1:2:3:4:5:6".parse().unwrap()),
+            "2001:db8:1:2::/64"
+        );
+        assert_eq!(
+            client_network("2001:db8:1:2::ffff".parse().unwrap()),
+            client_network("2001:db8:1:2:aaaa::1".parse().unwrap())
+        );
+    }
+}
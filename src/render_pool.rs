@@ -0,0 +1,190 @@
+//! Dedicated threads for rendering images, so a burst of image requests can't
+//! use every thread of the blocking pool.
+
+use std::{
+    panic::AssertUnwindSafe,
+    sync::{
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use lazy_static::lazy_static;
+use prometheus::{
+    register_counter, register_histogram, register_int_gauge, Counter, Histogram, IntGauge,
+};
+use tokio::sync::oneshot;
+
+// Task and queue metrics keep their names from when images were rendered on
+// the blocking pool, so existing dashboards and alerts still work.
+lazy_static! {
+    static ref BLOCKING_TASKS: IntGauge = register_int_gauge!(
+        "mcapi_blocking_tasks",
+        "Number of images waiting for or being rendered"
+    )
+    .unwrap();
+    static ref RENDERS_QUEUED: IntGauge = register_int_gauge!(
+        "mcapi_renders_queued",
+        "Number of images waiting for a render thread"
+    )
+    .unwrap();
+    static ref BLOCKING_QUEUE_DURATION: Histogram = register_histogram!(
+        "mcapi_blocking_queue_duration_seconds",
+        "Duration images waited for a render thread"
+    )
+    .unwrap();
+    static ref RENDERS_REJECTED: Counter = register_counter!(
+        "mcapi_renders_rejected_total",
+        "Number of images not rendered because the render queue was full"
+    )
+    .unwrap();
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RenderError {
+    #[error("too many images are being rendered")]
+    Full,
+    #[error("rendering failed")]
+    Failed,
+}
+
+/// A fixed number of threads rendering images from a bounded queue.
+pub struct RenderPool {
+    sender: SyncSender<Job>,
+}
+
+impl RenderPool {
+    /// Start a pool with a number of threads, or one for each CPU if 0, and
+    /// room for `queue` images waiting for a thread.
+    pub fn new(threads: usize, queue: usize) -> Self {
+        let threads = match threads {
+            0 => std::thread::available_parallelism()
+                .map(usize::from)
+                .unwrap_or(1),
+            threads => threads,
+        };
+
+        tracing::info!("starting {} render threads", threads);
+
+        let (sender, receiver) = mpsc::sync_channel::<Job>(queue);
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for index in 0..threads {
+            let receiver = receiver.clone();
+
+            std::thread::Builder::new()
+                .name(format!("render-{}", index))
+                .spawn(move || run_worker(receiver))
+                .expect("could not start render thread");
+        }
+
+        Self { sender }
+    }
+
+    /// Run a function on a render thread, or fail immediately if the queue is
+    /// full.
+    pub async fn render<F, R>(&self, f: F) -> Result<R, RenderError>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let queued_at = Instant::now();
+
+        // Dropped when a thread starts the job, or with the job if it could
+        // not be queued.
+        RENDERS_QUEUED.inc();
+        let queued = Gauged(&RENDERS_QUEUED);
+
+        let job: Job = Box::new(move || {
+            drop(queued);
+            BLOCKING_QUEUE_DURATION.observe(queued_at.elapsed().as_secs_f64());
+
+            let _ = tx.send(f());
+        });
+
+        match self.sender.try_send(job) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_job)) => {
+                RENDERS_REJECTED.inc();
+                return Err(RenderError::Full);
+            }
+            Err(TrySendError::Disconnected(_job)) => return Err(RenderError::Failed),
+        }
+
+        BLOCKING_TASKS.inc();
+        let _guard = Gauged(&BLOCKING_TASKS);
+
+        rx.await.map_err(|_err| RenderError::Failed)
+    }
+}
+
+/// Decrements a gauge when dropped.
+struct Gauged(&'static IntGauge);
+
+impl Drop for Gauged {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+fn run_worker(receiver: Arc<Mutex<Receiver<Job>>>) {
+    loop {
+        // The lock is released before running the job so other threads can
+        // take the next one.
+        let job = match receiver.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_err) => return,
+        };
+
+        // A panic drops the sender, failing the render instead of stopping
+        // the thread.
+        if std::panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+            tracing::error!("image rendering panicked");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_render_pool() {
+        let pool = RenderPool::new(1, 1);
+        assert_eq!(pool.render(|| 1 + 1).await.unwrap(), 2);
+
+        assert!(matches!(
+            pool.render(|| panic!("render failed")).await,
+            Err(RenderError::Failed)
+        ));
+        assert_eq!(pool.render(|| 2 + 2).await.unwrap(), 4);
+
+        // Once the only thread is busy, one more image fits in the queue.
+        let (started, wait_started) = mpsc::channel();
+        let (release, wait_release) = mpsc::channel::<()>();
+
+        let (blocked, (queued, full)) = futures::join!(
+            pool.render(move || {
+                started.send(()).unwrap();
+                wait_release.recv().unwrap();
+            }),
+            async {
+                wait_started.recv().unwrap();
+
+                futures::join!(pool.render(|| 3), async {
+                    let full = pool.render(|| 4).await;
+                    release.send(()).unwrap();
+                    full
+                })
+            }
+        );
+
+        assert!(blocked.is_ok());
+        assert_eq!(queued.unwrap(), 3);
+        assert!(matches!(full, Err(RenderError::Full)));
+    }
+}
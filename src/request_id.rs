@@ -0,0 +1,106 @@
+use std::future::Future;
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse},
+    error::InternalError,
+    http::header::{HeaderName, HeaderValue},
+    HttpMessage,
+};
+use tracing::Instrument;
+
+/// Header used to accept and return request IDs.
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Maximum length of a request ID provided by a client.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// An identifier for a request, either provided by the client or generated.
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// Get the ID from the `X-Request-Id` header if it is valid, otherwise
+    /// generate a new one.
+    pub fn from_request(req: &ServiceRequest) -> Self {
+        if let Some(id) = req
+            .headers()
+            .get(X_REQUEST_ID)
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| Self::is_valid(id))
+        {
+            return Self(id.to_string());
+        }
+
+        Self(format!("{:032x}", rand::random::<u128>()))
+    }
+
+    /// If a client provided ID is short and only contains safe characters.
+    fn is_valid(id: &str) -> bool {
+        !id.is_empty()
+            && id.len() <= MAX_REQUEST_ID_LEN
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    }
+}
+
+/// Middleware to assign each request an ID, attach it to all logs for the
+/// request, and return it in the response headers.
+///
+/// This must wrap the tracing middleware so its span is created within the
+/// span holding the ID.
+pub fn middleware<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<BoxBody>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody + 'static,
+{
+    let request_id = RequestId::from_request(&req);
+    req.extensions_mut().insert(request_id.clone());
+
+    let span = tracing::info_span!("request", request_id = %request_id.0);
+    let fut = span.in_scope(|| srv.call(req));
+
+    async move {
+        let header = HeaderValue::from_str(&request_id.0).ok();
+
+        match fut.await {
+            Ok(res) => {
+                let mut res = res.map_into_boxed_body();
+                if let Some(value) = header {
+                    res.headers_mut().insert(X_REQUEST_ID, value);
+                }
+
+                Ok(res)
+            }
+            // Errors are turned into responses after this point, so build the
+            // response now to be able to add the header.
+            Err(err) => {
+                let mut res = err.error_response();
+                if let Some(value) = header {
+                    res.headers_mut().insert(X_REQUEST_ID, value);
+                }
+
+                Err(InternalError::from_response(err, res).into())
+            }
+        }
+    }
+    .instrument(span)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid() {
+        assert!(RequestId::is_valid("abc-123_DEF.4"));
+        assert!(!RequestId::is_valid(""));
+        assert!(!RequestId::is_valid("has spaces"));
+        assert!(!RequestId::is_valid("line\nbreak"));
+        assert!(!RequestId::is_valid(&"a".repeat(MAX_REQUEST_ID_LEN + 1)));
+    }
+}
@@ -6,27 +6,168 @@ use std::{
 use futures_retry::{ErrorHandler, FutureRetry, RetryPolicy};
 use lazy_static::lazy_static;
 use lru::LruCache;
-use prometheus::{register_counter, Counter};
-use rand::prelude::IteratorRandom;
+use prometheus::{
+    register_counter, register_counter_vec, register_histogram_vec, Counter, CounterVec,
+    HistogramVec,
+};
+use rand::prelude::SliceRandom;
 use tokio::sync::Mutex;
 use tracing_unwrap::ResultExt;
 use trust_dns_resolver::{
+    config::ResolverConfig,
     error::{ResolveError, ResolveErrorKind},
+    proto::op::ResponseCode,
     TokioAsyncResolver,
 };
 
+use crate::{protocol::Outbound, types::Error};
+
+/// Longest hostname allowed in DNS, without a trailing dot.
+const MAX_HOST_LENGTH: usize = 253;
+
+/// Longest label allowed in DNS.
+const MAX_LABEL_LENGTH: usize = 63;
+
 lazy_static! {
     static ref RESOLVES: Counter =
         register_counter!("mcapi_dns_resolves_total", "Total number of DNS resolves").unwrap();
+    static ref RESOLVE_DURATION: HistogramVec = register_histogram_vec!(
+        "mcapi_dns_resolve_duration_seconds",
+        "Duration of DNS resolves by record type",
+        &["record"]
+    )
+    .unwrap();
+    static ref RESOLVE_ERRORS: CounterVec = register_counter_vec!(
+        "mcapi_dns_resolve_errors_total",
+        "Number of DNS resolve errors by record type and kind",
+        &["record", "kind"]
+    )
+    .unwrap();
+    static ref LOOKUP_DURATION: HistogramVec = register_histogram_vec!(
+        "mcapi_dns_lookup_duration_seconds",
+        "Duration of complete lookups, including retries, by the path that succeeded",
+        &["path"]
+    )
+    .unwrap();
 }
 
-/// Get default DNS resolver configuration.
-fn get_dns_resolver() -> TokioAsyncResolver {
-    TokioAsyncResolver::tokio(
-        trust_dns_resolver::config::ResolverConfig::cloudflare(),
-        Default::default(),
-    )
-    .expect_or_log("could not create dns resolver")
+/// Convert a hostname to the ASCII form used for DNS and handshakes, encoding
+/// internationalized domain names with punycode, and check that it is a valid
+/// hostname.
+///
+/// IP addresses are returned unchanged.
+pub fn normalize_host(host: &str) -> Result<String, Error> {
+    if host.parse::<IpAddr>().is_ok() {
+        return Ok(host.to_string());
+    }
+
+    let invalid = || Error::InvalidHost(host.to_string());
+
+    let ascii = idna::domain_to_ascii(host).map_err(|_err| invalid())?;
+    let ascii = ascii.strip_suffix('.').unwrap_or(&ascii);
+
+    if ascii.is_empty() || ascii.len() > MAX_HOST_LENGTH {
+        return Err(invalid());
+    }
+
+    let valid_label = |label: &str| {
+        !label.is_empty()
+            && label.len() <= MAX_LABEL_LENGTH
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    };
+
+    if !ascii.split('.').all(valid_label) {
+        return Err(invalid());
+    }
+
+    Ok(ascii.to_string())
+}
+
+/// Key identifying a server however its host was written, so differences in
+/// case or a trailing dot are treated as the same server.
+///
+/// Invalid hosts are only lowercased.
+pub fn server_key(host: &str, port: u16) -> String {
+    match normalize_host(host) {
+        Ok(host) => format!("{}:{}", host, port),
+        Err(_err) => format!("{}:{}", host, port).to_lowercase(),
+    }
+}
+
+/// Key for a configured `host:port` entry, matching [`server_key`].
+pub fn entry_key(entry: &str) -> String {
+    match entry
+        .rsplit_once(':')
+        .and_then(|(host, port)| Some((host, port.parse().ok()?)))
+    {
+        Some((host, port)) => server_key(host, port),
+        None => entry.to_lowercase(),
+    }
+}
+
+/// Get a short description of a resolve error, for use in metric labels.
+fn error_kind(err: &ResolveError) -> &'static str {
+    match err.kind() {
+        ResolveErrorKind::NoRecordsFound { response_code, .. }
+            if *response_code == ResponseCode::NXDomain =>
+        {
+            "nxdomain"
+        }
+        ResolveErrorKind::NoRecordsFound { .. } => "no_records",
+        ResolveErrorKind::Timeout => "timeout",
+        ResolveErrorKind::NoConnections => "no_connections",
+        ResolveErrorKind::Io(_) => "io",
+        ResolveErrorKind::Proto(_) => "proto",
+        _ => "other",
+    }
+}
+
+/// Which path was used to find an address for a host.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LookupPath {
+    /// The host was an IP address.
+    Ip,
+    /// The address came from a SRV record.
+    Srv,
+    /// The address came from A or AAAA records of the host itself.
+    Direct,
+    /// No address could be found.
+    None,
+}
+
+impl LookupPath {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ip => "ip",
+            Self::Srv => "srv",
+            Self::Direct => "direct",
+            Self::None => "none",
+        }
+    }
+}
+
+/// Get default DNS resolver configuration, sending queries from the outbound
+/// addresses.
+fn get_dns_resolver(outbound: &Outbound) -> TokioAsyncResolver {
+    let cloudflare = ResolverConfig::cloudflare();
+
+    let mut name_servers = cloudflare.name_servers().to_vec();
+    for name_server in &mut name_servers {
+        name_server.bind_addr = Some(outbound.local_addr(&name_server.socket_addr));
+    }
+
+    let config = ResolverConfig::from_parts(
+        cloudflare.domain().cloned(),
+        cloudflare.search().to_vec(),
+        name_servers,
+    );
+
+    TokioAsyncResolver::tokio(config, Default::default())
+        .expect_or_log("could not create dns resolver")
 }
 
 /// Retry method for DNS requests.
@@ -59,96 +200,203 @@ impl ErrorHandler<ResolveError> for ResolverRetry {
     }
 }
 
+/// Addresses found for a host.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Target {
+    /// Every usable address, in a random order.
+    pub addrs: Vec<SocketAddr>,
+    /// Host from the SRV record the addresses came from, if any.
+    pub srv_target: Option<String>,
+}
+
 /// A caching resolver for looking up Minecraft-related DNS records.
 pub struct Resolver {
-    cache: Mutex<LruCache<(String, u16), Option<SocketAddr>>>,
+    cache: Mutex<LruCache<(String, u16, bool), Target>>,
     resolver: TokioAsyncResolver,
 }
 
 impl Default for Resolver {
     fn default() -> Self {
+        Self::new(&Outbound::default())
+    }
+}
+
+impl Resolver {
+    /// Create a new resolver, sending queries from the outbound addresses.
+    pub fn new(outbound: &Outbound) -> Self {
         Self {
             cache: Mutex::new(LruCache::new(1024)),
-            resolver: get_dns_resolver(),
+            resolver: get_dns_resolver(outbound),
         }
     }
-}
 
-impl Resolver {
-    /// Attempt to lookup a host and port into a `SocketAddr`.
+    /// Attempt to lookup a host and port into every usable `SocketAddr`, in a
+    /// random order.
+    ///
+    /// It will retry multiple times if errors occur, then cache the result. If
+    /// no addresses could be found, it returns an empty list.
+    pub async fn lookup(&self, host: String, port: u16) -> Vec<SocketAddr> {
+        self.lookup_target(host, port).await.addrs
+    }
+
+    /// Attempt to lookup a host and port like [`Resolver::lookup`], including
+    /// the SRV record the addresses came from.
+    pub async fn lookup_target(&self, host: String, port: u16) -> Target {
+        self.lookup_cached(host, port, true).await
+    }
+
+    /// Attempt to lookup a host and port like [`Resolver::lookup`], using only
+    /// the addresses of the host itself and ignoring any SRV records.
+    pub async fn lookup_target_without_srv(&self, host: String, port: u16) -> Target {
+        self.lookup_cached(host, port, false).await
+    }
+
+    /// Lookup a host and port, using the cache if possible.
     ///
-    /// It will retry multiple times if errors occur, then cache the result.
-    pub async fn lookup(&self, host: String, port: u16) -> Option<SocketAddr> {
-        let entry = (host, port);
+    /// IP addresses are returned without any DNS queries, as they cannot have
+    /// SRV records.
+    #[tracing::instrument(skip(self))]
+    async fn lookup_cached(&self, host: String, port: u16, srv: bool) -> Target {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            tracing::trace!("host was ip");
+            LOOKUP_DURATION
+                .with_label_values(&[LookupPath::Ip.as_str()])
+                .observe(0.0);
+
+            return Target {
+                addrs: vec![SocketAddr::new(ip, port)],
+                srv_target: None,
+            };
+        }
+
+        let entry = (host, port, srv);
 
         {
             let mut cache = self.cache.lock().await;
-            if let Some(addr) = cache.get(&entry) {
-                tracing::trace!("had cached socketaddr for {}:{}: {:?}", entry.0, port, addr);
-                return addr.to_owned();
+            if let Some(target) = cache.get(&entry) {
+                tracing::trace!(
+                    "had cached socketaddrs for {}:{}: {:?}",
+                    entry.0,
+                    port,
+                    target
+                );
+                return target.to_owned();
             }
         }
 
-        let addr = FutureRetry::new(|| self.resolve(&entry.0, port), ResolverRetry::new(3))
-            .await
-            .map(|(addr, _attempts)| addr)
-            .map_err(|(err, _attempts)| {
-                tracing::error!("could not resolve host {:?}", err);
-                err
-            })
-            .ok()
-            .flatten();
+        let timer = std::time::Instant::now();
+
+        let (target, path) =
+            FutureRetry::new(|| self.resolve(&entry.0, port, srv), ResolverRetry::new(3))
+                .await
+                .map(|(target, _attempts)| target)
+                .map_err(|(err, _attempts)| {
+                    tracing::error!("could not resolve host {:?}", err);
+                    err
+                })
+                .unwrap_or((Target::default(), LookupPath::None));
 
-        tracing::debug!("resolved {}:{}, {:?}", entry.0, port, addr);
+        LOOKUP_DURATION
+            .with_label_values(&[path.as_str()])
+            .observe(timer.elapsed().as_secs_f64());
+
+        tracing::debug!("resolved {}:{}, {:?}", entry.0, port, target);
 
         {
             let mut cache = self.cache.lock().await;
-            cache.put(entry, addr);
+            cache.put(entry, target.clone());
         }
 
-        addr
+        target
     }
 
-    /// Attempt to resolve a host and port into a usable `SocketAddr`.
+    /// Check that DNS resolution is working by looking up the IP addresses
+    /// for a name, bypassing the cache.
+    pub async fn check(&self, name: &str) -> Result<(), ResolveError> {
+        RESOLVES.inc();
+        self.resolver.lookup_ip(name).await?;
+        Ok(())
+    }
+
+    /// Attempt to resolve a host and port into usable `SocketAddr`s.
     ///
-    /// It first attempts to resolve any potential SRV records then falls back to
-    /// using the given host and port.
-    async fn resolve(&self, host: &str, port: u16) -> Result<Option<SocketAddr>, ResolveError> {
-        let records = self
-            .resolve_srv(host)
-            .await?
+    /// It first attempts to resolve any potential SRV records, unless `srv` is
+    /// false, then falls back to using the given host and port. All addresses
+    /// of the first record with any addresses are returned.
+    async fn resolve(
+        &self,
+        host: &str,
+        port: u16,
+        srv: bool,
+    ) -> Result<(Target, LookupPath), ResolveError> {
+        let srv_records = if srv {
+            self.resolve_srv(host).await?
+        } else {
+            Vec::new()
+        };
+        let srv_count = srv_records.len();
+
+        let records = srv_records
             .into_iter()
-            .chain(vec![(host.to_owned(), port)]);
+            .chain(vec![(host.to_owned(), port)])
+            .enumerate();
 
-        for (host, port) in records {
-            let ip = if let Ok(ip_addr) = host.parse::<IpAddr>() {
+        for (index, (host, port)) in records {
+            let ips = if let Ok(ip_addr) = host.parse::<IpAddr>() {
                 tracing::trace!("host was ip");
-                Some(ip_addr)
+                vec![ip_addr]
             } else {
                 tracing::trace!("looking up ip for host");
                 RESOLVES.inc();
-                match self.resolver.lookup_ip(host).await {
+                let timer = RESOLVE_DURATION.with_label_values(&["ip"]).start_timer();
+                let ips = self.resolver.lookup_ip(host.as_str()).await;
+                timer.observe_duration();
+
+                match ips {
                     Ok(ips) => {
-                        let ips = ips.into_iter();
+                        let mut ips: Vec<_> = ips.into_iter().collect();
 
                         let mut rng = rand::thread_rng();
-                        ips.choose(&mut rng)
+                        ips.shuffle(&mut rng);
+
+                        ips
                     }
-                    Err(err) if matches!(err.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {
-                        None
+                    Err(err) => {
+                        RESOLVE_ERRORS
+                            .with_label_values(&["ip", error_kind(&err)])
+                            .inc();
+
+                        if !matches!(err.kind(), ResolveErrorKind::NoRecordsFound { .. }) {
+                            return Err(err);
+                        }
+
+                        vec![]
                     }
-                    Err(err) => return Err(err),
                 }
             };
 
-            if let Some(ip) = ip {
-                tracing::debug!("found ip for host: {}", ip);
-                return Ok(Some(SocketAddr::new(ip, port)));
+            if !ips.is_empty() {
+                tracing::debug!("found ips for host: {:?}", ips);
+
+                let path = if index < srv_count {
+                    LookupPath::Srv
+                } else {
+                    LookupPath::Direct
+                };
+
+                let addrs = ips
+                    .into_iter()
+                    .map(|ip| SocketAddr::new(ip, port))
+                    .collect();
+                let srv_target =
+                    (path == LookupPath::Srv).then(|| host.trim_end_matches('.').to_owned());
+
+                return Ok((Target { addrs, srv_target }, path));
             }
         }
 
         tracing::debug!("found no usable records");
-        Ok(None)
+        Ok((Target::default(), LookupPath::None))
     }
 
     /// Attempt to resolve SRV records for a given host. Returns any discovered
@@ -157,12 +405,23 @@ impl Resolver {
         let name = format!("_minecraft._tcp.{}", host);
 
         RESOLVES.inc();
-        let records = match self.resolver.srv_lookup(name).await {
+        let timer = RESOLVE_DURATION.with_label_values(&["srv"]).start_timer();
+        let records = self.resolver.srv_lookup(name).await;
+        timer.observe_duration();
+
+        let records = match records {
             Ok(records) => records,
-            Err(err) if matches!(err.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {
-                return Ok(vec![])
+            Err(err) => {
+                RESOLVE_ERRORS
+                    .with_label_values(&["srv", error_kind(&err)])
+                    .inc();
+
+                if matches!(err.kind(), ResolveErrorKind::NoRecordsFound { .. }) {
+                    return Ok(vec![]);
+                }
+
+                return Err(err);
             }
-            Err(err) => return Err(err),
         };
 
         let records = records
@@ -174,3 +433,71 @@ impl Resolver {
         Ok(records)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_host() {
+        assert_eq!(normalize_host("Example.com").unwrap(), "example.com");
+        assert_eq!(normalize_host("mc.example.com.").unwrap(), "mc.example.com");
+        assert_eq!(
+            normalize_host("bücher.example").unwrap(),
+            "xn--bcher-kva.example"
+        );
+        assert_eq!(
+            normalize_host("xn--bcher-kva.example").unwrap(),
+            "xn--bcher-kva.example"
+        );
+        assert_eq!(normalize_host("127.0.0.1").unwrap(), "127.0.0.1");
+        assert_eq!(normalize_host("::1").unwrap(), "::1");
+
+        for host in [
+            "",
+            ".",
+            "example..com",
+            "-example.com",
+            "example-.com",
+            "exa mple.com",
+            "example.com/path",
+            &format!("{}.com", "a".repeat(64)),
+            &format!("{}com", "a.".repeat(127)),
+        ] {
+            assert!(
+                matches!(normalize_host(host), Err(Error::InvalidHost(_))),
+                "{} should be invalid",
+                host
+            );
+        }
+    }
+
+    #[test]
+    fn test_server_key() {
+        assert_eq!(server_key("Example.COM.", 25565), "example.com:25565");
+        assert_eq!(
+            server_key("bücher.example", 25565),
+            "xn--bcher-kva.example:25565"
+        );
+        assert_eq!(server_key("Not Valid", 25565), "not valid:25565");
+
+        assert_eq!(entry_key("Example.COM.:25566"), "example.com:25566");
+        assert_eq!(entry_key("Example.COM"), "example.com");
+    }
+
+    #[tokio::test]
+    async fn test_lookup_ip() {
+        let resolver = Resolver::default();
+
+        for (host, port) in [("127.0.0.1", 25565), ("::1", 25566)] {
+            let target = resolver.lookup_target(host.to_string(), port).await;
+            assert_eq!(
+                target,
+                Target {
+                    addrs: vec![SocketAddr::new(host.parse().unwrap(), port)],
+                    srv_target: None,
+                }
+            );
+        }
+    }
+}
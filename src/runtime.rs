@@ -0,0 +1,122 @@
+//! Metrics for the Tokio runtimes.
+
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use prometheus::{
+    core::{Collector, Desc},
+    proto::MetricFamily,
+    CounterVec, GaugeVec, Opts,
+};
+use tokio::runtime::Handle;
+
+lazy_static! {
+    static ref RUNTIMES: Mutex<Vec<Handle>> = Default::default();
+}
+
+/// Collects metrics from every registered runtime when gathered.
+///
+/// Actix runs a single threaded runtime for each worker, so each is labeled
+/// by the order it was registered in.
+struct RuntimeCollector {
+    workers: GaugeVec,
+    alive_tasks: GaugeVec,
+    global_queue_depth: GaugeVec,
+    busy: CounterVec,
+    descs: Vec<Desc>,
+}
+
+impl RuntimeCollector {
+    fn new() -> prometheus::Result<Self> {
+        let workers = GaugeVec::new(
+            Opts::new("mcapi_runtime_workers", "Number of worker threads"),
+            &["runtime"],
+        )?;
+        let alive_tasks = GaugeVec::new(
+            Opts::new(
+                "mcapi_runtime_alive_tasks",
+                "Number of tasks not yet finished",
+            ),
+            &["runtime"],
+        )?;
+        let global_queue_depth = GaugeVec::new(
+            Opts::new(
+                "mcapi_runtime_global_queue_depth",
+                "Number of tasks waiting in the global queue",
+            ),
+            &["runtime"],
+        )?;
+        let busy = CounterVec::new(
+            Opts::new(
+                "mcapi_runtime_busy_seconds_total",
+                "Total time worker threads spent running tasks",
+            ),
+            &["runtime"],
+        )?;
+
+        let descs = [&workers, &alive_tasks, &global_queue_depth]
+            .iter()
+            .flat_map(|metric| metric.desc())
+            .chain(busy.desc())
+            .cloned()
+            .collect();
+
+        Ok(Self {
+            workers,
+            alive_tasks,
+            global_queue_depth,
+            busy,
+            descs,
+        })
+    }
+}
+
+impl Collector for RuntimeCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        for (index, handle) in RUNTIMES.lock().unwrap().iter().enumerate() {
+            let runtime = index.to_string();
+            let metrics = handle.metrics();
+
+            self.workers
+                .with_label_values(&[&runtime])
+                .set(metrics.num_workers() as f64);
+            self.alive_tasks
+                .with_label_values(&[&runtime])
+                .set(metrics.num_alive_tasks() as f64);
+            self.global_queue_depth
+                .with_label_values(&[&runtime])
+                .set(metrics.global_queue_depth() as f64);
+
+            let busy: f64 = (0..metrics.num_workers())
+                .map(|worker| metrics.worker_total_busy_duration(worker).as_secs_f64())
+                .sum();
+            let counter = self.busy.with_label_values(&[&runtime]);
+            counter.reset();
+            counter.inc_by(busy);
+        }
+
+        let mut families = self.workers.collect();
+        families.extend(self.alive_tasks.collect());
+        families.extend(self.global_queue_depth.collect());
+        families.extend(self.busy.collect());
+        families
+    }
+}
+
+/// Register the collector for runtime metrics.
+pub fn register_collector() {
+    let collector = RuntimeCollector::new().expect("runtime metrics were invalid");
+
+    if let Err(err) = prometheus::register(Box::new(collector)) {
+        tracing::error!("could not register runtime metrics: {}", err);
+    }
+}
+
+/// Include the runtime of the current thread in runtime metrics.
+pub fn register_current() {
+    RUNTIMES.lock().unwrap().push(Handle::current());
+}
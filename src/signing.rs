@@ -0,0 +1,205 @@
+use actix_web::{
+    dev::Payload, error::InternalError, http::Method, web, FromRequest, HttpRequest, HttpResponse,
+};
+use futures::future::{ready, Ready};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::config::Config;
+
+/// Query parameter holding the signature.
+pub const SIGNATURE_PARAM: &str = "sig";
+
+/// Query parameter holding the unix timestamp a signature expires at.
+pub const EXPIRES_PARAM: &str = "expires";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum SignatureError {
+    #[error("missing signature")]
+    Missing,
+    #[error("invalid signature")]
+    Invalid,
+    #[error("signature has expired")]
+    Expired,
+    #[error("signed urls must use GET")]
+    Method,
+}
+
+/// The signed content of a URL, its path followed by every query parameter
+/// except the signature, sorted so parameters may be given in any order.
+fn canonical(path: &str, query: &str) -> String {
+    let mut params: Vec<_> = query
+        .split('&')
+        .filter(|param| {
+            let name = param.split('=').next().unwrap_or_default();
+            !param.is_empty() && name != SIGNATURE_PARAM
+        })
+        .collect();
+    params.sort_unstable();
+
+    format!("{}?{}", path, params.join("&"))
+}
+
+/// Sign a path and query, returning the hex encoded HMAC-SHA256 of the
+/// canonical URL.
+pub fn sign(secret: &str, path: &str, query: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any length");
+    mac.update(canonical(path, query).as_bytes());
+
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Check that a URL was signed with the secret and has not expired at `now`.
+pub fn verify(secret: &str, path: &str, query: &str, now: u64) -> Result<(), SignatureError> {
+    let param = |name: &str| {
+        query
+            .split('&')
+            .find_map(|param| param.strip_prefix(name)?.strip_prefix('='))
+    };
+
+    let signature = param(SIGNATURE_PARAM).ok_or(SignatureError::Missing)?;
+    let expires: u64 = param(EXPIRES_PARAM)
+        .ok_or(SignatureError::Missing)?
+        .parse()
+        .map_err(|_err| SignatureError::Invalid)?;
+
+    let expected = sign(secret, path, query);
+    if !crate::admin::tokens_match(expected.as_bytes(), signature.to_lowercase().as_bytes()) {
+        return Err(SignatureError::Invalid);
+    }
+
+    // Only trust the expiry once it is known to be signed.
+    if expires <= now {
+        return Err(SignatureError::Expired);
+    }
+
+    Ok(())
+}
+
+/// Proof that a request was made with a valid signed URL, or that URLs do not
+/// need to be signed.
+///
+/// When an image signing secret is configured, requests must include an
+/// `expires` parameter and a `sig` parameter created by [`sign`]. Only the URL
+/// is signed, so requests must also use `GET` instead of sending options in a
+/// body.
+pub struct Signed;
+
+impl FromRequest for Signed {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let secret = req
+            .app_data::<web::Data<Config>>()
+            .and_then(|config| config.image_signing_secret.as_deref());
+
+        let result = match secret {
+            Some(_secret) if req.method() != Method::GET && req.method() != Method::HEAD => {
+                Err(SignatureError::Method)
+            }
+            Some(secret) => verify(
+                secret,
+                req.path(),
+                req.query_string(),
+                crate::unix_timestamp(),
+            ),
+            None => Ok(()),
+        };
+
+        ready(result.map(|()| Signed).map_err(|err| {
+            InternalError::from_response(
+                err,
+                HttpResponse::Forbidden().json(serde_json::json!({
+                    "status": "error",
+                    "error": err.to_string(),
+                })),
+            )
+            .into()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test::TestRequest};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_signed_post() {
+        let config = web::Data::new(Config {
+            image_signing_secret: Some("secret".to_string()),
+            ..Default::default()
+        });
+
+        let query = format!("ip=example.com&expires={}", crate::unix_timestamp() + 60);
+        let uri = format!(
+            "/server/image?{}&sig={}",
+            query,
+            sign("secret", "/server/image", &query)
+        );
+
+        let req = TestRequest::get()
+            .uri(&uri)
+            .app_data(config.clone())
+            .to_http_request();
+        assert!(Signed::extract(&req).await.is_ok());
+
+        // The body is not signed, so it could change any option.
+        let (req, mut payload) = TestRequest::post()
+            .uri(&uri)
+            .app_data(config)
+            .set_json(serde_json::json!({ "ip": "other.example.com" }))
+            .to_http_parts();
+        let err = Signed::from_request(&req, &mut payload)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.error_response().status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn test_verify() {
+        let query = "ip=example.com&expires=100&theme=dark";
+        let signature = sign("secret", "/server/image", query);
+        let signed = format!("{}&sig={}", query, signature);
+
+        assert_eq!(verify("secret", "/server/image", &signed, 50), Ok(()));
+
+        // Parameters may be reordered, but not changed.
+        let reordered = format!("theme=dark&sig={}&expires=100&ip=example.com", signature);
+        assert_eq!(verify("secret", "/server/image", &reordered, 50), Ok(()));
+        assert_eq!(
+            verify(
+                "secret",
+                "/server/image",
+                &signed.replace("dark", "light"),
+                50
+            ),
+            Err(SignatureError::Invalid)
+        );
+        assert_eq!(
+            verify("secret", "/server/icon", &signed, 50),
+            Err(SignatureError::Invalid)
+        );
+        assert_eq!(
+            verify("other", "/server/image", &signed, 50),
+            Err(SignatureError::Invalid)
+        );
+
+        assert_eq!(
+            verify("secret", "/server/image", &signed, 100),
+            Err(SignatureError::Expired)
+        );
+        assert_eq!(
+            verify("secret", "/server/image", query, 50),
+            Err(SignatureError::Missing)
+        );
+    }
+}
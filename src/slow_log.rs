@@ -0,0 +1,179 @@
+use std::{
+    cell::RefCell,
+    future::Future,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse},
+    web,
+};
+use lazy_static::lazy_static;
+use prometheus::{register_counter_vec, CounterVec};
+
+use crate::{config::Config, load_shed::LONG_POLL_PATHS};
+
+lazy_static! {
+    static ref SLOW_OPERATIONS: CounterVec = register_counter_vec!(
+        "mcapi_slow_operations_total",
+        "Number of requests and refreshes that took longer than the slow threshold",
+        &["kind", "method"]
+    )
+    .unwrap();
+}
+
+tokio::task_local! {
+    /// Time spent in each phase of the refreshes made for the current request.
+    static REQUEST_TIMINGS: Rc<RefCell<RefreshTimings>>;
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+/// How long each phase of refreshing cached data took.
+#[derive(Debug, Default)]
+pub struct RefreshTimings {
+    /// Checking the cache for existing data.
+    pub lookup: Duration,
+    /// Waiting for the lock and checking if another refresh finished first.
+    pub lock: Duration,
+    /// Checking the server.
+    pub refresh: Duration,
+    /// Saving the data and releasing the lock.
+    pub store: Duration,
+}
+
+impl RefreshTimings {
+    fn total(&self) -> Duration {
+        self.lookup + self.lock + self.refresh + self.store
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.lookup += other.lookup;
+        self.lock += other.lock;
+        self.refresh += other.refresh;
+        self.store += other.store;
+    }
+
+    /// Add the refresh to the current request's timings, and log it if it
+    /// took at least the threshold.
+    pub fn check(&self, threshold: Option<Duration>, method: &str, key: &str) {
+        let _ = REQUEST_TIMINGS.try_with(|timings| timings.borrow_mut().add(self));
+
+        let total = self.total();
+
+        match threshold {
+            Some(threshold) if total >= threshold => (),
+            _ => return,
+        }
+
+        SLOW_OPERATIONS
+            .with_label_values(&["refresh", method])
+            .inc();
+
+        tracing::warn!(
+            method,
+            key,
+            total_ms = millis(total),
+            lookup_ms = millis(self.lookup),
+            lock_ms = millis(self.lock),
+            refresh_ms = millis(self.refresh),
+            store_ms = millis(self.store),
+            "slow refresh"
+        );
+    }
+}
+
+/// Middleware to log requests that took at least the slow threshold, with the
+/// time their refreshes spent in each phase.
+///
+/// It must be inside the request ID middleware for logs to include the ID, and
+/// inside the deadline middleware so refreshes that run after a client
+/// disconnects are still timed.
+pub fn middleware<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+{
+    // Long polls are expected to take as long as they wait for.
+    let threshold = req
+        .app_data::<web::Data<Config>>()
+        .and_then(|config| config.slow_threshold())
+        .filter(|_| !LONG_POLL_PATHS.contains(&req.path()));
+    let method = req.method().clone();
+    // Query strings may contain anything from clients, so only the path is
+    // logged.
+    let path = req.path().to_string();
+
+    let timings = Rc::new(RefCell::new(RefreshTimings::default()));
+
+    let start = Instant::now();
+    let fut = REQUEST_TIMINGS.scope(timings.clone(), srv.call(req));
+
+    async move {
+        let res = fut.await;
+        let elapsed = start.elapsed();
+
+        if matches!(threshold, Some(threshold) if elapsed >= threshold) {
+            let status = match &res {
+                Ok(res) => res.status().as_u16(),
+                Err(err) => err.as_response_error().status_code().as_u16(),
+            };
+
+            SLOW_OPERATIONS
+                .with_label_values(&["request", method.as_str()])
+                .inc();
+
+            let timings = timings.borrow();
+
+            tracing::warn!(
+                method = %method,
+                path = %path,
+                status,
+                total_ms = millis(elapsed),
+                lock_ms = millis(timings.lock),
+                refresh_ms = millis(timings.refresh),
+                store_ms = millis(timings.store),
+                "slow request"
+            );
+        }
+
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_request_timings() {
+        let refresh = RefreshTimings {
+            lookup: Duration::from_millis(1),
+            lock: Duration::from_millis(2),
+            refresh: Duration::from_millis(3),
+            store: Duration::from_millis(4),
+        };
+        assert_eq!(refresh.total(), Duration::from_millis(10));
+
+        // Refreshes outside of a request are only checked.
+        refresh.check(None, "ping", "ping:example.com:25565");
+
+        let timings = Rc::new(RefCell::new(RefreshTimings::default()));
+        REQUEST_TIMINGS
+            .scope(timings.clone(), async {
+                refresh.check(None, "ping", "ping:example.com:25565");
+                refresh.check(None, "query", "query:example.com:25565");
+            })
+            .await;
+
+        let timings = timings.borrow();
+        assert_eq!(timings.lock, Duration::from_millis(4));
+        assert_eq!(timings.refresh, Duration::from_millis(6));
+        assert_eq!(timings.store, Duration::from_millis(8));
+    }
+}
@@ -0,0 +1,364 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+};
+
+const VERSION: u8 = 0x05;
+
+const AUTH_NONE: u8 = 0x00;
+const AUTH_PASSWORD: u8 = 0x02;
+const AUTH_UNACCEPTABLE: u8 = 0xFF;
+
+const COMMAND_CONNECT: u8 = 0x01;
+const COMMAND_UDP_ASSOCIATE: u8 = 0x03;
+
+const ADDRESS_IPV4: u8 = 0x01;
+const ADDRESS_DOMAIN: u8 = 0x03;
+const ADDRESS_IPV6: u8 = 0x04;
+
+/// Reserved bytes and fragment number at the start of each UDP datagram.
+const ADDRESS_HEADER_PREFIX: [u8; 3] = [0x00, 0x00, 0x00];
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("proxy sent invalid response")]
+    InvalidResponse,
+    #[error("proxy did not accept any authentication methods")]
+    NoAcceptableAuth,
+    #[error("proxy rejected credentials")]
+    AuthFailed,
+    #[error("proxy request failed with code {0}")]
+    RequestFailed(u8),
+    #[error("credentials are too long")]
+    CredentialsTooLong,
+}
+
+/// A SOCKS5 proxy to send connections through.
+#[derive(Clone, Debug)]
+pub struct Proxy {
+    pub addr: SocketAddr,
+    /// Username and password, if the proxy requires authentication.
+    pub credentials: Option<(String, String)>,
+}
+
+impl Proxy {
+    /// Open a TCP connection to `target` through an existing connection to the
+    /// proxy.
+    pub async fn connect(
+        &self,
+        mut stream: TcpStream,
+        target: SocketAddr,
+    ) -> Result<TcpStream, Error> {
+        self.authenticate(&mut stream).await?;
+        request(&mut stream, COMMAND_CONNECT, target).await?;
+
+        Ok(stream)
+    }
+
+    /// Start relaying UDP datagrams through the proxy, using an existing
+    /// connection to the proxy to control the association and `socket` to
+    /// send and receive datagrams.
+    pub async fn associate(
+        &self,
+        mut control: TcpStream,
+        socket: UdpSocket,
+        target: SocketAddr,
+    ) -> Result<UdpAssociation, Error> {
+        self.authenticate(&mut control).await?;
+        let relay = request(&mut control, COMMAND_UDP_ASSOCIATE, socket.local_addr()?).await?;
+
+        // Proxies may respond with an unspecified address when the relay is on
+        // the same address as the proxy itself.
+        let relay = if relay.ip().is_unspecified() {
+            SocketAddr::new(self.addr.ip(), relay.port())
+        } else {
+            relay
+        };
+
+        socket.connect(relay).await?;
+
+        Ok(UdpAssociation {
+            _control: control,
+            socket,
+            header: encode_address(ADDRESS_HEADER_PREFIX.to_vec(), target),
+        })
+    }
+
+    /// Negotiate authentication with the proxy.
+    async fn authenticate<S>(&self, stream: &mut S) -> Result<(), Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let method = if self.credentials.is_some() {
+            AUTH_PASSWORD
+        } else {
+            AUTH_NONE
+        };
+
+        stream.write_all(&[VERSION, 1, method]).await?;
+
+        // Proxies closing the connection early are an error from `read_exact`,
+        // so the whole response is always available.
+        let mut response = [0; 2];
+        stream.read_exact(&mut response).await?;
+        let [version, method] = response;
+
+        if version != VERSION {
+            return Err(Error::InvalidResponse);
+        }
+
+        match (method, &self.credentials) {
+            (AUTH_NONE, None) => Ok(()),
+            (AUTH_PASSWORD, Some((username, password))) => {
+                if username.len() > 255 || password.len() > 255 {
+                    return Err(Error::CredentialsTooLong);
+                }
+
+                let mut message = vec![0x01, username.len() as u8];
+                message.extend(username.as_bytes());
+                message.push(password.len() as u8);
+                message.extend(password.as_bytes());
+                stream.write_all(&message).await?;
+
+                let mut response = [0; 2];
+                stream.read_exact(&mut response).await?;
+                let [version, status] = response;
+
+                if version != 0x01 {
+                    return Err(Error::InvalidResponse);
+                }
+
+                if status != 0x00 {
+                    return Err(Error::AuthFailed);
+                }
+
+                Ok(())
+            }
+            (AUTH_UNACCEPTABLE, _) => Err(Error::NoAcceptableAuth),
+            _ => Err(Error::InvalidResponse),
+        }
+    }
+}
+
+/// UDP datagrams relayed through a proxy to a single target.
+///
+/// The association only lasts as long as the control connection, so it is
+/// held until this is dropped.
+pub struct UdpAssociation {
+    _control: TcpStream,
+    socket: UdpSocket,
+    header: Vec<u8>,
+}
+
+impl UdpAssociation {
+    /// Send a datagram to the target.
+    pub async fn send(&self, data: &[u8]) -> Result<usize, Error> {
+        let mut datagram = self.header.clone();
+        datagram.extend(data);
+
+        self.socket.send(&datagram).await?;
+        Ok(data.len())
+    }
+
+    /// Receive a datagram from the target into `buf`, returning its length.
+    pub async fn recv(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut datagram = vec![0; buf.len() + 262];
+        let len = self.socket.recv(&mut datagram).await?;
+
+        let header_len = udp_header_len(&datagram[..len])?;
+        let data = &datagram[header_len..len];
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+
+        Ok(len)
+    }
+}
+
+/// Send a request to the proxy, returning the address it bound.
+async fn request<S>(stream: &mut S, command: u8, target: SocketAddr) -> Result<SocketAddr, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let message = encode_address(vec![VERSION, command, 0x00], target);
+    stream.write_all(&message).await?;
+
+    let mut response = [0; 4];
+    stream.read_exact(&mut response).await?;
+    let [version, reply, _reserved, address_type] = response;
+
+    if version != VERSION {
+        return Err(Error::InvalidResponse);
+    }
+
+    if reply != 0x00 {
+        return Err(Error::RequestFailed(reply));
+    }
+
+    let ip = match address_type {
+        ADDRESS_IPV4 => {
+            let mut ip = [0; 4];
+            stream.read_exact(&mut ip).await?;
+            IpAddr::V4(Ipv4Addr::from(ip))
+        }
+        ADDRESS_IPV6 => {
+            let mut ip = [0; 16];
+            stream.read_exact(&mut ip).await?;
+            IpAddr::V6(Ipv6Addr::from(ip))
+        }
+        // Domains are not useful as a bound address, but must still be read.
+        ADDRESS_DOMAIN => {
+            let len = stream.read_u8().await?;
+            let mut domain = vec![0; len as usize];
+            stream.read_exact(&mut domain).await?;
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+        }
+        _ => return Err(Error::InvalidResponse),
+    };
+
+    let port = stream.read_u16().await?;
+
+    Ok(SocketAddr::new(ip, port))
+}
+
+/// Append an address type, address, and port to a message.
+fn encode_address(mut message: Vec<u8>, addr: SocketAddr) -> Vec<u8> {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            message.push(ADDRESS_IPV4);
+            message.extend(ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            message.push(ADDRESS_IPV6);
+            message.extend(ip.octets());
+        }
+    }
+
+    message.extend(addr.port().to_be_bytes());
+    message
+}
+
+/// Get the length of the header on a relayed UDP datagram.
+fn udp_header_len(datagram: &[u8]) -> Result<usize, Error> {
+    // Fragmented datagrams are not supported, so they are treated as invalid.
+    if datagram.len() < 4 || datagram[2] != 0x00 {
+        return Err(Error::InvalidResponse);
+    }
+
+    let address_len = match datagram[3] {
+        ADDRESS_IPV4 => 4,
+        ADDRESS_IPV6 => 16,
+        ADDRESS_DOMAIN if datagram.len() > 4 => 1 + datagram[4] as usize,
+        _ => return Err(Error::InvalidResponse),
+    };
+
+    let len = 4 + address_len + 2;
+    if datagram.len() < len {
+        return Err(Error::InvalidResponse);
+    }
+
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_connect_request() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+
+        let proxy = Proxy {
+            addr: "127.0.0.1:1080".parse().unwrap(),
+            credentials: Some(("user".to_string(), "pass".to_string())),
+        };
+
+        let server = tokio::spawn(async move {
+            let mut greeting = [0; 3];
+            server.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [VERSION, 1, AUTH_PASSWORD]);
+            server.write_all(&[VERSION, AUTH_PASSWORD]).await.unwrap();
+
+            let mut auth = [0; 11];
+            server.read_exact(&mut auth).await.unwrap();
+            assert_eq!(&auth, b"\x01\x04user\x04pass");
+            server.write_all(&[0x01, 0x00]).await.unwrap();
+
+            let mut request = [0; 10];
+            server.read_exact(&mut request).await.unwrap();
+            assert_eq!(
+                request,
+                [
+                    VERSION,
+                    COMMAND_CONNECT,
+                    0,
+                    ADDRESS_IPV4,
+                    192,
+                    0,
+                    2,
+                    1,
+                    0x63,
+                    0xDD
+                ]
+            );
+            server
+                .write_all(&[VERSION, 0, 0, ADDRESS_IPV4, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+        });
+
+        proxy.authenticate(&mut client).await.unwrap();
+        let bound = request(
+            &mut client,
+            COMMAND_CONNECT,
+            "192.0.2.1:25565".parse().unwrap(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(bound, "0.0.0.0:0".parse().unwrap());
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_short_response() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+
+        let proxy = Proxy {
+            addr: "127.0.0.1:1080".parse().unwrap(),
+            credentials: None,
+        };
+
+        let server = tokio::spawn(async move {
+            let mut greeting = [0; 3];
+            server.read_exact(&mut greeting).await.unwrap();
+            server.write_all(&[VERSION]).await.unwrap();
+        });
+
+        assert!(matches!(
+            proxy.authenticate(&mut client).await,
+            Err(Error::Io(_))
+        ));
+        server.await.unwrap();
+    }
+
+    #[test]
+    fn test_udp_header_len() {
+        let header = encode_address(
+            ADDRESS_HEADER_PREFIX.to_vec(),
+            "192.0.2.1:25565".parse().unwrap(),
+        );
+        assert_eq!(udp_header_len(&header).unwrap(), 10);
+
+        let header = encode_address(
+            ADDRESS_HEADER_PREFIX.to_vec(),
+            "[2001:db8::1]:25565".parse().unwrap(),
+        );
+        assert_eq!(udp_header_len(&header).unwrap(), 22);
+
+        assert!(udp_header_len(&[0, 0, 1, ADDRESS_IPV4]).is_err());
+    }
+}
@@ -0,0 +1,381 @@
+use std::time::Duration;
+
+use actix_web::web;
+use serde::Serialize;
+
+use mcapi_rs::{
+    resolver,
+    types::{Error, ServerPing},
+};
+
+use crate::{
+    cache::{CacheStore, Increment},
+    config::Config,
+    usage::{format_date, SECONDS_PER_DAY},
+};
+
+/// Sorted set holding a score for each server.
+const LEADERBOARD_KEY: &str = "stats:top";
+
+/// Hash counting every lookup.
+const TOTAL_KEY: &str = "stats:total";
+
+/// Seconds to keep the total after the last lookup.
+const TOTAL_TTL: u32 = 60 * 60 * 24 * 365;
+
+/// Seconds to keep the counters for each day, long enough to be read for the
+/// whole day.
+const DAY_TTL: u32 = 60 * 60 * 24 * 2;
+
+/// Hash counting lookups and checks on a date.
+fn day_key(date: &str) -> String {
+    format!("stats:day:{}", date)
+}
+
+/// Unique count of the servers looked up on a date.
+fn servers_key(date: &str) -> String {
+    format!("stats:servers:{}", date)
+}
+
+fn today() -> String {
+    format_date(crate::unix_timestamp() / SECONDS_PER_DAY)
+}
+
+/// Member counted for a server, if it is worth counting.
+fn server_member(host: &str, port: u16) -> Option<String> {
+    // Servers on ports that are never checked are not worth counting.
+    crate::validate_port(port).ok()?;
+
+    // Count each server once however its host was written.
+    let host = resolver::normalize_host(host).ok()?;
+
+    Some(format!("{}:{}", host, port))
+}
+
+/// Counters across every instance sharing the cache.
+#[derive(Debug, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct AggregateStats {
+    /// Lookups of any server since lookups were first counted.
+    pub total_lookups: u64,
+    /// Date the rest of the counters are for, in UTC.
+    pub date: String,
+    pub lookups_today: u64,
+    pub unique_servers_today: u64,
+    /// Percent of checks today that found the server online.
+    pub online_percent: Option<f64>,
+    /// Average milliseconds for online servers to answer a ping today.
+    pub average_latency_ms: Option<f64>,
+}
+
+/// Count a lookup of a server for the aggregate stats, if they are enabled.
+///
+/// The count is updated in the background so the request is not delayed by
+/// the cache.
+pub fn record_lookup(config: &Config, cache: &web::Data<dyn CacheStore>, host: &str, port: u16) {
+    if !config.aggregate_stats {
+        return;
+    }
+
+    let member = match server_member(host, port) {
+        Some(member) => member,
+        None => return,
+    };
+    let cache = cache.clone();
+
+    actix_web::rt::spawn(async move {
+        if let Err(err) = count_lookup(cache.get_ref(), &member).await {
+            tracing::warn!("could not count lookup for stats: {}", err);
+        }
+    });
+}
+
+/// Count a check of a server for today's stats, if they are enabled.
+pub async fn record_check(config: &Config, cache: &dyn CacheStore, data: &ServerPing) {
+    if !config.aggregate_stats {
+        return;
+    }
+
+    let mut counters = vec![("checks", 1)];
+    if data.online {
+        counters.push(("online", 1));
+    }
+    if let Some(latency) = data.latency {
+        counters.extend([("latency_ms", latency), ("latency_checks", 1)]);
+    }
+
+    if let Err(err) = cache
+        .increment_counters(&day_key(&today()), &counters, DAY_TTL)
+        .await
+    {
+        tracing::warn!("could not count check for stats: {}", err);
+    }
+}
+
+/// Get the total lookups and today's counters.
+pub async fn aggregate(cache: &dyn CacheStore) -> Result<AggregateStats, Error> {
+    let date = today();
+
+    let total = cache.counters(TOTAL_KEY).await?;
+    let day = cache.counters(&day_key(&date)).await?;
+    let unique_servers_today = cache.unique_count(&servers_key(&date)).await?;
+
+    let count = |name: &str| day.get(name).copied().unwrap_or_default();
+    let ratio = |part: u64, total: u64| (total > 0).then_some(part as f64 / total as f64);
+
+    Ok(AggregateStats {
+        total_lookups: total.get("lookups").copied().unwrap_or_default(),
+        lookups_today: count("lookups"),
+        unique_servers_today,
+        online_percent: ratio(count("online") * 100, count("checks")),
+        average_latency_ms: ratio(count("latency_ms"), count("latency_checks")),
+        date,
+    })
+}
+
+/// How often scores are decayed and the leaderboard is trimmed.
+const DECAY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How long the decay lock is held, until shortly before the next interval.
+const DECAY_LOCK_TTL: Duration = Duration::from_secs(60 * 59);
+
+/// Most servers to keep scores for between decays, as a multiple of the
+/// leaderboard size, so new servers have room to climb.
+const MEMBERS_PER_ENTRY: usize = 2;
+
+/// A server on the leaderboard.
+#[derive(Debug, PartialEq, Serialize, utoipa::ToSchema)]
+pub struct TopServer {
+    pub host: String,
+    pub port: u16,
+    /// Lookups for the server, with older lookups counting for less.
+    pub score: f64,
+}
+
+/// Counts lookups for each server to find the most requested servers.
+///
+/// Each lookup adds one to a server's score, then every score is
+/// periodically reduced so the count reflects recent demand. Scores halve
+/// after every half-life.
+#[derive(Debug)]
+pub struct Leaderboard {
+    /// Most servers to keep scores for, disabled if zero.
+    size: usize,
+    half_life: Duration,
+}
+
+impl Leaderboard {
+    pub fn new(size: usize, half_life: Duration) -> Self {
+        Self { size, half_life }
+    }
+
+    /// If lookups are being counted.
+    pub fn is_enabled(&self) -> bool {
+        self.size > 0
+    }
+
+    /// Count a lookup for a server, if the leaderboard is enabled.
+    ///
+    /// The count is updated in the background so the request is not delayed
+    /// by the cache.
+    pub fn record(&self, cache: &web::Data<dyn CacheStore>, host: &str, port: u16) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let member = match server_member(host, port) {
+            Some(member) => member,
+            None => return,
+        };
+        let cache = cache.clone();
+        let max = self.size * MEMBERS_PER_ENTRY;
+
+        actix_web::rt::spawn(async move {
+            if let Err(err) = cache
+                .increment_score(LEADERBOARD_KEY, &member, 1.0, max)
+                .await
+            {
+                tracing::warn!("could not count lookup for {}: {}", member, err);
+            }
+        });
+    }
+
+    /// Get up to `count` of the most requested servers.
+    pub async fn top(
+        &self,
+        cache: &dyn CacheStore,
+        count: usize,
+    ) -> Result<Vec<TopServer>, mcapi_rs::types::Error> {
+        let scores = cache.top_scores(LEADERBOARD_KEY, count).await?;
+
+        Ok(scores
+            .into_iter()
+            .filter_map(|(member, score)| {
+                let (host, port) = member.rsplit_once(':')?;
+
+                Some(TopServer {
+                    host: host.to_string(),
+                    port: port.parse().ok()?,
+                    score,
+                })
+            })
+            .collect())
+    }
+
+    /// Periodically decay scores and trim the leaderboard.
+    ///
+    /// The lock is never released, so scores are only decayed once per
+    /// interval across instances sharing a cache.
+    pub async fn run_decay(&self, cache: web::Data<dyn CacheStore>) {
+        let factor = self.decay_factor(DECAY_INTERVAL);
+        let mut interval = tokio::time::interval(DECAY_INTERVAL);
+
+        // The first tick completes immediately, but there is nothing to decay
+        // yet.
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+
+            let lock_key = format!("lock:{}:decay", LEADERBOARD_KEY);
+            match cache.lock(lock_key.as_bytes(), DECAY_LOCK_TTL).await {
+                Ok(Some(_lock)) => (),
+                Ok(None) => {
+                    tracing::debug!("leaderboard was already decayed");
+                    continue;
+                }
+                Err(err) => {
+                    tracing::error!("could not lock leaderboard: {}", err);
+                    continue;
+                }
+            }
+
+            if let Err(err) = cache.decay_scores(LEADERBOARD_KEY, factor, self.size).await {
+                tracing::error!("could not decay leaderboard: {}", err);
+            }
+        }
+    }
+
+    /// Amount to multiply scores by after each interval.
+    fn decay_factor(&self, interval: Duration) -> f64 {
+        0.5f64.powf(interval.as_secs_f64() / self.half_life.as_secs_f64())
+    }
+}
+
+/// Count a lookup in the total and today's counters.
+async fn count_lookup(cache: &dyn CacheStore, member: &str) -> Result<(), Error> {
+    let date = today();
+    let (day_key, servers_key) = (day_key(&date), servers_key(&date));
+
+    cache
+        .increment_many(&[
+            Increment::Counters {
+                key: TOTAL_KEY,
+                counters: &[("lookups", 1)],
+                ttl: TOTAL_TTL,
+            },
+            Increment::Counters {
+                key: &day_key,
+                counters: &[("lookups", 1)],
+                ttl: DAY_TTL,
+            },
+            Increment::Unique {
+                key: &servers_key,
+                member,
+                ttl: DAY_TTL,
+            },
+        ])
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::cache::MemoryStore;
+
+    #[test]
+    fn test_decay_factor() {
+        let leaderboard = Leaderboard::new(10, Duration::from_secs(60 * 60 * 24));
+
+        let factor = leaderboard.decay_factor(DECAY_INTERVAL);
+        assert!((factor.powi(24) - 0.5).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_aggregate() {
+        let cache = MemoryStore::new(10);
+        let config = Config {
+            aggregate_stats: true,
+            ..Default::default()
+        };
+
+        assert_eq!(aggregate(&cache).await.unwrap().online_percent, None);
+
+        count_lookup(&cache, "example.com:25565").await.unwrap();
+        count_lookup(&cache, "example.com:25565").await.unwrap();
+        count_lookup(&cache, "mc.example.com:25566").await.unwrap();
+
+        let online = ServerPing {
+            online: true,
+            latency: Some(40),
+            ..Default::default()
+        };
+        record_check(&config, &cache, &online).await;
+        record_check(
+            &config,
+            &cache,
+            &ServerPing {
+                latency: Some(20),
+                ..online.clone()
+            },
+        )
+        .await;
+        record_check(&config, &cache, &ServerPing::from(Error::ResolveFailed)).await;
+
+        // Checks are not counted unless the stats are enabled.
+        record_check(&Config::default(), &cache, &online).await;
+
+        assert_eq!(
+            aggregate(&cache).await.unwrap(),
+            AggregateStats {
+                total_lookups: 3,
+                date: today(),
+                lookups_today: 3,
+                unique_servers_today: 2,
+                online_percent: Some(200.0 / 3.0),
+                average_latency_ms: Some(30.0),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_top() {
+        let cache = MemoryStore::new(10);
+        let leaderboard = Leaderboard::new(10, Duration::from_secs(60));
+
+        cache
+            .increment_score(LEADERBOARD_KEY, "example.com:25565", 2.0, 20)
+            .await
+            .unwrap();
+        cache
+            .increment_score(LEADERBOARD_KEY, "mc.example.com:25566", 1.0, 20)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            leaderboard.top(&cache, 10).await.unwrap(),
+            vec![
+                TopServer {
+                    host: "example.com".to_string(),
+                    port: 25565,
+                    score: 2.0
+                },
+                TopServer {
+                    host: "mc.example.com".to_string(),
+                    port: 25566,
+                    score: 1.0
+                },
+            ]
+        );
+    }
+}
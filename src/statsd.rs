@@ -0,0 +1,231 @@
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+
+use prometheus::proto::{MetricFamily, MetricType};
+use tokio::{net::UdpSocket, sync::oneshot};
+
+/// Maximum size of a single datagram, chosen to fit within a typical MTU.
+const MAX_DATAGRAM_SIZE: usize = 1432;
+
+/// Periodically sends all registered Prometheus metrics to a DogStatsD
+/// server.
+///
+/// Counters and histograms are sent as counts of how much they changed since
+/// the previous flush, gauges are sent as their current value.
+pub struct StatsdSink {
+    addr: SocketAddr,
+    interval: Duration,
+    tags: Vec<String>,
+    previous: HashMap<String, f64>,
+}
+
+impl StatsdSink {
+    /// Create a new sink sending to `addr` every `interval`, adding `tags` to
+    /// every metric.
+    pub fn new(addr: SocketAddr, interval: Duration, tags: Vec<String>) -> Self {
+        Self {
+            addr,
+            interval,
+            tags,
+            previous: HashMap::new(),
+        }
+    }
+
+    /// Send metrics until `shutdown` is triggered, then send them one final
+    /// time.
+    pub async fn run(mut self, mut shutdown: oneshot::Receiver<()>) {
+        let socket = match UdpSocket::bind("0.0.0.0:0").await {
+            Ok(socket) => socket,
+            Err(err) => {
+                tracing::error!("could not bind statsd socket: {:?}", err);
+                return;
+            }
+        };
+
+        if let Err(err) = socket.connect(self.addr).await {
+            tracing::error!("could not connect statsd socket: {:?}", err);
+            return;
+        }
+
+        let mut interval = tokio::time::interval(self.interval);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => self.flush(&socket).await,
+                _ = &mut shutdown => {
+                    tracing::debug!("flushing statsd metrics before shutdown");
+                    self.flush(&socket).await;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Send all current metrics.
+    async fn flush(&mut self, socket: &UdpSocket) {
+        let lines = self.encode(&prometheus::gather());
+
+        for datagram in pack(lines) {
+            if let Err(err) = socket.send(datagram.as_bytes()).await {
+                tracing::warn!("could not send statsd metrics: {:?}", err);
+            }
+        }
+    }
+
+    /// Get how much a value changed since it was last seen.
+    fn delta(&mut self, key: String, value: f64) -> f64 {
+        let previous = self.previous.insert(key, value).unwrap_or(0.0);
+        value - previous
+    }
+
+    /// Format a single metric line with the given labels and global tags.
+    fn line(&self, name: &str, value: f64, kind: &str, labels: &[(String, String)]) -> String {
+        let tags: Vec<String> = labels
+            .iter()
+            .map(|(name, value)| format!("{}:{}", name, value))
+            .chain(self.tags.iter().cloned())
+            .collect();
+
+        if tags.is_empty() {
+            format!("{}:{}|{}", name, value, kind)
+        } else {
+            format!("{}:{}|{}|#{}", name, value, kind, tags.join(","))
+        }
+    }
+
+    /// Convert gathered metrics into DogStatsD lines, updating the previously
+    /// seen values used to calculate counts.
+    fn encode(&mut self, families: &[MetricFamily]) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        for family in families {
+            let name = family.get_name();
+
+            for metric in family.get_metric() {
+                let labels: Vec<(String, String)> = metric
+                    .get_label()
+                    .iter()
+                    .map(|pair| (pair.get_name().to_string(), pair.get_value().to_string()))
+                    .collect();
+                let key = format!("{}{:?}", name, labels);
+
+                match family.get_field_type() {
+                    MetricType::COUNTER => {
+                        let delta = self.delta(key, metric.get_counter().get_value());
+                        lines.push(self.line(name, delta, "c", &labels));
+                    }
+                    MetricType::GAUGE => {
+                        lines.push(self.line(name, metric.get_gauge().get_value(), "g", &labels));
+                    }
+                    MetricType::UNTYPED => {
+                        lines.push(self.line(name, metric.get_untyped().get_value(), "g", &labels));
+                    }
+                    MetricType::HISTOGRAM => {
+                        let histogram = metric.get_histogram();
+
+                        let count = self.delta(
+                            format!("{}:count", key),
+                            histogram.get_sample_count() as f64,
+                        );
+                        lines.push(self.line(&format!("{}.count", name), count, "c", &labels));
+
+                        let sum = self.delta(format!("{}:sum", key), histogram.get_sample_sum());
+                        lines.push(self.line(&format!("{}.sum", name), sum, "c", &labels));
+
+                        for bucket in histogram.get_bucket() {
+                            let le = bucket.get_upper_bound().to_string();
+                            let delta = self.delta(
+                                format!("{}:{}", key, le),
+                                bucket.get_cumulative_count() as f64,
+                            );
+
+                            let mut labels = labels.clone();
+                            labels.push(("le".to_string(), le));
+                            lines.push(self.line(&format!("{}.bucket", name), delta, "c", &labels));
+                        }
+                    }
+                    MetricType::SUMMARY => {
+                        let summary = metric.get_summary();
+
+                        let count =
+                            self.delta(format!("{}:count", key), summary.get_sample_count() as f64);
+                        lines.push(self.line(&format!("{}.count", name), count, "c", &labels));
+
+                        let sum = self.delta(format!("{}:sum", key), summary.get_sample_sum());
+                        lines.push(self.line(&format!("{}.sum", name), sum, "c", &labels));
+                    }
+                }
+            }
+        }
+
+        lines
+    }
+}
+
+/// Combine lines into newline separated datagrams no larger than
+/// [MAX_DATAGRAM_SIZE].
+fn pack(lines: Vec<String>) -> Vec<String> {
+    let mut datagrams = Vec::new();
+    let mut current = String::new();
+
+    for line in lines {
+        if !current.is_empty() && current.len() + line.len() + 1 > MAX_DATAGRAM_SIZE {
+            datagrams.push(std::mem::take(&mut current));
+        }
+
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(&line);
+    }
+
+    if !current.is_empty() {
+        datagrams.push(current);
+    }
+
+    datagrams
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let registry = prometheus::Registry::new();
+
+        let counter = prometheus::CounterVec::new(
+            prometheus::Opts::new("test_requests_total", "Test requests"),
+            &["method"],
+        )
+        .unwrap();
+        registry.register(Box::new(counter.clone())).unwrap();
+
+        let mut sink = StatsdSink::new(
+            "127.0.0.1:8125".parse().unwrap(),
+            Duration::from_secs(10),
+            vec!["env:test".to_string()],
+        );
+
+        counter.with_label_values(&["ping"]).inc_by(3.0);
+        assert_eq!(
+            sink.encode(&registry.gather()),
+            vec!["test_requests_total:3|c|#method:ping,env:test"]
+        );
+
+        counter.with_label_values(&["ping"]).inc();
+        assert_eq!(
+            sink.encode(&registry.gather()),
+            vec!["test_requests_total:1|c|#method:ping,env:test"]
+        );
+    }
+
+    #[test]
+    fn test_pack() {
+        let lines = vec!["a".repeat(1000), "b".repeat(400), "c".repeat(100)];
+        let datagrams = pack(lines);
+
+        assert_eq!(datagrams.len(), 2);
+        assert_eq!(datagrams[0].len(), 1000 + 1 + 400);
+        assert_eq!(datagrams[1], "c".repeat(100));
+    }
+}
@@ -0,0 +1,571 @@
+use actix_web::{
+    get,
+    http::header::{self, CacheControl, CacheDirective, ContentType},
+    web, HttpRequest, HttpResponse, Responder,
+};
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use mcapi_rs::{image, motd, resolver::Resolver, types::ServerPing};
+
+use crate::{
+    cache::CacheStore,
+    config::Config,
+    monitor::Monitor,
+    signing,
+    stats::{self, Leaderboard},
+};
+use crate::{HostMetrics, ServerAddr};
+
+/// Landing page filled in with the site's branding.
+const LANDING_TEMPLATE: &str = include_str!("../static/index.html");
+
+/// Page filled in with each server's status.
+const STATUS_TEMPLATE: &str = include_str!("../static/status.html");
+
+/// Card filled in with a server's status, to be embedded in other sites.
+const WIDGET_TEMPLATE: &str = include_str!("../static/widget.html");
+
+#[derive(Debug, Deserialize)]
+pub struct StatusPageRequest {
+    /// Address of the server, which may include the port.
+    host: String,
+}
+
+impl ServerAddr for StatusPageRequest {
+    fn host(&self) -> &str {
+        &self.host
+    }
+
+    fn port(&self) -> Option<u16> {
+        None
+    }
+}
+
+/// Seconds that signed preview image URLs on status pages are valid for.
+const OG_IMAGE_SIGNATURE_TTL: u64 = 60 * 60 * 24 * 7;
+
+/// Seconds signed preview image expirations are rounded up to, so the URLs
+/// stay the same long enough to be cached.
+const OG_IMAGE_EXPIRY_BUCKET: u64 = 60 * 60 * 24;
+
+/// URL the site is served from, without a trailing slash.
+///
+/// Without a configured public URL, it is built from the `Host` header.
+/// Forwarded headers are ignored, as any client could set them to change the
+/// links on pages that are cached for everyone.
+fn base_url(config: &Config, req: &HttpRequest) -> String {
+    if let Some(url) = &config.public_url {
+        return url.trim_end_matches('/').to_string();
+    }
+
+    let scheme = if req.app_config().secure() {
+        "https"
+    } else {
+        "http"
+    };
+    let host = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or_else(|| req.app_config().host());
+
+    format!("{}://{}", scheme, host)
+}
+
+/// When a preview image URL signed at `now` expires, at least the signature
+/// TTL later.
+fn og_image_expiry(now: u64) -> u64 {
+    let expires = now + OG_IMAGE_SIGNATURE_TTL;
+
+    expires - expires % OG_IMAGE_EXPIRY_BUCKET + OG_IMAGE_EXPIRY_BUCKET
+}
+
+/// Escape text to be included in HTML content or attributes.
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Percent encode text to be included in a query string.
+fn encode_query(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b':' => {
+                (byte as char).to_string()
+            }
+            byte => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Open Graph tags for a server's preview image, signing its URL if image
+/// URLs must be signed.
+fn og_image_tags(base_url: &str, host: &str, port: u16, secret: Option<&str>) -> String {
+    let path = "/server/og-image";
+    let mut query = format!("ip={}&port={}", encode_query(host), port);
+
+    if let Some(secret) = secret {
+        let expires = og_image_expiry(crate::unix_timestamp());
+        query.push_str(&format!("&{}={}", signing::EXPIRES_PARAM, expires));
+
+        let signature = signing::sign(secret, path, &query);
+        query.push_str(&format!("&{}={}", signing::SIGNATURE_PARAM, signature));
+    }
+
+    let (width, height) = image::OG_IMAGE_SIZE;
+
+    format!(
+        r#"<meta property="og:image" content="{}">
+    <meta property="og:image:width" content="{}">
+    <meta property="og:image:height" content="{}">
+    <meta name="twitter:card" content="summary_large_image">"#,
+        escape_html(&format!("{}{}?{}", base_url, path, query)),
+        width,
+        height,
+    )
+}
+
+/// Render a MOTD as HTML, with each span styled like it is in game.
+fn motd_html(description: &serde_json::Value) -> String {
+    motd::lines(&motd::parse(description))
+        .iter()
+        .map(|line| {
+            line.iter()
+                .map(|span| {
+                    let style = span.style;
+                    let mut css = Vec::new();
+
+                    if let Some(motd::Color(r, g, b)) = style.color {
+                        css.push(format!("color:#{:02x}{:02x}{:02x}", r, g, b));
+                    }
+                    if style.bold {
+                        css.push("font-weight:bold".to_string());
+                    }
+                    if style.italic {
+                        css.push("font-style:italic".to_string());
+                    }
+
+                    let decorations: Vec<_> = [
+                        (style.underlined, "underline"),
+                        (style.strikethrough, "line-through"),
+                    ]
+                    .iter()
+                    .filter_map(|(enabled, decoration)| enabled.then_some(*decoration))
+                    .collect();
+                    if !decorations.is_empty() {
+                        css.push(format!("text-decoration:{}", decorations.join(" ")));
+                    }
+
+                    let text = escape_html(&span.text);
+                    if css.is_empty() {
+                        text
+                    } else {
+                        format!(r#"<span style="{}">{}</span>"#, css.join(";"), text)
+                    }
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Values for the placeholders shared by every template.
+fn placeholders(
+    host: &str,
+    port: u16,
+    data: &ServerPing,
+    refresh: u32,
+) -> Vec<(&'static str, String)> {
+    let title = if port == 25565 {
+        host.to_string()
+    } else {
+        format!("{}:{}", host, port)
+    };
+
+    let icon = image::favicon_png(&data.favicon)
+        .unwrap_or_else(|| image::encode_png(image::placeholder_icon()));
+    let icon = format!("data:image/png;base64,{}", base64::encode(icon));
+
+    let (status, status_text, motd, description) = if data.online {
+        (
+            "online",
+            "Online",
+            motd_html(&data.motd_json),
+            format!(
+                "{} is online with {} of {} players",
+                title, data.players.now, data.players.max
+            ),
+        )
+    } else {
+        let error = data.error.as_deref().unwrap_or("unknown error");
+
+        (
+            "offline",
+            "Offline",
+            escape_html(error),
+            format!("{} is offline", title),
+        )
+    };
+
+    let player_count = if data.online {
+        format!("{} / {}", data.players.now, data.players.max)
+    } else {
+        "Unknown".to_string()
+    };
+
+    let mut players = player_count.clone();
+    if data.online && !data.players.sample.is_empty() {
+        players.push_str(r#"<ul class="players">"#);
+        for player in &data.players.sample {
+            players.push_str(&format!("<li>{}</li>", escape_html(&player.name)));
+        }
+        players.push_str("</ul>");
+    }
+
+    let version = data
+        .server
+        .name
+        .as_deref()
+        .filter(|_name| data.online)
+        .map(escape_html)
+        .unwrap_or_else(|| "Unknown".to_string());
+
+    vec![
+        ("{{title}}", escape_html(&title)),
+        ("{{description}}", escape_html(&description)),
+        ("{{refresh}}", refresh.to_string()),
+        ("{{icon}}", icon),
+        ("{{status}}", status.to_string()),
+        ("{{status_text}}", status_text.to_string()),
+        ("{{motd}}", motd),
+        ("{{player_count}}", player_count),
+        ("{{players}}", players),
+        ("{{version}}", version),
+    ]
+}
+
+/// Replace each placeholder in a template, without replacing placeholders
+/// that appear in the values.
+pub fn fill(template: &str, placeholders: &[(&str, String)]) -> String {
+    let mut page = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        page.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        match placeholders
+            .iter()
+            .find(|(placeholder, _value)| rest.starts_with(placeholder))
+        {
+            Some((placeholder, value)) => {
+                page.push_str(value);
+                rest = &rest[placeholder.len()..];
+            }
+            None => {
+                page.push_str("{{");
+                rest = &rest[2..];
+            }
+        }
+    }
+
+    page.push_str(rest);
+    page
+}
+
+/// Fill in the landing page with the site's branding and examples using the
+/// URL it was requested from.
+fn landing_html(config: &Config, base_url: &str) -> String {
+    let links: String = config
+        .site_links
+        .iter()
+        .map(|link| {
+            format!(
+                r#"                    <li class="navbar-item">
+                        <a class="nav-link" href="{}">{}</a>
+                    </li>
+"#,
+                escape_html(&link.url),
+                escape_html(&link.name)
+            )
+        })
+        .collect();
+
+    fill(
+        LANDING_TEMPLATE,
+        &[
+            ("{{title}}", escape_html(&config.site_title)),
+            ("{{url}}", escape_html(base_url)),
+            (
+                "{{example_server}}",
+                escape_html(&config.site_example_server),
+            ),
+            (
+                "{{example_server_query}}",
+                escape_html(&encode_query(&config.site_example_server)),
+            ),
+            ("{{links}}", links),
+            ("{{footer}}", config.site_footer.clone().unwrap_or_default()),
+            (
+                "{{analytics}}",
+                config.site_analytics.clone().unwrap_or_default(),
+            ),
+        ],
+    )
+}
+
+/// Show the landing page, describing how to use the API.
+pub async fn landing_page(config: web::Data<Config>, http_req: HttpRequest) -> impl Responder {
+    let base_url = base_url(&config, &http_req);
+
+    HttpResponse::Ok()
+        .insert_header(ContentType::html())
+        .body(landing_html(&config, &base_url))
+}
+
+/// Show a server's status as a page that can be shared, refreshing as often
+/// as the status is cached.
+#[get("/status/{host}")]
+pub async fn status_page(
+    resolver: web::Data<Resolver>,
+    cache: web::Data<dyn CacheStore>,
+    config: web::Data<Config>,
+    monitor: web::Data<Monitor>,
+    host_metrics: web::Data<HostMetrics>,
+    leaderboard: web::Data<Leaderboard>,
+    http_req: HttpRequest,
+    req: web::Path<StatusPageRequest>,
+) -> impl Responder {
+    let (host, port) = req.parse_host();
+    leaderboard.record(&cache, host, port);
+    stats::record_lookup(&config, &cache, host, port);
+
+    tracing::info!("attempting to get status page for {}:{}", host, port);
+
+    let data = crate::get_ping(&cache, &resolver, &config, &monitor, host, port).await;
+    host_metrics.observe(host, port, &data);
+
+    let refresh = config.ping_max_age.max(1);
+
+    let base_url = base_url(&config, &http_req);
+
+    let mut placeholders = placeholders(host, port, &data, refresh);
+    placeholders.push((
+        "{{url}}",
+        escape_html(&format!("{}{}", base_url, http_req.path())),
+    ));
+    placeholders.push((
+        "{{og_image}}",
+        if config.endpoint_enabled("image") {
+            og_image_tags(
+                &base_url,
+                host,
+                port,
+                config.image_signing_secret.as_deref(),
+            )
+        } else {
+            String::new()
+        },
+    ));
+
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(refresh),
+        ]))
+        .insert_header(ContentType::html())
+        .body(fill(STATUS_TEMPLATE, &placeholders))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ServerWidgetRequest {
+    /// Address of the server, which may include the port.
+    #[serde(rename = "ip")]
+    host: String,
+    /// Port of the server, defaults to 25565.
+    port: Option<u16>,
+    /// Either `light` or `dark`.
+    #[param(value_type = Option<String>)]
+    theme: Option<image::Theme>,
+}
+
+impl ServerAddr for ServerWidgetRequest {
+    fn host(&self) -> &str {
+        &self.host
+    }
+
+    fn port(&self) -> Option<u16> {
+        self.port
+    }
+}
+
+/// Show a server's status as a small card meant to be embedded in an iframe,
+/// which updates itself as often as the status is cached.
+#[utoipa::path(
+    get,
+    path = "/server/widget",
+    tag = "server",
+    params(ServerWidgetRequest),
+    responses((status = 200, description = "HTML page showing the status of the server", content_type = "text/html"))
+)]
+#[get("/server/widget")]
+pub async fn server_widget(
+    resolver: web::Data<Resolver>,
+    cache: web::Data<dyn CacheStore>,
+    config: web::Data<Config>,
+    monitor: web::Data<Monitor>,
+    host_metrics: web::Data<HostMetrics>,
+    leaderboard: web::Data<Leaderboard>,
+    web::Query(req): web::Query<ServerWidgetRequest>,
+) -> impl Responder {
+    let (host, port) = req.parse_host();
+    leaderboard.record(&cache, host, port);
+    stats::record_lookup(&config, &cache, host, port);
+
+    tracing::info!("attempting to get widget for {}:{}", host, port);
+
+    let data = crate::get_ping(&cache, &resolver, &config, &monitor, host, port).await;
+    host_metrics.observe(host, port, &data);
+
+    let refresh = config.ping_max_age.max(1);
+
+    let mut placeholders = placeholders(host, port, &data, refresh);
+    placeholders.push((
+        "{{theme}}",
+        match req.theme.unwrap_or_default() {
+            image::Theme::Light => "light",
+            image::Theme::Dark => "dark",
+        }
+        .to_string(),
+    ));
+
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(refresh),
+        ]))
+        .insert_header(ContentType::html())
+        .body(fill(WIDGET_TEMPLATE, &placeholders))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_motd_html() {
+        let description = serde_json::json!({
+            "text": "§aHello <world>\n§lbold",
+        });
+
+        assert_eq!(
+            motd_html(&description),
+            "<span style=\"color:#55ff55\">Hello &lt;world&gt;</span>\n<span style=\"color:#55ff55;font-weight:bold\">bold</span>"
+        );
+    }
+
+    #[test]
+    fn test_og_image_tags() {
+        let tags = og_image_tags("https://mcapi.us", "exämple.com", 25565, None);
+        assert!(tags.contains(
+            r#"content="https://mcapi.us/server/og-image?ip=ex%C3%A4mple.com&amp;port=25565""#
+        ));
+
+        let tags = og_image_tags("https://mcapi.us", "example.com", 25565, Some("secret"));
+        let url = tags.split('"').nth(3).unwrap().replace("&amp;", "&");
+        let query = url.split_once('?').unwrap().1;
+        assert_eq!(
+            signing::verify("secret", "/server/og-image", query, crate::unix_timestamp()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_og_image_expiry() {
+        let now = 1_700_000_000;
+        let expires = og_image_expiry(now);
+
+        assert!(expires > now + OG_IMAGE_SIGNATURE_TTL);
+        assert!(expires <= now + OG_IMAGE_SIGNATURE_TTL + OG_IMAGE_EXPIRY_BUCKET);
+        assert_eq!(expires % OG_IMAGE_EXPIRY_BUCKET, 0);
+        assert_eq!(og_image_expiry(now + 60), expires);
+    }
+
+    #[test]
+    fn test_landing_html() {
+        let config = Config {
+            site_title: "Example <API>".to_string(),
+            site_example_server: "play.example.com".to_string(),
+            site_links: vec!["Docs=/docs".parse().unwrap()],
+            site_footer: Some("<p>Run by Example</p>".to_string()),
+            ..Default::default()
+        };
+
+        let page = landing_html(&config, "https://api.example.com");
+        assert!(page.contains("<title>Example &lt;API&gt;</title>"));
+        assert!(page.contains("https://api.example.com/server/status?ip=play.example.com"));
+        assert!(page.contains(r#"<a class="nav-link" href="/docs">Docs</a>"#));
+        assert!(page.contains("<p>Run by Example</p>"));
+        assert!(!page.contains("{{"));
+
+        let config = Config {
+            site_example_server: "a&b=c".to_string(),
+            ..Default::default()
+        };
+
+        let page = landing_html(&config, "https://api.example.com");
+        assert!(page.contains("/server/status?ip=a%26b%3Dc"));
+        assert!(!page.contains("ip=a&amp;b=c"));
+    }
+
+    #[test]
+    fn test_fill() {
+        let data = ServerPing {
+            online: true,
+            motd_json: serde_json::json!("A <b>server</b>"),
+            server: mcapi_rs::types::ServerPingServer {
+                name: Some("1.20.1".to_string()),
+                protocol: 763,
+            },
+            ..Default::default()
+        };
+
+        let mut values = placeholders("example.com", 25566, &data, 60);
+        values.push(("{{url}}", String::new()));
+        values.push(("{{og_image}}", String::new()));
+
+        let page = fill(STATUS_TEMPLATE, &values);
+        assert!(page.contains("<h1>example.com:25566</h1>"));
+        assert!(page.contains("A &lt;b&gt;server&lt;/b&gt;"));
+        assert!(page.contains("<dd>1.20.1</dd>"));
+        assert!(!page.contains("{{"));
+
+        let offline = ServerPing::from(mcapi_rs::types::Error::ResolveFailed);
+        let page = fill(
+            WIDGET_TEMPLATE,
+            &placeholders("example.com", 25565, &offline, 60),
+        );
+        assert!(page.contains(r#"class="card offline""#));
+
+        // Placeholders in values are left alone.
+        let page = fill(
+            "{{a}} {{b}}",
+            &[("{{a}}", "{{b}}".to_string()), ("{{b}}", "b".to_string())],
+        );
+        assert_eq!(page, "{{b}} b");
+    }
+}
@@ -0,0 +1,182 @@
+use opentelemetry::{
+    propagation::Injector,
+    sdk::{propagation::TraceContextPropagator, trace, Resource},
+    KeyValue,
+};
+use opentelemetry_otlp::{WithExportConfig, OTEL_EXPORTER_OTLP_ENDPOINT};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use tonic::metadata::{MetadataKey, MetadataMap, MetadataValue};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{filter::LevelFilter, layer::SubscriberExt, util::SubscriberInitExt};
+
+/// Environment variable containing comma separated `key=value` pairs to send
+/// as headers with each OTLP export.
+const OTEL_EXPORTER_OTLP_HEADERS: &str = "OTEL_EXPORTER_OTLP_HEADERS";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("otlp header was missing value: {0}")]
+    MissingValue(String),
+    #[error("otlp header was invalid: {0}")]
+    InvalidHeader(String),
+    #[error("could not create otlp tracer: {0}")]
+    Trace(#[from] opentelemetry::trace::TraceError),
+}
+
+/// Parse OTLP headers from the environment into gRPC metadata.
+fn otlp_headers() -> Result<MetadataMap, Error> {
+    let headers = std::env::var(OTEL_EXPORTER_OTLP_HEADERS).unwrap_or_default();
+
+    parse_otlp_headers(&headers)
+}
+
+/// Parse comma separated `key=value` pairs into gRPC metadata.
+fn parse_otlp_headers(headers: &str) -> Result<MetadataMap, Error> {
+    let mut metadata = MetadataMap::new();
+
+    for header in headers.split(',').filter(|header| !header.is_empty()) {
+        let (key, value) = header
+            .split_once('=')
+            .ok_or_else(|| Error::MissingValue(header.to_string()))?;
+
+        match (
+            MetadataKey::from_bytes(key.trim().to_lowercase().as_bytes()),
+            MetadataValue::from_str(value.trim()),
+        ) {
+            (Ok(key), Ok(value)) => {
+                metadata.insert(key, value);
+            }
+            _ => return Err(Error::InvalidHeader(key.to_string())),
+        }
+    }
+
+    Ok(metadata)
+}
+
+/// Sets headers on outgoing requests from the trace context.
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        match (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            (Ok(key), Ok(value)) => {
+                self.0.insert(key, value);
+            }
+            _ => tracing::warn!("trace context header was invalid: {}", key),
+        }
+    }
+}
+
+/// Get `traceparent` and `tracestate` headers for the current span, so
+/// requests to other services continue the same trace.
+///
+/// When traces are not exported, no headers are returned.
+pub fn trace_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let context = tracing::Span::current().context();
+
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(&mut headers))
+    });
+
+    headers
+}
+
+/// Initialize tracing.
+///
+/// Logs are always written to stdout. If `OTEL_EXPORTER_OTLP_ENDPOINT` is set,
+/// spans are also exported to that collector, continuing traces from the
+/// `traceparent` and `tracestate` headers of incoming requests.
+///
+/// Errors are returned before anything is initialized, as they could not be
+/// logged yet.
+pub fn init() -> Result<(), Error> {
+    let registry = tracing_subscriber::registry()
+        .with(LevelFilter::INFO)
+        .with(tracing_subscriber::fmt::layer());
+
+    if std::env::var(OTEL_EXPORTER_OTLP_ENDPOINT).is_err() {
+        registry.init();
+        return Ok(());
+    }
+
+    let exporter = opentelemetry_otlp::new_exporter()
+        .tonic()
+        .with_env()
+        .with_metadata(otlp_headers()?);
+
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(exporter)
+        .with_trace_config(
+            trace::config().with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                "mcapi-rs",
+            )])),
+        )
+        .install_batch(opentelemetry::runtime::TokioCurrentThread)?;
+
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+
+    registry
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .init();
+
+    tracing::info!("exporting traces with otlp");
+
+    Ok(())
+}
+
+/// Flush any remaining spans before exiting.
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use opentelemetry::{
+        propagation::TextMapPropagator,
+        trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState},
+        Context,
+    };
+
+    #[test]
+    fn test_parse_otlp_headers() {
+        let metadata = parse_otlp_headers("api-key=secret, X-Team = mcapi,").unwrap();
+        assert_eq!(metadata.get("api-key").unwrap().to_str().unwrap(), "secret");
+        assert_eq!(metadata.get("x-team").unwrap().to_str().unwrap(), "mcapi");
+
+        assert!(matches!(
+            parse_otlp_headers("api-key"),
+            Err(Error::MissingValue(_))
+        ));
+        assert!(matches!(
+            parse_otlp_headers("bad key=value"),
+            Err(Error::InvalidHeader(_))
+        ));
+    }
+
+    #[test]
+    fn test_header_injector() {
+        let context = Context::new().with_remote_span_context(SpanContext::new(
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+            SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        ));
+
+        let mut headers = HeaderMap::new();
+        TraceContextPropagator::new().inject_context(&context, &mut HeaderInjector(&mut headers));
+
+        assert_eq!(
+            headers["traceparent"],
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        );
+    }
+}
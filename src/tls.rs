@@ -0,0 +1,118 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    Certificate, PrivateKey, ServerConfig,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("no private key found")]
+    MissingKey,
+    #[error("no certificates found")]
+    MissingCertificates,
+    #[error("unsupported private key")]
+    UnsupportedKey,
+}
+
+/// Resolves the server certificate from files on disk, allowing them to be
+/// reloaded without restarting.
+pub struct ReloadingResolver {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    key: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadingResolver {
+    /// Create a new resolver, loading the certificate chain and private key
+    /// from the given PEM files.
+    pub fn new(cert_path: &Path, key_path: &Path) -> Result<Self, Error> {
+        let key = load_certified_key(cert_path, key_path)?;
+
+        Ok(Self {
+            cert_path: cert_path.to_owned(),
+            key_path: key_path.to_owned(),
+            key: RwLock::new(Arc::new(key)),
+        })
+    }
+
+    /// Load the certificate chain and private key again. If they could not be
+    /// loaded, the previous certificate continues to be used.
+    pub fn reload(&self) -> Result<(), Error> {
+        let key = load_certified_key(&self.cert_path, &self.key_path)?;
+        *self.key.write().unwrap() = Arc::new(key);
+
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for ReloadingResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.key.read().unwrap().clone())
+    }
+}
+
+/// Load a certificate chain and private key from PEM files.
+fn load_certified_key(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey, Error> {
+    let mut reader = BufReader::new(File::open(cert_path)?);
+    let certs: Vec<Certificate> = rustls_pemfile::certs(&mut reader)?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+
+    if certs.is_empty() {
+        return Err(Error::MissingCertificates);
+    }
+
+    let mut reader = BufReader::new(File::open(key_path)?);
+    let key = loop {
+        match rustls_pemfile::read_one(&mut reader)? {
+            Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => break PrivateKey(key),
+            Some(_) => continue,
+            None => return Err(Error::MissingKey),
+        }
+    };
+
+    let key = rustls::sign::any_supported_type(&key).map_err(|_err| Error::UnsupportedKey)?;
+
+    Ok(CertifiedKey::new(certs, key))
+}
+
+/// Build a server configuration using the given resolver.
+pub fn server_config(resolver: Arc<ReloadingResolver>) -> ServerConfig {
+    ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver)
+}
+
+/// Reload certificates each time the process receives a SIGHUP.
+#[cfg(unix)]
+pub async fn reload_on_sighup(resolver: Arc<ReloadingResolver>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(err) => {
+            tracing::error!("could not listen for sighup: {:?}", err);
+            return;
+        }
+    };
+
+    while hangups.recv().await.is_some() {
+        match resolver.reload() {
+            Ok(()) => tracing::info!("reloaded tls certificate"),
+            Err(err) => tracing::error!("could not reload tls certificate: {}", err),
+        }
+    }
+}
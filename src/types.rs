@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -6,56 +7,173 @@ pub enum Error {
     Redis(#[from] redis::RedisError),
     #[error("json error: {0}")]
     Json(#[from] serde_json::Error),
+    #[error("memcached error: {0}")]
+    Memcached(String),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
 
     #[error("invalid port {0}")]
     InvalidPort(u16),
+    #[error("invalid hostname {0}")]
+    InvalidHost(String),
     #[error("could not resolve host")]
     ResolveFailed,
     #[error("timeout: {0}")]
     Timeout(#[from] tokio::time::error::Elapsed),
     #[error("protocol error: {0}")]
     Protocol(#[from] crate::protocol::Error),
+    #[error("upstream error: {0}")]
+    Upstream(String),
+}
+
+/// Why a server could not be checked, so clients can act on the reason
+/// without parsing error messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OfflineReason {
+    /// The address was not a valid host or port.
+    InvalidAddress,
+    /// The host had no addresses in DNS.
+    Dns,
+    /// Nothing was accepting connections on the port.
+    ConnectionRefused,
+    /// The server closed the connection before responding.
+    ConnectionReset,
+    /// The server did not respond in time.
+    Timeout,
+    /// The server responded with data that was not a valid status.
+    Protocol,
+    /// Any other network error.
+    Network,
+    /// The proxy used to connect to servers failed.
+    Proxy,
+    /// The upstream instance could not be reached.
+    Upstream,
+    /// Something went wrong in the API itself, such as with the cache.
+    Internal,
+}
+
+impl OfflineReason {
+    fn from_io(err: &std::io::Error) -> Self {
+        use std::io::ErrorKind;
+
+        match err.kind() {
+            ErrorKind::ConnectionRefused => Self::ConnectionRefused,
+            ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::BrokenPipe
+            | ErrorKind::UnexpectedEof => Self::ConnectionReset,
+            ErrorKind::TimedOut => Self::Timeout,
+            _ => Self::Network,
+        }
+    }
+}
+
+impl Error {
+    /// Get why a server appears offline because of this error.
+    pub fn offline_reason(&self) -> OfflineReason {
+        use crate::protocol::Error as ProtocolError;
+
+        match self {
+            Self::InvalidPort(_) | Self::InvalidHost(_) => OfflineReason::InvalidAddress,
+            Self::ResolveFailed => OfflineReason::Dns,
+            Self::Timeout(_) | Self::Protocol(ProtocolError::Timeout(_)) => OfflineReason::Timeout,
+            Self::Io(err) | Self::Protocol(ProtocolError::Io(err)) => OfflineReason::from_io(err),
+            Self::Protocol(ProtocolError::Proxy(_)) => OfflineReason::Proxy,
+            Self::Protocol(_) => OfflineReason::Protocol,
+            Self::Upstream(_) => OfflineReason::Upstream,
+            Self::Redis(_) | Self::Json(_) | Self::Memcached(_) => OfflineReason::Internal,
+        }
+    }
 }
 
 pub trait Metadata {
     const NAME: &'static str;
 
     fn updated_at(&self) -> u64;
+    fn duration(&self) -> u64;
     fn set_times(self, last_updated: u64, duration: u64) -> Self;
     fn is_online(&self) -> bool;
+    fn players_online(&self) -> i64;
 }
 
-#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+#[derive(Clone, Default, Debug, Serialize, Deserialize, ToSchema)]
 pub struct ServerPingPlayers {
     pub max: i32,
     pub now: i32,
     pub sample: Vec<crate::protocol::PlayerSample>,
 }
 
-#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+#[derive(Clone, Default, Debug, Serialize, Deserialize, ToSchema)]
 pub struct ServerPingServer {
     pub name: Option<String>,
     pub protocol: i32,
 }
 
-#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+/// Where a server was found, for debugging DNS records.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ResolvedAddress {
+    /// IP address that was connected to.
+    #[schema(value_type = String)]
+    pub ip: std::net::IpAddr,
+    pub port: u16,
+    /// Host from the SRV record that was used, if any.
+    pub srv_target: Option<String>,
+}
+
+impl ResolvedAddress {
+    pub fn new(addr: std::net::SocketAddr, srv_target: Option<String>) -> Self {
+        Self {
+            ip: addr.ip(),
+            port: addr.port(),
+            srv_target,
+        }
+    }
+}
+
+#[derive(Clone, Default, Debug, Serialize, Deserialize, ToSchema)]
 pub struct ServerPing {
     pub status: String,
     pub online: bool,
 
     pub motd: String,
+    #[schema(value_type = Object)]
     pub motd_json: serde_json::Value,
 
     pub favicon: Option<String>,
     pub error: Option<String>,
+    /// Why the server could not be checked, if it could not.
+    #[serde(default)]
+    pub offline_reason: Option<OfflineReason>,
 
     pub players: ServerPingPlayers,
     pub server: ServerPingServer,
 
+    /// Fields the server sent that are not otherwise included, such as from
+    /// mods or plugins.
+    #[serde(default, skip_serializing_if = "serde_json::Map::is_empty")]
+    #[schema(value_type = Object)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+
+    /// Milliseconds for the server to answer a ping packet, if it answered.
+    pub latency: Option<u64>,
+
+    /// Where the server was found, only included when requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolved: Option<ResolvedAddress>,
+
+    /// Status as the server sent it without the favicon, only included when
+    /// requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub raw: Option<serde_json::Value>,
+
     #[serde(with = "string")]
+    #[schema(value_type = String)]
     pub last_updated: u64,
 
     #[serde(with = "string")]
+    #[schema(value_type = String)]
     pub duration: u64,
 }
 
@@ -66,6 +184,10 @@ impl Metadata for ServerPing {
         self.last_updated
     }
 
+    fn duration(&self) -> u64 {
+        self.duration
+    }
+
     fn set_times(mut self, last_updated: u64, duration: u64) -> Self {
         self.last_updated = last_updated;
         self.duration = duration;
@@ -76,6 +198,9 @@ impl Metadata for ServerPing {
     fn is_online(&self) -> bool {
         self.online
     }
+    fn players_online(&self) -> i64 {
+        self.players.now as i64
+    }
 }
 
 impl From<crate::protocol::Ping> for ServerPing {
@@ -87,6 +212,7 @@ impl From<crate::protocol::Ping> for ServerPing {
             motd_json: data.description,
             favicon: data.favicon,
             error: None,
+            offline_reason: None,
             players: ServerPingPlayers {
                 max: data.players.max,
                 now: data.players.online,
@@ -96,6 +222,10 @@ impl From<crate::protocol::Ping> for ServerPing {
                 name: data.version.name,
                 protocol: data.version.protocol,
             },
+            extra: data.extra,
+            latency: data.latency.map(|latency| latency.as_millis() as u64),
+            resolved: data.addr.map(|addr| ResolvedAddress::new(addr, None)),
+            raw: data.raw,
             last_updated: 0,
             duration: 0,
         }
@@ -108,23 +238,27 @@ impl From<Error> for ServerPing {
             online: false,
             status: "error".to_string(),
             error: Some(err.to_string()),
+            offline_reason: Some(err.offline_reason()),
             ..Default::default()
         }
     }
 }
 
-#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+#[derive(Clone, Default, Debug, Serialize, Deserialize, ToSchema)]
 pub struct ServerQueryPlayers {
     pub max: usize,
     pub now: usize,
     pub list: Vec<String>,
 }
 
-#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+#[derive(Clone, Default, Debug, Serialize, Deserialize, ToSchema)]
 pub struct ServerQuery {
     pub status: String,
     pub online: bool,
     pub error: Option<String>,
+    /// Why the server could not be checked, if it could not.
+    #[serde(default)]
+    pub offline_reason: Option<OfflineReason>,
 
     pub server_mod: String,
     pub plugins: Vec<String>,
@@ -133,10 +267,16 @@ pub struct ServerQuery {
     #[serde(flatten)]
     pub kv: std::collections::HashMap<String, String>,
 
+    /// Where the server was found, only included when requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolved: Option<ResolvedAddress>,
+
     #[serde(with = "string")]
+    #[schema(value_type = String)]
     pub last_updated: u64,
 
     #[serde(with = "string")]
+    #[schema(value_type = String)]
     pub duration: u64,
 }
 
@@ -147,6 +287,10 @@ impl Metadata for ServerQuery {
         self.last_updated
     }
 
+    fn duration(&self) -> u64 {
+        self.duration
+    }
+
     fn set_times(mut self, last_updated: u64, duration: u64) -> Self {
         self.last_updated = last_updated;
         self.duration = duration;
@@ -157,6 +301,9 @@ impl Metadata for ServerQuery {
     fn is_online(&self) -> bool {
         self.online
     }
+    fn players_online(&self) -> i64 {
+        self.players.now as i64
+    }
 }
 
 impl ServerQuery {
@@ -196,6 +343,7 @@ impl From<crate::protocol::Query> for ServerQuery {
         Self {
             status: "success".to_string(),
             error: None,
+            offline_reason: None,
             online: true,
 
             server_mod: data.server.0,
@@ -203,6 +351,8 @@ impl From<crate::protocol::Query> for ServerQuery {
             kv,
             players,
 
+            resolved: None,
+
             last_updated: 0,
             duration: 0,
         }
@@ -215,6 +365,7 @@ impl From<Error> for ServerQuery {
             online: false,
             status: "error".to_string(),
             error: Some(err.to_string()),
+            offline_reason: Some(err.offline_reason()),
             ..Default::default()
         }
     }
@@ -245,3 +396,52 @@ pub mod string {
             .map_err(de::Error::custom)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offline_reason() {
+        use crate::protocol::Error as ProtocolError;
+        use std::io::{Error as IoError, ErrorKind};
+
+        let io = |kind| ProtocolError::Io(IoError::new(kind, "test"));
+
+        for (err, reason) in [
+            (Error::ResolveFailed, OfflineReason::Dns),
+            (
+                Error::InvalidHost("example..com".to_string()),
+                OfflineReason::InvalidAddress,
+            ),
+            (
+                Error::Protocol(io(ErrorKind::ConnectionRefused)),
+                OfflineReason::ConnectionRefused,
+            ),
+            (
+                Error::Protocol(io(ErrorKind::UnexpectedEof)),
+                OfflineReason::ConnectionReset,
+            ),
+            (
+                Error::Protocol(ProtocolError::Timeout("connect")),
+                OfflineReason::Timeout,
+            ),
+            (
+                Error::Protocol(ProtocolError::PacketTooLarge),
+                OfflineReason::Protocol,
+            ),
+            (
+                Error::Protocol(io(ErrorKind::AddrNotAvailable)),
+                OfflineReason::Network,
+            ),
+        ] {
+            assert_eq!(err.offline_reason(), reason, "{}", err);
+        }
+
+        let ping = ServerPing::from(Error::ResolveFailed);
+        assert_eq!(
+            serde_json::to_value(&ping).unwrap()["offline_reason"],
+            "dns"
+        );
+    }
+}
@@ -0,0 +1,124 @@
+//! Forwarding cache misses to another instance of the API, such as a probe in
+//! another region, instead of checking servers directly.
+
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use prometheus::{register_counter_vec, CounterVec};
+use serde::de::DeserializeOwned;
+
+use mcapi_rs::types::{Error, Metadata};
+
+lazy_static! {
+    static ref CLIENT: reqwest::Client = reqwest::Client::builder()
+        .user_agent(concat!("mcapi-rs/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .unwrap_or_default();
+    static ref UPSTREAM_REQUESTS: CounterVec = register_counter_vec!(
+        "mcapi_upstream_requests_total",
+        "Number of requests forwarded to the upstream instance by result",
+        &["method", "result"]
+    )
+    .unwrap();
+}
+
+/// Build the URL of an endpoint on the upstream instance.
+///
+/// The base URL is treated as a directory, so an instance served under a path
+/// works with or without a trailing slash.
+fn endpoint_url(base: &str, path: &str) -> Result<reqwest::Url, Error> {
+    let base = if base.ends_with('/') {
+        reqwest::Url::parse(base)
+    } else {
+        reqwest::Url::parse(&format!("{}/", base))
+    };
+
+    base.and_then(|base| base.join(path))
+        .map_err(|err| Error::Upstream(err.to_string()))
+}
+
+/// Check that the upstream URL can be used, for validating configuration.
+pub fn validate_url(base: &str) -> Result<(), Error> {
+    endpoint_url(base, "").map(|_url| ())
+}
+
+/// Get data for a server from an endpoint on the upstream instance.
+///
+/// The resolved address and raw status are always requested, so they can be
+/// included in responses from this instance when asked for.
+#[tracing::instrument(skip(base, timeout), fields(method = D::NAME))]
+pub async fn fetch<D>(
+    base: &str,
+    path: &str,
+    host: &str,
+    port: u16,
+    timeout: Duration,
+) -> Result<D, Error>
+where
+    D: Metadata + DeserializeOwned,
+{
+    let url = endpoint_url(base, path)?;
+    let port = port.to_string();
+
+    let result = async {
+        let mut value = CLIENT
+            .get(url)
+            .query(&[
+                ("ip", host),
+                ("port", &port),
+                ("resolved", "true"),
+                ("raw", "true"),
+            ])
+            .headers(crate::telemetry::trace_headers())
+            .timeout(timeout)
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status())
+            .map_err(|err| err.to_string())?
+            .json::<serde_json::Value>()
+            .await
+            .map_err(|err| err.to_string())?;
+
+        // Fields describing the upstream cache would otherwise end up in the
+        // extra fields of query data.
+        if let Some(fields) = value.as_object_mut() {
+            for field in crate::CACHED_FIELDS {
+                fields.remove(*field);
+            }
+        }
+
+        serde_json::from_value::<D>(value).map_err(|err| format!("invalid response: {}", err))
+    }
+    .await;
+
+    let label = if result.is_ok() { "success" } else { "error" };
+    UPSTREAM_REQUESTS.with_label_values(&[D::NAME, label]).inc();
+
+    result.map_err(|err| {
+        tracing::warn!("upstream request failed: {}", err);
+        Error::Upstream(err)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoint_url() {
+        let url = endpoint_url("https://eu.example.com", "server/status").unwrap();
+        assert_eq!(url.as_str(), "https://eu.example.com/server/status");
+
+        let url = endpoint_url("https://example.com/mcapi", "server/query").unwrap();
+        assert_eq!(url.as_str(), "https://example.com/mcapi/server/query");
+
+        let url = endpoint_url("https://example.com/mcapi/", "server/query").unwrap();
+        assert_eq!(url.as_str(), "https://example.com/mcapi/server/query");
+
+        assert!(validate_url("https://example.com").is_ok());
+        assert!(matches!(
+            validate_url("example.com"),
+            Err(Error::Upstream(_))
+        ));
+    }
+}
@@ -0,0 +1,246 @@
+use std::{convert::TryFrom, fmt, future::Future, str::FromStr};
+
+use actix_web::{
+    body::{BodySize, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::header::HeaderName,
+    web,
+};
+use serde::Serialize;
+
+use mcapi_rs::types::Error;
+
+use crate::{
+    cache::{CacheBackend, CacheStore},
+    config::Config,
+};
+
+/// Header clients provide their API key in.
+pub const X_API_KEY: HeaderName = HeaderName::from_static("x-api-key");
+
+pub const SECONDS_PER_DAY: u64 = 60 * 60 * 24;
+
+/// A key clients may identify themselves with, configured like `id=token`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ApiKey {
+    /// Name usage is recorded under.
+    pub id: String,
+    token: String,
+}
+
+impl fmt::Debug for ApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ApiKey")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FromStr for ApiKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (id, token) = s
+            .split_once('=')
+            .ok_or_else(|| "api key must be formatted as id=token".to_string())?;
+        let (id, token) = (id.trim(), token.trim());
+
+        if id.is_empty()
+            || !id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+        {
+            return Err(format!("invalid api key id: {}", id));
+        }
+
+        if token.is_empty() {
+            return Err(format!("api key {} is missing a token", id));
+        }
+
+        Ok(Self {
+            id: id.to_string(),
+            token: token.to_string(),
+        })
+    }
+}
+
+/// Requests made with an API key on one day.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct DailyUsage {
+    /// Date in UTC, like `2023-01-31`.
+    pub date: String,
+    pub requests: u64,
+    /// Bytes sent in response bodies.
+    pub bytes: u64,
+}
+
+/// Find the ID of the key matching a token.
+pub fn key_id<'a>(keys: &'a [ApiKey], token: &str) -> Option<&'a str> {
+    keys.iter()
+        .find(|key| crate::admin::tokens_match(key.token.as_bytes(), token.as_bytes()))
+        .map(|key| key.id.as_str())
+}
+
+/// Format days since the unix epoch as a `YYYY-MM-DD` date.
+///
+/// Uses the `civil_from_days` algorithm from
+/// <http://howardhinnant.github.io/date_algorithms.html>.
+pub fn format_date(days: u64) -> String {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Cache key holding the counters for an API key on a date.
+fn usage_key(id: &str, date: &str) -> String {
+    format!("usage:{}:{}", id, date)
+}
+
+/// Count a request made with an API key, in the background so the response
+/// is not delayed by the cache.
+fn record(cache: web::Data<dyn CacheStore>, id: String, bytes: u64, retention_days: u64) {
+    let key = usage_key(&id, &format_date(crate::unix_timestamp() / SECONDS_PER_DAY));
+    let ttl = u32::try_from(retention_days.saturating_mul(SECONDS_PER_DAY)).unwrap_or(u32::MAX);
+
+    actix_web::rt::spawn(async move {
+        if let Err(err) = cache
+            .increment_counters(&key, &[("requests", 1), ("bytes", bytes)], ttl)
+            .await
+        {
+            tracing::warn!("could not record usage for api key {}: {}", id, err);
+        }
+    });
+}
+
+/// Get usage of an API key for each of the last `days` days, starting with
+/// today.
+pub async fn daily_usage(
+    cache: &dyn CacheStore,
+    id: &str,
+    days: u64,
+) -> Result<Vec<DailyUsage>, Error> {
+    let today = crate::unix_timestamp() / SECONDS_PER_DAY;
+    let mut usage = Vec::new();
+
+    for day in (0..days).filter_map(|ago| today.checked_sub(ago)) {
+        let date = format_date(day);
+        let counters = cache.counters(&usage_key(id, &date)).await?;
+
+        usage.push(DailyUsage {
+            date,
+            requests: counters.get("requests").copied().unwrap_or_default(),
+            bytes: counters.get("bytes").copied().unwrap_or_default(),
+        });
+    }
+
+    Ok(usage)
+}
+
+/// Middleware to count requests and response bytes for requests that include
+/// a configured API key in the `X-Api-Key` header.
+///
+/// Requests without a key, or with an unknown key, are handled as usual.
+pub fn middleware<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, actix_web::Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    // Memcached cannot store counters, which is logged when starting.
+    let usage = req
+        .app_data::<web::Data<Config>>()
+        .filter(|config| config.cache_backend != CacheBackend::Memcached)
+        .and_then(|config| {
+            let token = req.headers().get(X_API_KEY)?.to_str().ok()?;
+            let id = key_id(&config.api_keys, token)?;
+            let cache = req.app_data::<web::Data<dyn CacheStore>>()?.clone();
+
+            Some((cache, id.to_string(), config.api_key_usage_retention))
+        });
+
+    let fut = srv.call(req);
+
+    async move {
+        let res = fut.await?;
+
+        if let Some((cache, id, retention_days)) = usage {
+            // Streamed bodies have an unknown size, so only count the request.
+            let bytes = match res.response().body().size() {
+                BodySize::Sized(size) => size,
+                _ => 0,
+            };
+
+            record(cache, id, bytes, retention_days);
+        }
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::cache::MemoryStore;
+
+    #[test]
+    fn test_parse_api_key() {
+        let key: ApiKey = " example-site = secret ".parse().unwrap();
+        assert_eq!(key.id, "example-site");
+        assert_eq!(key.token, "secret");
+
+        assert!("secret".parse::<ApiKey>().is_err());
+        assert!("=secret".parse::<ApiKey>().is_err());
+        assert!("bad:id=secret".parse::<ApiKey>().is_err());
+        assert!("example=".parse::<ApiKey>().is_err());
+
+        let keys = vec![key];
+        assert_eq!(key_id(&keys, "secret"), Some("example-site"));
+        assert_eq!(key_id(&keys, "other"), None);
+    }
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(11_016), "2000-02-29");
+        assert_eq!(format_date(20_742), "2026-10-16");
+    }
+
+    #[tokio::test]
+    async fn test_daily_usage() {
+        let cache = MemoryStore::new(10);
+        let today = format_date(crate::unix_timestamp() / SECONDS_PER_DAY);
+
+        cache
+            .increment_counters(
+                &usage_key("example", &today),
+                &[("requests", 2), ("bytes", 300)],
+                60,
+            )
+            .await
+            .unwrap();
+
+        let usage = daily_usage(&cache, "example", 2).await.unwrap();
+        assert_eq!(usage.len(), 2);
+        assert_eq!(
+            usage[0],
+            DailyUsage {
+                date: today,
+                requests: 2,
+                bytes: 300,
+            }
+        );
+        assert_eq!(usage[1].requests, 0);
+    }
+}
@@ -0,0 +1,178 @@
+use std::time::Duration;
+
+use actix_web::{
+    get,
+    http::header::{CacheControl, CacheDirective},
+    web, HttpResponse, Responder,
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::{sleep, Instant};
+use utoipa::{IntoParams, ToSchema};
+
+use mcapi_rs::resolver::Resolver;
+
+use crate::{
+    cache::CacheStore, changes::ServerChanges, config::Config, monitor::Monitor, Cached,
+    CachedServerPing, ServerAddr,
+};
+
+/// How long to wait before first checking the cached status for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Longest time between checks, as the interval doubles after each check.
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(8);
+
+/// Seconds to wait for a change when no timeout was requested.
+const DEFAULT_TIMEOUT: u64 = 30;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ServerWaitRequest {
+    /// Address of the server, which may include the port.
+    #[serde(rename = "ip")]
+    host: String,
+    /// Port of the server, defaults to 25565.
+    port: Option<u16>,
+
+    /// Seconds to wait for a change, limited to the max wait timeout.
+    timeout: Option<u64>,
+}
+
+impl ServerWaitRequest {
+    /// How long to wait for a change, limited to `max` seconds.
+    fn timeout(&self, max: u64) -> Duration {
+        Duration::from_secs(self.timeout.unwrap_or(DEFAULT_TIMEOUT).min(max))
+    }
+}
+
+impl ServerAddr for ServerWaitRequest {
+    fn host(&self) -> &str {
+        &self.host
+    }
+
+    fn port(&self) -> Option<u16> {
+        self.port
+    }
+}
+
+/// The latest status of a server after waiting.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct WaitResponse {
+    #[serde(flatten)]
+    status: CachedServerPing,
+    /// If the status changed before the timeout elapsed.
+    changed: bool,
+}
+
+/// Wait until the status of a server changes or the timeout elapses, then
+/// respond with the latest status.
+///
+/// The cached status is checked with a backoff, which refreshes it at most as
+/// often as other requests would.
+#[utoipa::path(
+    get,
+    path = "/server/wait",
+    tag = "server",
+    params(ServerWaitRequest),
+    responses((status = 200, description = "Latest status of the server, and if it changed before the timeout", body = WaitResponse))
+)]
+#[get("/server/wait")]
+pub async fn server_wait(
+    resolver: web::Data<Resolver>,
+    cache: web::Data<dyn CacheStore>,
+    config: web::Data<Config>,
+    monitor: web::Data<Monitor>,
+    web::Query(req): web::Query<ServerWaitRequest>,
+) -> impl Responder {
+    let (host, port) = req.parse_host();
+
+    let timeout = req.timeout(config.max_wait_timeout);
+    let deadline = Instant::now() + timeout;
+
+    tracing::info!(
+        "waiting up to {} seconds for status of {}:{} to change",
+        timeout.as_secs(),
+        host,
+        port
+    );
+
+    let (cache, resolver, config, monitor) = (&cache, &resolver, &config, &monitor);
+    let ping = move || {
+        crate::fetch_ping(
+            cache,
+            resolver,
+            config,
+            monitor,
+            host,
+            port,
+            config.ping_max_age,
+            true,
+        )
+    };
+
+    let (initial, mut checked) = ping().await;
+    let mut current = initial.clone();
+    let mut interval = POLL_INTERVAL;
+
+    let changed = loop {
+        let now = Instant::now();
+        if now >= deadline {
+            break false;
+        }
+
+        sleep(interval.min(deadline - now)).await;
+        interval = next_interval(interval);
+
+        let (data, refreshed) = ping().await;
+        current = data;
+        checked |= refreshed;
+
+        if ServerChanges::between(Some(&initial), &current).changed {
+            break true;
+        }
+    };
+
+    // Details only included when requested from `/server/status`.
+    current.resolved = None;
+    current.raw = None;
+
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(WaitResponse {
+            status: Cached::new(current, checked, config.ping_max_age),
+            changed,
+        })
+}
+
+/// Time to wait before the next check, doubling the interval up to the max.
+fn next_interval(interval: Duration) -> Duration {
+    (interval * 2).min(MAX_POLL_INTERVAL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timeout() {
+        let req = web::Query::<ServerWaitRequest>::from_query("ip=example.com").unwrap();
+        assert_eq!(req.timeout(60), Duration::from_secs(DEFAULT_TIMEOUT));
+        assert_eq!(req.timeout(10), Duration::from_secs(10));
+
+        let req = web::Query::<ServerWaitRequest>::from_query("ip=example.com&timeout=5").unwrap();
+        assert_eq!(req.timeout(60), Duration::from_secs(5));
+        assert_eq!(req.timeout(0), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_next_interval() {
+        let intervals: Vec<_> = std::iter::successors(Some(POLL_INTERVAL), |interval| {
+            Some(next_interval(*interval))
+        })
+        .take(6)
+        .map(|interval| interval.as_secs())
+        .collect();
+
+        assert_eq!(intervals, vec![1, 2, 4, 8, 8, 8]);
+    }
+}
@@ -0,0 +1,184 @@
+use std::{collections::HashSet, time::Duration};
+
+use actix_web::web;
+use redis::AsyncCommands;
+
+use mcapi_rs::{resolver::Resolver, types::Error};
+
+use crate::{cache::CacheStore, config::Config, monitor::Monitor};
+
+/// Port used for seeds that do not include one.
+const DEFAULT_PORT: u16 = 25565;
+
+/// A server to check when starting.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Seed {
+    pub host: String,
+    pub port: u16,
+}
+
+impl Seed {
+    /// Parse a seed formatted like `host:port`, or just `host` for the default
+    /// port. IPv6 addresses with a port must be in brackets, like `[::1]:25565`.
+    pub fn parse(seed: &str) -> Option<Self> {
+        let seed = seed.trim();
+        if seed.is_empty() {
+            return None;
+        }
+
+        let (host, port) = match seed.strip_prefix('[') {
+            Some(rest) => match rest.split_once(']')? {
+                (host, "") => (host, DEFAULT_PORT),
+                (host, port) => (host, port.strip_prefix(':')?.parse().ok()?),
+            },
+            None => match seed.rsplit_once(':') {
+                // Hosts with more than one colon are IPv6 addresses without a
+                // port.
+                Some((host, _port)) if host.contains(':') => (seed, DEFAULT_PORT),
+                Some((host, port)) => (host, port.parse().ok()?),
+                None => (seed, DEFAULT_PORT),
+            },
+        };
+
+        Some(Self {
+            host: host.to_lowercase(),
+            port,
+        })
+    }
+}
+
+/// Parse seeds with one server on each line, skipping blank lines and comments
+/// starting with `#`.
+fn parse_seeds(contents: &str) -> Vec<Seed> {
+    contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| {
+            let seed = Seed::parse(line);
+            if seed.is_none() {
+                tracing::warn!("skipping invalid warmup seed: {}", line.trim());
+            }
+            seed
+        })
+        .collect()
+}
+
+/// Load seeds from the warmup file and Redis set, if they are configured,
+/// removing duplicates.
+pub async fn load_seeds(config: &Config) -> Result<Vec<Seed>, Error> {
+    let mut seeds = Vec::new();
+
+    if let Some(path) = &config.warmup_file {
+        seeds.extend(parse_seeds(&tokio::fs::read_to_string(path).await?));
+    }
+
+    // The config requires a Redis server when a set is configured.
+    if let (Some(key), Some(server)) = (&config.warmup_redis_set, config.redis_server.first()) {
+        seeds.extend(load_redis_set(server, key).await?);
+    }
+
+    let mut seen = HashSet::new();
+    seeds.retain(|seed| seen.insert(seed.clone()));
+
+    Ok(seeds)
+}
+
+async fn load_redis_set(server: &str, key: &str) -> Result<Vec<Seed>, Error> {
+    let client = redis::Client::open(server)?;
+    let mut con = client.get_async_connection().await?;
+
+    let members: Vec<String> = con.smembers(key).await?;
+
+    Ok(members
+        .iter()
+        .filter_map(|member| Seed::parse(member))
+        .collect())
+}
+
+/// Check each seed to fill the cache, starting at most `warmup_rate` checks
+/// each second.
+///
+/// Checks go through the cache like any other request, so servers that are
+/// already cached, such as by another instance, are not checked again.
+pub async fn run(
+    seeds: Vec<Seed>,
+    cache: web::Data<dyn CacheStore>,
+    resolver: web::Data<Resolver>,
+    config: web::Data<Config>,
+    monitor: web::Data<Monitor>,
+) {
+    tracing::info!("warming cache with {} servers", seeds.len());
+
+    let mut interval = tokio::time::interval(Duration::from_secs_f64(1.0 / config.warmup_rate));
+    let mut checks = Vec::with_capacity(seeds.len());
+
+    for seed in seeds {
+        interval.tick().await;
+
+        let cache = cache.clone();
+        let resolver = resolver.clone();
+        let config = config.clone();
+        let monitor = monitor.clone();
+
+        checks.push(actix_web::rt::spawn(async move {
+            tracing::debug!("warming server {}:{}", seed.host, seed.port);
+
+            crate::fetch_ping(
+                &cache,
+                &resolver,
+                &config,
+                &monitor,
+                &seed.host,
+                seed.port,
+                config.ping_max_age,
+                true,
+            )
+            .await;
+        }));
+    }
+
+    futures::future::join_all(checks).await;
+
+    tracing::info!("finished warming cache");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_seeds() {
+        let seeds = parse_seeds(
+            "# popular servers\nExample.com:25566\n\nmc.example.com # no port\nbad:port\n",
+        );
+
+        assert_eq!(
+            seeds,
+            vec![
+                Seed {
+                    host: "example.com".to_string(),
+                    port: 25566,
+                },
+                Seed {
+                    host: "mc.example.com".to_string(),
+                    port: 25565,
+                },
+            ]
+        );
+
+        assert_eq!(
+            parse_seeds("[::1]:25566\n::1\n[::1]25566\n"),
+            vec![
+                Seed {
+                    host: "::1".to_string(),
+                    port: 25566,
+                },
+                Seed {
+                    host: "::1".to_string(),
+                    port: 25565,
+                },
+            ]
+        );
+    }
+}
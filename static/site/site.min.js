@@ -1 +1 @@
-(function(){window.hljs.initHighlightingOnLoad();(function(a){return fetch("/server/status?ip="+a).then(function(a){return a.json()}).then(function(a){document.querySelector("#live-server-response").innerHTML=JSON.stringify(a,null,"  ");Array.from(document.querySelectorAll("pre code")).forEach(function(a){return window.hljs.highlightBlock(a)})})})("c.nerd.nu")})();
+(function(){window.hljs.initHighlightingOnLoad();(function(a){return fetch("/server/status?ip="+a).then(function(a){return a.json()}).then(function(a){document.querySelector("#live-server-response").innerHTML=JSON.stringify(a,null,"  ");Array.from(document.querySelectorAll("pre code")).forEach(function(a){return window.hljs.highlightBlock(a)})})})(document.querySelector("#live-server-response").dataset.server)})();
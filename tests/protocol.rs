@@ -0,0 +1,279 @@
+mod testsupport;
+
+use futures_retry::FutureRetry;
+use mcapi_rs::protocol::{self, Error, Limits, Outbound, Retry};
+
+use testsupport::{packet, varint, QueryReply, QueryServer, StatusReply, StatusServer};
+
+const STATUS_JSON: &str = r#"{
+    "version": {"name": "1.18.2", "protocol": 758},
+    "players": {"max": 20, "online": 2, "sample": [{"name": "Notch", "id": "069a79f4-44e9-4726-a5be-fca90e38aaf5"}]},
+    "description": {"text": "A Minecraft ", "extra": [{"text": "Server"}]}
+}"#;
+
+async fn ping(server: &StatusServer) -> Result<protocol::Ping, Error> {
+    protocol::send_ping(
+        &[server.addr],
+        "localhost",
+        server.addr.port(),
+        &testsupport::timeouts(),
+        &Limits::default(),
+        &Outbound::default(),
+    )
+    .await
+}
+
+async fn trace(server: &StatusServer) -> protocol::PingTrace {
+    protocol::trace_ping(
+        &[server.addr],
+        "localhost",
+        server.addr.port(),
+        &testsupport::timeouts(),
+        &Limits::default(),
+        &Outbound::default(),
+    )
+    .await
+}
+
+async fn query(server: &QueryServer) -> Result<protocol::Query, Error> {
+    protocol::send_query(server.addr, &testsupport::timeouts(), &Outbound::default()).await
+}
+
+#[tokio::test]
+async fn test_ping() {
+    let server = StatusServer::start(vec![StatusReply::Json(STATUS_JSON.to_string())]).await;
+
+    let ping = ping(&server).await.unwrap();
+    assert_eq!(ping.version.name.as_deref(), Some("1.18.2"));
+    assert_eq!(ping.players.online, 2);
+    assert_eq!(ping.players.sample.as_ref().unwrap()[0].name, "Notch");
+    assert_eq!(ping.get_motd().as_deref(), Some("A Minecraft Server"));
+    assert_eq!(ping.addr, Some(server.addr));
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+
+    let (handshake, request) = &requests[0];
+    assert_eq!(handshake[0], 0x00);
+    assert!(handshake.windows(9).any(|window| window == b"localhost"));
+    assert_eq!(request, &[0x00]);
+}
+
+#[tokio::test]
+async fn test_trace_ping() {
+    let server = StatusServer::start(vec![StatusReply::Json(STATUS_JSON.to_string())]).await;
+    let traced = trace(&server).await;
+    assert!(traced.error.is_none());
+    assert_eq!(traced.addr, Some(server.addr));
+    assert!(traced.connect.is_some() && traced.read.is_some() && traced.parse.is_some());
+    assert_eq!(traced.raw_status.as_deref(), Some(STATUS_JSON));
+    assert_eq!(traced.ping.unwrap().players.online, 2);
+
+    let packets = &traced.packets.data;
+    assert_eq!(packets[0].direction, protocol::Direction::Sent);
+    assert!(packets[0]
+        .data
+        .windows(9)
+        .any(|window| window == b"localhost"));
+    assert_eq!(packets[1].direction, protocol::Direction::Received);
+    assert!(packets[1].data.ends_with(STATUS_JSON.as_bytes()));
+    assert!(!traced.packets.truncated);
+
+    // Malformed status is still returned, but parsing fails.
+    let server = StatusServer::start(vec![StatusReply::Json("{\"version\":".to_string())]).await;
+    let traced = trace(&server).await;
+    assert!(matches!(traced.error, Some(Error::Json(_))));
+    assert_eq!(traced.raw_status.as_deref(), Some("{\"version\":"));
+    assert!(traced.parse.is_some());
+    assert!(traced.ping.is_none());
+    assert_eq!(traced.packets.data.len(), 2);
+
+    // Later phases have no duration after the server closes the connection.
+    let server = StatusServer::start(vec![StatusReply::Raw(vec![])]).await;
+    let traced = trace(&server).await;
+    assert!(matches!(traced.error, Some(Error::Io(_))));
+    assert!(traced.read.is_none() && traced.parse.is_none());
+}
+
+#[tokio::test]
+async fn test_ping_malformed_json() {
+    let server = StatusServer::start(vec![StatusReply::Json("{\"version\":".to_string())]).await;
+    assert!(matches!(ping(&server).await, Err(Error::Json(_))));
+
+    let server = StatusServer::start(vec![StatusReply::Json("{}".to_string())]).await;
+    assert!(matches!(ping(&server).await, Err(Error::Json(_))));
+}
+
+#[tokio::test]
+async fn test_ping_invalid_utf8() {
+    let mut data = varint(2);
+    data.extend([0xC3, 0x28]);
+
+    let server = StatusServer::start(vec![StatusReply::Raw(packet(0x00, &data))]).await;
+    assert!(matches!(ping(&server).await, Err(Error::Utf8(_))));
+}
+
+#[tokio::test]
+async fn test_ping_truncated() {
+    // Connection closed without any response.
+    let server = StatusServer::start(vec![StatusReply::Raw(vec![])]).await;
+    assert!(matches!(ping(&server).await, Err(Error::Io(_))));
+
+    // Response declares more data than is sent.
+    let mut data = varint(STATUS_JSON.len() as u32);
+    data.extend_from_slice(&STATUS_JSON.as_bytes()[..10]);
+
+    let server = StatusServer::start(vec![StatusReply::Raw(packet(0x00, &data))]).await;
+    assert!(matches!(ping(&server).await, Err(Error::Io(_))));
+}
+
+#[tokio::test]
+async fn test_ping_invalid_varint() {
+    let server = StatusServer::start(vec![StatusReply::Raw(vec![0xFF; 6])]).await;
+    assert!(matches!(ping(&server).await, Err(Error::Varint)));
+}
+
+#[tokio::test]
+async fn test_ping_too_large() {
+    let mut data = varint(0x00);
+    data.extend(varint(1024 * 1024 * 11));
+
+    let mut response = varint(data.len() as u32);
+    response.extend(data);
+
+    let server = StatusServer::start(vec![StatusReply::Raw(response)]).await;
+    assert!(matches!(ping(&server).await, Err(Error::PacketTooLarge)));
+}
+
+#[tokio::test]
+async fn test_ping_silent() {
+    let server = StatusServer::start(vec![StatusReply::Silent]).await;
+    assert!(matches!(ping(&server).await, Err(Error::Timeout("read"))));
+}
+
+#[tokio::test]
+async fn test_ping_retry() {
+    let server = StatusServer::start(vec![
+        StatusReply::Raw(vec![]),
+        StatusReply::Json(STATUS_JSON.to_string()),
+    ])
+    .await;
+
+    let retry = Retry {
+        retries: 1,
+        delay: std::time::Duration::from_millis(10),
+    };
+
+    let (ping, attempts) = FutureRetry::new(|| ping(&server), retry).await.unwrap();
+    assert_eq!(ping.players.max, 20);
+    assert_eq!(attempts, 2);
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn test_query() {
+    let server = QueryServer::start(QueryReply::Stat {
+        kv: vec![
+            ("hostname".to_string(), "A Minecraft Server".to_string()),
+            ("numplayers".to_string(), "2".to_string()),
+            (
+                "plugins".to_string(),
+                "Paper on 1.18.2: WorldEdit 7.2; Essentials 2.19".to_string(),
+            ),
+        ],
+        players: vec!["Notch".to_string(), "jeb_".to_string()],
+    })
+    .await;
+
+    let query = query(&server).await.unwrap();
+    assert_eq!(query.kv["hostname"], "A Minecraft Server");
+    assert_eq!(query.kv["numplayers"], "2");
+    assert_eq!(query.server.0, "Paper on 1.18.2");
+    assert_eq!(query.server.1, vec!["WorldEdit 7.2", "Essentials 2.19"]);
+    assert_eq!(query.players, vec!["Notch", "jeb_"]);
+}
+
+#[tokio::test]
+async fn test_query_malformed_challenge() {
+    let server = QueryServer::start(QueryReply::RawChallenge(vec![0x09])).await;
+    assert!(matches!(query(&server).await, Err(Error::PacketTooShort)));
+
+    let mut challenge = vec![0x09, 0, 0, 0, 0];
+    challenge.extend(b"not-a-number\0");
+
+    let server = QueryServer::start(QueryReply::RawChallenge(challenge)).await;
+    assert!(matches!(query(&server).await, Err(Error::Number(_))));
+}
+
+#[tokio::test]
+async fn test_query_truncated_stat() {
+    let server = QueryServer::start(QueryReply::RawStat(vec![0x00, 0, 0, 0, 0, 0])).await;
+    assert!(matches!(query(&server).await, Err(Error::PacketTooShort)));
+
+    // Data ending partway through a value is ignored.
+    let mut stat = vec![0x00, 0, 0, 0, 0];
+    stat.extend(b"splitnum\0\x80\0hostname\0A Minecr");
+
+    let server = QueryServer::start(QueryReply::RawStat(stat)).await;
+    let query = query(&server).await.unwrap();
+    assert!(query.kv.is_empty());
+    assert!(query.players.is_empty());
+}
+
+#[tokio::test]
+async fn test_query_silent() {
+    let server = QueryServer::start(QueryReply::Silent).await;
+    assert!(matches!(query(&server).await, Err(Error::Timeout("read"))));
+}
+
+/// Run a blocking function on another thread, so the test servers keep
+/// running on this runtime.
+#[cfg(feature = "blocking")]
+async fn off_runtime<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(f());
+    });
+
+    rx.await.unwrap()
+}
+
+#[cfg(feature = "blocking")]
+#[tokio::test]
+async fn test_blocking() {
+    let server = StatusServer::start(vec![StatusReply::Json(STATUS_JSON.to_string())]).await;
+    let addr = server.addr;
+
+    let ping = off_runtime(move || {
+        protocol::send_ping_blocking(
+            &[addr],
+            "localhost",
+            addr.port(),
+            &testsupport::timeouts(),
+            &Limits::default(),
+            &Outbound::default(),
+        )
+    })
+    .await
+    .unwrap();
+    assert_eq!(ping.players.online, 2);
+
+    let server = QueryServer::start(QueryReply::Stat {
+        kv: vec![("hostname".to_string(), "A Minecraft Server".to_string())],
+        players: vec!["Notch".to_string()],
+    })
+    .await;
+    let addr = server.addr;
+
+    let query = off_runtime(move || {
+        protocol::send_query_blocking(addr, &testsupport::timeouts(), &Outbound::default())
+    })
+    .await
+    .unwrap();
+    assert_eq!(query.kv["hostname"], "A Minecraft Server");
+    assert_eq!(query.players, vec!["Notch"]);
+}
@@ -0,0 +1,258 @@
+//! Fake Minecraft servers with scriptable responses for testing the protocol
+//! implementation.
+
+#![allow(dead_code)]
+
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+};
+
+use mcapi_rs::protocol::Timeouts;
+
+/// Timeouts short enough to quickly test servers that never respond.
+pub fn timeouts() -> Timeouts {
+    Timeouts {
+        dns: Duration::from_secs(1),
+        connect: Duration::from_secs(1),
+        read: Duration::from_millis(200),
+        total: Duration::from_secs(2),
+    }
+}
+
+/// Encode a number as a VarInt.
+pub fn varint(mut num: u32) -> Vec<u8> {
+    let mut data = vec![];
+
+    loop {
+        let byte = (num & 0b0111_1111) as u8;
+        num >>= 7;
+
+        if num == 0 {
+            data.push(byte);
+            return data;
+        }
+
+        data.push(byte | 0b1000_0000);
+    }
+}
+
+/// Prefix packet data with its ID and length.
+pub fn packet(id: u32, data: &[u8]) -> Vec<u8> {
+    let mut body = varint(id);
+    body.extend_from_slice(data);
+
+    let mut packet = varint(body.len() as u32);
+    packet.extend(body);
+    packet
+}
+
+/// Handshake and request packets received on each connection.
+type Requests = Arc<Mutex<Vec<(Vec<u8>, Vec<u8>)>>>;
+
+/// How the status server responds to a connection.
+#[derive(Clone, Debug)]
+pub enum StatusReply {
+    /// Respond with a correctly framed status response containing this JSON.
+    Json(String),
+    /// Send these bytes exactly, then close the connection.
+    Raw(Vec<u8>),
+    /// Read the request but never respond.
+    Silent,
+}
+
+/// A fake server responding to status pings over TCP.
+///
+/// Each connection is given the next reply, repeating the last reply once
+/// all others have been used.
+pub struct StatusServer {
+    pub addr: SocketAddr,
+    requests: Requests,
+}
+
+impl StatusServer {
+    pub async fn start(replies: Vec<StatusReply>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let mut replies = VecDeque::from(replies);
+
+        let received = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _addr) = match listener.accept().await {
+                    Ok(conn) => conn,
+                    Err(_err) => return,
+                };
+
+                let reply = if replies.len() > 1 {
+                    replies.pop_front().unwrap()
+                } else {
+                    replies.front().cloned().unwrap_or(StatusReply::Silent)
+                };
+
+                tokio::spawn(handle_status(stream, reply, received.clone()));
+            }
+        });
+
+        Self { addr, requests }
+    }
+
+    /// The handshake and request packets received on each connection.
+    pub fn requests(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn handle_status(mut stream: TcpStream, reply: StatusReply, requests: Requests) {
+    let handshake = match read_packet(&mut stream).await {
+        Some(packet) => packet,
+        None => return,
+    };
+
+    let request = match read_packet(&mut stream).await {
+        Some(packet) => packet,
+        None => return,
+    };
+
+    requests.lock().unwrap().push((handshake, request));
+
+    match reply {
+        StatusReply::Json(json) => {
+            let mut data = varint(json.len() as u32);
+            data.extend_from_slice(json.as_bytes());
+
+            let _ = stream.write_all(&packet(0x00, &data)).await;
+        }
+        StatusReply::Raw(data) => {
+            let _ = stream.write_all(&data).await;
+        }
+        StatusReply::Silent => {
+            // Hold the connection open until the client gives up.
+            let _ = stream.read_to_end(&mut vec![]).await;
+        }
+    }
+}
+
+/// Read a complete length-prefixed packet, including its ID.
+async fn read_packet(stream: &mut TcpStream) -> Option<Vec<u8>> {
+    let mut len = 0;
+
+    for index in 0..5 {
+        let byte = stream.read_u8().await.ok()?;
+        len |= u32::from(byte & 0b0111_1111) << (7 * index);
+
+        if byte & 0b1000_0000 == 0 {
+            break;
+        }
+    }
+
+    let mut data = vec![0; len as usize];
+    stream.read_exact(&mut data).await.ok()?;
+
+    Some(data)
+}
+
+/// Challenge token given to clients by the query server.
+pub const CHALLENGE_TOKEN: i32 = 9_513_307;
+
+/// How the query server responds to each request.
+#[derive(Clone, Debug)]
+pub enum QueryReply {
+    /// Respond correctly, with these keys and values and players.
+    Stat {
+        kv: Vec<(String, String)>,
+        players: Vec<String>,
+    },
+    /// Send these bytes instead of a challenge token.
+    RawChallenge(Vec<u8>),
+    /// Respond correctly to the handshake, then send these bytes instead of
+    /// the stat response.
+    RawStat(Vec<u8>),
+    /// Never respond.
+    Silent,
+}
+
+/// A fake server responding to queries over UDP.
+pub struct QueryServer {
+    pub addr: SocketAddr,
+}
+
+impl QueryServer {
+    pub async fn start(reply: QueryReply) -> Self {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut buf = vec![0; 1024];
+
+            loop {
+                let (len, peer) = match socket.recv_from(&mut buf).await {
+                    Ok(recv) => recv,
+                    Err(_err) => return,
+                };
+
+                if let Some(response) = query_response(&reply, &buf[..len]) {
+                    let _ = socket.send_to(&response, peer).await;
+                }
+            }
+        });
+
+        Self { addr }
+    }
+}
+
+fn query_response(reply: &QueryReply, request: &[u8]) -> Option<Vec<u8>> {
+    if request.len() < 7 || request[0..2] != [0xFE, 0xFD] {
+        return None;
+    }
+
+    let kind = request[2];
+    let session_id = &request[3..7];
+
+    let mut response = vec![kind];
+    response.extend_from_slice(session_id);
+
+    match (kind, reply) {
+        (_, QueryReply::Silent) => None,
+        (0x09, QueryReply::RawChallenge(data)) => Some(data.clone()),
+        (0x09, _) => {
+            response.extend(CHALLENGE_TOKEN.to_string().as_bytes());
+            response.push(0x00);
+            Some(response)
+        }
+        (0x00, QueryReply::RawStat(data)) => Some(data.clone()),
+        (0x00, QueryReply::Stat { kv, players }) => {
+            if request.len() < 11 || request[7..11] != CHALLENGE_TOKEN.to_be_bytes() {
+                return None;
+            }
+
+            response.extend(b"splitnum\0\x80\0");
+            for (key, value) in kv {
+                response.extend(key.as_bytes());
+                response.push(0x00);
+                response.extend(value.as_bytes());
+                response.push(0x00);
+            }
+            response.push(0x00);
+
+            response.extend(b"\x01player_\0\0");
+            for player in players {
+                response.extend(player.as_bytes());
+                response.push(0x00);
+            }
+            response.push(0x00);
+
+            Some(response)
+        }
+        _ => None,
+    }
+}